@@ -1,6 +1,9 @@
 use crate::benchmarks::benchtemplate::BenchTemplate;
 use common::commands::Commands;
-use criterion::{criterion_group, Criterion};
+use common::ids::ValueId;
+use common::md_index::{DistanceMetric, KdTree, MdIndex, QuadTree, R_Tree, ZOrderIndex};
+use common::Field;
+use criterion::{criterion_group, BenchmarkId, Criterion};
 use utilities::template::Template;
 use rstar::RTree;
 use rand::Rng;
@@ -8,6 +11,8 @@ use rand::Rng;
 const BASE_PATH: &str = "../test_data/";
 const KD: usize = 0;
 const R: usize = 1;
+const QUAD: usize = 2;
+const Z_ORDER: usize = 3;
 const data_num: usize = 100;
 const k: usize = 5;
 const MAX: i64 = 65536;
@@ -15,21 +20,16 @@ const MAX: i64 = 65536;
 fn setup_table(bt: &mut Template, table: &str, dim: usize, tree_type: usize) {
     bt.string_to_setup("CREATE TABLE test (a int primary key,b int, c int);".to_string());
     bt.string_to_setup(format!("\\i {}{}.csv test", BASE_PATH, table));
+    let tree_name = match tree_type {
+        KD => "KD",
+        R => "R",
+        _ => "QUAD",
+    };
     if dim == 2 {
-        if tree_type == KD {
-            bt.string_to_setup("\\createIndex KD md_index test (b,c)".to_string());
-        }
-        else {
-            bt.string_to_setup("\\createIndex R md_index test (b,c)".to_string());
-        }
+        bt.string_to_setup(format!("\\createIndex {} md_index test (b,c)", tree_name));
     }
     else if dim == 3 {
-        if tree_type == KD {
-            bt.string_to_setup("\\createIndex KD md_index test (a,b,c)".to_string());
-        }
-        else {
-            bt.string_to_setup("\\createIndex R md_index test (a,b,c)".to_string());
-        }
+        bt.string_to_setup(format!("\\createIndex {} md_index test (a,b,c)", tree_name));
     }
 }
 
@@ -537,6 +537,162 @@ fn bench_index_r_3d_knn_5000(c: &mut Criterion) {
     bt.bench_server(c, "index_r_3d_knn_5000");
 }
 
+fn bench_index_quad_2d_range_100(c: &mut Criterion) {
+    let mut bt = Template::new();
+
+    setup_table(&mut bt, "index100", 2, QUAD);
+    let data = setup_data_range(&mut bt, 2);
+    bt.add_command(Commands::QuietMode);
+    for single_data in data {
+        bt.add_command(Commands::UseIndex(format!("\\useIndex QUAD RANGE md_index test {}", single_data))
+        );
+    }
+    bt.show_configuration();
+    bt.bench_server(c, "index_quad_2d_range_100");
+}
+
+fn bench_index_quad_3d_range_100(c: &mut Criterion) {
+    let mut bt = Template::new();
+
+    setup_table(&mut bt, "index100", 3, QUAD);
+    let data = setup_data_range(&mut bt, 3);
+    bt.add_command(Commands::QuietMode);
+    for single_data in data {
+        bt.add_command(Commands::UseIndex(format!("\\useIndex QUAD RANGE md_index test {}", single_data))
+        );
+    }
+    bt.show_configuration();
+    bt.bench_server(c, "index_quad_3d_range_100");
+}
+
+fn bench_index_quad_2d_knn_100(c: &mut Criterion) {
+    let mut bt = Template::new();
+
+    setup_table(&mut bt, "index100", 2, QUAD);
+    let data = setup_data_knn(&mut bt, 2);
+    bt.add_command(Commands::QuietMode);
+    for single_data in data {
+        bt.add_command(Commands::UseIndex(format!("\\useIndex QUAD KNN md_index test {} {}", single_data, k))
+        );
+    }
+    bt.show_configuration();
+    bt.bench_server(c, "index_quad_2d_knn_100");
+}
+
+fn bench_index_quad_3d_knn_100(c: &mut Criterion) {
+    let mut bt = Template::new();
+
+    setup_table(&mut bt, "index100", 3, QUAD);
+    let data = setup_data_knn(&mut bt, 3);
+    bt.add_command(Commands::QuietMode);
+    for single_data in data {
+        bt.add_command(Commands::UseIndex(format!("\\useIndex QUAD KNN md_index test {} {}", single_data, k))
+        );
+    }
+    bt.show_configuration();
+    bt.bench_server(c, "index_quad_3d_knn_100");
+}
+
+fn setup_records(n: usize) -> Vec<(Vec<Field>, ValueId)> {
+    let mut rng = rand::thread_rng();
+    (0..n)
+        .map(|i| {
+            let record = vec![Field::IntField(rng.gen_range(0..MAX) as i32), Field::IntField(rng.gen_range(0..MAX) as i32)];
+            (record, ValueId::new_slot(0, (i / 1000) as u16, (i % 1000) as u16))
+        })
+        .collect()
+}
+
+fn r_tree_by_insert(records: &[(Vec<Field>, ValueId)]) -> R_Tree {
+    let mut tree = R_Tree::new(2, vec![0, 1], 2);
+    for (record, rid) in records {
+        tree.insert(record, *rid);
+    }
+    tree
+}
+
+fn r_tree_by_bulk_load(records: &[(Vec<Field>, ValueId)]) -> R_Tree {
+    let mut tree = R_Tree::new(2, vec![0, 1], 2);
+    tree.data_into_tree(records);
+    tree
+}
+
+// build time of an R-tree inserting one record at a time against packing it with a bulk load
+fn bench_r_tree_2d_build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("r_tree_2d_build");
+    for n in [1000, 10000, 50000] {
+        let records = setup_records(n);
+        group.bench_with_input(BenchmarkId::new("insert", n), &records, |b, records| b.iter(|| r_tree_by_insert(records)));
+        group.bench_with_input(BenchmarkId::new("bulk_load", n), &records, |b, records| b.iter(|| r_tree_by_bulk_load(records)));
+    }
+    group.finish();
+}
+
+// range and knn query time on the trees built both ways
+fn bench_r_tree_2d_query(c: &mut Criterion) {
+    let mut group = c.benchmark_group("r_tree_2d_query");
+    let mut rng = rand::thread_rng();
+    let probes: Vec<(Vec<Field>, Vec<Field>)> = (0..data_num)
+        .map(|_| {
+            let (x, y) = (rng.gen_range(0..MAX - 2048), rng.gen_range(0..MAX - 2048));
+            (vec![Field::IntField(x as i32), Field::IntField(y as i32)], vec![Field::IntField((x + 2048) as i32), Field::IntField((y + 2048) as i32)])
+        })
+        .collect();
+    for n in [1000, 10000, 50000] {
+        let records = setup_records(n);
+        for (name, tree) in [("insert", r_tree_by_insert(&records)), ("bulk_load", r_tree_by_bulk_load(&records))] {
+            group.bench_function(BenchmarkId::new(format!("range_{}", name), n), |b| {
+                b.iter(|| probes.iter().map(|(min, max)| tree.range_query(min, max).len()).sum::<usize>())
+            });
+            group.bench_function(BenchmarkId::new(format!("knn_{}", name), n), |b| {
+                b.iter(|| probes.iter().map(|(key, _)| tree.knn(key, k).len()).sum::<usize>())
+            });
+        }
+    }
+    group.finish();
+}
+
+fn md_index_by_bulk_load(tree_type: usize, records: &[(Vec<Field>, ValueId)]) -> Box<dyn MdIndex> {
+    let mut index: Box<dyn MdIndex> = match tree_type {
+        KD => Box::new(KdTree::new(2, vec![0, 1], 2)),
+        R => Box::new(R_Tree::new(2, vec![0, 1], 2)),
+        QUAD => Box::new(QuadTree::new(2, vec![0, 1], 2)),
+        _ => Box::new(ZOrderIndex::new(2, vec![0, 1], 2)),
+    };
+    index.bulk_load(records).unwrap();
+    index
+}
+
+// build and query time of the in-memory index types over the same records and probes
+fn bench_md_index_2d_compare(c: &mut Criterion) {
+    let mut group = c.benchmark_group("md_index_2d_compare");
+    let mut rng = rand::thread_rng();
+    let probes: Vec<(Vec<Option<Field>>, Vec<Option<Field>>)> = (0..data_num)
+        .map(|_| {
+            let (x, y) = (rng.gen_range(0..MAX - 2048), rng.gen_range(0..MAX - 2048));
+            (vec![Some(Field::IntField(x as i32)), Some(Field::IntField(y as i32))],
+             vec![Some(Field::IntField((x + 2048) as i32)), Some(Field::IntField((y + 2048) as i32))])
+        })
+        .collect();
+    let keys: Vec<Vec<Field>> = probes.iter().map(|(min, _)| min.iter().flatten().cloned().collect()).collect();
+    for n in [1000, 10000, 50000] {
+        let records = setup_records(n);
+        for (name, tree_type) in [("kd", KD), ("r", R), ("quad", QUAD), ("z", Z_ORDER)] {
+            group.bench_with_input(BenchmarkId::new(format!("build_{}", name), n), &records, |b, records| {
+                b.iter(|| md_index_by_bulk_load(tree_type, records))
+            });
+            let index = md_index_by_bulk_load(tree_type, &records);
+            group.bench_function(BenchmarkId::new(format!("range_{}", name), n), |b| {
+                b.iter(|| probes.iter().map(|(min, max)| index.range_query(min, max).unwrap().len()).sum::<usize>())
+            });
+            group.bench_function(BenchmarkId::new(format!("knn_{}", name), n), |b| {
+                b.iter(|| keys.iter().map(|key| index.knn_query(key, k, DistanceMetric::Euclidean).unwrap().len()).sum::<usize>())
+            });
+        }
+    }
+    group.finish();
+}
+
 criterion_group! {
     name = indexbench;
     config = Criterion::default().sample_size(10);
@@ -546,6 +702,13 @@ criterion_group! {
     // bench_index_kd_3d_range_100,
     // bench_index_scan_3d_range_100,
     bench_index_kd_2d_knn_100,
+    bench_r_tree_2d_build,
+    bench_r_tree_2d_query,
+    bench_md_index_2d_compare,
+    // bench_index_quad_2d_range_100,
+    // bench_index_quad_3d_range_100,
+    bench_index_quad_2d_knn_100,
+    // bench_index_quad_3d_knn_100,
     // bench_index_r_2d_knn_100,
     // bench_index_kd_3d_knn_100,
     // bench_index_r_3d_knn_100,
@@ -19,6 +19,15 @@ pub trait Catalog {
         name: Option<String>,
     ) -> Result<ContainerId, CrustyError>;
 
+    /// Get the indexes built on a table. Catalogs without indexes have none.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_id` - Id of table to get the indexes of.
+    fn get_indexes(&self, _table_id: ContainerId) -> Vec<IndexInfo> {
+        Vec::new()
+    }
+
     /// Get the table pointer for the catalog.
     ///
     /// # Arguments
@@ -19,6 +19,8 @@ pub enum Commands {
     ConvertQuery(String),
     /// Show the tables of a database.
     ShowTables,
+    /// Show the indexes of a database.
+    ShowIndexes,
     /// Show the registered queries of a database.
     ShowQueries,
     /// List databases
@@ -39,6 +41,10 @@ pub enum Commands {
     CreateIndex(String),
     /// Use MD Index
     UseIndex(String),
+    /// Check an MD Index against its table
+    CheckIndex(String),
+    /// Cluster the pages of a table in the curve order of its Z-order index
+    Cluster(String),
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
@@ -105,12 +111,25 @@ pub fn parse_command(mut cmd: String) -> Option<Commands> {
         // usage: \createIndex <sql>
         return Some(Commands::CreateIndex(clean_cmd.to_string()));
     } else if let Some(clean_cmd) = cmd.strip_prefix("\\useIndex") {
-        // usage: \useIndex <sql>
+        // usage: \useIndex <tree type> <EQ | RANGE | KNN | RADIUS | KNNJOIN | WITHIN | SAMECELL> <index name> <table> <key> [<k> | <radius> | <cell size> [<metric>]]
+        // a RANGE key is `(<min>);(<max>)`, and `*` in a key or a missing max leaves the attribute open
+        // KNN, RADIUS and KNNJOIN results end with a distance_milli column, the distance in thousandths
+        // a KNNJOIN key is `<outer table>(<join attributes>)`, joined to the k nearest records of <table>
+        // so is a WITHIN or SAMECELL key, joined to the records of <table> within the radius or in the same grid cell
         debug!("YOO");
         return Some(Commands::UseIndex(clean_cmd.to_string()));
+    } else if let Some(clean_cmd) = cmd.strip_prefix("\\checkIndex") {
+        // usage: \checkIndex <index name>
+        return Some(Commands::CheckIndex(clean_cmd.trim().to_string()));
+    } else if let Some(clean_cmd) = cmd.strip_prefix("\\cluster") {
+        // usage: \cluster <Z-order index name>
+        return Some(Commands::Cluster(clean_cmd.trim().to_string()));
     } else if cmd == "\\dt" {
         // usage: \dt
         return Some(Commands::ShowTables);
+    } else if cmd == "\\di" {
+        // usage: \di
+        return Some(Commands::ShowIndexes);
     } else if cmd == "\\dq" {
         // useage: \dq
         return Some(Commands::ShowQueries);
@@ -178,4 +197,28 @@ mod test {
         let show_tables: String = String::from("\\dt\n");
         assert_eq!(Commands::ShowTables, parse_command(show_tables).unwrap());
     }
+
+    #[test]
+    fn test_show_indexes() {
+        let show_indexes: String = String::from("\\di\n");
+        assert_eq!(Commands::ShowIndexes, parse_command(show_indexes).unwrap());
+    }
+
+    #[test]
+    fn test_check_index() {
+        let check_index: String = String::from("\\checkIndex md_index\n");
+        assert_eq!(
+            Commands::CheckIndex("md_index".to_string()),
+            parse_command(check_index).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_cluster() {
+        let cluster: String = String::from("\\cluster z_index\n");
+        assert_eq!(
+            Commands::Cluster("z_index".to_string()),
+            parse_command(cluster).unwrap()
+        );
+    }
 }
@@ -23,6 +23,9 @@ pub struct Database {
     pub tables: Arc<RwLock<HashMap<ContainerId, Arc<RwLock<Table>>>>>,
     // #[serde(skip)]
     pub named_containers: Arc<RwLock<HashMap<ContainerId, (String, StateType)>>>,
+    /// Indexes built on the tables, by index name.
+    #[serde(default)]
+    pub indexes: Arc<RwLock<HashMap<String, IndexInfo>>>,
 }
 
 impl Database {
@@ -36,6 +39,7 @@ impl Database {
             name,
             tables: Arc::new(RwLock::new(HashMap::new())),
             named_containers: Arc::new(RwLock::new(HashMap::new())),
+            indexes: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
@@ -53,6 +57,70 @@ impl Database {
             Some(single_table) => Some(single_table.read().unwrap().clone()),
         }
     }
+
+    /// Record a new index in the catalog. Index names are unique within a database.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Metadata of the index.
+    pub fn add_index(&self, index: IndexInfo) -> Result<(), CrustyError> {
+        let mut indexes = self.indexes.write().unwrap();
+        if indexes.contains_key(&index.name) {
+            return Err(CrustyError::CrustyError(format!(
+                "Index {} already exists",
+                index.name
+            )));
+        }
+        indexes.insert(index.name.clone(), index);
+        Ok(())
+    }
+
+    /// Get the metadata of an index.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the index.
+    pub fn get_index(&self, name: &str) -> Option<IndexInfo> {
+        self.indexes.read().unwrap().get(name).cloned()
+    }
+
+    /// Get the metadata of every index of the database, sorted by name.
+    pub fn get_all_indexes(&self) -> Vec<IndexInfo> {
+        let mut res: Vec<IndexInfo> = self.indexes.read().unwrap().values().cloned().collect();
+        res.sort_by(|a, b| a.name.cmp(&b.name));
+        res
+    }
+
+    /// Set the number of records of an index.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the index.
+    /// * `num_rows` - Number of records in the index.
+    pub fn set_index_rows(&self, name: &str, num_rows: usize) {
+        if let Some(index) = self.indexes.write().unwrap().get_mut(name) {
+            index.num_rows = num_rows;
+        }
+    }
+
+    /// Remove a table from the catalog, along with its name. Indexes of the table are left to the caller.
+    ///
+    /// # Arguments
+    ///
+    /// * `container_id` - Container of the table.
+    pub fn remove_table(&self, container_id: ContainerId) {
+        self.tables.write().unwrap().remove(&container_id);
+        self.named_containers.write().unwrap().remove(&container_id);
+    }
+
+    /// Remove an index from the catalog, returning its metadata if it was there.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the index.
+    pub fn remove_index(&self, name: &str) -> Option<IndexInfo> {
+        self.indexes.write().unwrap().remove(name)
+    }
 }
 
 impl Catalog for Database {
@@ -61,6 +129,19 @@ impl Catalog for Database {
         self.tables.clone()
     }
 
+    fn get_indexes(&self, table_id: ContainerId) -> Vec<IndexInfo> {
+        let mut res: Vec<IndexInfo> = self
+            .indexes
+            .read()
+            .unwrap()
+            .values()
+            .filter(|index| index.container_id == table_id)
+            .cloned()
+            .collect();
+        res.sort_by(|a, b| a.name.cmp(&b.name));
+        res
+    }
+
     fn get_table_id(&self, name: &str) -> Option<ContainerId> {
         //TODO mixed usage of &str and &String. for code that had &str it was coded as &x.to_string()
         let containers = self.named_containers.read().unwrap();
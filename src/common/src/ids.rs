@@ -71,7 +71,7 @@ pub struct StateMeta {
 /// For example a disk-based SM may use pages to store the records, where
 /// a main-memory based storage manager may not.
 /// It is up to a particular SM to determine how and when to use
-#[derive(PartialEq, Clone, Copy, Eq, Hash, Debug, Serialize, Deserialize)]
+#[derive(PartialEq, Clone, Copy, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
 pub struct ValueId {
     /// The source of the value. This could represent a table, index, or other data structure.
     /// All values stored must be associated with a container that is created by the storage manager.
@@ -102,6 +102,15 @@ impl ValueId {
             slot_id: None,
         }
     }
+
+    pub fn new_slot(container_id: ContainerId, page_id: PageId, slot_id: SlotId) -> Self {
+        ValueId {
+            container_id,
+            segment_id: None,
+            page_id: Some(page_id),
+            slot_id: Some(slot_id),
+        }
+    }
 }
 
 /// Stuff delta storage manager
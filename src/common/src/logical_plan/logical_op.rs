@@ -3,6 +3,7 @@ use std::fmt;
 use std::fmt::Debug;
 
 use crate::ids::ContainerId;
+use crate::md_index::DistanceMetric;
 use crate::Field;
 
 /// Scan node.
@@ -72,6 +73,7 @@ pub struct FilterNode {
 pub enum Predicate {
     SimplePredicate(SimplePredicate),
     CompoundPredicate(CompoundPredicate),
+    RadiusPredicate(RadiusPredicate),
 }
 
 /// All the operations that can be in a predicate
@@ -140,6 +142,19 @@ pub struct CompoundPredicate {
     pub simple_predicates: Vec<SimplePredicate>,
 }
 
+/// Radius predicate: the point made of some columns is within a distance of a constant point.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RadiusPredicate {
+    /// Columns giving the coordinates of the point.
+    pub columns: Vec<FieldIdentifier>,
+    /// Point to measure the distance to, one value per column.
+    pub center: Vec<Field>,
+    /// Largest distance allowed (inclusive).
+    pub radius: f64,
+    /// Metric the distance is measured with.
+    pub metric: DistanceMetric,
+}
+
 /// Operations for compound predicates
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub enum CompoundPredicateOp {
@@ -0,0 +1,160 @@
+use std::any::Any;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::{Neighbor, DistanceMetric, SpatialPredicate};
+use crate::ids::ValueId;
+use crate::{CrustyError, Field};
+
+/// Operations shared by every kind of multi-dimensional index, so that storage can keep and query
+/// indexes without knowing which tree backs them. Records are given in full, while keys hold the
+/// values of the indexed attributes in index order. Indexes stored on disk report I/O failures as
+/// errors.
+///
+/// Inserts and deletes take full records. Point, range, knn and radius queries take keys (or
+/// bounds) over `idx_fields` only, never records: callers holding a record use `key_of` first.
+pub trait MdIndex: Send + Sync {
+    /// Kind of tree backing the index, as named in `\createIndex`.
+    fn tree_type(&self) -> &'static str;
+
+    /// Positions of the indexed attributes in the records.
+    fn idx_fields(&self) -> Vec<usize>;
+
+    /// Take the values of the indexed attributes out of a full record.
+    fn key_of(&self, val: &[Field]) -> Vec<Field> {
+        self.idx_fields().iter().map(|idx| val[*idx].clone()).collect()
+    }
+
+    /// Add the record stored under `rid`.
+    fn insert(&mut self, val: &[Field], rid: ValueId) -> Result<(), CrustyError>;
+
+    /// Remove the entry of the record stored under `rid`.
+    fn delete(&mut self, val: &[Field], rid: ValueId) -> Result<(), CrustyError>;
+
+    /// Add many records at once, laying the index out for them rather than inserting one at a time.
+    fn bulk_load(&mut self, arr: &[(Vec<Field>, ValueId)]) -> Result<(), CrustyError>;
+
+    /// Ids of the records whose key is `key`.
+    fn point_query(&self, key: &[Field]) -> Result<Vec<ValueId>, CrustyError>;
+
+    /// Ids of the records whose key lies between `min` and `max` (inclusive). A `None` or
+    /// missing bound leaves its side of the attribute open.
+    fn range_query(&self, min: &[Option<Field>], max: &[Option<Field>]) -> Result<Vec<ValueId>, CrustyError>;
+
+    /// The `k` records closest to `key`, closest first.
+    fn knn_query(&self, key: &[Field], k: usize, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError>;
+
+    /// The records within `radius` of `center`, closest first.
+    fn radius_query(&self, center: &[Field], radius: f64, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError>;
+
+    /// Size and depth of the index.
+    fn stats(&self) -> Result<MdIndexStats, CrustyError>;
+
+    /// Serializable form of the index, stored under `name`.
+    fn serialize(&self, name: &str) -> SerializedIndex;
+
+    /// Describe every place the index breaks the invariants of its tree. Empty if it is sound or
+    /// if the tree has no checks of its own.
+    fn check_invariants(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Pairs of ids of the records of this index and `other` whose keys match under `predicate`,
+    /// for index types that can walk down two indexes together.
+    fn spatial_join(&self, _other: &dyn MdIndex, _predicate: &SpatialPredicate) -> Result<Vec<(ValueId, ValueId)>, CrustyError> {
+        Err(CrustyError::CrustyError(format!("{} indexes do not support spatial joins", self.tree_type())))
+    }
+
+    /// File the index is stored in, for index types that live in their own file.
+    fn path(&self) -> Option<&Path> {
+        None
+    }
+
+    /// The index as `Any`, to get back the tree behind another index of the same type.
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// Size and shape of an index, as reported by `MdIndex::stats`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MdIndexStats {
+    /// Number of entries in the index.
+    pub num_entries: usize,
+    /// Number of levels from the root down to the entries.
+    pub depth: usize,
+}
+
+/// Serializable form of an index on a heap file. Next to what every index has, it holds a payload
+/// that only the index's own type reads back: the whole tree, its entries, or the path of the file
+/// the index already lives in.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SerializedIndex {
+    pub tree_type: String,
+    pub name: String,
+    pub idx_fields: Vec<usize>,
+    pub total_dim: usize,
+    pub payload: serde_json::Value,
+}
+
+impl SerializedIndex {
+    /// Serializable form of `index` over records with `total_dim` attributes, stored under `name`.
+    pub fn new<T: Serialize + ?Sized>(index: &dyn MdIndex, name: &str, total_dim: usize, payload: &T) -> Self {
+        SerializedIndex {
+            tree_type: index.tree_type().to_string(),
+            name: name.to_string(),
+            idx_fields: index.idx_fields(),
+            total_dim,
+            payload: serde_json::to_value(payload).expect("Can't serialize index"),
+        }
+    }
+
+    /// Read the payload back as the type its index stored.
+    pub fn payload<T: DeserializeOwned>(&self) -> Result<T, CrustyError> {
+        T::deserialize(&self.payload).map_err(|e| {
+            CrustyError::CrustyError(format!("Invalid serialized index {}: {}", self.name, e))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::md_index::{Distance, KdTree, QuadTree, R_Tree, ZOrderIndex};
+    use crate::Field::IntField;
+
+    #[test]
+    fn test_indexes_agree() {
+        let arr: Vec<(Vec<Field>, ValueId)> = (0..50)
+            .map(|i| (vec![IntField(i), IntField((i * 7) % 13), IntField(i % 5)], ValueId::new_slot(0, 0, i as u16)))
+            .collect();
+        let mut indexes: Vec<Box<dyn MdIndex>> = vec![
+            Box::new(KdTree::new(2, vec![1, 2], 3)),
+            Box::new(R_Tree::new(2, vec![1, 2], 3)),
+            Box::new(QuadTree::new(2, vec![1, 2], 3)),
+            Box::new(ZOrderIndex::new(2, vec![1, 2], 3)),
+        ];
+        let mut results = Vec::new();
+        for index in indexes.iter_mut() {
+            index.bulk_load(&arr[..40]).unwrap();
+            for (val, rid) in &arr[40..] {
+                index.insert(val, *rid).unwrap();
+            }
+            index.delete(&arr[3].0, arr[3].1).unwrap();
+            assert_eq!(index.stats().unwrap().num_entries, 49);
+            assert_eq!(index.key_of(&arr[3].0), vec![IntField(8), IntField(3)]);
+            assert!(index.point_query(&[IntField(8), IntField(3)]).unwrap().is_empty());
+            let mut point = index.point_query(&index.key_of(&arr[10].0)).unwrap();
+            let mut range = index.range_query(&[Some(IntField(2))], &[Some(IntField(6)), Some(IntField(1))]).unwrap();
+            point.sort();
+            range.sort();
+            let knn: Vec<Distance> = index.knn_query(&[IntField(5), IntField(5)], 4, DistanceMetric::Euclidean).unwrap().iter().map(|neighbor| neighbor.dist).collect();
+            let radius = index.radius_query(&[IntField(5), IntField(5)], 2.0, DistanceMetric::Manhattan).unwrap().len();
+            results.push((point, range, knn, radius));
+        }
+        assert!(results.iter().all(|result| *result == results[0]));
+        assert!(results[0].0.contains(&arr[10].1));
+        assert!(indexes[1].spatial_join(indexes[0].as_ref(), &SpatialPredicate::SameCell { cell_size: 2 }).is_err());
+        assert!(indexes[1].spatial_join(indexes[1].as_ref(), &SpatialPredicate::SameCell { cell_size: 2 }).is_ok());
+    }
+}
@@ -1,126 +1,119 @@
-use std::cmp::Ordering::{Less, Greater, Equal};
-use std::any::type_name;
+use std::cmp::Ordering::{self, Less, Greater, Equal};
+use std::thread;
 use crate::Tuple;
 use crate::Field;
+use crate::ids::ValueId;
 use std::collections::BinaryHeap;
-use std::cmp::Ordering;
 use crate::Field::{IntField, StringField};
+use std::any::Any;
+use crate::CrustyError;
+use super::{DistanceMetric, IndexEntry, MdIndex, MdIndexStats, Neighbor, SerializedIndex};
 
-#[derive(Clone, PartialEq, PartialOrd)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct KdTree {
     pub dim: usize, // dimension of the tree, i.e: k
-    pub arr: Vec<Option<Vec<Field>>>, // the tree represented as an array
+    pub nodes: Vec<Option<KdNode>>, // the nodes of the tree, linked to each other by their position in this vector
+    pub root: Option<usize>, // position of the root node
+    free: Vec<usize>, // positions left empty by deletes, reused by later inserts
     pub idx_fields: Vec<usize>, // vector of indeces of attributes that are used for the index
     pub total_dim: usize, // total number of attributes of each data point
+    #[serde(default)]
+    pub size: usize, // number of entries in the tree
+    #[serde(default)]
+    rebuild_after: usize, // size to reach before retrying a rebuild that did not make the tree shallower
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
-struct KNN {
-    dist: i32,
-    node_idx: usize,
+/// A node of a `KdTree`. Children are positions in `KdTree::nodes`.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct KdNode {
+    pub entry: IndexEntry,
+    pub left: Option<usize>,
+    pub right: Option<usize>,
 }
 
-impl Ord for KNN {
-    fn cmp(&self, other: &KNN) -> Ordering {
-        // Notice that the we flip the ordering on costs.
-        // In case of a tie we compare positions - this step is necessary
-        // to make implementations of `PartialEq` and `Ord` consistent.
-        self.dist.cmp(&other.dist)
-    }
+/// Weight balance of the tree. When an insert lands deeper than `log(size)` in base `1 / ALPHA`,
+/// the first ancestor with a child holding more than `ALPHA` of its entries is rebuilt.
+const ALPHA: f64 = 0.6;
+
+/// Nodes at this depth or above never trigger a rebuild, since small trees are cheap to walk anyway.
+const MIN_REBUILD_DEPTH: usize = 4;
+
+/// Shape of a KD-tree, as reported by `KdTree::stats`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KdTreeStats {
+    /// Number of levels of the tree.
+    pub depth: usize,
+    /// Number of entries in the tree.
+    pub num_nodes: usize,
+    /// Length of the vector holding the nodes, including slots freed by deletes.
+    pub arr_len: usize,
+    /// Fraction of the node slots holding an entry.
+    pub utilization: f64,
 }
 
-// `PartialOrd` needs to be implemented as well.
-impl PartialOrd for KNN {
-    fn partial_cmp(&self, other: &KNN) -> Option<Ordering> {
-        Some(self.dist.cmp(&other.dist))
-    }
-}
+/// Subtrees with at least this many entries on each side of the median are built on two threads.
+const PARALLEL_BUILD_MIN: usize = 1 << 14;
 
+// orders entries along `dim`, breaking ties by key and record id so that the median, and so the
+// layout of a built tree, does not depend on the order the entries come in
+fn cmp_at_dim(entry1: &IndexEntry, entry2: &IndexEntry, dim: usize) -> Ordering {
+    entry1.key[dim].cmp(&entry2.key[dim]).then_with(|| entry1.cmp(entry2))
+}
 
+// lays out a balanced subtree in preorder into `slots`, which has one slot per entry and starts
+// at position `base` of the node vector. The halves of large subtrees go to separate threads.
+fn build_nodes(entries: &mut [IndexEntry], slots: &mut [Option<KdNode>], base: usize, depth: usize, dim: usize, threads: usize) {
+    if entries.is_empty() {
+        return
+    }
+    let median = entries.len() / 2;
+    entries.select_nth_unstable_by(median, |entry1, entry2| cmp_at_dim(entry1, entry2, depth % dim));
+    let (left, rest) = entries.split_at_mut(median);
+    let (median_entry, right) = rest.split_first_mut().unwrap();
+    let (root_slot, slots) = slots.split_first_mut().unwrap();
+    let (left_slots, right_slots) = slots.split_at_mut(median);
+    *root_slot = Some(KdNode {
+        entry: median_entry.clone(),
+        left: if left.is_empty() {None} else {Some(base + 1)},
+        right: if right.is_empty() {None} else {Some(base + 1 + median)},
+    });
+    if threads > 1 && right.len() >= PARALLEL_BUILD_MIN {
+        thread::scope(|scope| {
+            scope.spawn(|| build_nodes(left, left_slots, base + 1, depth + 1, dim, threads / 2));
+            build_nodes(right, right_slots, base + 1 + median, depth + 1, dim, threads - threads / 2);
+        });
+    }
+    else {
+        build_nodes(left, left_slots, base + 1, depth + 1, dim, 1);
+        build_nodes(right, right_slots, base + 1 + median, depth + 1, dim, 1);
+    }
+}
 
 impl KdTree {
     pub fn new(dim: usize, idx_fields: Vec<usize>, total_dim: usize) -> Self {
         Self {
             dim,
-            arr: Vec::new(),
+            nodes: Vec::new(),
+            root: None,
+            free: Vec::new(),
             idx_fields,
             total_dim,
+            size: 0,
+            rebuild_after: 0,
         }
     }
 
-    fn padding(&self, val: &Vec<Field>) -> Vec<Field> {
-        if val.len() == self.total_dim {
-            return val.clone();
-        }
+    /// Take the values of the indexed attributes out of a full record.
+    pub fn get_key(&self, val: &[Field]) -> Vec<Field> {
         let mut res = Vec::new();
-        let mut curr_padded_idx = 0;
-        let mut curr_idx = 0;
         for idx in &self.idx_fields {
-            while curr_padded_idx < *idx {
-                res.push(IntField(0));
-                curr_padded_idx += 1;
-            }
-            res.push(val[curr_idx].clone());
-            curr_padded_idx += 1;
-            curr_idx += 1;
-        }
-        res
-    }
-
-    pub fn padding2(val: &Vec<Field>, idx_fields: &Vec<usize>, total_dim: usize) -> Vec<Field> {
-        if val.len() == total_dim {
-            return val.clone();
-        }
-        let mut res = Vec::new();
-        let mut curr_padded_idx = 0;
-        let mut curr_idx = 0;
-        for idx in idx_fields {
-            while curr_padded_idx < *idx {
-                res.push(IntField(0));
-                curr_padded_idx += 1;
-            }
-            res.push(val[curr_idx].clone());
-            curr_padded_idx += 1;
-            curr_idx += 1;
+            res.push(val[*idx].clone());
         }
         res
     }
 
-    fn compare_val(&self, val1: &Vec<Field>, val2: &Vec<Field>) -> bool {
-        if val1.len() == 0 {
-            if val2.len() == 0 {
-                return true
-            }
-            else {
-                return false
-            }
-        }
-        else {
-            for i in 0..self.idx_fields.len() {
-                match (&val1[self.idx_fields[i]], &val2[self.idx_fields[i]]) {    
-                    (IntField(x), IntField(y)) => {
-                        if x != y {
-                            return false
-                        }
-                    },                        
-                    (StringField(x), StringField(y)) => {
-                        if !x.eq(y) {
-                            return false
-                        }
-                    }, 
-                    (_, _) => {
-                        return false
-                    }
-                }  
-            }
-            return true
-        }
-    }
-
-
-    fn compare_val_at_dim(&self, val1: &Vec<Field>, val2: &Vec<Field>, dim: usize) -> i8 {
-        let curr_idx = self.idx_fields[dim];
-        match (&val1[curr_idx], &val2[curr_idx]) {    
+    fn compare_val_at_dim(&self, val1: &[Field], val2: &[Field], dim: usize) -> i8 {
+        match (&val1[dim], &val2[dim]) {
             (IntField(x), IntField(y)) => {
                 if x < y {
                     return -1
@@ -131,561 +124,635 @@ impl KdTree {
                 else {
                     return 1
                 }
-            },                    
+            },
             (StringField(x), StringField(y)) => {
-                match x.cmp(&y) {
+                match x.cmp(y) {
                     Less => -1,
                     Equal => 0,
                     Greater => 1,
                 }
-            }, 
+            },
             (_, _) => {
                 return 0
             }
-        }             
+        }
     }
 
-    fn get_val(&self, val: &Vec<Field>) -> Vec<Field> {
-        let mut res = Vec::new();
-        for i in 0..self.total_dim {
-            match &val[i] {
-                IntField(x) => {res.push(IntField(*x));}
-                StringField(x) => {res.push(StringField(x.to_string()));}
+    fn node(&self, node_idx: usize) -> &KdNode {
+        self.nodes[node_idx].as_ref().expect("KD-tree link to a freed node")
+    }
+
+    fn node_mut(&mut self, node_idx: usize) -> &mut KdNode {
+        self.nodes[node_idx].as_mut().expect("KD-tree link to a freed node")
+    }
+
+    // stores a node without children, in a slot freed by a delete if there is one
+    fn alloc(&mut self, entry: IndexEntry) -> usize {
+        let node = KdNode {entry, left: None, right: None};
+        match self.free.pop() {
+            Some(node_idx) => {
+                self.nodes[node_idx] = Some(node);
+                node_idx
+            },
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            },
+        }
+    }
+
+    fn release(&mut self, node_idx: usize) -> IndexEntry {
+        let node = self.nodes[node_idx].take().expect("KD-tree node freed twice");
+        self.free.push(node_idx);
+        node.entry
+    }
+
+    // returns the path from the root down to the position the entry was stored at
+    fn insert_helper(&mut self, entry: IndexEntry) -> Vec<usize> {
+        let new_idx = self.alloc(entry);
+        let mut path = Vec::new();
+        let mut curr = match self.root {
+            Some(root) => root,
+            None => {
+                self.root = Some(new_idx);
+                return vec![new_idx]
+            },
+        };
+        loop {
+            path.push(curr);
+            let curr_dim = (path.len() - 1) % self.dim;
+            let node = self.node(curr);
+            let go_left = self.compare_val_at_dim(&self.node(new_idx).entry.key, &node.entry.key, curr_dim) < 0;
+            let next = if go_left {node.left} else {node.right};
+            match next {
+                Some(next) => curr = next,
+                None => {
+                    let node = self.node_mut(curr);
+                    if go_left {
+                        node.left = Some(new_idx);
+                    }
+                    else {
+                        node.right = Some(new_idx);
+                    }
+                    path.push(new_idx);
+                    return path
+                },
             }
         }
-        return res;
     }
 
-    fn print_val2(&self, val: &Vec<Field>) {
-        if val.len() == 0 {
-            println!("Empty val");
-        }
-        println!("[");
-        for element in val {
-            match element {
-                IntField(x) => {println!("{},", x)},
-                StringField(x) => {println!("{},", x)},            
+    /// Insert a record into the tree. `val` is the full record and `rid` is where it is stored.
+    /// Rebuilds the subtree that got out of balance if the insert made the tree too deep.
+    pub fn insert(&mut self, val: &[Field], rid: ValueId) {
+        let entry = IndexEntry::new(self.get_key(val), rid);
+        let path = self.insert_helper(entry);
+        self.size += 1;
+        let max_depth = ((self.size as f64).ln() / (1.0 / ALPHA).ln()).floor() as usize;
+        let max_depth = max_depth.max(MIN_REBUILD_DEPTH);
+        if path.len() - 1 > max_depth && self.size >= self.rebuild_after {
+            if let Some(depth) = self.find_scapegoat(&path) {
+                let parent = if depth == 0 {None} else {Some(path[depth - 1])};
+                self.rebuild_subtree(parent, path[depth], depth);
             }
         }
-        println!("]\n");
     }
 
-    fn insert_helper(&mut self, val: &Vec<Field>, node_idx: usize, depth: usize) {
-        let arr_len = self.arr.len();
-        if arr_len == 0 || arr_len <= node_idx {
-            for i in arr_len..node_idx {
-                self.arr.push(None);
-            }
-            self.arr.push(Some(self.get_val(val)));
-            return
+    fn subtree_size(&self, node_idx: Option<usize>) -> usize {
+        match node_idx {
+            Some(node_idx) => {
+                let node = self.node(node_idx);
+                1 + self.subtree_size(node.left) + self.subtree_size(node.right)
+            },
+            None => 0,
         }
-        if self.arr[node_idx].is_none() {
-            self.arr[node_idx] = Some(self.get_val(val));
-            return
+    }
+
+    fn subtree_height(&self, node_idx: Option<usize>) -> usize {
+        match node_idx {
+            Some(node_idx) => {
+                let node = self.node(node_idx);
+                1 + self.subtree_height(node.left).max(self.subtree_height(node.right))
+            },
+            None => 0,
         }
-        let curr_dim = depth % self.dim;
-        if self.compare_val_at_dim(&val, &self.arr[node_idx].as_ref().unwrap(), curr_dim) < 0 {
-            self.insert_helper(val, node_idx*2+1, depth + 1)
+    }
+
+    // walks up the insert path to the first ancestor that is out of balance, and returns its depth
+    fn find_scapegoat(&self, path: &[usize]) -> Option<usize> {
+        let mut child_size = 1;
+        for depth in (0..path.len() - 1).rev() {
+            let parent = self.node(path[depth]);
+            let sibling = if parent.left == Some(path[depth + 1]) {parent.right} else {parent.left};
+            let parent_size = 1 + child_size + self.subtree_size(sibling);
+            if child_size as f64 > ALPHA * parent_size as f64 {
+                return Some(depth)
+            }
+            child_size = parent_size;
+        }
+        None
+    }
+
+    fn take_subtree(&mut self, node_idx: Option<usize>, res: &mut Vec<IndexEntry>) {
+        let node_idx = match node_idx {
+            Some(node_idx) => node_idx,
+            None => return,
+        };
+        let (left, right) = (self.node(node_idx).left, self.node(node_idx).right);
+        res.push(self.release(node_idx));
+        self.take_subtree(left, res);
+        self.take_subtree(right, res);
+    }
+
+    fn build_threads() -> usize {
+        thread::available_parallelism().map_or(1, |threads| threads.get())
+    }
+
+    // lays out a balanced tree of `entries` as the whole node vector
+    fn build_tree(&mut self, entries: &mut [IndexEntry]) {
+        let mut slots = vec![None; entries.len()];
+        build_nodes(entries, &mut slots, 0, 0, self.dim, KdTree::build_threads());
+        self.nodes = slots;
+        self.free.clear();
+        self.root = if self.nodes.is_empty() {None} else {Some(0)};
+    }
+
+    // places the median of each dimension at the top of the subtree, so it is as shallow as possible.
+    // Entries equal to the median along the split dimension can end up on either side of it.
+    fn build_subtree(&mut self, entries: &mut [IndexEntry], depth: usize) -> Option<usize> {
+        let mut slots = vec![None; entries.len()];
+        build_nodes(entries, &mut slots, 0, depth, self.dim, KdTree::build_threads());
+        // the built nodes link to positions in `slots`, which get mapped to the node slots allocated
+        let mut links = Vec::with_capacity(slots.len());
+        let node_idxs: Vec<usize> = slots
+            .into_iter()
+            .map(|node| {
+                let node = node.expect("KD-tree build left a slot empty");
+                links.push((node.left, node.right));
+                self.alloc(node.entry)
+            })
+            .collect();
+        for (node_idx, (left, right)) in node_idxs.iter().zip(links) {
+            let node = self.node_mut(*node_idx);
+            node.left = left.map(|left| node_idxs[left]);
+            node.right = right.map(|right| node_idxs[right]);
+        }
+        node_idxs.first().copied()
+    }
+
+    // rebuilds the subtree at `node_idx` and links the new one in its place under `parent`
+    fn rebuild_subtree(&mut self, parent: Option<usize>, node_idx: usize, depth: usize) {
+        let old_height = self.subtree_height(Some(node_idx));
+        let mut entries = Vec::new();
+        self.take_subtree(Some(node_idx), &mut entries);
+        let new_idx = self.build_subtree(&mut entries, depth);
+        match parent {
+            Some(parent) => {
+                let parent = self.node_mut(parent);
+                if parent.left == Some(node_idx) {
+                    parent.left = new_idx;
+                }
+                else {
+                    parent.right = new_idx;
+                }
+            },
+            None => self.root = new_idx,
         }
-        else {
-            self.insert_helper(val, node_idx*2+2, depth + 1)
+        // a rebuild that did not help is not retried until the tree has doubled, to keep inserts amortized
+        if self.subtree_height(new_idx) >= old_height {
+            self.rebuild_after = self.size * 2;
         }
     }
 
-    pub fn insert(&mut self, val: &Vec<Field>) {
-        self.insert_helper(val, 0, 0)
-    } 
+    /// Rebuild the whole tree into a balanced one, dropping the node slots left empty by deletes.
+    pub fn rebuild(&mut self) {
+        let mut entries = Vec::new();
+        self.take_subtree(self.root, &mut entries);
+        self.build_tree(&mut entries);
+    }
+
+    /// Depth, number of nodes and slot utilization of the tree.
+    pub fn stats(&self) -> KdTreeStats {
+        let num_nodes = self.num_entries();
+        KdTreeStats {
+            depth: self.subtree_height(self.root),
+            num_nodes,
+            arr_len: self.nodes.len(),
+            utilization: if self.nodes.is_empty() {0.0} else {num_nodes as f64 / self.nodes.len() as f64},
+        }
+    }
 
-    fn search_helper(&mut self, val: &Vec<Field>, node_idx: usize, depth: usize) -> bool {
-        let arr_len = self.arr.len();
-        if arr_len == 0 || arr_len <= node_idx {
-            return false
+    /// Describe every place the tree breaks its invariants: a node whose key is not between the
+    /// splits of its ancestors along their dimension, a link to a freed or already visited node
+    /// slot, or a size that does not match the number of nodes. An empty result means the tree is sound.
+    pub fn check_invariants(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        let mut visited = vec![false; self.nodes.len()];
+        let mut num_nodes = 0;
+        // each node is checked against the lowest and highest split it must lie between on every dimension
+        let mut stack = vec![(self.root, 0, vec![None; self.dim], vec![None; self.dim])];
+        while let Some((node_idx, depth, lower, upper)) = stack.pop() {
+            let node_idx = match node_idx {
+                Some(node_idx) => node_idx,
+                None => continue,
+            };
+            let node = match self.nodes.get(node_idx).and_then(Option::as_ref) {
+                Some(node) => node,
+                None => {
+                    res.push(format!("Link at depth {} to the empty node slot {}", depth, node_idx));
+                    continue
+                }
+            };
+            if visited[node_idx] {
+                res.push(format!("Node slot {} is linked to more than once", node_idx));
+                continue
+            }
+            visited[node_idx] = true;
+            num_nodes += 1;
+            let key = &node.entry.key;
+            for dim in 0..self.dim {
+                let below = lower[dim].is_some_and(|lower| self.compare_val_at_dim(key, lower, dim) < 0);
+                let above = upper[dim].is_some_and(|upper| self.compare_val_at_dim(key, upper, dim) > 0);
+                if below || above {
+                    res.push(format!(
+                        "Entry {:?} of {:?} at depth {} is on the wrong side of an ancestor along attribute {}",
+                        key, node.entry.rid, depth, dim
+                    ));
+                }
+            }
+            let curr_dim = depth % self.dim;
+            let mut left_upper = upper.clone();
+            left_upper[curr_dim] = Some(key);
+            let mut right_lower = lower.clone();
+            right_lower[curr_dim] = Some(key);
+            stack.push((node.left, depth + 1, lower, left_upper));
+            stack.push((node.right, depth + 1, right_lower, upper));
         }
-        if self.arr[node_idx].is_none() {
-            return false
+        if num_nodes != self.size {
+            res.push(format!("The tree counts {} entries but holds {}", self.size, num_nodes));
         }
-        if self.compare_val(&self.arr[node_idx].as_ref().unwrap(), val) {
+        res
+    }
+
+    fn search_helper(&self, key: &[Field], node_idx: Option<usize>, depth: usize) -> bool {
+        let node = match node_idx {
+            Some(node_idx) => self.node(node_idx),
+            None => return false,
+        };
+        if node.entry.key == key {
             return true
         }
         let curr_dim = depth % self.dim;
-        if self.compare_val_at_dim(val, &self.arr[node_idx].as_ref().unwrap(), curr_dim) < 0 {
-            self.search_helper(val, node_idx*2+1, depth + 1)
-        }
-        else {
-            self.search_helper(val, node_idx*2+2, depth + 1)
+        match self.compare_val_at_dim(key, &node.entry.key, curr_dim) {
+            cmp if cmp < 0 => self.search_helper(key, node.left, depth + 1),
+            cmp if cmp > 0 => self.search_helper(key, node.right, depth + 1),
+            _ => self.search_helper(key, node.left, depth + 1) || self.search_helper(key, node.right, depth + 1),
         }
     }
 
-    pub fn search(&mut self, val: &Vec<Field>) -> bool {
-        self.search_helper(&self.padding(val), 0, 0)
+    pub fn search(&self, val: &[Field]) -> bool {
+        self.search_helper(val, self.root, 0)
     }
 
-    fn get_helper(&mut self, val: &Vec<Field>, node_idx: usize, depth: usize, res: &mut Vec<Vec<Field>>) {
-        let arr_len = self.arr.len();
-        if arr_len == 0 || arr_len <= node_idx {
-            return
-        }
-        if self.arr[node_idx].is_none() {
-            return
-        }
-        if self.compare_val(&self.arr[node_idx].as_ref().unwrap(), val) {
-            res.push(self.arr[node_idx].as_ref().unwrap().clone());
+    fn get_helper(&self, key: &[Field], node_idx: Option<usize>, depth: usize, res: &mut Vec<ValueId>) {
+        let node = match node_idx {
+            Some(node_idx) => self.node(node_idx),
+            None => return,
+        };
+        if node.entry.key == key {
+            res.push(node.entry.rid);
         }
         let curr_dim = depth % self.dim;
-        if self.compare_val_at_dim(val, &self.arr[node_idx].as_ref().unwrap(), curr_dim) < 0 {
-            self.get_helper(val, node_idx*2+1, depth + 1, res)
+        let cmp = self.compare_val_at_dim(key, &node.entry.key, curr_dim);
+        if cmp <= 0 {
+            self.get_helper(key, node.left, depth + 1, res)
         }
-        else {
-            self.get_helper(val, node_idx*2+2, depth + 1, res)
+        if cmp >= 0 {
+            self.get_helper(key, node.right, depth + 1, res)
         }
     }
 
-    pub fn get(&mut self, val: &Vec<Field>) -> Vec<Vec<Field>> {
+    /// Get the ids of all records whose key is equal to `val`.
+    pub fn get(&self, val: &[Field]) -> Vec<ValueId> {
         let mut res = Vec::new();
-        self.get_helper(&self.padding(val), 0, 0, &mut res);
+        self.get_helper(val, self.root, 0, &mut res);
         res
     }
 
-    fn if_smaller(val1: &Vec<Field>, val2: &Vec<Field>, idx_fields: &Vec<usize>) -> bool {
-        if val1.len() == 0 {
-            if val2.len() == 0 {
-                return true
-            }
-            else {
-                return false
-            }
-        }
-        else {
-            for i in 0..idx_fields.len() {
-                match (&val1[idx_fields[i]], &val2[idx_fields[i]]) {    
-                    (IntField(x), IntField(y)) => {
-                        if x > y {
-                            return false
-                        }
-                    },                        
-                    (StringField(x), StringField(y)) => {
-                        if let Greater = x.cmp(y) {
-                            return false
-                        }
-                    }, 
-                    (_, _) => {
+    fn if_smaller(val1: &[Field], val2: &[Field]) -> bool {
+        for i in 0..val1.len() {
+            match (&val1[i], &val2[i]) {
+                (IntField(x), IntField(y)) => {
+                    if x > y {
                         return false
                     }
-                }  
+                },
+                (StringField(x), StringField(y)) => {
+                    if let Greater = x.cmp(y) {
+                        return false
+                    }
+                },
+                (_, _) => {
+                    return false
+                }
             }
-            return true
         }
+        true
     }
 
-    fn if_greater(val1: &Vec<Field>, val2: &Vec<Field>, idx_fields: &Vec<usize>) -> bool {
-        if val1.len() == 0 {
-            if val2.len() == 0 {
-                return true
-            }
-            else {
-                return false
-            }
-        }
-        else {
-            for i in 0..idx_fields.len() {
-                match (&val1[idx_fields[i]], &val2[idx_fields[i]]) {    
-                    (IntField(x), IntField(y)) => {
-                        if x < y {
-                            return false
-                        }
-                    },                        
-                    (StringField(x), StringField(y)) => {
-                        if let Less = x.cmp(y) {
-                            return false
-                        }
-                    }, 
-                    (_, _) => {
+    fn if_greater(val1: &[Field], val2: &[Field]) -> bool {
+        for i in 0..val1.len() {
+            match (&val1[i], &val2[i]) {
+                (IntField(x), IntField(y)) => {
+                    if x < y {
                         return false
                     }
-                }  
+                },
+                (StringField(x), StringField(y)) => {
+                    if let Less = x.cmp(y) {
+                        return false
+                    }
+                },
+                (_, _) => {
+                    return false
+                }
             }
-            return true
         }
+        true
     }
 
-    pub fn if_within_range(val: &Vec<Field>, min: &Vec<Field>, max: &Vec<Field>, idx_fields: &Vec<usize>) -> bool {
-        KdTree::if_smaller(val, max, idx_fields) && KdTree::if_greater(val, min, idx_fields)
+    /// Whether every attribute of `key` lies between the matching attributes of `min` and `max`.
+    pub fn if_within_range(key: &[Field], min: &[Field], max: &[Field]) -> bool {
+        KdTree::if_smaller(key, max) && KdTree::if_greater(key, min)
     }
 
-    fn range_query_helper(&mut self, min:&Vec<Field>, max: &Vec<Field>, node_idx: usize, depth: usize, res: &mut Vec<Vec<Field>>) {
-        let arr_len = self.arr.len();
-        if arr_len == 0 || arr_len <= node_idx {
-            return
-        }
-        if self.arr[node_idx].is_none() {
-            return
-        }
-        if KdTree::if_within_range(&self.arr[node_idx].as_ref().unwrap(), min, max, &self.idx_fields) {
-            res.push(self.arr[node_idx].as_ref().unwrap().clone());
+    /// Whether every attribute of `key` lies between the matching bounds of `min` and `max`.
+    /// Attributes without a bound, including those past the end of the bounds, are not constrained.
+    pub fn if_within_bounds(key: &[Field], min: &[Option<Field>], max: &[Option<Field>]) -> bool {
+        key.iter().enumerate().all(|(i, val)| {
+            min.get(i).and_then(Option::as_ref).is_none_or(|min| val >= min)
+                && max.get(i).and_then(Option::as_ref).is_none_or(|max| val <= max)
+        })
+    }
+
+    // only the dimensions with a bound prune subtrees
+    fn range_query_helper(&self, min: &[Option<Field>], max: &[Option<Field>], node_idx: Option<usize>, depth: usize, res: &mut Vec<ValueId>) {
+        let node = match node_idx {
+            Some(node_idx) => self.node(node_idx),
+            None => return,
+        };
+        if KdTree::if_within_bounds(&node.entry.key, min, max) {
+            res.push(node.entry.rid);
         }
         let curr_dim = depth % self.dim;
-        if self.compare_val_at_dim(min, &self.arr[node_idx].as_ref().unwrap(), curr_dim) <= 0 {
-            self.range_query_helper(min, max, node_idx*2+1, depth + 1, res)
+        let split = &node.entry.key[curr_dim];
+        if min.get(curr_dim).and_then(Option::as_ref).is_none_or(|min| min <= split) {
+            self.range_query_helper(min, max, node.left, depth + 1, res)
+        }
+        if max.get(curr_dim).and_then(Option::as_ref).is_none_or(|max| split <= max) {
+            self.range_query_helper(min, max, node.right, depth + 1, res)
         }
-        if self.compare_val_at_dim(&self.arr[node_idx].as_ref().unwrap(), max, curr_dim) <= 0 {
-            self.range_query_helper(min, max, node_idx*2+2, depth + 1, res)
-        }        
-    }
-
-    pub fn range_query(&mut self, min: &Vec<Field>, max: &Vec<Field>) -> Vec<Vec<Field>> {
-        let mut res = Vec::new();
-        self.range_query_helper(&self.padding(min), &self.padding(max), 0, 0, &mut res);
-        res
     }
 
-    fn calculate_dist(&self, val1: &Vec<Field>, val2: &Vec<Field>) -> i32 {
-        let mut res = 0;
-        for i in 0..self.idx_fields.len() {
-            match (&val1[self.idx_fields[i]], &val2[self.idx_fields[i]]) {    
-                (IntField(x), IntField(y)) => {let temp = x-y; res += temp.pow(2);},                        
-                (_, _) => {res = i32::MAX},
-            }  
-        }
-        return res   
+    /// Get the ids of all records whose key lies in the box between `min` and `max` (inclusive).
+    pub fn range_query(&self, min: &[Field], max: &[Field]) -> Vec<ValueId> {
+        let min: Vec<Option<Field>> = min.iter().cloned().map(Some).collect();
+        let max: Vec<Option<Field>> = max.iter().cloned().map(Some).collect();
+        self.partial_range_query(&min, &max)
     }
 
-    fn calculate_direct_split(&self, val1: &Vec<Field>, val2: &Vec<Field>, curr_dim: usize) -> i32 {
-        match (&val1[self.idx_fields[curr_dim]], &val2[self.idx_fields[curr_dim]]) {  
-            (IntField(x), IntField(y)) => {x-y},                        
-            (_, _) => {i32::MAX},
-        }          
+    /// Get the ids of all records whose key lies between `min` and `max` (inclusive). The bounds
+    /// are given over the indexed attributes, and a `None` bound leaves its side of the attribute
+    /// open, so a query can constrain only some of the dimensions.
+    pub fn partial_range_query(&self, min: &[Option<Field>], max: &[Option<Field>]) -> Vec<ValueId> {
+        let mut res = Vec::new();
+        self.range_query_helper(min, max, self.root, 0, &mut res);
+        res
     }
 
-    fn knn_helper(&mut self, val: &Vec<Field>, node_idx: usize, depth: usize, k: usize, heap: &mut BinaryHeap<KNN>) {
-        let arr_len = self.arr.len();
-        if arr_len == 0 || arr_len <= node_idx {
-            return
-        }
-        if self.arr[node_idx].is_none() {
-            return
-        }
+    fn knn_helper(&self, key: &[Field], node_idx: Option<usize>, depth: usize, k: usize, metric: DistanceMetric,
+        heap: &mut BinaryHeap<Neighbor>) {
+        let node = match node_idx {
+            Some(node_idx) => self.node(node_idx),
+            None => return,
+        };
         let curr_dim = depth % self.dim;
-        let curr_dist = self.calculate_dist(val, &self.arr[node_idx].as_ref().unwrap());
+        // the heap keeps the k closest entries found so far, the farthest one on top
+        let candidate = Neighbor {dist: metric.distance(key, &node.entry.key), key: node.entry.key.clone(), rid: node.entry.rid};
         if heap.len() < k {
-            heap.push(KNN {dist: curr_dist, node_idx});
+            heap.push(candidate);
         }
-        else {
-            let curr_max = heap.peek().unwrap();
-            if curr_dist < curr_max.dist {
-                heap.pop();
-                heap.push(KNN {dist: curr_dist, node_idx});
-            }
+        else if candidate < *heap.peek().unwrap() {
+            heap.pop();
+            heap.push(candidate);
         }
-        //println!("{:?}", heap);
-        let mut next = node_idx*2 + 2;
-        let mut other = node_idx*2 + 1;
-        if self.compare_val_at_dim(val, &self.arr[node_idx].as_ref().unwrap(), curr_dim) < 0 {
-            next = node_idx*2 + 1;
-            other = node_idx*2 + 2;
+        let (next, other) = if self.compare_val_at_dim(key, &node.entry.key, curr_dim) < 0 {
+            (node.left, node.right)
         }
-        self.knn_helper(val, next, depth + 1, k, heap);
-        let direct_split = self.calculate_direct_split(val, &self.arr[node_idx].as_ref().unwrap(), curr_dim);
-        //println!("direct split is {}", direct_split);
-        if self.calculate_dist(val, &self.arr[heap.peek().unwrap().node_idx].as_ref().unwrap()) >= direct_split * direct_split {
-            self.knn_helper(val, other, depth + 1, k, heap);
+        else {
+            (node.right, node.left)
+        };
+        self.knn_helper(key, next, depth + 1, k, metric, heap);
+        // every entry on the other side is at least as far as the splitting plane, whatever the metric
+        let split_dist = metric.combine(std::iter::once(
+            DistanceMetric::coordinate(&key[curr_dim]) - DistanceMetric::coordinate(&node.entry.key[curr_dim])));
+        if heap.len() < k || heap.peek().unwrap().dist >= split_dist {
+            self.knn_helper(key, other, depth + 1, k, metric, heap);
         }
     }
 
-    pub fn knn(&mut self, val:&Vec<Field>, k: usize) -> Vec<Vec<Field>> {
+    /// Get the ids of the `k` records whose keys are closest to `val` in euclidean distance.
+    pub fn knn(&self, val: &[Field], k: usize) -> Vec<ValueId> {
+        self.knn_with_metric(val, k, DistanceMetric::Euclidean).into_iter().map(|neighbor| neighbor.rid).collect()
+    }
+
+    /// Get the `k` records whose keys are closest to `val`, closest first.
+    pub fn knn_with_metric(&self, val: &[Field], k: usize, metric: DistanceMetric) -> Vec<Neighbor> {
         let mut heap = BinaryHeap::new();
-        self.knn_helper(&self.padding(val), 0, 0, k, &mut heap);
-        let mut res = Vec::new();
-        for element in &heap {
-            res.push(self.arr[element.node_idx].as_ref().unwrap().clone());
+        if k == 0 {
+            return Vec::new();
         }
-        res       
+        self.knn_helper(val, self.root, 0, k, metric, &mut heap);
+        heap.into_sorted_vec()
     }
 
-    fn copy_from_vec(&self, array: &mut [Vec<Field>], vec: &Vec<Vec<Field>>) {
-        if array.len() == 0 {
-            return
+    fn radius_query_helper(&self, center: &[Field], radius: f64, node_idx: Option<usize>, depth: usize,
+        metric: DistanceMetric, res: &mut Vec<Neighbor>) {
+        let node = match node_idx {
+            Some(node_idx) => self.node(node_idx),
+            None => return,
+        };
+        let dist = metric.distance(center, &node.entry.key);
+        if dist.0 <= radius {
+            res.push(Neighbor {dist, key: node.entry.key.clone(), rid: node.entry.rid});
         }
-        if array.len() < vec.len() {
-        }
-        if array.len() > vec.len() {
+        let curr_dim = depth % self.dim;
+        let gap = DistanceMetric::coordinate(&center[curr_dim]) - DistanceMetric::coordinate(&node.entry.key[curr_dim]);
+        // the side across the splitting plane is only searched if the plane is within the radius
+        let split_within = metric.combine(std::iter::once(gap)).0 <= radius;
+        if gap <= 0 || split_within {
+            self.radius_query_helper(center, radius, node.left, depth + 1, metric, res);
         }
-        for i in 0..array.len() {
-            array[i] = self.get_val(&vec[i]);
+        if gap >= 0 || split_within {
+            self.radius_query_helper(center, radius, node.right, depth + 1, metric, res);
         }
     }
 
-
-    fn print_arr(&self, array: &[Vec<Field>]) {
-        let arr_len = array.len();
-        if arr_len == 0 {
-            println!("Empty arr\n");
-        }  
-        println!("[");
-        for element in array {
-            println!("[");
-            for single_val in element {
-                match single_val {
-                    IntField(x) => {println!("{},", x)},
-                    StringField(x) => {println!("{},", x)},           
-                }
-            }
-            println!("], ");
-        }  
-        println!("]\n");
+    /// Get the records whose keys are within `radius` of `center`, closest first.
+    pub fn radius_query(&self, center: &[Field], radius: f64, metric: DistanceMetric) -> Vec<Neighbor> {
+        let mut res = Vec::new();
+        self.radius_query_helper(center, radius, self.root, 0, metric, &mut res);
+        res.sort();
+        res
     }
 
     pub fn print_vec(vec: &Vec<Vec<Field>>) {
         let vec_len = vec.len();
         if vec_len == 0 {
             println!("Empty vec\n");
-        }  
+        }
         println!("[");
         for element in vec {
             println!("[");
             for single_val in element {
                 match single_val {
                     IntField(x) => {println!("{},", x)},
-                    StringField(x) => {println!("{},", x)},            
+                    StringField(x) => {println!("{},", x)},
                 }
             }
             println!("], ");
-        }  
-        println!("]\n");
-    }
-    
-    fn sort(&self, array: &mut [Vec<Field>], curr_dim: usize) {
-        let middle = array.len() / 2;
-        if array.len() < 2 {
-          return // No need to sort vectors with one element
-        }
-      
-        let mut sorted = array.to_vec();
-      
-        self.sort(&mut array[..middle], curr_dim);
-        self.sort(&mut array[middle..], curr_dim);
-        self.merge(&array[..middle], &array[middle..], &mut sorted, curr_dim);
-        self.copy_from_vec(array, &sorted); // Copy the sorted result into original vector
-      }
-      
-      fn merge(&self,l_arr: &[Vec<Field>], r_arr: &[Vec<Field>], sorted: &mut [Vec<Field>], curr_dim: usize) {
-        // Current loop position in left half, right half, and sorted vector
-        let (mut left, mut right, mut i) = (0, 0, 0);
-        while left < l_arr.len() && right < r_arr.len() {
-          if self.compare_val_at_dim(&l_arr[left], &r_arr[right], curr_dim) <= 0 {
-            sorted[i] = l_arr[left].clone();
-            i += 1;
-            left += 1;
-          } else {
-            sorted[i] = r_arr[right].clone();
-            i += 1;
-            right += 1;
-          }
-        }
-      
-        if left < l_arr.len() {
-          // If there is anything left in the left half append it after sorted members
-          self.copy_from_vec(&mut sorted[i..], &l_arr[left..].to_vec());
-        }
-      
-        if right < r_arr.len() {
-          // If there is anything left in the right half append it after sorted members
-          self.copy_from_vec(&mut sorted[i..], &r_arr[right..].to_vec());
         }
-      }
-
-    fn data_into_tree_helper(&mut self, arr: &mut [Vec<Field>], depth: usize) {
-        let len_arr = arr.len();
-        if len_arr == 0 {
-            return
-        }
-        let curr_dim = depth % self.dim;
-        self.sort(arr, curr_dim);
-        let median: usize = len_arr/2;
-        self.insert(&arr[median]);
-        self.data_into_tree_helper(&mut arr[..median], depth + 1);
-        self.data_into_tree_helper(&mut arr[median+1..], depth + 1);
+        println!("]\n");
     }
 
-    pub fn data_into_tree(&mut self, arr: &mut [Vec<Field>]) {
-        let len_arr = arr.len();
-        if len_arr == 0 {
+    /// Bulk load the tree from full records and the ids they are stored under. The tree is rebuilt
+    /// balanced over its current entries and the new ones, splitting the work across threads.
+    /// Its layout only depends on the entries, not on the order they are given in.
+    pub fn data_into_tree(&mut self, arr: &[(Vec<Field>, ValueId)]) {
+        if arr.is_empty() {
             return
         }
-        self.data_into_tree_helper(arr, 0);
+        let mut entries = Vec::with_capacity(self.size + arr.len());
+        self.take_subtree(self.root, &mut entries);
+        entries.extend(arr.iter().map(|(val, rid)| IndexEntry::new(self.get_key(val), *rid)));
+        self.build_tree(&mut entries);
+        self.size += arr.len();
     }
 
-    fn cmp_min_node(&self, val1: usize, val2: Option<usize>, val3: Option<usize>, curr_dim: usize) -> Option<usize> {
+    fn cmp_min_node(&self, val1: usize, val2: Option<usize>, val3: Option<usize>, curr_dim: usize) -> usize {
         let mut res = val1;
-        let arr_len = self.arr.len();
-        if val2.is_some() && arr_len > val2.unwrap() && self.arr[val2.unwrap()].is_some() &&
-        self.compare_val_at_dim(&self.arr[val2.unwrap()].as_ref().unwrap(), &self.arr[val1].as_ref().unwrap(), curr_dim) < 0 {
-            res = val2.unwrap();
-        }
-        if val3.is_some() && arr_len > val3.unwrap() && self.arr[val3.unwrap()].is_some() &&
-        self.compare_val_at_dim(&self.arr[val3.unwrap()].as_ref().unwrap(), &self.arr[val1].as_ref().unwrap(), curr_dim) < 0 {
-            res = val3.unwrap();
-        }
-        return Some(res);
-    }
-
-    fn find_min_helper(&self, node_idx: usize, curr_dim: usize, depth: usize) -> Option<usize> {
-        let arr_len = self.arr.len();
-        if arr_len == 0 || arr_len <= node_idx || self.arr[node_idx].is_none() {
-            return None
-        }
-        let curr_dim2 = depth % self.dim;
-        if curr_dim2 == curr_dim {
-            if arr_len < (node_idx*2 + 1) || self.arr[node_idx*2 + 1].is_none() {
-                return Some(node_idx);
-            }
-            else {
-                return self.find_min_helper(node_idx*2 + 1, curr_dim, depth + 1);
+        for val in [val2, val3].into_iter().flatten() {
+            if self.compare_val_at_dim(&self.node(val).entry.key, &self.node(res).entry.key, curr_dim) < 0 {
+                res = val;
             }
         }
-        return self.cmp_min_node(node_idx, self.find_min_helper(node_idx*2 + 1, curr_dim, depth + 1),
-        self.find_min_helper(node_idx*2 + 2, curr_dim, depth + 1), curr_dim);
-    }
-
-    fn find_min(&self, node_idx: usize, curr_dim: usize, depth: usize) -> Option<usize> {
-        self.find_min_helper(node_idx, curr_dim, depth)
-    }
-
-    fn copy_single_val(&self, val: &Field) -> Field {
-        match val {
-            IntField(x) => {IntField(*x)},
-            StringField(x) => {StringField(x.to_string())},
-        }        
-    }
-
-    fn get_new_copy(&self, node_idx: usize) -> Vec<Field> {
-        let mut new_copy = Vec::new();
-        let copy_val = self.arr[node_idx].as_ref().unwrap();
-        for i in 0..self.total_dim {
-            new_copy.push(self.copy_single_val(&copy_val[i]));
-        }
-        return new_copy
-    }
-
-    fn copy_and_delete(&mut self, old_node_idx: usize, new_node_idx: usize) {
-        let arr_len = self.arr.len();
-        if arr_len == 0 || arr_len <= old_node_idx || self.arr[old_node_idx].is_none() {
-            return
-        }
-        for i in self.arr.len()..(new_node_idx + 1) {
-            self.arr.push(None);
-        }
-        self.arr[new_node_idx] = Some(self.get_new_copy(old_node_idx));
-        self.arr[old_node_idx] = None;
-        if (arr_len > old_node_idx*2 + 1) && self.arr[old_node_idx*2 + 1].is_some() {
-            self.copy_and_delete(old_node_idx*2 + 1, new_node_idx*2 + 1);
-        } 
-        if (arr_len > old_node_idx*2 + 2) && self.arr[old_node_idx*2 + 2].is_some() {
-            self.copy_and_delete(old_node_idx*2 + 2, new_node_idx*2 + 2);
-        }
+        res
     }
 
-    fn convert_left_to_right_tree(&mut self, node_idx: usize) {
-        let arr_len = self.arr.len();
-        if arr_len == 0 || arr_len <= node_idx || self.arr[node_idx].is_none() {
-            return
-        }
-        let direction = if node_idx % 2 == 0 {2} else {1};
-        let par_node_idx = (node_idx - direction)/2;
-        let new_node_idx = par_node_idx*2 + 2;
-        for i in arr_len..(new_node_idx + 1) {
-            self.arr.push(None);
-        }
-        if ((arr_len > node_idx*2 + 1) && self.arr[node_idx*2 + 1].is_some()) || 
-        ((arr_len > node_idx*2 + 2) && self.arr[node_idx*2 + 2].is_some()) {
-            self.copy_and_delete(node_idx*2 + 1, new_node_idx*2 + 1);
-            self.copy_and_delete(node_idx*2 + 2, new_node_idx*2 + 2);
+    fn find_min(&self, node_idx: Option<usize>, curr_dim: usize, depth: usize) -> Option<usize> {
+        let node = self.node(node_idx?);
+        if depth % self.dim == curr_dim {
+            return match node.left {
+                Some(left) => self.find_min(Some(left), curr_dim, depth + 1),
+                None => node_idx,
+            }
         }
-        self.arr[new_node_idx] = Some(self.get_new_copy(node_idx));
-        self.arr[node_idx] = None;
+        Some(self.cmp_min_node(node_idx?, self.find_min(node.left, curr_dim, depth + 1),
+        self.find_min(node.right, curr_dim, depth + 1), curr_dim))
     }
 
-    fn delete_helper(&mut self, val: &Vec<Field>, node_idx: usize, depth: usize) {
-        let arr_len = self.arr.len();
-        if arr_len == 0 || arr_len <= node_idx || self.arr[node_idx].is_none() {
-            return
-        }
+    // returns the new root of the subtree at `node_idx` if the target was found in it
+    fn delete_helper(&mut self, target: &IndexEntry, node_idx: Option<usize>, depth: usize) -> Option<Option<usize>> {
+        let node_idx = node_idx?;
+        let node = self.node(node_idx);
+        let (left, right) = (node.left, node.right);
         let curr_dim = depth % self.dim;
-        if self.compare_val(&self.arr[node_idx].as_ref().unwrap(), val) {
-            if arr_len > (node_idx * 2 + 2) && self.arr[node_idx*2 + 2].is_some() {
-                let min_node_idx = self.find_min(node_idx * 2 + 2, curr_dim, depth + 1);
-                if min_node_idx.is_none() {
-                    return
-                }
-                self.arr[node_idx] = Some(self.get_new_copy(min_node_idx.unwrap()));
-                let new_val = self.get_new_copy(min_node_idx.unwrap());
-                self.delete_helper(&new_val, node_idx*2 + 2, depth + 1);        
+        if node.entry == *target {
+            // the entry is replaced by the minimum along the split dimension of a subtree, which is
+            // deleted from there instead. A lone left subtree is moved to the right, so that entries
+            // equal to the new minimum stay on its right.
+            let subtree = match right.or(left) {
+                Some(subtree) => subtree,
+                None => {
+                    self.release(node_idx);
+                    return Some(None)
+                },
+            };
+            let min_idx = self.find_min(Some(subtree), curr_dim, depth + 1)?;
+            let new_val = self.node(min_idx).entry.clone();
+            let new_subtree = self.delete_helper(&new_val, Some(subtree), depth + 1)?;
+            let node = self.node_mut(node_idx);
+            node.entry = new_val;
+            node.left = None;
+            node.right = new_subtree;
+            if right.is_some() {
+                node.left = left;
             }
-            else if arr_len > (node_idx * 2 + 1) && self.arr[node_idx*2 + 1].is_some() {
-                let mut min_node_idx = self.find_min(node_idx * 2 + 1, curr_dim, depth + 1);
-                if min_node_idx.is_none() {
-                    return
-                }
-                self.arr[node_idx] = Some(self.get_new_copy(min_node_idx.unwrap()));
-                let new_val = self.get_new_copy(min_node_idx.unwrap());
-                self.delete_helper(&new_val, node_idx*2 + 1, depth + 1);
-                self.convert_left_to_right_tree(node_idx*2 + 1);                      
+            return Some(Some(node_idx))
+        }
+        // inserts put entries with an equal key in the right subtree, but rebuilds
+        // may have put them on either side
+        let cmp = self.compare_val_at_dim(&target.key, &node.entry.key, curr_dim);
+        if cmp <= 0 {
+            if let Some(new_left) = self.delete_helper(target, left, depth + 1) {
+                self.node_mut(node_idx).left = new_left;
+                return Some(Some(node_idx))
             }
-            else {
-                self.arr[node_idx] = None;
-                return
-            }
-            return
         }
-        else {
-            if self.compare_val_at_dim(val, &self.arr[node_idx].as_ref().unwrap(), curr_dim) < 0 {
-                self.delete_helper(val, node_idx * 2 + 1, depth + 1)
-            }
-            else {
-                self.delete_helper(val, node_idx * 2 + 2, depth + 1)
+        if cmp >= 0 {
+            if let Some(new_right) = self.delete_helper(target, right, depth + 1) {
+                self.node_mut(node_idx).right = new_right;
+                return Some(Some(node_idx))
             }
         }
+        None
     }
 
-    pub fn delete(&mut self, val: &Vec<Field>) {
-        self.delete_helper(val, 0, 0)
+    /// Delete the entry of the record stored under `rid`. `val` is the full record.
+    pub fn delete(&mut self, val: &[Field], rid: ValueId) {
+        let target = IndexEntry::new(self.get_key(val), rid);
+        if let Some(root) = self.delete_helper(&target, self.root, 0) {
+            self.root = root;
+            self.size -= 1;
+        }
     }
 
-    fn print_val(val: &Option<Vec<Field>>) {
-        if val.is_none() {
-            debug!("Empty tree");
-        }
-        let val_arr = val.as_ref().unwrap();
+    /// Number of entries in the tree.
+    pub fn num_entries(&self) -> usize {
+        self.size
+    }
+
+    fn print_val(entry: &IndexEntry) {
         debug!("[");
-        for element in val_arr {
+        for element in &entry.key {
             match element {
                 IntField(x) => {debug!("{},", x)},
-                StringField(x) => {debug!("{},", x)},          
+                StringField(x) => {debug!("{},", x)},
             }
         }
-        debug!("]\n");
+        debug!("] -> {:?}\n", entry.rid);
     }
 
     fn print_tree_helper(&self, node_idx: usize, depth: usize) {
         debug!("depth level: {}\n", depth);
-        let arr_len = self.arr.len();
-        if arr_len == 0 || arr_len <= node_idx {
-            return 
-        }
-        KdTree::print_val(&self.arr[node_idx]);
-        if arr_len > (node_idx*2 + 1) && self.arr[node_idx*2 + 1].is_some() {
+        let node = self.node(node_idx);
+        KdTree::print_val(&node.entry);
+        if let Some(left) = node.left {
             debug!("left\n");
-            self.print_tree_helper(node_idx*2 + 1, depth + 1);
+            self.print_tree_helper(left, depth + 1);
         }
-        if arr_len > (node_idx*2 + 2) && self.arr[node_idx*2 + 2].is_some() {
+        if let Some(right) = node.right {
             debug!("right\n");
-            self.print_tree_helper(node_idx*2 + 2, depth + 1);
+            self.print_tree_helper(right, depth + 1);
         }
     }
 
     pub fn print_tree(&self) {
-        self.print_tree_helper(0, 0)
+        match self.root {
+            Some(root) => self.print_tree_helper(root, 0),
+            None => debug!("Empty tree"),
+        }
     }
-    
+
     pub fn int_val_to_field(vec: &Vec<i32>) -> Vec<Field> {
         let mut res: Vec<Field> = Vec::new();
         for element in vec {
@@ -728,15 +795,72 @@ impl KdTree {
         let mut res_str: String = "[".to_owned();
         for single_field in &tuple.field_vals {
             match single_field {
-                Field::IntField(x) => {res_str.push_str(&*x.to_string().to_owned())},
-                Field::StringField(x) => {res_str.push_str(&x.to_owned())},
+                Field::IntField(x) => {res_str.push_str(&x.to_string())},
+                Field::StringField(x) => {res_str.push_str(x)},
             }
         }
-        res_str.push_str("]");
+        res_str.push(']');
         debug!("{}", res_str);
     }
 }
 
+impl MdIndex for KdTree {
+    fn tree_type(&self) -> &'static str {
+        "KD"
+    }
+
+    fn idx_fields(&self) -> Vec<usize> {
+        self.idx_fields.clone()
+    }
+
+    fn insert(&mut self, val: &[Field], rid: ValueId) -> Result<(), CrustyError> {
+        KdTree::insert(self, val, rid);
+        Ok(())
+    }
+
+    fn delete(&mut self, val: &[Field], rid: ValueId) -> Result<(), CrustyError> {
+        KdTree::delete(self, val, rid);
+        Ok(())
+    }
+
+    fn bulk_load(&mut self, arr: &[(Vec<Field>, ValueId)]) -> Result<(), CrustyError> {
+        self.data_into_tree(arr);
+        Ok(())
+    }
+
+    fn point_query(&self, key: &[Field]) -> Result<Vec<ValueId>, CrustyError> {
+        Ok(self.get(key))
+    }
+
+    fn range_query(&self, min: &[Option<Field>], max: &[Option<Field>]) -> Result<Vec<ValueId>, CrustyError> {
+        Ok(self.partial_range_query(min, max))
+    }
+
+    fn knn_query(&self, key: &[Field], k: usize, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        Ok(self.knn_with_metric(key, k, metric))
+    }
+
+    fn radius_query(&self, center: &[Field], radius: f64, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        Ok(KdTree::radius_query(self, center, radius, metric))
+    }
+
+    fn stats(&self) -> Result<MdIndexStats, CrustyError> {
+        Ok(MdIndexStats {num_entries: self.num_entries(), depth: KdTree::stats(self).depth})
+    }
+
+    fn serialize(&self, name: &str) -> SerializedIndex {
+        SerializedIndex::new(self, name, self.total_dim, self)
+    }
+
+    fn check_invariants(&self) -> Vec<String> {
+        KdTree::check_invariants(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
 
 #[cfg(test)]
 mod test {
@@ -744,6 +868,37 @@ mod test {
     use crate::testutil::*;
     use std::{println as debug};
 
+    // rows in these tests are named by letters, and each one is stored in the slot of its letter
+    pub fn rid(name: char) -> ValueId {
+        ValueId::new_slot(0, 0, name as u16 - 'a' as u16)
+    }
+
+    pub fn rids(names: &[char]) -> Vec<ValueId> {
+        let mut res: Vec<ValueId> = names.iter().map(|name| rid(*name)).collect();
+        res.sort();
+        res
+    }
+
+    fn layout_helper(tree: &KdTree, node_idx: Option<usize>, pos: usize, res: &mut Vec<Option<ValueId>>) {
+        let node = match node_idx {
+            Some(node_idx) => tree.node(node_idx),
+            None => return,
+        };
+        if res.len() <= pos {
+            res.resize(pos + 1, None);
+        }
+        res[pos] = Some(node.entry.rid);
+        layout_helper(tree, node.left, pos*2 + 1, res);
+        layout_helper(tree, node.right, pos*2 + 2, res);
+    }
+
+    // the record ids of the tree, laid out as a heap-ordered array with the children of `i` at `2i+1` and `2i+2`
+    pub fn layout(tree: &KdTree) -> Vec<Option<ValueId>> {
+        let mut res = Vec::new();
+        layout_helper(tree, tree.root, 0, &mut res);
+        res
+    }
+
     pub fn tree1() -> KdTree {
         let a: Vec<Field> = vec![IntField(4), IntField(7)];
         let b: Vec<Field> = vec![IntField(3), IntField(8)];
@@ -753,13 +908,13 @@ mod test {
         let f: Vec<Field> = vec![IntField(10), IntField(1)];
         let g: Vec<Field> = vec![IntField(11), IntField(3)];
         let mut tree = KdTree::new(2, vec![0, 1], 2);
-        tree.insert(&a);
-        tree.insert(&b);
-        tree.insert(&c);
-        tree.insert(&d);
-        tree.insert(&e);
-        tree.insert(&f);
-        tree.insert(&g);
+        tree.insert(&a, rid('a'));
+        tree.insert(&b, rid('b'));
+        tree.insert(&c, rid('c'));
+        tree.insert(&d, rid('d'));
+        tree.insert(&e, rid('e'));
+        tree.insert(&f, rid('f'));
+        tree.insert(&g, rid('g'));
         tree
     }
 
@@ -769,10 +924,10 @@ mod test {
         let j: Vec<Field> = vec![IntField(4), IntField(20)];
         let k: Vec<Field> =  vec![IntField(4), IntField(30)];
         let mut tree = KdTree::new(2, vec![0, 1], 2);
-        tree.insert(&h);
-        tree.insert(&i);
-        tree.insert(&j);
-        tree.insert(&k);
+        tree.insert(&h, rid('h'));
+        tree.insert(&i, rid('i'));
+        tree.insert(&j, rid('j'));
+        tree.insert(&k, rid('k'));
         tree
     }
 
@@ -784,9 +939,9 @@ mod test {
         let p: Vec<Field> =  vec![IntField(50), IntField(30)];
         let q: Vec<Field> =  vec![IntField(35), IntField(45)];
         let mut tree = KdTree::new(2, vec![0, 1], 2);
-        let mut tree_arr: [&Vec<Field>; 6] = [&l, &m, &n, &o, &p, &q];
-        for element in tree_arr {
-            tree.insert(element);
+        let tree_arr: [(&Vec<Field>, char); 6] = [(&l, 'l'), (&m, 'm'), (&n, 'n'), (&o, 'o'), (&p, 'p'), (&q, 'q')];
+        for (element, name) in tree_arr {
+            tree.insert(element, rid(name));
         }
         tree 
     }
@@ -804,17 +959,17 @@ mod test {
         let j: Vec<Field> = vec![IntField(19), IntField(5), IntField(19)];  
         let k: Vec<Field> = vec![IntField(20), IntField(3), IntField(6)];
         let mut tree = KdTree::new(3, vec![0, 1, 2], 3);
-        tree.insert(&a);
-        tree.insert(&b);
-        tree.insert(&c);
-        tree.insert(&d);
-        tree.insert(&e);
-        tree.insert(&f);
-        tree.insert(&g);
-        tree.insert(&h);
-        tree.insert(&i);
-        tree.insert(&j);
-        tree.insert(&k);
+        tree.insert(&a, rid('a'));
+        tree.insert(&b, rid('b'));
+        tree.insert(&c, rid('c'));
+        tree.insert(&d, rid('d'));
+        tree.insert(&e, rid('e'));
+        tree.insert(&f, rid('f'));
+        tree.insert(&g, rid('g'));
+        tree.insert(&h, rid('h'));
+        tree.insert(&i, rid('i'));
+        tree.insert(&j, rid('j'));
+        tree.insert(&k, rid('k'));
         tree
     }
 
@@ -831,9 +986,9 @@ mod test {
         let j: Vec<Field> = vec![IntField(19), IntField(5), IntField(19)];  
         let k: Vec<Field> = vec![IntField(20), IntField(3), IntField(6)];
         let mut tree5 = KdTree::new(2, vec![1, 2], 3);
-        let mut tree_arr_5: [Vec<Field>; 11] = [a.clone(), b.clone(), c.clone(), d.clone(), e.clone(), 
-        f.clone(), g.clone(), h.clone(), i.clone(), j.clone(), k.clone()];
-        tree5.data_into_tree(&mut tree_arr_5);
+        let tree_arr_5: [(Vec<Field>, ValueId); 11] = [(a.clone(), rid('a')), (b.clone(), rid('b')), (c.clone(), rid('c')), (d.clone(), rid('d')), (e.clone(), rid('e')), 
+        (f.clone(), rid('f')), (g.clone(), rid('g')), (h.clone(), rid('h')), (i.clone(), rid('i')), (j.clone(), rid('j')), (k.clone(), rid('k'))];
+        tree5.data_into_tree(&tree_arr_5);
         tree5
     }
 
@@ -845,12 +1000,12 @@ mod test {
         let e: Vec<Field> = vec![IntField(10), IntField(2)];
         let f: Vec<Field> = vec![IntField(8), IntField(7)];
         let mut tree6 = KdTree::new(2, vec![0, 1], 2);
-        tree6.insert(&a);
-        tree6.insert(&b);
-        tree6.insert(&c);
-        tree6.insert(&d);
-        tree6.insert(&e);
-        tree6.insert(&f);
+        tree6.insert(&a, rid('a'));
+        tree6.insert(&b, rid('b'));
+        tree6.insert(&c, rid('c'));
+        tree6.insert(&d, rid('d'));
+        tree6.insert(&e, rid('e'));
+        tree6.insert(&f, rid('f'));
         tree6
     }
 
@@ -880,11 +1035,11 @@ mod test {
         let w: Vec<Field> =  vec![IntField(17), IntField(22)];
         let x: Vec<Field> =  vec![IntField(18), IntField(24)];
         let mut tree7 = KdTree::new(2, vec![0, 1], 2);
-        let mut tree_arr_7: [Vec<Field>; 24] = [a.clone(), b.clone(), c.clone(), d.clone(), e.clone(), 
-        f.clone(), g.clone(), h.clone(), i.clone(), j.clone(), k.clone(), l.clone(), m.clone(), n.clone(),
-        o.clone(), p.clone(), q.clone(), r.clone(), s.clone(), t.clone(), u.clone(), v.clone(), w.clone(),
-        x.clone()];
-        tree7.data_into_tree(&mut tree_arr_7);
+        let tree_arr_7: [(Vec<Field>, ValueId); 24] = [(a.clone(), rid('a')), (b.clone(), rid('b')), (c.clone(), rid('c')), (d.clone(), rid('d')), (e.clone(), rid('e')), 
+        (f.clone(), rid('f')), (g.clone(), rid('g')), (h.clone(), rid('h')), (i.clone(), rid('i')), (j.clone(), rid('j')), (k.clone(), rid('k')), (l.clone(), rid('l')), (m.clone(), rid('m')), (n.clone(), rid('n')),
+        (o.clone(), rid('o')), (p.clone(), rid('p')), (q.clone(), rid('q')), (r.clone(), rid('r')), (s.clone(), rid('s')), (t.clone(), rid('t')), (u.clone(), rid('u')), (v.clone(), rid('v')), (w.clone(), rid('w')),
+        (x.clone(), rid('x'))];
+        tree7.data_into_tree(&tree_arr_7);
         tree7
     }
 
@@ -942,14 +1097,9 @@ mod test {
         let f: Vec<Field> = vec![IntField(10), IntField(1)];
         let g: Vec<Field> = vec![IntField(11), IntField(3)];
         let tree_1 = tree1();
-        assert!(tree_1 == KdTree {
-            dim: 2,
-            arr: vec![Some(a.clone()), Some(b.clone()), Some(c.clone()), None,
-            Some(e.clone()), Some(f.clone()), Some(d.clone()), None, None, None,
-            None, None, None, None, Some(g.clone())],
-            idx_fields: vec![0, 1],
-            total_dim: 2,
-        });     
+        assert!(layout(&tree_1) == vec![Some(rid('a')), Some(rid('b')), Some(rid('c')), None,
+            Some(rid('e')), Some(rid('f')), Some(rid('d')), None, None, None,
+            None, None, None, None, Some(rid('g'))]);     
     }
 
     #[test]
@@ -962,15 +1112,10 @@ mod test {
         let f: Vec<Field> = vec![IntField(10), IntField(1)];
         let g: Vec<Field> = vec![IntField(11), IntField(3)];
         let mut bulk_load_tree_1 = KdTree::new(2, vec![0, 1], 2);
-        let mut tree_arr_1: [Vec<Field>; 7] = [a.clone(), b.clone(), c.clone(), d.clone(), e.clone(), f.clone(), g.clone()];
-        bulk_load_tree_1.data_into_tree(&mut tree_arr_1);
-        assert!(bulk_load_tree_1 == KdTree {
-            dim: 2,
-            arr: vec![Some(c.clone()), Some(b.clone()), Some(g.clone()), Some(a.clone()),
-            Some(e.clone()), Some(f.clone()), Some(d.clone())],
-            idx_fields: vec![0, 1],
-            total_dim: 2,
-        });
+        let tree_arr_1: [(Vec<Field>, ValueId); 7] = [(a.clone(), rid('a')), (b.clone(), rid('b')), (c.clone(), rid('c')), (d.clone(), rid('d')), (e.clone(), rid('e')), (f.clone(), rid('f')), (g.clone(), rid('g'))];
+        bulk_load_tree_1.data_into_tree(&tree_arr_1);
+        assert!(layout(&bulk_load_tree_1) == vec![Some(rid('c')), Some(rid('b')), Some(rid('g')), Some(rid('a')),
+            Some(rid('e')), Some(rid('f')), Some(rid('d'))]);
     }
 
     #[test]
@@ -983,24 +1128,27 @@ mod test {
         let f: Vec<Field> = vec![IntField(10), IntField(1)];
         let g: Vec<Field> = vec![IntField(11), IntField(3)];
         let mut tree_1 = tree1();
-        tree_1.delete(&g);
-        assert!(tree_1 == KdTree {
-            dim: 2,
-            arr: vec![Some(a.clone()), Some(b.clone()), Some(c.clone()), None,
-            Some(e.clone()), Some(f.clone()), Some(d.clone()), None, None, None,
-            None, None, None, None, None,],
-            idx_fields: vec![0, 1],
-            total_dim: 2,
-        });           
-        tree_1.delete(&a);
-        assert!(tree_1 == KdTree {
-            dim: 2,
-            arr: vec![Some(c.clone()), Some(b.clone()), Some(d.clone()), None,
-            Some(e.clone()), Some(f.clone()), None, None, None, None,
-            None, None, None, None, None],
-            idx_fields: vec![0, 1],
-            total_dim: 2,
-        });                 
+        tree_1.delete(&g, rid('g'));
+        assert!(layout(&tree_1) == vec![Some(rid('a')), Some(rid('b')), Some(rid('c')), None,
+            Some(rid('e')), Some(rid('f')), Some(rid('d'))]);           
+        tree_1.delete(&a, rid('a'));
+        assert!(layout(&tree_1) == vec![Some(rid('c')), Some(rid('b')), Some(rid('d')), None,
+            Some(rid('e')), Some(rid('f'))]);                 
+    }
+
+    #[test]
+    pub fn test_delete_duplicate_key() {
+        let a: Vec<Field> = vec![IntField(1), IntField(4), IntField(7)];
+        let b: Vec<Field> = vec![IntField(2), IntField(4), IntField(7)];
+        let c: Vec<Field> = vec![IntField(3), IntField(4), IntField(7)];
+        let mut tree = KdTree::new(2, vec![1, 2], 3);
+        tree.insert(&a, rid('a'));
+        tree.insert(&b, rid('b'));
+        tree.insert(&c, rid('c'));
+        tree.delete(&b, rid('b'));
+        assert!(tree.get(&vec![IntField(4), IntField(7)]) == rids(&['a', 'c']));
+        tree.delete(&a, rid('a'));
+        assert!(tree.get(&vec![IntField(4), IntField(7)]) == rids(&['c']));
     }
 
     #[test]
@@ -1023,14 +1171,9 @@ mod test {
         let j: Vec<Field> = vec![IntField(4), IntField(20)];
         let k: Vec<Field> =  vec![IntField(4), IntField(30)];
         let tree_2 = tree2();
-        assert!(tree_2 == KdTree {
-            dim: 2,
-            arr: vec![Some(h.clone()), Some(i.clone()), None, None,
-            Some(j.clone()), None, None, None, None, None,
-            Some(k.clone()),],
-            idx_fields: vec![0, 1],
-            total_dim: 2,
-        });                   
+        assert!(layout(&tree_2) == vec![Some(rid('h')), Some(rid('i')), None, None,
+            Some(rid('j')), None, None, None, None, None,
+            Some(rid('k')),]);                   
     }
 
     #[test]
@@ -1040,16 +1183,9 @@ mod test {
         let j: Vec<Field> = vec![IntField(4), IntField(20)];
         let k: Vec<Field> =  vec![IntField(4), IntField(30)];
         let mut bulk_load_tree_2 = KdTree::new(2, vec![0, 1], 2);
-        let mut tree_arr_2: [Vec<Field>; 4] = [h.clone(), i.clone(), j.clone(), k.clone(),];
-        bulk_load_tree_2.data_into_tree(&mut tree_arr_2);
-        assert!(bulk_load_tree_2 == KdTree {
-            dim: 2,
-            arr: vec![Some(k.clone()), None, Some(j.clone()), None,
-            None, Some(i.clone()), None, None, None, None, None,
-            None, Some(h.clone()), ],
-            idx_fields: vec![0, 1],
-            total_dim: 2,
-        });
+        let tree_arr_2: [(Vec<Field>, ValueId); 4] = [(h.clone(), rid('h')), (i.clone(), rid('i')), (j.clone(), rid('j')), (k.clone(), rid('k')),];
+        bulk_load_tree_2.data_into_tree(&tree_arr_2);
+        assert!(layout(&bulk_load_tree_2) == vec![Some(rid('k')), Some(rid('j')), Some(rid('h')), Some(rid('i'))]);
     }
 
     #[test]
@@ -1059,14 +1195,9 @@ mod test {
         let j: Vec<Field> = vec![IntField(4), IntField(20)];
         let k: Vec<Field> =  vec![IntField(4), IntField(30)];
         let mut tree_2 = tree2();
-        tree_2.delete(&h);
-        assert!(tree_2 == KdTree {
-            dim: 2,
-            arr: vec![Some(i.clone()), None, Some(j.clone()), None,
-            None, None, Some(k.clone()), None, None, None, None,],
-            idx_fields: vec![0, 1],
-            total_dim: 2,
-        });
+        tree_2.delete(&h, rid('h'));
+        assert!(layout(&tree_2) == vec![Some(rid('i')), None, Some(rid('j')), None,
+            None, None, Some(rid('k'))]);
     }
 
     #[test]
@@ -1095,14 +1226,9 @@ mod test {
         let p: Vec<Field> =  vec![IntField(50), IntField(30)];
         let q: Vec<Field> =  vec![IntField(35), IntField(45)];
         let tree_3 = tree3();
-        assert!(tree_3 == KdTree {
-            dim: 2,
-            arr: vec![Some(l.clone()), Some(m.clone()), Some(n.clone()),
-            Some(o.clone()), None, Some(p.clone()), None, None, None, None,
-            None, Some(q.clone()),],
-            idx_fields: vec![0, 1],
-            total_dim: 2,
-        });                   
+        assert!(layout(&tree_3) == vec![Some(rid('l')), Some(rid('m')), Some(rid('n')),
+            Some(rid('o')), None, Some(rid('p')), None, None, None, None,
+            None, Some(rid('q')),]);                   
     }
 
     #[test]
@@ -1114,15 +1240,10 @@ mod test {
         let p: Vec<Field> =  vec![IntField(50), IntField(30)];
         let q: Vec<Field> =  vec![IntField(35), IntField(45)];
         let mut bulk_load_tree_3 = KdTree::new(2, vec![0, 1], 2);
-        let mut tree_arr_3: [Vec<Field>; 6] = [l.clone(), m.clone(), n.clone(), o.clone(), p.clone(), q.clone(),];
-        bulk_load_tree_3.data_into_tree(&mut tree_arr_3);
-        assert!(bulk_load_tree_3 == KdTree {
-            dim: 2,
-            arr: vec![Some(q.clone()), Some(m.clone()), Some(n.clone()), 
-            Some(o.clone()), Some(l.clone()),Some(p.clone()),],
-            idx_fields: vec![0, 1],
-            total_dim: 2,
-        });
+        let tree_arr_3: [(Vec<Field>, ValueId); 6] = [(l.clone(), rid('l')), (m.clone(), rid('m')), (n.clone(), rid('n')), (o.clone(), rid('o')), (p.clone(), rid('p')), (q.clone(), rid('q')),];
+        bulk_load_tree_3.data_into_tree(&tree_arr_3);
+        assert!(layout(&bulk_load_tree_3) == vec![Some(rid('q')), Some(rid('m')), Some(rid('n')), 
+            Some(rid('o')), Some(rid('l')),Some(rid('p')),]);
     }
 
     #[test]
@@ -1134,26 +1255,14 @@ mod test {
         let p: Vec<Field> =  vec![IntField(50), IntField(30)];
         let q: Vec<Field> =  vec![IntField(35), IntField(45)];
         let mut tree_3 = tree3();
-        tree_3.delete(&l);
+        tree_3.delete(&l, rid('l'));
         tree_3.print_tree();
-        assert!(tree_3 == KdTree {
-            dim: 2,
-            arr: vec![Some(q.clone()), Some(m.clone()), Some(n.clone()),
-            Some(o.clone()), None, Some(p.clone()), None, None, None,
-            None, None, None,],
-            idx_fields: vec![0, 1],
-            total_dim: 2,
-        });
+        assert!(layout(&tree_3) == vec![Some(rid('q')), Some(rid('m')), Some(rid('n')),
+            Some(rid('o')), None, Some(rid('p'))]);
         tree_3 = tree3();
-        tree_3.delete(&n);
-        assert!(tree_3 == KdTree {
-            dim: 2,
-            arr: vec![Some(l.clone()), Some(m.clone()), Some(p.clone()),
-            Some(o.clone()), None, None, Some(q.clone()), None,
-            None, None, None, None],
-            idx_fields: vec![0, 1],
-            total_dim: 2,
-        });
+        tree_3.delete(&n, rid('n'));
+        assert!(layout(&tree_3) == vec![Some(rid('l')), Some(rid('m')), Some(rid('p')),
+            Some(rid('o')), None, None, Some(rid('q'))]);
     }
 
     #[test]
@@ -1170,30 +1279,19 @@ mod test {
         let j: Vec<Field> = vec![IntField(19), IntField(5), IntField(19)];  
         let k: Vec<Field> = vec![IntField(20), IntField(3), IntField(6)];
         let mut bulk_load_tree_4 = KdTree::new(3, vec![0, 1, 2], 3);
-        let mut tree_arr_4: [Vec<Field>; 11] = [a.clone(), b.clone(), c.clone(), d.clone(), e.clone(), 
-        f.clone(), g.clone(), h.clone(), i.clone(), j.clone(), k.clone()];
-        bulk_load_tree_4.data_into_tree(&mut tree_arr_4);
-        assert!(bulk_load_tree_4 == KdTree {
-            dim: 3,
-            arr: vec![Some(i.clone()), Some(e.clone()), Some(f.clone()), Some(a.clone()),
-            Some(c.clone()), Some(j.clone()), Some(g.clone()), Some(b.clone()), 
-            None, Some(d.clone()),None, Some(k.clone()), None,
-            Some(h.clone()),],
-            idx_fields: vec![0, 1, 2],
-            total_dim: 3,
-        });
+        let tree_arr_4: [(Vec<Field>, ValueId); 11] = [(a.clone(), rid('a')), (b.clone(), rid('b')), (c.clone(), rid('c')), (d.clone(), rid('d')), (e.clone(), rid('e')), 
+        (f.clone(), rid('f')), (g.clone(), rid('g')), (h.clone(), rid('h')), (i.clone(), rid('i')), (j.clone(), rid('j')), (k.clone(), rid('k'))];
+        bulk_load_tree_4.data_into_tree(&tree_arr_4);
+        assert!(layout(&bulk_load_tree_4) == vec![Some(rid('i')), Some(rid('e')), Some(rid('f')), Some(rid('a')),
+            Some(rid('c')), Some(rid('j')), Some(rid('g')), Some(rid('b')), 
+            None, Some(rid('d')),None, Some(rid('k')), None,
+            Some(rid('h')),]);
         let mut range_query_result = bulk_load_tree_4.range_query(&vec![IntField(18), IntField(3), IntField(6)], &vec![IntField(20), IntField(7), IntField(19)]);
-        range_query_result.sort_by(|a, b| a[0].cmp(&b[0]));
-        bulk_load_tree_4.delete(&i);
-        assert!(bulk_load_tree_4 == KdTree {
-            dim: 3,
-            arr: vec![Some(h.clone()), Some(e.clone()), Some(f.clone()), Some(a.clone()),
-            Some(c.clone()), Some(j.clone()), Some(g.clone()), Some(b.clone()), 
-            None, Some(d.clone()),None, Some(k.clone()), None,
-            None,],
-            idx_fields: vec![0, 1, 2],
-            total_dim: 3,
-        });        
+        range_query_result.sort();
+        bulk_load_tree_4.delete(&i, rid('i'));
+        assert!(layout(&bulk_load_tree_4) == vec![Some(rid('h')), Some(rid('e')), Some(rid('f')), Some(rid('a')),
+            Some(rid('c')), Some(rid('j')), Some(rid('g')), Some(rid('b')), 
+            None, Some(rid('d')),None, Some(rid('k'))]);        
     }
 
     #[test]
@@ -1210,12 +1308,26 @@ mod test {
         let j: Vec<Field> = vec![IntField(19), IntField(5), IntField(19)];  
         let k: Vec<Field> = vec![IntField(20), IntField(3), IntField(6)];
         let mut bulk_load_tree_4 = KdTree::new(3, vec![0, 1, 2], 3);
-        let mut tree_arr_4: [Vec<Field>; 11] = [a.clone(), b.clone(), c.clone(), d.clone(), e.clone(), 
-        f.clone(), g.clone(), h.clone(), i.clone(), j.clone(), k.clone()];
-        bulk_load_tree_4.data_into_tree(&mut tree_arr_4);
+        let tree_arr_4: [(Vec<Field>, ValueId); 11] = [(a.clone(), rid('a')), (b.clone(), rid('b')), (c.clone(), rid('c')), (d.clone(), rid('d')), (e.clone(), rid('e')), 
+        (f.clone(), rid('f')), (g.clone(), rid('g')), (h.clone(), rid('h')), (i.clone(), rid('i')), (j.clone(), rid('j')), (k.clone(), rid('k'))];
+        bulk_load_tree_4.data_into_tree(&tree_arr_4);
         let mut range_query_result = bulk_load_tree_4.range_query(&vec![IntField(18), IntField(3), IntField(6)], &vec![IntField(20), IntField(7), IntField(19)]);
-        range_query_result.sort_by(|a, b| a[0].cmp(&b[0]));
-        assert!(range_query_result == vec![f.clone(), j.clone(), k.clone()]);        
+        range_query_result.sort();
+        assert!(range_query_result == rids(&['f', 'j', 'k']));        
+    }
+
+    #[test]
+    pub fn test_partial_range_query_tree4() {
+        let tree_4 = tree4();
+        // only the second attribute is constrained
+        let mut res = tree_4.partial_range_query(&[None, Some(IntField(13))], &[]);
+        res.sort();
+        assert_eq!(res, rids(&['c', 'd', 'g', 'h']));
+        // one-sided bounds on the first and last attributes
+        let mut res = tree_4.partial_range_query(&[None, None, Some(IntField(10))], &[Some(IntField(8)), None, None]);
+        res.sort();
+        assert_eq!(res, rids(&['a', 'e']));
+        assert_eq!(tree_4.partial_range_query(&[], &[]).len(), 11);
     }
 
     pub fn test_get_tree4 () {
@@ -1231,12 +1343,12 @@ mod test {
         let j: Vec<Field> = vec![IntField(19), IntField(5), IntField(19)];  
         let k: Vec<Field> = vec![IntField(20), IntField(3), IntField(6)];
         let mut bulk_load_tree_4 = KdTree::new(3, vec![0, 1, 2], 3);
-        let mut tree_arr_4: [Vec<Field>; 11] = [a.clone(), b.clone(), c.clone(), d.clone(), e.clone(), 
-        f.clone(), g.clone(), h.clone(), i.clone(), j.clone(), k.clone()];
-        bulk_load_tree_4.data_into_tree(&mut tree_arr_4);
+        let tree_arr_4: [(Vec<Field>, ValueId); 11] = [(a.clone(), rid('a')), (b.clone(), rid('b')), (c.clone(), rid('c')), (d.clone(), rid('d')), (e.clone(), rid('e')), 
+        (f.clone(), rid('f')), (g.clone(), rid('g')), (h.clone(), rid('h')), (i.clone(), rid('i')), (j.clone(), rid('j')), (k.clone(), rid('k'))];
+        bulk_load_tree_4.data_into_tree(&tree_arr_4);
         let mut get_result = bulk_load_tree_4.get(&vec![IntField(10), IntField(21), IntField(3)]);
-        get_result.sort_by(|a, b| a[0].cmp(&b[0]));
-        assert!(get_result == vec![h.clone()]);        
+        get_result.sort();
+        assert!(get_result == rids(&['h']));        
     }
 
     #[test]
@@ -1280,14 +1392,9 @@ mod test {
         let j: Vec<Field> = vec![IntField(19), IntField(5), IntField(19)];  
         let k: Vec<Field> = vec![IntField(20), IntField(3), IntField(6)];        
         let tree_5 = tree5();
-        assert!(tree_5 == KdTree {
-            dim: 2,
-            arr: vec![Some(e.clone()), Some(a.clone()), Some(g.clone()), Some(b.clone()),
-            Some(j.clone()), Some(h.clone()), Some(c.clone()), Some(k.clone()), 
-            None, None, Some(i.clone()), None, Some(d.clone()), Some(f.clone())],
-            idx_fields: vec![1,2],
-            total_dim: 3,
-        });
+        assert!(layout(&tree_5) == vec![Some(rid('e')), Some(rid('a')), Some(rid('g')), Some(rid('b')),
+            Some(rid('j')), Some(rid('h')), Some(rid('c')), Some(rid('k')), 
+            None, Some(rid('i')), None, Some(rid('d')), None, Some(rid('f'))]);
     }
 
     #[test]
@@ -1304,24 +1411,14 @@ mod test {
         let j: Vec<Field> = vec![IntField(19), IntField(5), IntField(19)];  
         let k: Vec<Field> = vec![IntField(20), IntField(3), IntField(6)];
         let mut tree_5 = tree5();
-        tree_5.delete(&i);
-        assert!(tree_5 == KdTree {
-            dim: 2,
-            arr: vec![Some(e.clone()), Some(a.clone()), Some(g.clone()), Some(b.clone()),
-            Some(j.clone()), Some(h.clone()), Some(c.clone()), Some(k.clone()), 
-            None, None, None, None, Some(d.clone()), Some(f.clone())],
-            idx_fields: vec![1, 2],
-            total_dim: 3,
-        });           
-        tree_5.delete(&e);
-        assert!(tree_5 == KdTree {
-            dim: 2,
-            arr: vec![Some(f.clone()), Some(a.clone()), Some(g.clone()), Some(b.clone()),
-            Some(j.clone()), Some(h.clone()), Some(c.clone()), Some(k.clone()), 
-            None, None, None, None, Some(d.clone()), None],
-            idx_fields: vec![1, 2],
-            total_dim: 3,
-        });                 
+        tree_5.delete(&i, rid('i'));
+        assert!(layout(&tree_5) == vec![Some(rid('e')), Some(rid('a')), Some(rid('g')), Some(rid('b')),
+            Some(rid('j')), Some(rid('h')), Some(rid('c')), Some(rid('k')), 
+            None, None, None, Some(rid('d')), None, Some(rid('f'))]);           
+        tree_5.delete(&e, rid('e'));
+        assert!(layout(&tree_5) == vec![Some(rid('f')), Some(rid('a')), Some(rid('g')), Some(rid('b')),
+            Some(rid('j')), Some(rid('h')), Some(rid('c')), Some(rid('k')), 
+            None, None, None, Some(rid('d'))]);                 
     }
 
     #[test]
@@ -1331,11 +1428,36 @@ mod test {
         let f: Vec<Field> = vec![IntField(18), IntField(7), IntField(15)];
         let mut tree_5 = tree5();    
         let mut get_result = tree_5.get(&vec![IntField(4), IntField(11)]);
-        get_result.sort_by(|a, b| a[0].cmp(&b[0]));
-        assert!(get_result == vec![a.clone()]);      
+        get_result.sort();
+        assert!(get_result == rids(&['a']));      
         get_result = tree_5.get(&vec![IntField(7), IntField(15)]);
-        get_result.sort_by(|a, b| a[0].cmp(&b[0]));
-        assert!(get_result == vec![e.clone(), f.clone()]);      
+        get_result.sort();
+        assert!(get_result == rids(&['e', 'f']));      
+    }
+
+    #[test]
+    fn test_permuted_full_width_index() {
+        // every attribute is indexed, but in reverse order, so a key and a record have the
+        // same length and queries must not read the key as a record
+        let a: Vec<Field> = vec![IntField(1), IntField(2), IntField(3)];
+        let b: Vec<Field> = vec![IntField(3), IntField(2), IntField(1)];
+        let c: Vec<Field> = vec![IntField(1), IntField(5), IntField(9)];
+        let mut tree = KdTree::new(3, vec![2, 1, 0], 3);
+        tree.insert(&a, rid('a'));
+        tree.insert(&b, rid('b'));
+        tree.insert(&c, rid('c'));
+        assert_eq!(tree.get_key(&a), vec![IntField(3), IntField(2), IntField(1)]);
+        assert_eq!(tree.get(&tree.get_key(&a)), rids(&['a']));
+        assert_eq!(tree.get(&tree.get_key(&b)), rids(&['b']));
+        assert!(tree.search(&tree.get_key(&c)));
+        assert!(!tree.search(&c));
+
+        let mut range_query_result = tree.range_query(&[IntField(2), IntField(0), IntField(0)], &[IntField(9), IntField(5), IntField(1)]);
+        range_query_result.sort();
+        assert_eq!(range_query_result, rids(&['a', 'c']));
+        assert_eq!(tree.knn(&[IntField(1), IntField(2), IntField(3)], 1), rids(&['b']));
+        tree.delete(&a, rid('a'));
+        assert!(tree.get(&tree.get_key(&a)).is_empty());
     }
 
     #[test]
@@ -1346,8 +1468,8 @@ mod test {
         let g: Vec<Field> = vec![IntField(12), IntField(13), IntField(9)];     
         let mut tree_5 = tree5();    
         let mut range_query_result = tree_5.range_query(&vec![IntField(7), IntField(3)], &vec![IntField(16), IntField(15)]);
-        range_query_result.sort_by(|a, b| a[0].cmp(&b[0]));
-        assert!(range_query_result == vec![c.clone(), e.clone(), g.clone(), f.clone()]);        
+        range_query_result.sort();
+        assert!(range_query_result == rids(&['c', 'e', 'g', 'f']));        
     }
 
     #[test]
@@ -1356,8 +1478,8 @@ mod test {
         let e: Vec<Field> = vec![IntField(8), IntField(7), IntField(15)];
         let f: Vec<Field> = vec![IntField(18), IntField(7), IntField(15)];
         let mut knn_result = tree_5.knn(&vec![IntField(7), IntField(15)], 2);
-        knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-        assert!(knn_result == vec![e.clone(), f.clone()]);    
+        knn_result.sort();
+        assert!(knn_result == rids(&['e', 'f']));    
     }
 
     #[test]
@@ -1370,31 +1492,31 @@ mod test {
         let f: Vec<Field> = vec![IntField(8), IntField(7)];
         let mut tree_6 = tree6();    
         let mut knn_result = tree_6.knn(&vec![IntField(9), IntField(4)], 1);
-        knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-        assert!(knn_result == vec![e.clone()]); 
+        knn_result.sort();
+        assert!(knn_result == rids(&['e'])); 
         knn_result = tree_6.knn(&vec![IntField(9), IntField(4)], 2);
-        knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-        assert!(knn_result == vec![f.clone(), e.clone()]); 
+        knn_result.sort();
+        assert!(knn_result == rids(&['f', 'e'])); 
         knn_result = tree_6.knn(&vec![IntField(9), IntField(4)], 3);
-        knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-        assert!(knn_result == vec![a.clone(), f.clone(), e.clone()]); 
+        knn_result.sort();
+        assert!(knn_result == rids(&['a', 'f', 'e'])); 
         knn_result = tree_6.knn(&vec![IntField(9), IntField(4)], 4);
-        knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-        assert!(knn_result == vec![a.clone(), f.clone(), e.clone(), c.clone()]);     
+        knn_result.sort();
+        assert!(knn_result == rids(&['a', 'f', 'e', 'c']));     
         knn_result = tree_6.knn(&vec![IntField(9), IntField(4)], 5);
-        knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-        assert!(knn_result == vec![d.clone(), a.clone(), f.clone(), e.clone(), c.clone()]); 
+        knn_result.sort();
+        assert!(knn_result == rids(&['d', 'a', 'f', 'e', 'c'])); 
         knn_result = tree_6.knn(&vec![IntField(5), IntField(6)], 1);
-        knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-        assert!(knn_result == vec![a.clone()]);    
+        knn_result.sort();
+        assert!(knn_result == rids(&['a']));    
         knn_result = tree_6.knn(&vec![IntField(5), IntField(6)], 2);
-        knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
+        knn_result.sort();
         // println!("Result is");
         // KdTree::print_vec(&knn_result);
-        assert!(knn_result == vec![b.clone(), a.clone()]);  
+        assert!(knn_result == rids(&['b', 'a']));  
         knn_result = tree_6.knn(&vec![IntField(5), IntField(6)], 3);
-        knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-        assert!(knn_result == vec![b.clone(), a.clone(), f.clone()]);                 
+        knn_result.sort();
+        assert!(knn_result == rids(&['b', 'a', 'f']));                 
     }
 
     #[test]
@@ -1425,21 +1547,154 @@ mod test {
         let x: Vec<Field> =  vec![IntField(18), IntField(24)];    
         let mut tree_7 = tree7();    
         let mut knn_result = tree_7.knn(&vec![IntField(10), IntField(28)], 3);
-        knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-        assert!(knn_result == vec![f.clone(), d.clone(), t.clone()]);     
+        knn_result.sort();
+        assert!(knn_result == rids(&['f', 'd', 't']));     
         knn_result = tree_7.knn(&vec![IntField(7), IntField(18)], 3);
-        knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-        assert!(knn_result == vec![g.clone(), k.clone(), h.clone()]);      
+        knn_result.sort();
+        assert!(knn_result == rids(&['g', 'k', 'h']));      
         knn_result = tree_7.knn(&vec![IntField(3), IntField(25)], 3);
-        knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-        assert!(knn_result == vec![a.clone(), b.clone(), i.clone()]);      
+        knn_result.sort();
+        assert!(knn_result == rids(&['a', 'b', 'i']));      
         knn_result = tree_7.knn(&vec![IntField(15), IntField(29)], 2);
-        knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-        assert!(knn_result == vec![q.clone(), u.clone()]);    
+        knn_result.sort();
+        assert!(knn_result == rids(&['q', 'u']));    
         knn_result = tree_7.knn(&vec![IntField(1), IntField(2)], 2);
-        knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
+        knn_result.sort();
         // println!("Result is");
         // KdTree::print_vec(&knn_result);
-        assert!(knn_result == vec![k.clone(), h.clone()]);               
+        assert!(knn_result == rids(&['k', 'h']));               
+    }
+
+    #[test]
+    fn test_insert_sorted_stays_balanced() {
+        let mut tree = KdTree::new(2, vec![0, 1], 2);
+        let mut records = Vec::new();
+        for i in 0..1000 {
+            let record = vec![IntField(i), IntField(i % 7)];
+            tree.insert(&record, ValueId::new_slot(0, 0, i as u16));
+            records.push(record);
+        }
+        let stats = tree.stats();
+        assert_eq!(stats.num_nodes, 1000);
+        // log(1000) in base 5/3 is 13.5, so no node is deeper than 13
+        assert!(stats.depth <= 14);
+        assert!(stats.arr_len < 1 << 14);
+        assert!(stats.utilization > 0.0 && stats.utilization <= 1.0);
+
+        for (i, record) in records.iter().enumerate() {
+            assert_eq!(tree.get(record), vec![ValueId::new_slot(0, 0, i as u16)]);
+        }
+        let mut range_query_result = tree.range_query(&vec![IntField(100), IntField(2)], &vec![IntField(200), IntField(3)]);
+        range_query_result.sort();
+        let expected: Vec<ValueId> = (100..=200)
+            .filter(|i| i % 7 == 2 || i % 7 == 3)
+            .map(|i| ValueId::new_slot(0, 0, i as u16))
+            .collect();
+        assert_eq!(range_query_result, expected);
+
+        for (i, record) in records.iter().enumerate().step_by(2) {
+            tree.delete(record, ValueId::new_slot(0, 0, i as u16));
+        }
+        assert_eq!(tree.num_entries(), 500);
+        for (i, record) in records.iter().enumerate() {
+            assert_eq!(tree.search(record), i % 2 == 1);
+        }
+    }
+
+    #[test]
+    fn test_insert_duplicates() {
+        let mut tree = KdTree::new(2, vec![0, 1], 2);
+        let record = vec![IntField(1), IntField(1)];
+        for i in 0..300 {
+            tree.insert(&record, ValueId::new_slot(0, 0, i));
+        }
+        assert_eq!(tree.num_entries(), 300);
+        assert_eq!(tree.get(&record).len(), 300);
+    }
+
+    #[test]
+    fn test_rebuild() {
+        let mut tree = tree1();
+        let c: Vec<Field> = vec![IntField(5), IntField(2)];
+        let g: Vec<Field> = vec![IntField(11), IntField(3)];
+        tree.delete(&g, rid('g'));
+        assert_eq!(tree.stats().arr_len, 7);
+        tree.rebuild();
+        let stats = tree.stats();
+        assert_eq!(stats, KdTreeStats {depth: 3, num_nodes: 6, arr_len: 6, utilization: 1.0});
+        assert_eq!(tree.get(&c), rids(&['c']));
+    }
+
+    #[test]
+    fn test_check_invariants() {
+        let mut tree = tree5();
+        assert!(tree.check_invariants().is_empty());
+        // the root splits on the first attribute at 7, so a left child can't be above it
+        let left = tree.node(tree.root.unwrap()).left.unwrap();
+        tree.node_mut(left).entry.key = vec![IntField(8), IntField(11)];
+        tree.size += 1;
+        let problems = tree.check_invariants();
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].contains("wrong side") && problems[0].contains("attribute 0"));
+        assert!(problems[1].contains("counts 12 entries but holds 11"));
+    }
+
+    #[test]
+    fn test_parallel_bulk_load() {
+        let n = 3 * PARALLEL_BUILD_MIN;
+        let arr: Vec<(Vec<Field>, ValueId)> = (0..n)
+            .map(|i| (vec![IntField((i * 7919 % 1000) as i32), IntField((i * 104729 % 997) as i32)], ValueId::new_slot(0, (i / 1000) as u16, (i % 1000) as u16)))
+            .collect();
+        let mut tree = KdTree::new(2, vec![0, 1], 2);
+        tree.data_into_tree(&arr);
+        assert_eq!(tree.stats(), KdTreeStats {depth: 16, num_nodes: n, arr_len: n, utilization: 1.0});
+
+        // the layout is the same whatever the order of the entries and the number of threads
+        let mut reversed: Vec<(Vec<Field>, ValueId)> = arr.iter().rev().cloned().collect();
+        let mut tree2 = KdTree::new(2, vec![0, 1], 2);
+        tree2.data_into_tree(&reversed[..PARALLEL_BUILD_MIN]);
+        tree2.data_into_tree(&reversed[PARALLEL_BUILD_MIN..]);
+        assert!(tree2 == tree);
+        let mut entries: Vec<IndexEntry> = reversed.drain(..).map(|(key, rid)| IndexEntry::new(key, rid)).collect();
+        let mut slots = vec![None; n];
+        build_nodes(&mut entries, &mut slots, 0, 0, 2, 1);
+        assert!(slots == tree.nodes);
+
+        let min = vec![IntField(100), IntField(200)];
+        let max = vec![IntField(300), IntField(250)];
+        let mut expected: Vec<ValueId> = arr
+            .iter()
+            .filter(|(key, _)| key[0] >= min[0] && key[0] <= max[0] && key[1] >= min[1] && key[1] <= max[1])
+            .map(|(_, rid)| *rid)
+            .collect();
+        let mut range_query_result = tree.range_query(&min, &max);
+        expected.sort();
+        range_query_result.sort();
+        assert_eq!(range_query_result, expected);
+    }
+
+    #[test]
+    fn test_nodes_proportional_to_entries() {
+        // sorted inserts, rebuilds and deletes keep one slot per entry, and freed slots get reused
+        let mut tree = KdTree::new(1, vec![0], 1);
+        for i in 0..40 {
+            tree.insert(&vec![IntField(i)], ValueId::new_slot(0, 0, i as u16));
+        }
+        let stats = tree.stats();
+        assert_eq!(stats.num_nodes, 40);
+        assert_eq!(stats.arr_len, 40);
+        assert_eq!(tree.range_query(&vec![IntField(10)], &vec![IntField(12)]).len(), 3);
+
+        for i in 0..10 {
+            tree.delete(&vec![IntField(i)], ValueId::new_slot(0, 0, i as u16));
+        }
+        for i in 40..50 {
+            tree.insert(&vec![IntField(i)], ValueId::new_slot(0, 0, i as u16));
+        }
+        let stats = tree.stats();
+        assert_eq!(stats.num_nodes, 40);
+        assert_eq!(stats.arr_len, 40);
+        assert_eq!(tree.get(&vec![IntField(45)]), vec![ValueId::new_slot(0, 0, 45)]);
+        assert!(!tree.search(&vec![IntField(5)]));
     }
 }
@@ -0,0 +1,152 @@
+use std::cmp::Ordering;
+use std::fmt;
+use crate::{Attribute, DataType, Field};
+use crate::ids::ValueId;
+use super::R_Tree;
+
+/// How KNN queries measure the distance between two keys. Every attribute of the key is a
+/// coordinate; strings use the same prefix coordinate the R-tree stores them at.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DistanceMetric {
+    #[default]
+    Euclidean,
+    Manhattan,
+    Chebyshev,
+}
+
+impl DistanceMetric {
+    /// Parse the name of a metric, as given to `\useIndex ... KNN`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "euclidean" | "l2" => Some(DistanceMetric::Euclidean),
+            "manhattan" | "l1" => Some(DistanceMetric::Manhattan),
+            "chebyshev" | "linf" => Some(DistanceMetric::Chebyshev),
+            _ => None,
+        }
+    }
+
+    /// Coordinate of an attribute along its dimension.
+    pub fn coordinate(field: &Field) -> i64 {
+        i64::from(R_Tree::field_to_i32(field))
+    }
+
+    /// Distance of a point whose coordinates differ by `gaps` along each dimension.
+    pub fn combine(&self, gaps: impl Iterator<Item = i64>) -> Distance {
+        let dist = match self {
+            DistanceMetric::Euclidean => (gaps.map(|gap| i128::from(gap) * i128::from(gap)).sum::<i128>() as f64).sqrt(),
+            DistanceMetric::Manhattan => gaps.map(|gap| gap.abs()).sum::<i64>() as f64,
+            DistanceMetric::Chebyshev => gaps.map(|gap| gap.abs()).max().unwrap_or(0) as f64,
+        };
+        Distance(dist)
+    }
+
+    /// Distance between two keys over the same attributes.
+    pub fn distance(&self, key1: &[Field], key2: &[Field]) -> Distance {
+        self.combine(
+            key1.iter()
+                .zip(key2)
+                .map(|(val1, val2)| DistanceMetric::coordinate(val1) - DistanceMetric::coordinate(val2)),
+        )
+    }
+}
+
+/// A distance that can be ordered, so that it can be used as the key of a priority queue.
+#[derive(Copy, Clone, Debug)]
+pub struct Distance(pub f64);
+
+impl Ord for Distance {
+    fn cmp(&self, other: &Distance) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl PartialOrd for Distance {
+    fn partial_cmp(&self, other: &Distance) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Distance {
+    fn eq(&self, other: &Distance) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Distance {}
+
+impl Distance {
+    /// Units of a distance column per unit of distance.
+    pub const COLUMN_SCALE: f64 = 1000.0;
+
+    /// The `distance_milli` column appended to KNN and radius results. Fields have no floating
+    /// point type, so the column is an integer holding the distance in thousandths, rounded to the
+    /// nearest and capped at `i32::MAX`. Results can then be compared and sorted on it.
+    pub fn column() -> Attribute {
+        Attribute::new(String::from("distance_milli"), DataType::Int)
+    }
+
+    /// Value of the distance in a distance column.
+    pub fn to_field(&self) -> Field {
+        // casts from f64 saturate, so distances too large for the column are capped
+        Field::IntField((self.0 * Distance::COLUMN_SCALE).round() as i32)
+    }
+}
+
+impl fmt::Display for Distance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A record found by a KNN query. Neighbors are ordered by distance, then by key and record id,
+/// so every index returns the same neighbors in the same order.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Neighbor {
+    pub dist: Distance,
+    pub key: Vec<Field>,
+    pub rid: ValueId,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Field::{IntField, StringField};
+
+    #[test]
+    fn test_metrics() {
+        let a = vec![IntField(1), IntField(2)];
+        let b = vec![IntField(4), IntField(-2)];
+        assert_eq!(DistanceMetric::Euclidean.distance(&a, &b), Distance(5.0));
+        assert_eq!(DistanceMetric::Manhattan.distance(&a, &b), Distance(7.0));
+        assert_eq!(DistanceMetric::Chebyshev.distance(&a, &b), Distance(4.0));
+    }
+
+    #[test]
+    fn test_column() {
+        assert_eq!("distance_milli", Distance::column().name());
+        assert_eq!(DataType::Int, Distance::column().dtype);
+        assert_eq!(IntField(5000), Distance(5.0).to_field());
+        assert_eq!(IntField(2828), DistanceMetric::Euclidean.distance(&[IntField(0), IntField(0)], &[IntField(2), IntField(2)]).to_field());
+        assert_eq!(IntField(i32::MAX), Distance(1e12).to_field());
+    }
+
+    #[test]
+    fn test_no_overflow() {
+        let a = vec![IntField(i32::MIN), IntField(i32::MIN)];
+        let b = vec![IntField(i32::MAX), IntField(i32::MAX)];
+        let side = i32::MAX as f64 - i32::MIN as f64;
+        assert_eq!(DistanceMetric::Manhattan.distance(&a, &b), Distance(2.0 * side));
+        assert_eq!(DistanceMetric::Chebyshev.distance(&a, &b), Distance(side));
+        assert!(DistanceMetric::Euclidean.distance(&a, &b) > Distance(side));
+    }
+
+    #[test]
+    fn test_strings() {
+        let a = vec![StringField("apple".to_string())];
+        let b = vec![StringField("banana".to_string())];
+        let c = vec![StringField("cherry".to_string())];
+        assert!(DistanceMetric::Euclidean.distance(&a, &b) < DistanceMetric::Euclidean.distance(&a, &c));
+        assert_eq!(DistanceMetric::from_name("L1"), Some(DistanceMetric::Manhattan));
+        assert_eq!(DistanceMetric::from_name("hamming"), None);
+    }
+}
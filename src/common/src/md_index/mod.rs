@@ -1,4 +1,31 @@
+pub use index::{MdIndex, MdIndexStats, SerializedIndex};
 pub use kd_tree::KdTree;
+pub use metric::{Distance, DistanceMetric, Neighbor};
+pub use quad_tree::{QuadNode, QuadTree};
 pub use r_tree::R_Tree;
+pub use spatial::SpatialPredicate;
+pub use z_order::ZOrderIndex;
+mod index;
 mod kd_tree;
+mod metric;
+mod quad_tree;
 mod r_tree;
+mod spatial;
+mod z_order;
+
+use crate::ids::ValueId;
+use crate::Field;
+
+/// An entry of a multi-dimensional index: the values of the indexed attributes
+/// (in index order) and the id of the record they were taken from.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub key: Vec<Field>,
+    pub rid: ValueId,
+}
+
+impl IndexEntry {
+    pub fn new(key: Vec<Field>, rid: ValueId) -> Self {
+        IndexEntry { key, rid }
+    }
+}
@@ -0,0 +1,627 @@
+use std::any::Any;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use crate::{CrustyError, Field};
+use crate::ids::ValueId;
+use super::{Distance, DistanceMetric, IndexEntry, KdTree, MdIndex, MdIndexStats, Neighbor, SerializedIndex};
+
+/// Largest number of entries a leaf holds before it is split. Leaves whose entries all share
+/// one point are never split, since no split could tell them apart.
+const LEAF_CAPACITY: usize = 8;
+
+/// A node of a `QuadTree`. Inner nodes have one child per orthant of their region, numbered by
+/// setting bit `d` when the child covers the upper half along dimension `d`. Children are
+/// positions in `QuadTree::nodes`.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum QuadNode {
+    Leaf(Vec<IndexEntry>),
+    Inner(Vec<usize>),
+}
+
+/// Coordinates of a key. Dimensions past the number of indexed attributes stay at 0.
+type Point = [i64; QuadTree::MAX_DIM];
+
+/// Box of coordinates covered by a node, inclusive at both ends.
+#[derive(Clone, Copy, Debug)]
+struct Region {
+    lower: Point,
+    upper: Point,
+}
+
+impl Region {
+    // the root covers every coordinate an attribute can have
+    fn full(dim: usize) -> Self {
+        Region {
+            lower: std::array::from_fn(|d| if d < dim {i64::from(i32::MIN)} else {0}),
+            upper: std::array::from_fn(|d| if d < dim {i64::from(i32::MAX)} else {0}),
+        }
+    }
+
+    fn mid(&self, d: usize) -> i64 {
+        self.lower[d] + (self.upper[d] - self.lower[d]) / 2
+    }
+
+    fn orthant_of(&self, coords: &Point) -> usize {
+        (0..coords.len()).filter(|d| coords[*d] > self.mid(*d)).fold(0, |orthant, d| orthant | 1 << d)
+    }
+
+    fn child(&self, orthant: usize) -> Region {
+        let mut child = *self;
+        for d in 0..self.lower.len() {
+            if orthant & 1 << d == 0 {
+                child.upper[d] = self.mid(d);
+            }
+            else {
+                child.lower[d] = self.mid(d) + 1;
+            }
+        }
+        child
+    }
+
+    fn contains(&self, coords: &Point) -> bool {
+        (0..coords.len()).all(|d| self.lower[d] <= coords[d] && coords[d] <= self.upper[d])
+    }
+
+    fn overlaps(&self, other: &Region) -> bool {
+        (0..self.lower.len()).all(|d| self.lower[d] <= other.upper[d] && other.lower[d] <= self.upper[d])
+    }
+
+    // distance from the point to the closest point of the region
+    fn dist(&self, coords: &Point, metric: DistanceMetric) -> Distance {
+        metric.combine((0..coords.len()).map(|d| (self.lower[d] - coords[d]).max(coords[d] - self.upper[d]).max(0)))
+    }
+}
+
+fn coords(key: &[Field]) -> Point {
+    let mut coords = [0; QuadTree::MAX_DIM];
+    for (coord, val) in coords.iter_mut().zip(key) {
+        *coord = DistanceMetric::coordinate(val);
+    }
+    coords
+}
+
+fn should_split(entries: &[IndexEntry]) -> bool {
+    entries.len() > LEAF_CAPACITY && entries.iter().any(|entry| coords(&entry.key) != coords(&entries[0].key))
+}
+
+/// Point-region quadtree over two attributes, or octree over three. Each node covers a box of
+/// coordinates (see `DistanceMetric::coordinate`), and a leaf holding more than `LEAF_CAPACITY`
+/// entries is split at the middle of its box. The shape of the tree only depends on the entries
+/// it holds, not on the order they came in.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuadTree {
+    pub dim: usize, // number of indexed attributes
+    pub nodes: Vec<Option<QuadNode>>, // the nodes of the tree, the root at position 0
+    free: Vec<usize>, // positions left empty by merges, reused by later splits
+    pub idx_fields: Vec<usize>, // vector of indeces of attributes that are used for the index
+    pub total_dim: usize, // total number of attributes of each data point
+    pub size: usize, // number of entries in the tree
+}
+
+impl QuadTree {
+    /// Smallest number of attributes a quadtree can index.
+    pub const MIN_DIM: usize = 2;
+    /// Largest number of attributes a quadtree (then an octree) can index.
+    pub const MAX_DIM: usize = 3;
+
+    pub fn new(dim: usize, idx_fields: Vec<usize>, total_dim: usize) -> Self {
+        if !(QuadTree::MIN_DIM..=QuadTree::MAX_DIM).contains(&dim) {
+            panic!("Dimension not supported");
+        }
+        QuadTree {
+            dim,
+            nodes: vec![Some(QuadNode::Leaf(Vec::new()))],
+            free: Vec::new(),
+            idx_fields,
+            total_dim,
+            size: 0,
+        }
+    }
+
+    /// Take the values of the indexed attributes out of a full record.
+    pub fn get_key(&self, val: &[Field]) -> Vec<Field> {
+        self.idx_fields.iter().map(|idx| val[*idx].clone()).collect()
+    }
+
+    fn node(&self, node_idx: usize) -> &QuadNode {
+        self.nodes[node_idx].as_ref().expect("Quadtree links to a freed node")
+    }
+
+    fn alloc(&mut self, node: QuadNode) -> usize {
+        match self.free.pop() {
+            Some(node_idx) => {
+                self.nodes[node_idx] = Some(node);
+                node_idx
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    // turns the node into an inner node if it holds too many entries, splitting its children in turn
+    fn split(&mut self, node_idx: usize, region: &Region) {
+        let entries = match self.nodes[node_idx].take() {
+            Some(QuadNode::Leaf(entries)) if should_split(&entries) => entries,
+            node => {
+                self.nodes[node_idx] = node;
+                return
+            }
+        };
+        let mut orthants = vec![Vec::new(); 1 << self.dim];
+        for entry in entries {
+            orthants[region.orthant_of(&coords(&entry.key))].push(entry);
+        }
+        let children: Vec<usize> = orthants.into_iter().map(|entries| self.alloc(QuadNode::Leaf(entries))).collect();
+        self.nodes[node_idx] = Some(QuadNode::Inner(children.clone()));
+        for (orthant, child) in children.into_iter().enumerate() {
+            self.split(child, &region.child(orthant));
+        }
+    }
+
+    // positions of the nodes from the root down to the leaf whose region holds the point, with their regions
+    fn path_to(&self, coords: &Point) -> Vec<(usize, Region)> {
+        let mut path = vec![(0, Region::full(self.dim))];
+        loop {
+            let (node_idx, region) = path.last().unwrap();
+            let next = match self.node(*node_idx) {
+                QuadNode::Leaf(_) => return path,
+                QuadNode::Inner(children) => {
+                    let orthant = region.orthant_of(coords);
+                    (children[orthant], region.child(orthant))
+                }
+            };
+            path.push(next);
+        }
+    }
+
+    // children of an inner node that hold any entries, with the orthant they cover
+    fn nonempty_children<'a>(&'a self, children: &'a [usize]) -> impl Iterator<Item = (usize, usize)> + 'a {
+        children.iter().copied().enumerate().filter(|(_, child)| !matches!(self.node(*child), QuadNode::Leaf(entries) if entries.is_empty()))
+    }
+
+    fn leaf_entries(&self, node_idx: usize) -> &[IndexEntry] {
+        match self.node(node_idx) {
+            QuadNode::Leaf(entries) => entries,
+            QuadNode::Inner(_) => panic!("Quadtree node {} is not a leaf", node_idx),
+        }
+    }
+
+    /// Insert a record into the tree. `val` is the full record and `rid` is where it is stored.
+    pub fn insert(&mut self, val: &[Field], rid: ValueId) {
+        let key = self.get_key(val);
+        let (leaf_idx, region) = self.path_to(&coords(&key)).pop().unwrap();
+        if let Some(QuadNode::Leaf(entries)) = self.nodes[leaf_idx].as_mut() {
+            entries.push(IndexEntry::new(key, rid));
+        }
+        self.size += 1;
+        self.split(leaf_idx, &region);
+    }
+
+    /// Delete the entry of the record stored under `rid`. `val` is the full record. Inner nodes
+    /// left with few enough entries are merged back into a leaf.
+    pub fn delete(&mut self, val: &[Field], rid: ValueId) {
+        let entry = IndexEntry::new(self.get_key(val), rid);
+        let mut path = self.path_to(&coords(&entry.key));
+        let (leaf_idx, _) = path.pop().unwrap();
+        if let Some(QuadNode::Leaf(entries)) = self.nodes[leaf_idx].as_mut() {
+            match entries.iter().position(|other| *other == entry) {
+                Some(pos) => {
+                    entries.swap_remove(pos);
+                }
+                None => return,
+            }
+        }
+        self.size -= 1;
+        // a parent that can't be merged has too many entries for any of its ancestors to be merged
+        while let Some((node_idx, _)) = path.pop() {
+            let children = match self.node(node_idx) {
+                QuadNode::Inner(children) => children.clone(),
+                QuadNode::Leaf(_) => unreachable!(),
+            };
+            if children.iter().any(|child| matches!(self.node(*child), QuadNode::Inner(_))) {
+                break
+            }
+            let entries: Vec<IndexEntry> = children.iter().flat_map(|child| self.leaf_entries(*child).iter().cloned()).collect();
+            if should_split(&entries) {
+                break
+            }
+            for child in children {
+                self.nodes[child] = None;
+                self.free.push(child);
+            }
+            self.nodes[node_idx] = Some(QuadNode::Leaf(entries));
+        }
+    }
+
+    /// Get the ids of all records with the given key.
+    pub fn get(&self, key: &[Field]) -> Vec<ValueId> {
+        let (leaf_idx, _) = self.path_to(&coords(key)).pop().unwrap();
+        // strings sharing a prefix share a point, so the keys found are checked against the query
+        self.leaf_entries(leaf_idx).iter().filter(|entry| entry.key == key).map(|entry| entry.rid).collect()
+    }
+
+    pub fn search(&self, val: &[Field]) -> bool {
+        !self.get(val).is_empty()
+    }
+
+    fn range_query_helper(&self, min: &[Option<Field>], max: &[Option<Field>], bounds: &Region, node_idx: usize,
+        region: &Region, res: &mut Vec<ValueId>) {
+        match self.node(node_idx) {
+            QuadNode::Leaf(entries) => {
+                res.extend(entries.iter().filter(|entry| KdTree::if_within_bounds(&entry.key, min, max)).map(|entry| entry.rid));
+            }
+            QuadNode::Inner(children) => {
+                for (orthant, child) in self.nonempty_children(children) {
+                    let child_region = region.child(orthant);
+                    if child_region.overlaps(bounds) {
+                        self.range_query_helper(min, max, bounds, child, &child_region, res);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Get the ids of all records whose key lies in the box between `min` and `max` (inclusive).
+    pub fn range_query(&self, min: &[Field], max: &[Field]) -> Vec<ValueId> {
+        let min: Vec<Option<Field>> = min.iter().cloned().map(Some).collect();
+        let max: Vec<Option<Field>> = max.iter().cloned().map(Some).collect();
+        self.partial_range_query(&min, &max)
+    }
+
+    /// Get the ids of all records whose key lies between `min` and `max` (inclusive). The bounds
+    /// are given over the indexed attributes, and a `None` bound leaves its side of the attribute open.
+    pub fn partial_range_query(&self, min: &[Option<Field>], max: &[Option<Field>]) -> Vec<ValueId> {
+        // regions are pruned by the coordinates of the bounds, and the keys left are checked exactly
+        let full = Region::full(self.dim);
+        let bounds = Region {
+            lower: std::array::from_fn(|d| min.get(d).and_then(Option::as_ref).filter(|_| d < self.dim).map_or(full.lower[d], DistanceMetric::coordinate)),
+            upper: std::array::from_fn(|d| max.get(d).and_then(Option::as_ref).filter(|_| d < self.dim).map_or(full.upper[d], DistanceMetric::coordinate)),
+        };
+        let mut res = Vec::new();
+        self.range_query_helper(min, max, &bounds, 0, &full, &mut res);
+        res
+    }
+
+    /// Get the ids of the `k` records whose keys are closest to `val` in euclidean distance.
+    pub fn knn(&self, val: &[Field], k: usize) -> Vec<ValueId> {
+        self.knn_with_metric(val, k, DistanceMetric::Euclidean).into_iter().map(|neighbor| neighbor.rid).collect()
+    }
+
+    /// Get the `k` records whose keys are closest to `key`, closest first.
+    pub fn knn_with_metric(&self, key: &[Field], k: usize, metric: DistanceMetric) -> Vec<Neighbor> {
+        let point = coords(key);
+        // best-first search: nodes and entries are visited in order of their distance to the key,
+        // nodes before entries at the same distance so that ties among entries go by key and record id
+        let mut regions = vec![(0, Region::full(self.dim))];
+        let mut heap = BinaryHeap::new();
+        heap.push(Reverse((Distance(0.0), None, 0)));
+        let mut res = Vec::new();
+        while let Some(Reverse((dist, entry, pos))) = heap.pop() {
+            if res.len() >= k {
+                break
+            }
+            if let Some(IndexEntry {key, rid}) = entry {
+                res.push(Neighbor {dist, key, rid});
+                continue
+            }
+            let (node_idx, region) = regions[pos];
+            match self.node(node_idx) {
+                QuadNode::Leaf(entries) => {
+                    for entry in entries {
+                        heap.push(Reverse((metric.distance(key, &entry.key), Some(entry.clone()), 0)));
+                    }
+                }
+                QuadNode::Inner(children) => {
+                    for (orthant, child) in self.nonempty_children(children) {
+                        let child_region = region.child(orthant);
+                        heap.push(Reverse((child_region.dist(&point, metric), None, regions.len())));
+                        regions.push((child, child_region));
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    /// Get the records whose keys are within `radius` of `center`, closest first.
+    pub fn radius_query(&self, center: &[Field], radius: f64, metric: DistanceMetric) -> Vec<Neighbor> {
+        let point = coords(center);
+        let mut stack = vec![(0, Region::full(self.dim))];
+        let mut res = Vec::new();
+        while let Some((node_idx, region)) = stack.pop() {
+            match self.node(node_idx) {
+                QuadNode::Leaf(entries) => {
+                    for entry in entries {
+                        let dist = metric.distance(center, &entry.key);
+                        if dist.0 <= radius {
+                            res.push(Neighbor {dist, key: entry.key.clone(), rid: entry.rid});
+                        }
+                    }
+                }
+                QuadNode::Inner(children) => {
+                    for (orthant, child) in self.nonempty_children(children) {
+                        let child_region = region.child(orthant);
+                        if child_region.dist(&point, metric).0 <= radius {
+                            stack.push((child, child_region));
+                        }
+                    }
+                }
+            }
+        }
+        res.sort();
+        res
+    }
+
+    // lays out a subtree holding `entries` and returns the position of its root
+    fn build(&mut self, entries: Vec<IndexEntry>, region: &Region) -> usize {
+        if !should_split(&entries) {
+            return self.alloc(QuadNode::Leaf(entries));
+        }
+        let mut orthants = vec![Vec::new(); 1 << self.dim];
+        for entry in entries {
+            orthants[region.orthant_of(&coords(&entry.key))].push(entry);
+        }
+        let node_idx = self.alloc(QuadNode::Inner(Vec::new()));
+        let children = orthants.into_iter().enumerate().map(|(orthant, entries)| self.build(entries, &region.child(orthant))).collect();
+        self.nodes[node_idx] = Some(QuadNode::Inner(children));
+        node_idx
+    }
+
+    /// Bulk load the tree from full records and the ids they are stored under. The tree is laid
+    /// out again over the entries it already holds and the new ones.
+    pub fn data_into_tree(&mut self, arr: &[(Vec<Field>, ValueId)]) {
+        let mut entries = self.get_all_entries();
+        entries.extend(arr.iter().map(|(val, rid)| IndexEntry::new(self.get_key(val), *rid)));
+        self.nodes.clear();
+        self.free.clear();
+        self.size = entries.len();
+        self.build(entries, &Region::full(self.dim));
+    }
+
+    pub fn get_all_entries(&self) -> Vec<IndexEntry> {
+        self.nodes.iter().flatten().flat_map(|node| match node {
+            QuadNode::Leaf(entries) => entries.clone(),
+            QuadNode::Inner(_) => Vec::new(),
+        }).collect()
+    }
+
+    /// Number of entries in the tree.
+    pub fn num_entries(&self) -> usize {
+        self.size
+    }
+
+    /// Number of levels from the root down to the deepest leaf.
+    pub fn depth(&self) -> usize {
+        if self.size == 0 {
+            return 0
+        }
+        let mut depth = 0;
+        let mut level = vec![0];
+        while !level.is_empty() {
+            depth += 1;
+            level = level.iter().flat_map(|node_idx| match self.node(*node_idx) {
+                QuadNode::Leaf(_) => Vec::new(),
+                QuadNode::Inner(children) => children.clone(),
+            }).collect();
+        }
+        depth
+    }
+
+    /// Describe every place the tree breaks its invariants: entries outside the region of their
+    /// leaf, leaves that should have been split or inner nodes that should have been merged,
+    /// links to freed or shared nodes, and a size that does not match the entries.
+    pub fn check_invariants(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut visited = vec![false; self.nodes.len()];
+        let mut count = 0;
+        // each node is checked with the entries of its subtree, gathered on the way back up
+        fn check(tree: &QuadTree, node_idx: usize, region: &Region, visited: &mut [bool], count: &mut usize,
+            problems: &mut Vec<String>) -> Vec<IndexEntry> {
+            if tree.nodes.get(node_idx).is_none_or(Option::is_none) {
+                problems.push(format!("Node {} is linked to but is empty", node_idx));
+                return Vec::new();
+            }
+            if visited[node_idx] {
+                problems.push(format!("Node {} is linked to more than once", node_idx));
+                return Vec::new();
+            }
+            visited[node_idx] = true;
+            match tree.node(node_idx) {
+                QuadNode::Leaf(entries) => {
+                    *count += entries.len();
+                    for entry in entries {
+                        if !region.contains(&coords(&entry.key)) {
+                            problems.push(format!("Entry {:?} of node {} lies outside of its region", entry.key, node_idx));
+                        }
+                    }
+                    if should_split(entries) {
+                        problems.push(format!("Leaf {} holds {} entries and should be split", node_idx, entries.len()));
+                    }
+                    entries.clone()
+                }
+                QuadNode::Inner(children) => {
+                    if children.len() != 1 << tree.dim {
+                        problems.push(format!("Node {} has {} children", node_idx, children.len()));
+                    }
+                    let entries: Vec<IndexEntry> = children.iter().enumerate()
+                        .flat_map(|(orthant, child)| check(tree, *child, &region.child(orthant), visited, count, problems))
+                        .collect();
+                    if !should_split(&entries) {
+                        problems.push(format!("Node {} holds {} entries and should be a leaf", node_idx, entries.len()));
+                    }
+                    entries
+                }
+            }
+        }
+        check(self, 0, &Region::full(self.dim), &mut visited, &mut count, &mut problems);
+        if count != self.size {
+            problems.push(format!("Tree holds {} entries but its size is {}", count, self.size));
+        }
+        problems
+    }
+}
+
+impl MdIndex for QuadTree {
+    fn tree_type(&self) -> &'static str {
+        "QUAD"
+    }
+
+    fn idx_fields(&self) -> Vec<usize> {
+        self.idx_fields.clone()
+    }
+
+    fn insert(&mut self, val: &[Field], rid: ValueId) -> Result<(), CrustyError> {
+        QuadTree::insert(self, val, rid);
+        Ok(())
+    }
+
+    fn delete(&mut self, val: &[Field], rid: ValueId) -> Result<(), CrustyError> {
+        QuadTree::delete(self, val, rid);
+        Ok(())
+    }
+
+    fn bulk_load(&mut self, arr: &[(Vec<Field>, ValueId)]) -> Result<(), CrustyError> {
+        self.data_into_tree(arr);
+        Ok(())
+    }
+
+    fn point_query(&self, key: &[Field]) -> Result<Vec<ValueId>, CrustyError> {
+        Ok(self.get(key))
+    }
+
+    fn range_query(&self, min: &[Option<Field>], max: &[Option<Field>]) -> Result<Vec<ValueId>, CrustyError> {
+        Ok(self.partial_range_query(min, max))
+    }
+
+    fn knn_query(&self, key: &[Field], k: usize, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        Ok(self.knn_with_metric(key, k, metric))
+    }
+
+    fn radius_query(&self, center: &[Field], radius: f64, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        Ok(QuadTree::radius_query(self, center, radius, metric))
+    }
+
+    fn stats(&self) -> Result<MdIndexStats, CrustyError> {
+        Ok(MdIndexStats {num_entries: self.num_entries(), depth: self.depth()})
+    }
+
+    fn serialize(&self, name: &str) -> SerializedIndex {
+        SerializedIndex::new(self, name, self.total_dim, self)
+    }
+
+    fn check_invariants(&self) -> Vec<String> {
+        QuadTree::check_invariants(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Field::{IntField, StringField};
+
+    fn rid(i: usize) -> ValueId {
+        ValueId::new_slot(0, (i / 100) as u16, (i % 100) as u16)
+    }
+
+    // points spread over a small grid so that many of them share coordinates
+    fn records(n: usize, dim: usize) -> Vec<(Vec<Field>, ValueId)> {
+        (0..n).map(|i| ((0..dim).map(|d| IntField(((i * (7 + 6 * d) + d) % 31) as i32 - 15)).collect(), rid(i))).collect()
+    }
+
+    fn sorted(mut rids: Vec<ValueId>) -> Vec<ValueId> {
+        rids.sort();
+        rids
+    }
+
+    #[test]
+    fn test_split_and_merge() {
+        let arr = records(200, 2);
+        let mut tree = QuadTree::new(2, vec![0, 1], 2);
+        for (val, rid) in &arr {
+            tree.insert(val, *rid);
+        }
+        assert!(tree.check_invariants().is_empty());
+        assert!(tree.depth() > 1);
+        let mut loaded = QuadTree::new(2, vec![0, 1], 2);
+        loaded.data_into_tree(&arr);
+        assert_eq!(loaded.depth(), tree.depth());
+        let serialized = serde_json::to_string(&tree).unwrap();
+        assert!(serde_json::from_str::<QuadTree>(&serialized).unwrap() == tree);
+        for (val, rid) in &arr[LEAF_CAPACITY..] {
+            tree.delete(val, *rid);
+            assert!(tree.check_invariants().is_empty());
+        }
+        assert_eq!(tree.num_entries(), LEAF_CAPACITY);
+        assert_eq!(tree.depth(), 1);
+        // deleting a missing entry leaves the tree as is
+        tree.delete(&arr[199].0, arr[199].1);
+        assert_eq!(tree.num_entries(), LEAF_CAPACITY);
+    }
+
+    #[test]
+    fn test_duplicates_stay_in_one_leaf() {
+        let mut tree = QuadTree::new(2, vec![0, 1], 2);
+        for i in 0..3 * LEAF_CAPACITY {
+            tree.insert(&[IntField(4), IntField(-4)], rid(i));
+        }
+        assert_eq!(tree.depth(), 1);
+        assert_eq!(tree.get(&[IntField(4), IntField(-4)]).len(), 3 * LEAF_CAPACITY);
+        tree.insert(&[IntField(4), IntField(-3)], rid(100));
+        assert!(tree.depth() > 1);
+        assert!(tree.check_invariants().is_empty());
+    }
+
+    #[test]
+    fn test_agrees_with_kd_tree() {
+        for dim in [2, 3] {
+            let arr = records(300, dim);
+            let mut quad = QuadTree::new(dim, (0..dim).collect(), dim);
+            let mut kd = KdTree::new(dim, (0..dim).collect(), dim);
+            quad.data_into_tree(&arr[..250]);
+            kd.data_into_tree(&arr[..250]);
+            for (val, rid) in &arr[250..] {
+                quad.insert(val, *rid);
+                kd.insert(val, *rid);
+            }
+            for (val, rid) in arr.iter().step_by(3) {
+                quad.delete(val, *rid);
+                kd.delete(val, *rid);
+            }
+            assert!(quad.check_invariants().is_empty());
+            assert_eq!(quad.num_entries(), kd.num_entries());
+            for (val, _) in arr.iter().take(20) {
+                assert_eq!(sorted(quad.get(val)), sorted(kd.get(val)));
+            }
+            let min = vec![Some(IntField(-5)), None, Some(IntField(0))];
+            let max = vec![Some(IntField(8)), Some(IntField(2))];
+            assert_eq!(sorted(quad.partial_range_query(&min, &max)), sorted(kd.partial_range_query(&min, &max)));
+            let center = vec![IntField(3); dim];
+            for metric in [DistanceMetric::Euclidean, DistanceMetric::Manhattan, DistanceMetric::Chebyshev] {
+                assert_eq!(quad.knn_with_metric(&center, 12, metric), kd.knn_with_metric(&center, 12, metric));
+                assert_eq!(quad.radius_query(&center, 6.0, metric), kd.radius_query(&center, 6.0, metric));
+            }
+        }
+    }
+
+    #[test]
+    fn test_strings() {
+        let names = ["apple", "apricot", "banana", "blueberry", "cherry", "date", "fig", "grape", "kiwi", "lemon"];
+        let arr: Vec<(Vec<Field>, ValueId)> = (0..100)
+            .map(|i| (vec![StringField(names[i % 10].to_string()), IntField((i / 10) as i32)], rid(i)))
+            .collect();
+        let mut tree = QuadTree::new(2, vec![0, 1], 2);
+        tree.data_into_tree(&arr);
+        assert_eq!(sorted(tree.get(&[StringField("apple".to_string()), IntField(3)])), vec![rid(30)]);
+        assert!(tree.get(&[StringField("appl".to_string()), IntField(3)]).is_empty());
+        let res = tree.range_query(&[StringField("b".to_string()), IntField(0)], &[StringField("c".to_string()), IntField(1)]);
+        assert_eq!(sorted(res), vec![rid(2), rid(3), rid(12), rid(13)]);
+        let knn = tree.knn(&[StringField("date".to_string()), IntField(9)], 1);
+        assert_eq!(knn, vec![rid(95)]);
+    }
+}
@@ -1,237 +1,503 @@
-use std::cmp::Ordering::{Less, Greater, Equal};
-use std::any::type_name;
-use crate::Tuple;
-use crate::Field;
-use std::cmp::Ordering;
-use crate::Field::{IntField, StringField};
-use rstar::{RTree, RTreeObject, AABB, PointDistance};
-
-#[derive(Clone, PartialEq, PartialOrd)]
-pub struct Tuple2d {
-    pub idx_fields: Vec<usize>,
-    vals: Vec<Field>,
-}
-
-impl RTreeObject for Tuple2d {
-    type Envelope = AABB<[i32; 2]>;
-    fn envelope(&self) -> Self::Envelope
-    {
-        AABB::from_point([self.vals[self.idx_fields[0]].unwrap_int_field(), self.vals[self.idx_fields[1]].unwrap_int_field()])
-    }    
-}
+use crate::{CrustyError, Field};
+use crate::ids::ValueId;
+use rstar::{Point, RTree, RTreeNode, RTreeObject, AABB, PointDistance};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::any::Any;
+use super::{Distance, DistanceMetric, IndexEntry, KdTree, MdIndex, MdIndexStats, Neighbor, SerializedIndex, SpatialPredicate};
 
-#[derive(Clone)]
-pub struct RTree2d {
-    pub r_tree: RTree<Tuple2d>,
-    pub idx_fields: Vec<usize>,
-    total_dim: usize,
+/// Entry of an R-tree over `N` indexed attributes.
+#[derive(Clone, PartialEq, Debug)]
+pub struct RTuple<const N: usize> {
+    pub key: Vec<Field>,
+    pub rid: ValueId,
 }
 
-impl PointDistance for Tuple2d
+impl<const N: usize> RTreeObject for RTuple<N>
+where
+    [i32; N]: Point<Scalar = i32>,
 {
-    fn distance_2(&self, point: &[i32; 2]) -> i32
+    type Envelope = AABB<[i32; N]>;
+    fn envelope(&self) -> Self::Envelope
     {
-        let d_x = self.vals[self.idx_fields[0]].unwrap_int_field() - point[0];
-        let d_y = self.vals[self.idx_fields[1]].unwrap_int_field() - point[1];
-        d_x.pow(2) + d_y.pow(2)
+        AABB::from_point(R_Tree::val_to_i32(&self.key))
     }
 }
 
-#[derive(Clone, PartialEq, PartialOrd)]
-pub struct Tuple3d {
-    pub idx_fields: Vec<usize>,
-    vals: Vec<Field>,
-}
-
-impl RTreeObject for Tuple3d {
-    type Envelope = AABB<[i32; 3]>;
-    fn envelope(&self) -> Self::Envelope
+impl<const N: usize> PointDistance for RTuple<N>
+where
+    [i32; N]: Point<Scalar = i32>,
+{
+    fn distance_2(&self, point: &[i32; N]) -> i32
     {
-        AABB::from_point([self.vals[self.idx_fields[0]].unwrap_int_field(), self.vals[self.idx_fields[1]].unwrap_int_field(), self.vals[self.idx_fields[2]].unwrap_int_field()])
-    }    
+        // with many dimensions the sum can get past i32, so it saturates instead
+        let mut res: i32 = 0;
+        for (val, coord) in self.key.iter().zip(point) {
+            let diff = R_Tree::field_to_i32(val).saturating_sub(*coord);
+            res = res.saturating_add(diff.saturating_mul(diff));
+        }
+        res
+    }
 }
 
+/// R-tree over `N` indexed attributes.
 #[derive(Clone)]
-pub struct RTree3d {
-    pub r_tree: RTree<Tuple3d>,
+pub struct RTreeNd<const N: usize>
+where
+    [i32; N]: Point<Scalar = i32>,
+{
+    pub r_tree: RTree<RTuple<N>>,
     pub idx_fields: Vec<usize>,
     total_dim: usize,
 }
 
-impl PointDistance for Tuple3d
+impl<const N: usize> RTreeNd<N>
+where
+    [i32; N]: Point<Scalar = i32>,
 {
-    fn distance_2(&self, point: &[i32; 3]) -> i32
-    {
-        let d_x = self.vals[self.idx_fields[0]].unwrap_int_field() - point[0];
-        let d_y = self.vals[self.idx_fields[1]].unwrap_int_field() - point[1];
-        let d_z = self.vals[self.idx_fields[2]].unwrap_int_field() - point[2];
-        d_x.pow(2) + d_y.pow(2)+ d_z.pow(2)
+    fn new(idx_fields: Vec<usize>, total_dim: usize) -> Self {
+        RTreeNd {r_tree: RTree::new(), idx_fields, total_dim}
     }
-}
 
-pub enum R_Tree {
-    Dim2(RTree2d),
-    Dim3(RTree3d),
-}
+    // strings sharing a prefix share a point, so the keys found are checked against the query
+    fn get(&self, key: &[Field]) -> Vec<ValueId> {
+        self.r_tree
+            .locate_all_at_point(&R_Tree::val_to_i32(key))
+            .filter(|tuple| tuple.key == key)
+            .map(|tuple| tuple.rid)
+            .collect()
+    }
 
-impl R_Tree {
+    // a missing bound spans the whole axis
+    fn range_query(&self, min: &[Option<Field>], max: &[Option<Field>]) -> Vec<ValueId> {
+        let mut lower = [i32::MIN; N];
+        let mut upper = [i32::MAX; N];
+        for (i, (lower, upper)) in lower.iter_mut().zip(upper.iter_mut()).enumerate() {
+            if let Some(min) = min.get(i).and_then(Option::as_ref) {
+                *lower = R_Tree::field_to_i32(min);
+            }
+            if let Some(max) = max.get(i).and_then(Option::as_ref) {
+                *upper = R_Tree::field_to_i32(max);
+            }
+        }
+        self.r_tree
+            .locate_in_envelope(&AABB::from_corners(lower, upper))
+            .filter(|tuple| KdTree::if_within_bounds(&tuple.key, min, max))
+            .map(|tuple| tuple.rid)
+            .collect()
+    }
 
-    fn copy_single_val(val: &Field) -> Field {
-        match val {
-            IntField(x) => {IntField(*x)},
-            StringField(x) => {StringField(x.to_string())},
-        }        
+    // distance from the point to the closest point of the envelope
+    fn envelope_dist(envelope: &AABB<[i32; N]>, point: &[i32; N], metric: DistanceMetric) -> Distance {
+        let (lower, upper) = (envelope.lower(), envelope.upper());
+        metric.combine((0..N).map(|i| {
+            let coord = i64::from(point[i]);
+            (i64::from(lower[i]) - coord).max(coord - i64::from(upper[i])).max(0)
+        }))
     }
 
-    fn get_new_copy(val: &Vec<Field>, total_dim: usize) -> Vec<Field> {
-        let mut new_copy = Vec::new();
-        for i in 0..val.len() {
-            new_copy.push(R_Tree::copy_single_val(&val[i]));
+    // subtrees whose envelope is farther than the radius are skipped
+    fn radius_query(&self, center: &[Field], radius: f64, metric: DistanceMetric) -> Vec<Neighbor> {
+        let point: [i32; N] = R_Tree::val_to_i32(center);
+        let mut stack = vec![self.r_tree.root()];
+        let mut res = Vec::new();
+        while let Some(node) = stack.pop() {
+            for child in node.children() {
+                match child {
+                    RTreeNode::Leaf(tuple) => {
+                        let dist = metric.distance(center, &tuple.key);
+                        if dist.0 <= radius {
+                            res.push(Neighbor {dist, key: tuple.key.clone(), rid: tuple.rid});
+                        }
+                    }
+                    RTreeNode::Parent(parent) => {
+                        if RTreeNd::envelope_dist(&parent.envelope(), &point, metric).0 <= radius {
+                            stack.push(parent);
+                        }
+                    }
+                }
+            }
         }
-        return new_copy
+        res.sort();
+        res
     }
 
-    pub fn new(dim: usize, idx_fields: Vec<usize>, total_dim: usize) -> Self {
-        if dim == 2 {
-            R_Tree::Dim2(RTree2d {r_tree: RTree::new(), idx_fields, total_dim})
+    fn node_children(node: &RTreeNode<RTuple<N>>) -> &[RTreeNode<RTuple<N>>] {
+        match node {
+            RTreeNode::Leaf(_) => &[],
+            RTreeNode::Parent(parent) => parent.children(),
         }
-        else if dim == 3 {
-            R_Tree::Dim3(RTree3d {r_tree: RTree::new(), idx_fields, total_dim})
+    }
+
+    fn envelope_coords(envelope: &AABB<[i32; N]>) -> (Vec<i64>, Vec<i64>) {
+        (envelope.lower().iter().map(|coord| i64::from(*coord)).collect(),
+         envelope.upper().iter().map(|coord| i64::from(*coord)).collect())
+    }
+
+    // synchronized traversal: both trees are walked down together, and a pair of subtrees is
+    // skipped when no point of one envelope can match a point of the other
+    fn spatial_join(&self, other: &RTreeNd<N>, predicate: &SpatialPredicate) -> Vec<(ValueId, ValueId)> {
+        let may_match = |node1: &RTreeNode<RTuple<N>>, node2: &RTreeNode<RTuple<N>>| {
+            let (lower1, upper1) = RTreeNd::envelope_coords(&node1.envelope());
+            let (lower2, upper2) = RTreeNd::envelope_coords(&node2.envelope());
+            predicate.boxes_may_match(&lower1, &upper1, &lower2, &upper2)
+        };
+        let mut stack = Vec::new();
+        for node1 in self.r_tree.root().children() {
+            for node2 in other.r_tree.root().children() {
+                if may_match(node1, node2) {
+                    stack.push((node1, node2));
+                }
+            }
         }
-        else {
-            panic!("Dimension not supported")
+        let mut res = Vec::new();
+        while let Some((node1, node2)) = stack.pop() {
+            match (node1, node2) {
+                (RTreeNode::Leaf(tuple1), RTreeNode::Leaf(tuple2)) => {
+                    if predicate.matches(&tuple1.key, &tuple2.key) {
+                        res.push((tuple1.rid, tuple2.rid));
+                    }
+                }
+                // a leaf is paired with the children of the other node
+                (RTreeNode::Leaf(_), _) => {
+                    stack.extend(RTreeNd::node_children(node2).iter().filter(|child| may_match(node1, child)).map(|child| (node1, child)));
+                }
+                (_, RTreeNode::Leaf(_)) => {
+                    stack.extend(RTreeNd::node_children(node1).iter().filter(|child| may_match(child, node2)).map(|child| (child, node2)));
+                }
+                _ => {
+                    for child1 in RTreeNd::node_children(node1) {
+                        for child2 in RTreeNd::node_children(node2) {
+                            if may_match(child1, child2) {
+                                stack.push((child1, child2));
+                            }
+                        }
+                    }
+                }
+            }
         }
+        res
     }
-    
-    pub fn insert(&mut self, val: &Vec<Field>) {
-        match self {
-            R_Tree::Dim2(tree) => tree.r_tree.insert(Tuple2d {idx_fields: tree.idx_fields.clone(), vals: R_Tree::get_new_copy(val, tree.total_dim)}),
-            R_Tree::Dim3(tree) => tree.r_tree.insert(Tuple3d {idx_fields: tree.idx_fields.clone(), vals: R_Tree::get_new_copy(val, tree.total_dim)}),
+
+    // best-first search: nodes and entries are visited in order of their distance to the key,
+    // nodes before entries at the same distance so that ties among entries go by key and record id
+    fn knn(&self, key: &[Field], k: usize, metric: DistanceMetric) -> Vec<Neighbor> {
+        let point: [i32; N] = R_Tree::val_to_i32(key);
+        let mut nodes = vec![self.r_tree.root()];
+        let mut heap = BinaryHeap::new();
+        heap.push(Reverse((Distance(0.0), None, 0)));
+        let mut res = Vec::new();
+        while let Some(Reverse((dist, entry, node_idx))) = heap.pop() {
+            if res.len() >= k {
+                break
+            }
+            if let Some(IndexEntry {key, rid}) = entry {
+                res.push(Neighbor {dist, key, rid});
+                continue
+            }
+            for child in nodes[node_idx].children() {
+                match child {
+                    RTreeNode::Leaf(tuple) => {
+                        let entry = IndexEntry::new(tuple.key.clone(), tuple.rid);
+                        heap.push(Reverse((metric.distance(key, &tuple.key), Some(entry), 0)));
+                    }
+                    RTreeNode::Parent(parent) => {
+                        heap.push(Reverse((RTreeNd::envelope_dist(&parent.envelope(), &point, metric), None, nodes.len())));
+                        nodes.push(parent);
+                    }
+                }
+            }
         }
+        res
+    }
+
+    // packs the leaves in sort-tile order (rstar's OMT load); entries already in the tree are
+    // packed along with the new ones rather than dropped
+    fn bulk_load(&mut self, entries: Vec<IndexEntry>) {
+        let mut tuples: Vec<RTuple<N>> = self.r_tree.iter().cloned().collect();
+        tuples.extend(entries.into_iter().map(|entry| RTuple {key: entry.key, rid: entry.rid}));
+        self.r_tree = RTree::bulk_load(tuples)
     }
 
-    pub fn search(&mut self, val: &Vec<Field>) -> bool {
-        match self {
-            R_Tree::Dim2(tree) => tree.r_tree.contains(&Tuple2d {idx_fields: tree.idx_fields.clone(), vals: R_Tree::get_new_copy(val, tree.total_dim)}),
-            R_Tree::Dim3(tree) => tree.r_tree.contains(&Tuple3d {idx_fields: tree.idx_fields.clone(), vals: R_Tree::get_new_copy(val, tree.total_dim)}), 
-        }       
+    fn entries(&self) -> Vec<IndexEntry> {
+        self.r_tree.iter().map(|tuple| IndexEntry::new(tuple.key.clone(), tuple.rid)).collect()
     }
 
-    pub fn val_to_i32_2d(val:&Vec<Field>) -> [i32;2] {
-        let mut arr: [i32; 2] = [0;2];
-        let mut i = 0;
-        for single_field in val {
-            arr[i] = single_field.unwrap_int_field();
-            i += 1;
+    // every leaf of an R-tree is at the same depth, so the first child is followed down
+    fn depth(&self) -> usize {
+        if self.r_tree.size() == 0 {
+            return 0
+        }
+        let mut depth = 1;
+        let mut node = self.r_tree.root();
+        while let Some(RTreeNode::Parent(parent)) = node.children().first() {
+            depth += 1;
+            node = parent;
         }
-        return arr;
+        depth
     }
+}
 
-    pub fn val_to_i32_3d(val:&Vec<Field>) -> [i32;3] {
-        let mut arr: [i32; 3] = [0;3];
-        let mut i = 0;
-        for single_field in val {
-            arr[i] = single_field.unwrap_int_field();
-            i += 1;
+/// R-tree index over `R_Tree::MIN_DIM` to `R_Tree::MAX_DIM` attributes.
+pub enum R_Tree {
+    Dim2(RTreeNd<2>),
+    Dim3(RTreeNd<3>),
+    Dim4(RTreeNd<4>),
+    Dim5(RTreeNd<5>),
+    Dim6(RTreeNd<6>),
+    Dim7(RTreeNd<7>),
+    Dim8(RTreeNd<8>),
+    Dim9(RTreeNd<9>),
+}
+
+// runs the same code on the tree of whatever dimension is inside the enum
+macro_rules! on_tree {
+    ($r_tree:expr, $tree:ident => $body:expr) => {
+        match $r_tree {
+            R_Tree::Dim2($tree) => $body,
+            R_Tree::Dim3($tree) => $body,
+            R_Tree::Dim4($tree) => $body,
+            R_Tree::Dim5($tree) => $body,
+            R_Tree::Dim6($tree) => $body,
+            R_Tree::Dim7($tree) => $body,
+            R_Tree::Dim8($tree) => $body,
+            R_Tree::Dim9($tree) => $body,
+        }
+    };
+}
+
+impl R_Tree {
+    /// Smallest number of attributes an R-tree can index.
+    pub const MIN_DIM: usize = 2;
+    /// Largest number of attributes an R-tree can index.
+    pub const MAX_DIM: usize = 9;
+
+    pub fn new(dim: usize, idx_fields: Vec<usize>, total_dim: usize) -> Self {
+        match dim {
+            2 => R_Tree::Dim2(RTreeNd::new(idx_fields, total_dim)),
+            3 => R_Tree::Dim3(RTreeNd::new(idx_fields, total_dim)),
+            4 => R_Tree::Dim4(RTreeNd::new(idx_fields, total_dim)),
+            5 => R_Tree::Dim5(RTreeNd::new(idx_fields, total_dim)),
+            6 => R_Tree::Dim6(RTreeNd::new(idx_fields, total_dim)),
+            7 => R_Tree::Dim7(RTreeNd::new(idx_fields, total_dim)),
+            8 => R_Tree::Dim8(RTreeNd::new(idx_fields, total_dim)),
+            9 => R_Tree::Dim9(RTreeNd::new(idx_fields, total_dim)),
+            _ => panic!("Dimension not supported"),
         }
-        return arr;
     }
 
-    pub fn get(&mut self, val: &Vec<Field>) -> Vec<Vec<Field>> {
-        match self {
-            R_Tree::Dim2(tree) => {
-                let mut res_iter = tree.r_tree.locate_all_at_point(&R_Tree::val_to_i32_2d(val));
-                let mut res = Vec::new();
-                while let Some(tuple) = res_iter.next() {
-                    res.push(tuple.vals.clone());
-                }
-                return res;
-                },
-            R_Tree::Dim3(tree) => {
-                let mut res_iter = tree.r_tree.locate_all_at_point(&R_Tree::val_to_i32_3d(val));
-                let mut res = Vec::new();
-                while let Some(tuple) = res_iter.next() {
-                    res.push(tuple.vals.clone());
-                }
-                return res;
-                }, 
-        }       
-    }
-
-    pub fn knn(&mut self, val:&Vec<Field>, k: usize) -> Vec<Vec<Field>> {
-        
-        match self {
-            R_Tree::Dim2(tree) => {
-                                    let mut heap_iter = tree.r_tree.nearest_neighbor_iter(&R_Tree::val_to_i32_2d(val));
-                                    let mut res = Vec::new();
-                                    for i in 0..k {
-                                        let neighbor = heap_iter.next();
-                                        if neighbor.is_none() {
-                                            return res;
-                                        }
-                                        else {
-                                            res.push(neighbor.unwrap().vals.clone());
-                                        }
-                                    }
-                                    return res;
-                                    },
-            R_Tree::Dim3(tree) => {let mut heap_iter = tree.r_tree.nearest_neighbor_iter(&R_Tree::val_to_i32_3d(val));
-                let mut res = Vec::new();
-                for i in 0..k {
-                    let neighbor = heap_iter.next();
-                    if neighbor.is_none() {
-                        return res;
-                    }
-                    else {
-                        res.push(neighbor.unwrap().vals.clone());
-                    }
+    /// Take the values of the indexed attributes out of a full record.
+    pub fn get_key(&self, val: &[Field]) -> Vec<Field> {
+        self.get_idx_fields().iter().map(|idx| val[*idx].clone()).collect()
+    }
+
+    /// Insert a record into the tree. `val` is the full record and `rid` is where it is stored.
+    pub fn insert(&mut self, val: &[Field], rid: ValueId) {
+        let key = self.get_key(val);
+        on_tree!(self, tree => tree.r_tree.insert(RTuple {key, rid}))
+    }
+
+    pub fn search(&self, val: &[Field]) -> bool {
+        !self.get(val).is_empty()
+    }
+
+    /// Coordinate of an attribute in the tree. Strings are mapped to their first four bytes,
+    /// which keeps their order but puts strings with the same prefix at the same coordinate.
+    pub fn field_to_i32(field: &Field) -> i32 {
+        match field {
+            Field::IntField(x) => *x,
+            Field::StringField(s) => {
+                let mut prefix = [0u8; 4];
+                for (byte, val) in prefix.iter_mut().zip(s.bytes()) {
+                    *byte = val;
                 }
-                return res;
-            },
-        }    
+                // flipping the sign bit turns the unsigned order into the signed one
+                (u32::from_be_bytes(prefix) ^ 0x8000_0000) as i32
+            }
+        }
     }
 
-    pub fn data_into_tree(&mut self, arr: &mut [Vec<Field>]) {
-        let len_arr = arr.len();
-        if len_arr == 0 {
-            return
+    pub fn val_to_i32<const N: usize>(val: &[Field]) -> [i32; N] {
+        let mut arr: [i32; N] = [0; N];
+        for (i, single_field) in val.iter().enumerate() {
+            arr[i] = R_Tree::field_to_i32(single_field);
         }
-        let mut vec_arr_2d = Vec::new();
-        let mut vec_arr_3d = Vec::new();
-        for tuple in arr {
-            match self {
-                R_Tree::Dim2(tree) => vec_arr_2d.push(Tuple2d {idx_fields: tree.idx_fields.clone(), vals: tuple.clone()}),
-                R_Tree::Dim3(tree) => vec_arr_3d.push(Tuple3d {idx_fields: tree.idx_fields.clone(), vals: tuple.clone()}),
-            }  
+        arr
+    }
+
+    /// Get the ids of all records with the given key.
+    pub fn get(&self, key: &[Field]) -> Vec<ValueId> {
+        on_tree!(self, tree => tree.get(key))
+    }
+
+    /// Get the ids of all records whose key lies in the box between `min` and `max` (inclusive).
+    pub fn range_query(&self, min: &[Field], max: &[Field]) -> Vec<ValueId> {
+        let min: Vec<Option<Field>> = min.iter().cloned().map(Some).collect();
+        let max: Vec<Option<Field>> = max.iter().cloned().map(Some).collect();
+        self.partial_range_query(&min, &max)
+    }
+
+    /// Get the ids of all records whose key lies between `min` and `max` (inclusive). The bounds
+    /// are given over the indexed attributes, and a `None` bound leaves its side of the attribute open.
+    pub fn partial_range_query(&self, min: &[Option<Field>], max: &[Option<Field>]) -> Vec<ValueId> {
+        on_tree!(self, tree => tree.range_query(min, max))
+    }
+
+    /// Get the ids of the `k` records whose keys are closest to `val` in euclidean distance.
+    pub fn knn(&self, val: &[Field], k: usize) -> Vec<ValueId> {
+        self.knn_with_metric(val, k, DistanceMetric::Euclidean).into_iter().map(|neighbor| neighbor.rid).collect()
+    }
+
+    /// Get the `k` records whose keys are closest to `key`, closest first.
+    pub fn knn_with_metric(&self, key: &[Field], k: usize, metric: DistanceMetric) -> Vec<Neighbor> {
+        on_tree!(self, tree => tree.knn(key, k, metric))
+    }
+
+    /// Get the records whose keys are within `radius` of `center`, closest first.
+    pub fn radius_query(&self, center: &[Field], radius: f64, metric: DistanceMetric) -> Vec<Neighbor> {
+        on_tree!(self, tree => tree.radius_query(center, radius, metric))
+    }
+
+    /// Get the pairs of ids of the records of this tree and of `other` whose keys match, by
+    /// walking down both trees together. Both trees must index the same number of attributes.
+    pub fn spatial_join(&self, other: &R_Tree, predicate: &SpatialPredicate) -> Result<Vec<(ValueId, ValueId)>, CrustyError> {
+        match (self, other) {
+            (R_Tree::Dim2(tree1), R_Tree::Dim2(tree2)) => Ok(tree1.spatial_join(tree2, predicate)),
+            (R_Tree::Dim3(tree1), R_Tree::Dim3(tree2)) => Ok(tree1.spatial_join(tree2, predicate)),
+            (R_Tree::Dim4(tree1), R_Tree::Dim4(tree2)) => Ok(tree1.spatial_join(tree2, predicate)),
+            (R_Tree::Dim5(tree1), R_Tree::Dim5(tree2)) => Ok(tree1.spatial_join(tree2, predicate)),
+            (R_Tree::Dim6(tree1), R_Tree::Dim6(tree2)) => Ok(tree1.spatial_join(tree2, predicate)),
+            (R_Tree::Dim7(tree1), R_Tree::Dim7(tree2)) => Ok(tree1.spatial_join(tree2, predicate)),
+            (R_Tree::Dim8(tree1), R_Tree::Dim8(tree2)) => Ok(tree1.spatial_join(tree2, predicate)),
+            (R_Tree::Dim9(tree1), R_Tree::Dim9(tree2)) => Ok(tree1.spatial_join(tree2, predicate)),
+            _ => Err(CrustyError::CrustyError(String::from("Can't join R-trees over different numbers of attributes"))),
         }
-        match self {
-            R_Tree::Dim2(tree) => tree.r_tree = RTree::bulk_load(vec_arr_2d.clone()),
-            R_Tree::Dim3(tree) => tree.r_tree = RTree::bulk_load(vec_arr_3d.clone())
-        }   
     }
 
-    pub fn delete(&mut self, val: &Vec<Field>) {
-        match self {
-            R_Tree::Dim2(tree) => {tree.r_tree.remove(&Tuple2d {idx_fields: tree.idx_fields.clone(), vals: R_Tree::get_new_copy(val, tree.total_dim)});},
-            R_Tree::Dim3(tree) => {tree.r_tree.remove(&Tuple3d {idx_fields: tree.idx_fields.clone(), vals: R_Tree::get_new_copy(val, tree.total_dim)});},
+    /// Bulk load the tree from full records and the ids they are stored under.
+    pub fn data_into_tree(&mut self, arr: &[(Vec<Field>, ValueId)]) {
+        let entries = arr.iter().map(|(val, rid)| IndexEntry::new(self.get_key(val), *rid)).collect();
+        self.entries_into_tree(entries);
+    }
+
+    /// Bulk load the tree from entries whose keys have already been taken out of the records.
+    /// The tree is repacked, keeping the entries it already holds.
+    pub fn entries_into_tree(&mut self, entries: Vec<IndexEntry>) {
+        if entries.is_empty() {
+            return
         }
-        return
+        on_tree!(self, tree => tree.bulk_load(entries))
+    }
+
+    /// Read back a tree serialized with its entries, bulk loading it again.
+    pub fn from_serialized(serialized: &SerializedIndex) -> Result<Self, CrustyError> {
+        let mut tree = R_Tree::new(serialized.idx_fields.len(), serialized.idx_fields.clone(), serialized.total_dim);
+        tree.entries_into_tree(serialized.payload()?);
+        Ok(tree)
+    }
+
+    /// Delete the entry of the record stored under `rid`. `val` is the full record.
+    pub fn delete(&mut self, val: &[Field], rid: ValueId) {
+        let key = self.get_key(val);
+        on_tree!(self, tree => {tree.r_tree.remove(&RTuple {key, rid});})
+    }
+
+    /// Number of entries in the tree.
+    pub fn num_entries(&self) -> usize {
+        on_tree!(self, tree => tree.r_tree.size())
+    }
+
+    pub fn get_total_dim(&self) -> usize {
+        on_tree!(self, tree => tree.total_dim)
+    }
+
+    pub fn get_all_entries(&self) -> Vec<IndexEntry> {
+        on_tree!(self, tree => tree.entries())
     }
 
     pub fn get_idx_fields(&self) -> Vec<usize> {
-        match self {
-            R_Tree::Dim2(tree) => tree.idx_fields.clone(),
-            R_Tree::Dim3(tree) => tree.idx_fields.clone(),
-        }        
+        on_tree!(self, tree => tree.idx_fields.clone())
+    }
+}
+
+impl MdIndex for R_Tree {
+    fn tree_type(&self) -> &'static str {
+        "R"
+    }
+
+    fn idx_fields(&self) -> Vec<usize> {
+        self.get_idx_fields()
+    }
+
+    fn insert(&mut self, val: &[Field], rid: ValueId) -> Result<(), CrustyError> {
+        R_Tree::insert(self, val, rid);
+        Ok(())
+    }
+
+    fn delete(&mut self, val: &[Field], rid: ValueId) -> Result<(), CrustyError> {
+        R_Tree::delete(self, val, rid);
+        Ok(())
+    }
+
+    fn bulk_load(&mut self, arr: &[(Vec<Field>, ValueId)]) -> Result<(), CrustyError> {
+        self.data_into_tree(arr);
+        Ok(())
+    }
+
+    fn point_query(&self, key: &[Field]) -> Result<Vec<ValueId>, CrustyError> {
+        Ok(self.get(key))
+    }
+
+    fn range_query(&self, min: &[Option<Field>], max: &[Option<Field>]) -> Result<Vec<ValueId>, CrustyError> {
+        Ok(self.partial_range_query(min, max))
+    }
+
+    fn knn_query(&self, key: &[Field], k: usize, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        Ok(self.knn_with_metric(key, k, metric))
+    }
+
+    fn radius_query(&self, center: &[Field], radius: f64, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        Ok(R_Tree::radius_query(self, center, radius, metric))
+    }
+
+    fn stats(&self) -> Result<MdIndexStats, CrustyError> {
+        Ok(MdIndexStats {num_entries: self.num_entries(), depth: on_tree!(self, tree => tree.depth())})
+    }
+
+    fn serialize(&self, name: &str) -> SerializedIndex {
+        SerializedIndex::new(self, name, self.get_total_dim(), &self.get_all_entries())
+    }
+
+    fn spatial_join(&self, other: &dyn MdIndex, predicate: &SpatialPredicate) -> Result<Vec<(ValueId, ValueId)>, CrustyError> {
+        match other.as_any().downcast_ref::<R_Tree>() {
+            Some(other) => R_Tree::spatial_join(self, other, predicate),
+            None => Err(CrustyError::CrustyError(format!("Can't join an R index with a {} index", other.tree_type()))),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::Field::IntField;
     use crate::testutil::*;
     use std::{println as debug};
 
+    // rows in these tests are named by letters, and each one is stored in the slot of its letter
+    pub fn rid(name: char) -> ValueId {
+        ValueId::new_slot(0, 0, name as u16 - 'a' as u16)
+    }
+
+    pub fn rids(names: &[char]) -> Vec<ValueId> {
+        let mut res: Vec<ValueId> = names.iter().map(|name| rid(*name)).collect();
+        res.sort();
+        res
+    }
+
     pub fn tree5() -> R_Tree {
         let a: Vec<Field> = vec![IntField(2), IntField(4), IntField(11)];
         let b: Vec<Field> = vec![IntField(6), IntField(6), IntField(7)];
@@ -245,9 +511,9 @@ mod test {
         let j: Vec<Field> = vec![IntField(19), IntField(5), IntField(19)];  
         let k: Vec<Field> = vec![IntField(20), IntField(3), IntField(6)];
         let mut tree5 = R_Tree::new(2, vec![1, 2], 3);
-        let mut tree_arr_5: [Vec<Field>; 11] = [a.clone(), b.clone(), c.clone(), d.clone(), e.clone(), 
-        f.clone(), g.clone(), h.clone(), i.clone(), j.clone(), k.clone()];
-        tree5.data_into_tree(&mut tree_arr_5);
+        let tree_arr_5: [(Vec<Field>, ValueId); 11] = [(a.clone(), rid('a')), (b.clone(), rid('b')), (c.clone(), rid('c')), (d.clone(), rid('d')), (e.clone(), rid('e')), 
+        (f.clone(), rid('f')), (g.clone(), rid('g')), (h.clone(), rid('h')), (i.clone(), rid('i')), (j.clone(), rid('j')), (k.clone(), rid('k'))];
+        tree5.data_into_tree(&tree_arr_5);
         tree5
     }
 
@@ -259,12 +525,12 @@ mod test {
         let e: Vec<Field> = vec![IntField(10), IntField(2)];
         let f: Vec<Field> = vec![IntField(8), IntField(7)];
         let mut tree6 = R_Tree::new(2, vec![0, 1], 2);
-        tree6.insert(&a);
-        tree6.insert(&b);
-        tree6.insert(&c);
-        tree6.insert(&d);
-        tree6.insert(&e);
-        tree6.insert(&f);
+        tree6.insert(&a, rid('a'));
+        tree6.insert(&b, rid('b'));
+        tree6.insert(&c, rid('c'));
+        tree6.insert(&d, rid('d'));
+        tree6.insert(&e, rid('e'));
+        tree6.insert(&f, rid('f'));
         tree6
     }
 
@@ -294,11 +560,11 @@ mod test {
         let w: Vec<Field> =  vec![IntField(17), IntField(22)];
         let x: Vec<Field> =  vec![IntField(18), IntField(24)];
         let mut tree7 = R_Tree::new(2, vec![0, 1], 2);
-        let mut tree_arr_7: [Vec<Field>; 24] = [a.clone(), b.clone(), c.clone(), d.clone(), e.clone(), 
-        f.clone(), g.clone(), h.clone(), i.clone(), j.clone(), k.clone(), l.clone(), m.clone(), n.clone(),
-        o.clone(), p.clone(), q.clone(), r.clone(), s.clone(), t.clone(), u.clone(), v.clone(), w.clone(),
-        x.clone()];
-        tree7.data_into_tree(&mut tree_arr_7);
+        let tree_arr_7: [(Vec<Field>, ValueId); 24] = [(a.clone(), rid('a')), (b.clone(), rid('b')), (c.clone(), rid('c')), (d.clone(), rid('d')), (e.clone(), rid('e')), 
+        (f.clone(), rid('f')), (g.clone(), rid('g')), (h.clone(), rid('h')), (i.clone(), rid('i')), (j.clone(), rid('j')), (k.clone(), rid('k')), (l.clone(), rid('l')), (m.clone(), rid('m')), (n.clone(), rid('n')),
+        (o.clone(), rid('o')), (p.clone(), rid('p')), (q.clone(), rid('q')), (r.clone(), rid('r')), (s.clone(), rid('s')), (t.clone(), rid('t')), (u.clone(), rid('u')), (v.clone(), rid('v')), (w.clone(), rid('w')),
+        (x.clone(), rid('x'))];
+        tree7.data_into_tree(&tree_arr_7);
         tree7
     }
 
@@ -329,11 +595,31 @@ pub fn test_get_tree5() {
     let f: Vec<Field> = vec![IntField(18), IntField(7), IntField(15)];
     let mut tree_5 = tree5();    
     let mut get_result = tree_5.get(&vec![IntField(4), IntField(11)]);
-    get_result.sort_by(|a, b| a[0].cmp(&b[0]));
-    assert!(get_result == vec![a.clone()]);      
+    get_result.sort();
+    assert!(get_result == rids(&['a']));      
     get_result = tree_5.get(&vec![IntField(7), IntField(15)]);
-    get_result.sort_by(|a, b| a[0].cmp(&b[0]));
-    assert!(get_result == vec![e.clone(), f.clone()]);      
+    get_result.sort();
+    assert!(get_result == rids(&['e', 'f']));      
+}
+
+#[test]
+pub fn test_range_query_tree5 () {
+    let tree_5 = tree5();
+    let mut range_query_result = tree_5.range_query(&vec![IntField(7), IntField(3)], &vec![IntField(16), IntField(15)]);
+    range_query_result.sort();
+    assert!(range_query_result == rids(&['c', 'e', 'g', 'f']));
+}
+
+#[test]
+pub fn test_delete_tree6 () {
+    let a: Vec<Field> = vec![IntField(5), IntField(4)];
+    let mut tree_6 = tree6();
+    tree_6.insert(&a, rid('g'));
+    tree_6.delete(&a, rid('a'));
+    assert!(tree_6.get(&a) == rids(&['g']));
+    assert!(tree_6.search(&a));
+    tree_6.delete(&a, rid('g'));
+    assert!(!tree_6.search(&a));
 }
 
 #[test]
@@ -346,29 +632,29 @@ pub fn test_knn_tree6 () {
     let f: Vec<Field> = vec![IntField(8), IntField(7)];
     let mut tree_6 = tree6();    
     let mut knn_result = tree_6.knn(&vec![IntField(9), IntField(4)], 1);
-    knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-    assert!(knn_result == vec![e.clone()]); 
+    knn_result.sort();
+    assert!(knn_result == rids(&['e'])); 
     knn_result = tree_6.knn(&vec![IntField(9), IntField(4)], 2);
-    knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-    assert!(knn_result == vec![f.clone(), e.clone()]); 
+    knn_result.sort();
+    assert!(knn_result == rids(&['f', 'e'])); 
     knn_result = tree_6.knn(&vec![IntField(9), IntField(4)], 3);
-    knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-    assert!(knn_result == vec![a.clone(), f.clone(), e.clone()]); 
+    knn_result.sort();
+    assert!(knn_result == rids(&['a', 'f', 'e'])); 
     knn_result = tree_6.knn(&vec![IntField(9), IntField(4)], 4);
-    knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-    assert!(knn_result == vec![a.clone(), f.clone(), e.clone(), c.clone()]);     
+    knn_result.sort();
+    assert!(knn_result == rids(&['a', 'f', 'e', 'c']));     
     knn_result = tree_6.knn(&vec![IntField(9), IntField(4)], 5);
-    knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-    assert!(knn_result == vec![d.clone(), a.clone(), f.clone(), e.clone(), c.clone()]); 
+    knn_result.sort();
+    assert!(knn_result == rids(&['d', 'a', 'f', 'e', 'c'])); 
     knn_result = tree_6.knn(&vec![IntField(5), IntField(6)], 1);
-    knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-    assert!(knn_result == vec![a.clone()]);    
+    knn_result.sort();
+    assert!(knn_result == rids(&['a']));    
     knn_result = tree_6.knn(&vec![IntField(5), IntField(6)], 2);
-    knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-    assert!(knn_result == vec![b.clone(), a.clone()]);  
+    knn_result.sort();
+    assert!(knn_result == rids(&['b', 'a']));  
     knn_result = tree_6.knn(&vec![IntField(5), IntField(6)], 3);
-    knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-    assert!(knn_result == vec![b.clone(), a.clone(), f.clone()]);                 
+    knn_result.sort();
+    assert!(knn_result == rids(&['b', 'a', 'f']));                 
 }
 
 #[test]
@@ -399,19 +685,235 @@ pub fn test_knn_tree7 () {
     let x: Vec<Field> =  vec![IntField(18), IntField(24)];    
     let mut tree_7 = tree7();    
     let mut knn_result = tree_7.knn(&vec![IntField(10), IntField(28)], 3);
-    knn_result.sort_by(|a, b| a[0].cmp(&b[0])); 
-    assert!(knn_result == vec![d.clone(), t.clone(),f.clone(),]);     
+    knn_result.sort(); 
+    assert!(knn_result == rids(&['d', 't', 'f']));     
     knn_result = tree_7.knn(&vec![IntField(7), IntField(18)], 3);
-    knn_result.sort_by(|a, b| a[0].cmp(&b[0]));     
-    assert!(knn_result == vec![g.clone(), h.clone(), k.clone()]);      
+    knn_result.sort();     
+    assert!(knn_result == rids(&['g', 'h', 'k']));      
     knn_result = tree_7.knn(&vec![IntField(3), IntField(25)], 3);
-    knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-    assert!(knn_result == vec![a.clone(), i.clone(), b.clone()]);      
+    knn_result.sort();
+    assert!(knn_result == rids(&['a', 'i', 'b']));      
     knn_result = tree_7.knn(&vec![IntField(15), IntField(29)], 2);
-    knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-    assert!(knn_result == vec![q.clone(), u.clone()]);    
+    knn_result.sort();
+    assert!(knn_result == rids(&['q', 'u']));    
     knn_result = tree_7.knn(&vec![IntField(1), IntField(2)], 2);
-    knn_result.sort_by(|a, b| a[0].cmp(&b[0]));
-    assert!(knn_result == vec![h.clone(), k.clone()]);               
+    knn_result.sort();
+    assert!(knn_result == rids(&['h', 'k']));               
+}
+
+#[test]
+pub fn test_high_dim() {
+    for dim in R_Tree::MIN_DIM..=R_Tree::MAX_DIM {
+        let records: Vec<Vec<Field>> = (0..40)
+            .map(|i| (0..dim).map(|d| IntField((i * (d as i32 + 3)) % 17)).collect())
+            .collect();
+        let arr: Vec<(Vec<Field>, ValueId)> = records
+            .iter()
+            .enumerate()
+            .map(|(i, record)| (record.clone(), ValueId::new_slot(0, 0, i as u16)))
+            .collect();
+        let mut tree = R_Tree::new(dim, (0..dim).collect(), dim);
+        tree.data_into_tree(&arr);
+        assert_eq!(tree.num_entries(), 40);
+
+        let min = vec![IntField(3); dim];
+        let max = vec![IntField(12); dim];
+        let mut expected: Vec<ValueId> = arr
+            .iter()
+            .filter(|(record, _)| record.iter().all(|val| *val >= IntField(3) && *val <= IntField(12)))
+            .map(|(_, rid)| *rid)
+            .collect();
+        let mut range_query_result = tree.range_query(&min, &max);
+        range_query_result.sort();
+        expected.sort();
+        assert_eq!(range_query_result, expected);
+
+        // only the upper bound of the first attribute is given
+        let mut expected: Vec<ValueId> = arr
+            .iter()
+            .filter(|(record, _)| record[0] <= IntField(5))
+            .map(|(_, rid)| *rid)
+            .collect();
+        let mut range_query_result = tree.partial_range_query(&[], &[Some(IntField(5))]);
+        range_query_result.sort();
+        expected.sort();
+        assert_eq!(range_query_result, expected);
+
+        let nearest = tree.knn(&records[5], 1);
+        assert_eq!(records[nearest[0].slot_id.unwrap() as usize], records[5]);
+        assert!(tree.get(&records[5]).contains(&arr[5].1));
+        tree.delete(&records[5], arr[5].1);
+        assert!(!tree.get(&records[5]).contains(&arr[5].1));
+        assert_eq!(tree.num_entries(), 39);
+    }
+}
+
+#[test]
+pub fn test_bulk_load_matches_insert() {
+    let arr: Vec<(Vec<Field>, ValueId)> = (0..500)
+        .map(|i| (vec![IntField((i * 37) % 101), IntField((i * 53) % 97)], ValueId::new_slot(0, 0, i as u16)))
+        .collect();
+    let mut inserted = R_Tree::new(2, vec![0, 1], 2);
+    for (record, rid) in &arr {
+        inserted.insert(record, *rid);
+    }
+    // the second load keeps the entries of the first one
+    let mut loaded = R_Tree::new(2, vec![0, 1], 2);
+    loaded.data_into_tree(&arr[..200]);
+    loaded.data_into_tree(&arr[200..]);
+    assert_eq!(loaded.num_entries(), 500);
+
+    let min = vec![IntField(20), IntField(10)];
+    let max = vec![IntField(60), IntField(45)];
+    let mut expected = inserted.range_query(&min, &max);
+    let mut range_query_result = loaded.range_query(&min, &max);
+    expected.sort();
+    range_query_result.sort();
+    assert_eq!(range_query_result, expected);
+    assert_eq!(loaded.knn(&arr[7].0, 5).len(), 5);
+    assert!(loaded.get(&arr[7].0).contains(&arr[7].1));
+}
+
+#[test]
+pub fn test_knn_metrics_match_kd_tree() {
+    // a grid with many points at the same distance, and some keys stored twice,
+    // so ties on the distance and on the key decide part of the answer
+    let arr: Vec<(Vec<Field>, ValueId)> = (0..130)
+        .map(|i| (vec![IntField(i % 100 % 10), IntField(i % 100 / 10), IntField(i % 100 % 7)], ValueId::new_slot(0, 0, (130 - i) as u16)))
+        .collect();
+    let mut r_tree = R_Tree::new(3, vec![0, 1, 2], 3);
+    r_tree.data_into_tree(&arr);
+    let mut kd_tree = KdTree::new(3, vec![0, 1, 2], 3);
+    for (record, rid) in &arr {
+        kd_tree.insert(record, *rid);
+    }
+    let key = vec![IntField(4), IntField(5), IntField(3)];
+    for metric in [DistanceMetric::Euclidean, DistanceMetric::Manhattan, DistanceMetric::Chebyshev] {
+        let mut expected: Vec<Neighbor> = arr
+            .iter()
+            .map(|(record, rid)| Neighbor {dist: metric.distance(&key, record), key: record.clone(), rid: *rid})
+            .collect();
+        expected.sort();
+        for k in [1, 5, 12, 100, 150] {
+            let expected: Vec<Neighbor> = expected.iter().take(k).cloned().collect();
+            assert_eq!(r_tree.knn_with_metric(&key, k, metric), expected);
+            assert_eq!(kd_tree.knn_with_metric(&key, k, metric), expected);
+        }
+    }
+}
+
+#[test]
+pub fn test_radius_query_matches_kd_tree() {
+    let arr: Vec<(Vec<Field>, ValueId)> = (0..200)
+        .map(|i| (vec![IntField(i * 7 % 23), IntField(i * 11 % 19)], ValueId::new_slot(0, 0, i as u16)))
+        .collect();
+    let mut r_tree = R_Tree::new(2, vec![0, 1], 2);
+    r_tree.data_into_tree(&arr);
+    let mut kd_tree = KdTree::new(2, vec![0, 1], 2);
+    for (record, rid) in &arr {
+        kd_tree.insert(record, *rid);
+    }
+    let center = vec![IntField(10), IntField(9)];
+    for metric in [DistanceMetric::Euclidean, DistanceMetric::Manhattan, DistanceMetric::Chebyshev] {
+        for radius in [0.0, 1.0, 2.5, 6.0, 100.0] {
+            let mut expected: Vec<Neighbor> = arr
+                .iter()
+                .map(|(record, rid)| Neighbor {dist: metric.distance(&center, record), key: record.clone(), rid: *rid})
+                .filter(|neighbor| neighbor.dist.0 <= radius)
+                .collect();
+            expected.sort();
+            assert_eq!(r_tree.radius_query(&center, radius, metric), expected);
+            assert_eq!(kd_tree.radius_query(&center, radius, metric), expected);
+        }
+    }
+}
+
+#[test]
+#[should_panic]
+pub fn test_unsupported_dim() {
+    R_Tree::new(R_Tree::MAX_DIM + 1, (0..R_Tree::MAX_DIM + 1).collect(), R_Tree::MAX_DIM + 1);
+}
+
+#[test]
+pub fn test_string_fields() {
+    use crate::Field::StringField;
+    let names = ["apple", "applesauce", "apricot", "banana", "b", "", "cherry", "apple"];
+    let arr: Vec<(Vec<Field>, ValueId)> = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (vec![StringField(name.to_string()), IntField(i as i32)], rid((b'a' + i as u8) as char)))
+        .collect();
+    let mut tree = R_Tree::new(2, vec![0, 1], 2);
+    tree.data_into_tree(&arr);
+
+    let mut get_result = tree.get(&vec![StringField(String::from("apple")), IntField(0)]);
+    get_result.sort();
+    assert_eq!(get_result, rids(&['a']));
+
+    let mut range_query_result = tree.range_query(
+        &vec![StringField(String::from("apple")), IntField(0)],
+        &vec![StringField(String::from("apricot")), IntField(10)],
+    );
+    range_query_result.sort();
+    assert_eq!(range_query_result, rids(&['a', 'b', 'c', 'h']));
+
+    let mut range_query_result = tree.range_query(
+        &vec![StringField(String::from("b")), IntField(0)],
+        &vec![StringField(String::from("banana")), IntField(10)],
+    );
+    range_query_result.sort();
+    assert_eq!(range_query_result, rids(&['d', 'e']));
+
+    let mut range_query_result = tree.range_query(
+        &vec![StringField(String::new()), IntField(0)],
+        &vec![StringField(String::from("applesauce")), IntField(1)],
+    );
+    range_query_result.sort();
+    assert_eq!(range_query_result, rids(&['a', 'b']));
+}
+
+#[test]
+pub fn test_string_order_preserved() {
+    let mut names = vec!["", "a", "ab", "abc", "abcd", "abce", "b", "zz", "\u{7f}", "é"];
+    names.sort();
+    let coords: Vec<i32> = names
+        .iter()
+        .map(|name| R_Tree::field_to_i32(&Field::StringField(name.to_string())))
+        .collect();
+    assert!(coords.windows(2).all(|pair| pair[0] <= pair[1]));
+}
+
+#[test]
+pub fn test_spatial_join() {
+    let points = |n: i32, step: i32| -> Vec<(Vec<Field>, ValueId)> {
+        (0..n)
+            .map(|i| (vec![IntField((i * step) % 97), IntField((i * 7 + step) % 89)], ValueId::new_slot(0, 0, i as u16)))
+            .collect()
+    };
+    let (arr1, arr2) = (points(300, 13), points(200, 31));
+    let mut tree1 = R_Tree::new(2, vec![0, 1], 2);
+    tree1.data_into_tree(&arr1);
+    let mut tree2 = R_Tree::new(2, vec![0, 1], 2);
+    tree2.data_into_tree(&arr2);
+    for predicate in [
+        SpatialPredicate::Within { radius: 2.5, metric: DistanceMetric::Euclidean },
+        SpatialPredicate::Within { radius: 3.0, metric: DistanceMetric::Manhattan },
+        SpatialPredicate::SameCell { cell_size: 8 },
+    ] {
+        let mut expected = Vec::new();
+        for (key1, rid1) in &arr1 {
+            for (key2, rid2) in &arr2 {
+                if predicate.matches(key1, key2) {
+                    expected.push((*rid1, *rid2));
+                }
+            }
+        }
+        expected.sort();
+        let mut res = tree1.spatial_join(&tree2, &predicate).unwrap();
+        res.sort();
+        assert!(!expected.is_empty());
+        assert_eq!(res, expected);
+    }
+    assert!(tree1.spatial_join(&R_Tree::new(3, vec![0, 1, 2], 3), &SpatialPredicate::SameCell { cell_size: 1 }).is_err());
+}
 }
-}
\ No newline at end of file
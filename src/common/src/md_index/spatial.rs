@@ -0,0 +1,115 @@
+use crate::{CrustyError, Field};
+use super::DistanceMetric;
+
+/// Condition under which the keys of two records match in a spatial join. Keys are compared
+/// through the coordinates of their attributes, as in KNN queries.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SpatialPredicate {
+    /// The keys are at most `radius` apart.
+    Within { radius: f64, metric: DistanceMetric },
+    /// The keys fall in the same cell of a grid whose cells are `cell_size` wide on every axis.
+    SameCell { cell_size: i64 },
+}
+
+impl SpatialPredicate {
+    /// Predicate matching the keys in the same cell of a grid whose cells are `cell_size` wide.
+    pub fn same_cell(cell_size: i64) -> Result<Self, CrustyError> {
+        let predicate = SpatialPredicate::SameCell { cell_size };
+        predicate.validate()?;
+        Ok(predicate)
+    }
+
+    /// Check that the predicate can be evaluated: grid cells must have a positive size.
+    pub fn validate(&self) -> Result<(), CrustyError> {
+        match self {
+            SpatialPredicate::SameCell { cell_size } if *cell_size <= 0 => Err(CrustyError::CrustyError(format!(
+                "Grid cells must have a positive size, got {}",
+                cell_size
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn cell(coord: i64, cell_size: i64) -> i64 {
+        coord.div_euclid(cell_size)
+    }
+
+    /// Whether two keys over the same attributes match.
+    pub fn matches(&self, key1: &[Field], key2: &[Field]) -> bool {
+        match self {
+            SpatialPredicate::Within { radius, metric } => metric.distance(key1, key2).0 <= *radius,
+            SpatialPredicate::SameCell { cell_size } => key1.iter().zip(key2).all(|(val1, val2)| {
+                SpatialPredicate::cell(DistanceMetric::coordinate(val1), *cell_size)
+                    == SpatialPredicate::cell(DistanceMetric::coordinate(val2), *cell_size)
+            }),
+        }
+    }
+
+    /// Whether a point of the box between `lower1` and `upper1` may match a point of the box
+    /// between `lower2` and `upper2`, all given as coordinates.
+    pub fn boxes_may_match(&self, lower1: &[i64], upper1: &[i64], lower2: &[i64], upper2: &[i64]) -> bool {
+        match self {
+            SpatialPredicate::Within { radius, metric } => {
+                let gaps = (0..lower1.len()).map(|i| (lower2[i] - upper1[i]).max(lower1[i] - upper2[i]).max(0));
+                metric.combine(gaps).0 <= *radius
+            }
+            SpatialPredicate::SameCell { cell_size } => (0..lower1.len()).all(|i| {
+                let cell = |coord| SpatialPredicate::cell(coord, *cell_size);
+                cell(lower1[i]) <= cell(upper2[i]) && cell(lower2[i]) <= cell(upper1[i])
+            }),
+        }
+    }
+
+    /// Bounds of the box holding every key in the same grid cell as `key`, to probe an index with.
+    /// Attributes that are not integers are left unbounded, so the keys found still have to be
+    /// checked with `matches`.
+    pub fn cell_bounds(key: &[Field], cell_size: i64) -> (Vec<Option<Field>>, Vec<Option<Field>>) {
+        let clamp = |coord: i64| Field::IntField(coord.clamp(i64::from(i32::MIN), i64::from(i32::MAX)) as i32);
+        let (min, max) = key
+            .iter()
+            .map(|val| match val {
+                Field::IntField(val) => {
+                    let lower = SpatialPredicate::cell(i64::from(*val), cell_size) * cell_size;
+                    (Some(clamp(lower)), Some(clamp(lower + cell_size - 1)))
+                }
+                Field::StringField(_) => (None, None),
+            })
+            .unzip();
+        (min, max)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Field::IntField;
+
+    #[test]
+    fn test_same_cell() {
+        let predicate = SpatialPredicate::SameCell { cell_size: 10 };
+        assert!(predicate.matches(&[IntField(11), IntField(-1)], &[IntField(19), IntField(-10)]));
+        assert!(!predicate.matches(&[IntField(11), IntField(-1)], &[IntField(19), IntField(0)]));
+        let (min, max) = SpatialPredicate::cell_bounds(&[IntField(11), IntField(-1)], 10);
+        assert_eq!(min, vec![Some(IntField(10)), Some(IntField(-10))]);
+        assert_eq!(max, vec![Some(IntField(19)), Some(IntField(-1))]);
+        assert!(predicate.boxes_may_match(&[0, 0], &[10, 5], &[19, 9], &[30, 9]));
+        assert!(!predicate.boxes_may_match(&[0, 0], &[9, 5], &[10, 0], &[30, 9]));
+    }
+
+    #[test]
+    fn test_same_cell_size() {
+        assert_eq!(SpatialPredicate::same_cell(3).unwrap(), SpatialPredicate::SameCell { cell_size: 3 });
+        assert!(SpatialPredicate::same_cell(0).is_err());
+        assert!(SpatialPredicate::same_cell(-4).is_err());
+        assert!(SpatialPredicate::SameCell { cell_size: 0 }.validate().is_err());
+    }
+
+    #[test]
+    fn test_within() {
+        let predicate = SpatialPredicate::Within { radius: 5.0, metric: DistanceMetric::Euclidean };
+        assert!(predicate.matches(&[IntField(0), IntField(0)], &[IntField(3), IntField(4)]));
+        assert!(!predicate.matches(&[IntField(0), IntField(0)], &[IntField(4), IntField(4)]));
+        assert!(predicate.boxes_may_match(&[0, 0], &[1, 1], &[4, 5], &[9, 9]));
+        assert!(!predicate.boxes_may_match(&[0, 0], &[1, 1], &[5, 5], &[9, 9]));
+    }
+}
@@ -0,0 +1,470 @@
+use std::any::Any;
+use std::collections::BTreeMap;
+use crate::{CrustyError, Field};
+use crate::ids::ValueId;
+use super::{DistanceMetric, IndexEntry, KdTree, MdIndex, MdIndexStats, Neighbor, SerializedIndex};
+
+/// Bits of each coordinate that go into a curve key.
+const COORD_BITS: u32 = 32;
+
+/// Most intervals a range query is split into. Cells still straddling the query box once the
+/// budget is reached are scanned whole, and their entries are checked against the box.
+const MAX_INTERVALS: usize = 256;
+
+/// Cells straddling the query box that hold at most this many entries are scanned whole
+/// rather than split further.
+const SMALL_CELL: usize = 8;
+
+// coordinates are shifted so that the smallest one maps to 0, which keeps their order
+fn unsigned(coord: i64) -> u32 {
+    (coord - i64::from(i32::MIN)) as u32
+}
+
+fn low_bits(bits: u32) -> u32 {
+    ((1u64 << bits) - 1) as u32
+}
+
+/// Morton key of a point: the bits of its coordinates interleaved from the most significant
+/// down, the first coordinate taking the highest bit of each group.
+fn interleave(coords: &[u32]) -> u128 {
+    let mut z = 0;
+    for bit in (0..COORD_BITS).rev() {
+        for coord in coords {
+            z = z << 1 | u128::from(coord >> bit & 1);
+        }
+    }
+    z
+}
+
+/// Intervals of curve keys that together hold every point of the box between `lower` and
+/// `upper` (inclusive), sorted and disjoint. `count` gives the number of entries whose key falls
+/// in an interval, and may stop counting past `SMALL_CELL`. Cells of the curve are split level by
+/// level: cells outside of the box or without entries are dropped, cells inside the box become an
+/// interval, and so do cells straddling the box with at most `SMALL_CELL` entries. Once the next
+/// level would go past `MAX_INTERVALS`, the cells left straddling the box become intervals as a
+/// whole as well, so the intervals may hold points outside of the box.
+pub fn decompose<F>(lower: &[u32], upper: &[u32], count: F) -> Vec<(u128, u128)>
+where
+    F: Fn(u128, u128) -> usize,
+{
+    let dim = lower.len();
+    let cell_upper = |corner: &[u32; ZOrderIndex::MAX_DIM], side_bits: u32| corner.map(|coord| coord | low_bits(side_bits));
+    let mut intervals = Vec::new();
+    // refinement starts from the smallest cell holding the whole box, found from the bits shared
+    // by its corners
+    let mut side_bits = (0..dim).map(|d| COORD_BITS - (lower[d] ^ upper[d]).leading_zeros()).max().unwrap_or(0);
+    let mut corner = [0u32; ZOrderIndex::MAX_DIM];
+    for d in 0..dim {
+        corner[d] = lower[d] & !low_bits(side_bits);
+    }
+    // lowest corners of the cells straddling the box, whose sides are `side_bits` bits long
+    let mut partial = vec![corner];
+    while side_bits > 0 && !partial.is_empty() && intervals.len() + (partial.len() << dim) <= MAX_INTERVALS {
+        side_bits -= 1;
+        let mut next = Vec::new();
+        for corner in partial {
+            // children are numbered in curve order, the first coordinate taking the highest bit
+            for child in 0..1usize << dim {
+                let mut child_lower = corner;
+                for (d, coord) in child_lower.iter_mut().take(dim).enumerate() {
+                    *coord |= ((child >> (dim - 1 - d) & 1) as u32) << side_bits;
+                }
+                let child_upper = cell_upper(&child_lower, side_bits);
+                if (0..dim).any(|d| child_upper[d] < lower[d] || upper[d] < child_lower[d]) {
+                    continue
+                }
+                let (start, end) = (interleave(&child_lower[..dim]), interleave(&child_upper[..dim]));
+                let entries = count(start, end);
+                if entries == 0 {
+                    continue
+                }
+                if entries <= SMALL_CELL || (0..dim).all(|d| lower[d] <= child_lower[d] && child_upper[d] <= upper[d]) {
+                    intervals.push((start, end));
+                }
+                else {
+                    next.push(child_lower);
+                }
+            }
+        }
+        partial = next;
+    }
+    for corner in partial {
+        intervals.push((interleave(&corner[..dim]), interleave(&cell_upper(&corner, side_bits)[..dim])));
+    }
+    intervals.sort();
+    let mut merged: Vec<(u128, u128)> = Vec::new();
+    for (start, end) in intervals {
+        match merged.last_mut() {
+            Some(last) if last.1 + 1 == start => last.1 = end,
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Index keeping its entries sorted by the Morton (Z-order) key of the indexed attributes, so
+/// that points close to each other tend to be close in the index. Range queries are split into
+/// intervals of keys (see `decompose`), and entries are checked against the query since strings
+/// sharing a prefix share a coordinate. Only Morton keys are implemented; there is no Hilbert
+/// curve ordering.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct ZOrderIndex {
+    pub dim: usize, // number of indexed attributes
+    pub entries: BTreeMap<u128, Vec<IndexEntry>>, // the entries, by their curve key
+    pub idx_fields: Vec<usize>, // vector of indeces of attributes that are used for the index
+    pub total_dim: usize, // total number of attributes of each data point
+    pub size: usize, // number of entries in the index
+}
+
+impl ZOrderIndex {
+    /// Smallest number of attributes a Z-order index can index.
+    pub const MIN_DIM: usize = 1;
+    /// Largest number of attributes a Z-order index can index, as curve keys are 128 bits long.
+    pub const MAX_DIM: usize = 4;
+
+    pub fn new(dim: usize, idx_fields: Vec<usize>, total_dim: usize) -> Self {
+        if !(ZOrderIndex::MIN_DIM..=ZOrderIndex::MAX_DIM).contains(&dim) {
+            panic!("Dimension not supported");
+        }
+        ZOrderIndex {
+            dim,
+            entries: BTreeMap::new(),
+            idx_fields,
+            total_dim,
+            size: 0,
+        }
+    }
+
+    /// Curve key of the values of the indexed attributes.
+    pub fn z_value(key: &[Field]) -> u128 {
+        let coords: Vec<u32> = key.iter().map(|field| unsigned(DistanceMetric::coordinate(field))).collect();
+        interleave(&coords)
+    }
+
+    /// Take the values of the indexed attributes out of a full record.
+    pub fn get_key(&self, val: &[Field]) -> Vec<Field> {
+        self.idx_fields.iter().map(|idx| val[*idx].clone()).collect()
+    }
+
+    /// Insert a record into the index. `val` is the full record and `rid` is where it is stored.
+    pub fn insert(&mut self, val: &[Field], rid: ValueId) {
+        let key = self.get_key(val);
+        self.entries.entry(ZOrderIndex::z_value(&key)).or_default().push(IndexEntry::new(key, rid));
+        self.size += 1;
+    }
+
+    /// Delete the entry of the record stored under `rid`. `val` is the full record.
+    pub fn delete(&mut self, val: &[Field], rid: ValueId) {
+        let entry = IndexEntry::new(self.get_key(val), rid);
+        let z = ZOrderIndex::z_value(&entry.key);
+        let bucket = match self.entries.get_mut(&z) {
+            Some(bucket) => bucket,
+            None => return,
+        };
+        if let Some(pos) = bucket.iter().position(|other| *other == entry) {
+            bucket.swap_remove(pos);
+            self.size -= 1;
+        }
+        if bucket.is_empty() {
+            self.entries.remove(&z);
+        }
+    }
+
+    /// Get the ids of all records with the given key.
+    pub fn get(&self, key: &[Field]) -> Vec<ValueId> {
+        match self.entries.get(&ZOrderIndex::z_value(key)) {
+            Some(bucket) => bucket.iter().filter(|entry| entry.key == key).map(|entry| entry.rid).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn search(&self, val: &[Field]) -> bool {
+        !self.get(val).is_empty()
+    }
+
+    // entries whose curve key falls in the intervals of the box, a superset of the entries in it
+    fn entries_in_box(&self, lower: &[u32], upper: &[u32]) -> impl Iterator<Item = &IndexEntry> + '_ {
+        decompose(lower, upper, |start, end| self.entries.range(start..=end).take(SMALL_CELL + 1).count())
+            .into_iter()
+            .flat_map(move |(start, end)| self.entries.range(start..=end))
+            .flat_map(|(_, bucket)| bucket)
+    }
+
+    /// Get the ids of all records whose key lies in the box between `min` and `max` (inclusive).
+    pub fn range_query(&self, min: &[Field], max: &[Field]) -> Vec<ValueId> {
+        let min: Vec<Option<Field>> = min.iter().cloned().map(Some).collect();
+        let max: Vec<Option<Field>> = max.iter().cloned().map(Some).collect();
+        self.partial_range_query(&min, &max)
+    }
+
+    /// Get the ids of all records whose key lies between `min` and `max` (inclusive). The bounds
+    /// are given over the indexed attributes, and a `None` bound leaves its side of the attribute open.
+    pub fn partial_range_query(&self, min: &[Option<Field>], max: &[Option<Field>]) -> Vec<ValueId> {
+        let bound = |bounds: &[Option<Field>], d: usize, open: u32| {
+            bounds.get(d).and_then(Option::as_ref).map_or(open, |field| unsigned(DistanceMetric::coordinate(field)))
+        };
+        let lower: Vec<u32> = (0..self.dim).map(|d| bound(min, d, 0)).collect();
+        let upper: Vec<u32> = (0..self.dim).map(|d| bound(max, d, u32::MAX)).collect();
+        if (0..self.dim).any(|d| lower[d] > upper[d]) {
+            return Vec::new();
+        }
+        self.entries_in_box(&lower, &upper)
+            .filter(|entry| KdTree::if_within_bounds(&entry.key, min, max))
+            .map(|entry| entry.rid)
+            .collect()
+    }
+
+    // entries within `reach` of the key along every dimension, which holds every entry within
+    // `reach` of it under any of the metrics
+    fn entries_near(&self, key: &[Field], reach: i64) -> impl Iterator<Item = &IndexEntry> + '_ {
+        let coords: Vec<i64> = key.iter().map(DistanceMetric::coordinate).collect();
+        let lower: Vec<u32> = coords.iter().map(|coord| unsigned(coord.saturating_sub(reach).max(i64::from(i32::MIN)))).collect();
+        let upper: Vec<u32> = coords.iter().map(|coord| unsigned(coord.saturating_add(reach).min(i64::from(i32::MAX)))).collect();
+        self.entries_in_box(&lower, &upper)
+    }
+
+    /// Get the ids of the `k` records whose keys are closest to `val` in euclidean distance.
+    pub fn knn(&self, val: &[Field], k: usize) -> Vec<ValueId> {
+        self.knn_with_metric(val, k, DistanceMetric::Euclidean).into_iter().map(|neighbor| neighbor.rid).collect()
+    }
+
+    /// Get the `k` records whose keys are closest to `key`, closest first.
+    pub fn knn_with_metric(&self, key: &[Field], k: usize, metric: DistanceMetric) -> Vec<Neighbor> {
+        if k == 0 {
+            return Vec::new();
+        }
+        // no k entries can all be closer than the k-th neighbor, so the k-th closest of the entries
+        // around the key along the curve bounds how far the neighbors can be
+        let z = ZOrderIndex::z_value(key);
+        let mut dists: Vec<f64> = self.entries.range(..z).rev().flat_map(|(_, bucket)| bucket).take(k)
+            .chain(self.entries.range(z..).flat_map(|(_, bucket)| bucket).take(k))
+            .map(|entry| metric.distance(key, &entry.key).0)
+            .collect();
+        let candidates: Vec<&IndexEntry> = if dists.len() < k {
+            self.entries.values().flatten().collect()
+        }
+        else {
+            dists.sort_by(f64::total_cmp);
+            self.entries_near(key, dists[k - 1].ceil() as i64).collect()
+        };
+        let mut res: Vec<Neighbor> = candidates
+            .into_iter()
+            .map(|entry| Neighbor {dist: metric.distance(key, &entry.key), key: entry.key.clone(), rid: entry.rid})
+            .collect();
+        res.sort();
+        res.truncate(k);
+        res
+    }
+
+    /// Get the records whose keys are within `radius` of `center`, closest first.
+    pub fn radius_query(&self, center: &[Field], radius: f64, metric: DistanceMetric) -> Vec<Neighbor> {
+        if radius < 0.0 {
+            return Vec::new();
+        }
+        let mut res: Vec<Neighbor> = self
+            .entries_near(center, radius.ceil() as i64)
+            .map(|entry| Neighbor {dist: metric.distance(center, &entry.key), key: entry.key.clone(), rid: entry.rid})
+            .filter(|neighbor| neighbor.dist.0 <= radius)
+            .collect();
+        res.sort();
+        res
+    }
+
+    /// Bulk load the index from full records and the ids they are stored under.
+    pub fn data_into_tree(&mut self, arr: &[(Vec<Field>, ValueId)]) {
+        for (val, rid) in arr {
+            self.insert(val, *rid);
+        }
+    }
+
+    /// The entries of the index in curve order.
+    pub fn get_all_entries(&self) -> Vec<IndexEntry> {
+        self.entries.values().flatten().cloned().collect()
+    }
+
+    /// Number of entries in the index.
+    pub fn num_entries(&self) -> usize {
+        self.size
+    }
+
+    /// Describe every place the index breaks its invariants: entries kept under the wrong curve
+    /// key, empty buckets, and a size that does not match the entries.
+    pub fn check_invariants(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (z, bucket) in &self.entries {
+            if bucket.is_empty() {
+                problems.push(format!("Curve key {} has no entries", z));
+            }
+            for entry in bucket {
+                if ZOrderIndex::z_value(&entry.key) != *z {
+                    problems.push(format!("Entry {:?} is kept under curve key {}", entry.key, z));
+                }
+            }
+        }
+        let count: usize = self.entries.values().map(Vec::len).sum();
+        if count != self.size {
+            problems.push(format!("Index holds {} entries but its size is {}", count, self.size));
+        }
+        problems
+    }
+}
+
+impl MdIndex for ZOrderIndex {
+    fn tree_type(&self) -> &'static str {
+        "Z"
+    }
+
+    fn idx_fields(&self) -> Vec<usize> {
+        self.idx_fields.clone()
+    }
+
+    fn insert(&mut self, val: &[Field], rid: ValueId) -> Result<(), CrustyError> {
+        ZOrderIndex::insert(self, val, rid);
+        Ok(())
+    }
+
+    fn delete(&mut self, val: &[Field], rid: ValueId) -> Result<(), CrustyError> {
+        ZOrderIndex::delete(self, val, rid);
+        Ok(())
+    }
+
+    fn bulk_load(&mut self, arr: &[(Vec<Field>, ValueId)]) -> Result<(), CrustyError> {
+        self.data_into_tree(arr);
+        Ok(())
+    }
+
+    fn point_query(&self, key: &[Field]) -> Result<Vec<ValueId>, CrustyError> {
+        Ok(self.get(key))
+    }
+
+    fn range_query(&self, min: &[Option<Field>], max: &[Option<Field>]) -> Result<Vec<ValueId>, CrustyError> {
+        Ok(self.partial_range_query(min, max))
+    }
+
+    fn knn_query(&self, key: &[Field], k: usize, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        Ok(self.knn_with_metric(key, k, metric))
+    }
+
+    fn radius_query(&self, center: &[Field], radius: f64, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        Ok(ZOrderIndex::radius_query(self, center, radius, metric))
+    }
+
+    // the entries are kept in a single sorted level
+    fn stats(&self) -> Result<MdIndexStats, CrustyError> {
+        Ok(MdIndexStats {num_entries: self.num_entries(), depth: usize::from(self.size > 0)})
+    }
+
+    fn serialize(&self, name: &str) -> SerializedIndex {
+        SerializedIndex::new(self, name, self.total_dim, self)
+    }
+
+    fn check_invariants(&self) -> Vec<String> {
+        ZOrderIndex::check_invariants(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Field::{IntField, StringField};
+
+    fn rid(i: usize) -> ValueId {
+        ValueId::new_slot(0, (i / 100) as u16, (i % 100) as u16)
+    }
+
+    // points spread over a small grid so that many of them share coordinates
+    fn records(n: usize, dim: usize) -> Vec<(Vec<Field>, ValueId)> {
+        (0..n).map(|i| ((0..dim).map(|d| IntField(((i * (7 + 6 * d) + d) % 31) as i32 - 15)).collect(), rid(i))).collect()
+    }
+
+    fn sorted(mut rids: Vec<ValueId>) -> Vec<ValueId> {
+        rids.sort();
+        rids
+    }
+
+    #[test]
+    fn test_interleave() {
+        assert_eq!(interleave(&[0b01, 0b10]), 0b0110);
+        assert_eq!(interleave(&[0b11, 0b00, 0b01]), 0b100101);
+        assert_eq!(interleave(&[u32::MAX; 4]), u128::MAX);
+        // the order of the coordinates is kept along each axis
+        assert!(ZOrderIndex::z_value(&[IntField(-1), IntField(0)]) < ZOrderIndex::z_value(&[IntField(0), IntField(0)]));
+        assert!(ZOrderIndex::z_value(&[StringField("ab".to_string())]) < ZOrderIndex::z_value(&[StringField("b".to_string())]));
+    }
+
+    #[test]
+    fn test_decompose_exact() {
+        let (lower, upper) = ([3, 5], [9, 12]);
+        let intervals = decompose(&lower, &upper, |_, _| usize::MAX);
+        assert!(intervals.windows(2).all(|pair| pair[0].1 + 1 < pair[1].0));
+        let covered: u128 = intervals.iter().map(|(start, end)| end - start + 1).sum();
+        assert_eq!(covered, 7 * 8);
+        for x in 3..=9 {
+            for y in 5..=12 {
+                let z = interleave(&[x, y]);
+                assert!(intervals.iter().any(|(start, end)| *start <= z && z <= *end));
+            }
+        }
+    }
+
+    #[test]
+    fn test_decompose_budget() {
+        let (lower, upper) = ([1, 2, 3], [1 << 20, 1 << 21, 1 << 22]);
+        let intervals = decompose(&lower, &upper, |_, _| usize::MAX);
+        assert!(intervals.len() <= MAX_INTERVALS);
+        for corner in [[1, 2, 3], [1 << 20, 1 << 21, 1 << 22], [1, 1 << 21, 3], [1 << 20, 2, 1 << 22]] {
+            let z = interleave(&corner);
+            assert!(intervals.iter().any(|(start, end)| *start <= z && z <= *end));
+        }
+        assert_eq!(decompose(&[0], &[u32::MAX], |_, _| usize::MAX), vec![(0, u128::from(u32::MAX))]);
+        // only the cells holding one of the points are kept, and cells with few points are not split
+        let points: Vec<u128> = (0..20).map(|i| interleave(&[100 + i, 200])).chain([interleave(&[5000, 7000])]).collect();
+        let count = |start: u128, end: u128| points.iter().filter(|z| start <= **z && **z <= end).count();
+        let intervals = decompose(&[1, 1], &[1 << 20, 1 << 20], count);
+        assert!(intervals.len() <= 3);
+        assert!(intervals.iter().all(|(start, end)| count(*start, *end) > 0));
+        assert!(points.iter().all(|z| intervals.iter().any(|(start, end)| start <= z && z <= end)));
+    }
+
+    #[test]
+    fn test_agrees_with_kd_tree() {
+        for dim in 1..=ZOrderIndex::MAX_DIM {
+            let arr = records(300, dim);
+            let mut z_order = ZOrderIndex::new(dim, (0..dim).collect(), dim);
+            let mut kd = KdTree::new(dim, (0..dim).collect(), dim);
+            z_order.data_into_tree(&arr);
+            kd.data_into_tree(&arr);
+            for (val, rid) in arr.iter().step_by(3) {
+                z_order.delete(val, *rid);
+                kd.delete(val, *rid);
+            }
+            assert!(z_order.check_invariants().is_empty());
+            assert_eq!(z_order.num_entries(), kd.num_entries());
+            for (val, _) in arr.iter().take(20) {
+                assert_eq!(sorted(z_order.get(val)), sorted(kd.get(val)));
+            }
+            let min = vec![Some(IntField(-5)), None, Some(IntField(0)), Some(IntField(-10))];
+            let max = vec![Some(IntField(8)), Some(IntField(2))];
+            assert_eq!(sorted(z_order.partial_range_query(&min, &max)), sorted(kd.partial_range_query(&min, &max)));
+            let center = vec![IntField(3); dim];
+            for metric in [DistanceMetric::Euclidean, DistanceMetric::Manhattan, DistanceMetric::Chebyshev] {
+                for k in [1, 12, 500] {
+                    assert_eq!(z_order.knn_with_metric(&center, k, metric), kd.knn_with_metric(&center, k, metric));
+                }
+                assert_eq!(z_order.radius_query(&center, 6.0, metric), kd.radius_query(&center, 6.0, metric));
+            }
+        }
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut index = ZOrderIndex::new(2, vec![0, 1], 2);
+        index.data_into_tree(&[(vec![IntField(i32::MAX), IntField(i32::MAX)], rid(0)), (vec![IntField(-4), IntField(2)], rid(1))]);
+        let serialized = serde_json::to_string(&index).unwrap();
+        assert!(serde_json::from_str::<ZOrderIndex>(&serialized).unwrap() == index);
+    }
+}
@@ -15,6 +15,7 @@ mod physical_op;
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum PhysicalOp {
     Scan(PhysicalScanNode),
+    IndexScan(PhysicalIndexScanNode),
     Project(PhysicalProjectNode),
     HashAggregate(PhysicalHashAggregateNode),
     SortedAggregate(PhysicalSortedAggregateNode),
@@ -285,6 +286,9 @@ impl PhysicalPlan {
                 ..
             })) => Some(*hash_table_state_id),
             Some(PhysicalOp::Scan(PhysicalScanNode { container_id, .. })) => Some(*container_id),
+            Some(PhysicalOp::IndexScan(PhysicalIndexScanNode { container_id, .. })) => {
+                Some(*container_id)
+            }
             _ => None,
         }
     }
@@ -720,6 +724,7 @@ mod tests {
             right_table: Some(String::from("b")),
             hash_table_state_id: 3,
             hash_table_key: FieldIdentifier::new("a", "acol"),
+            right_index: None,
         }));
         let ab_join2 = physical_plan2.add_node(PhysicalOp::HashJoin(PhysicalHashJoinNode {
             left: FieldIdentifier::new("a", "acol"),
@@ -729,6 +734,7 @@ mod tests {
             right_table: Some(String::from("b")),
             hash_table_state_id: 3,
             hash_table_key: FieldIdentifier::new("a", "acol"),
+            right_index: None,
         }));
 
         let abc_join = physical_plan2.add_node(PhysicalOp::HashJoin(PhysicalHashJoinNode {
@@ -739,6 +745,7 @@ mod tests {
             right_table: Some(String::from("b")),
             hash_table_state_id: 4,
             hash_table_key: FieldIdentifier::new("a", "acol"),
+            right_index: None,
         }));
 
         let aggregate =
@@ -766,6 +773,7 @@ mod tests {
             right_table: Some(String::from("b")),
             hash_table_state_id: 6,
             hash_table_key: FieldIdentifier::new("a", "acol"),
+            right_index: None,
         }));
 
         let project = physical_plan2.add_node(PhysicalOp::Project(PhysicalProjectNode {
@@ -9,6 +9,23 @@ pub struct PhysicalScanNode {
     pub container_id: ContainerId,
 }
 
+/// Physical Index Scan Operator
+/// Scans the records of a table whose indexed columns lie between `min` and `max`
+/// (inclusive), using one of the multidimensional indexes of the table.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PhysicalIndexScanNode {
+    pub alias: String,
+    pub container_id: ContainerId,
+    /// Name of the index to use.
+    pub index_name: String,
+    /// Kind of tree backing the index.
+    pub tree_type: String,
+    /// Lower bound of each indexed column, if it is bounded below.
+    pub min: Vec<Option<Field>>,
+    /// Upper bound of each indexed column, if it is bounded above.
+    pub max: Vec<Option<Field>>,
+}
+
 /// Physical Project Operator
 /// Same as Logical
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -66,6 +83,19 @@ pub struct PhysicalHashJoinNode {
     pub hash_table_state_id: ContainerId,
     /// Vector of the keys to hash by (seems like it may only need to be a single key for now)
     pub hash_table_key: FieldIdentifier,
+    /// Hash index of the right table on its join column, probed instead of building the hash table.
+    #[serde(default)]
+    pub right_index: Option<PhysicalJoinIndex>,
+}
+
+/// Index that a join probes for the records of its right table matching each left tuple.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PhysicalJoinIndex {
+    pub container_id: ContainerId,
+    /// Name of the index to use.
+    pub index_name: String,
+    /// Kind of index.
+    pub tree_type: String,
 }
 
 /// Physical Filter Operator
@@ -1,3 +1,4 @@
+use crate::ids::ContainerId;
 use crate::TableSchema;
 
 /// Table implementation.
@@ -20,3 +21,46 @@ impl Table {
         Table { name, schema }
     }
 }
+
+/// Metadata of an index built on a table.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct IndexInfo {
+    /// Index name.
+    pub name: String,
+    /// Kind of index, as named by its `tree_type` in `heapstore::index_registry::INDEX_TYPES`.
+    pub tree_type: String,
+    /// Container of the indexed table.
+    pub container_id: ContainerId,
+    /// Names of the indexed columns, in index order.
+    pub columns: Vec<String>,
+    /// Number of records in the index when the metadata was last refreshed.
+    #[serde(default)]
+    pub num_rows: usize,
+}
+
+impl IndexInfo {
+    /// Creates the metadata of a new index.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of index.
+    /// * `tree_type` - Kind of tree backing the index.
+    /// * `container_id` - Container of the indexed table.
+    /// * `columns` - Names of the indexed columns.
+    /// * `num_rows` - Number of records in the index.
+    pub fn new(
+        name: String,
+        tree_type: String,
+        container_id: ContainerId,
+        columns: Vec<String>,
+        num_rows: usize,
+    ) -> Self {
+        IndexInfo {
+            name,
+            tree_type,
+            container_id,
+            columns,
+            num_rows,
+        }
+    }
+}
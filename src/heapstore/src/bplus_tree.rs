@@ -0,0 +1,824 @@
+use crate::heapfile::HeapFile;
+use crate::page::Page;
+use common::ids::{PageId, ValueId};
+use common::md_index::{DistanceMetric, MdIndex, MdIndexStats, Neighbor, SerializedIndex};
+use common::{CrustyError, Field, PAGE_SIZE};
+use std::any::Any;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The first page of the file holds the metadata of the tree.
+const META_PAGE_ID: PageId = 0;
+
+/// Share of a page filled by a bulk load, leaving room for later inserts.
+const LOAD_FACTOR: f64 = 0.75;
+
+/// Entries are ordered by key and then by record id, so that the entries of a key can
+/// spread over several leaves and still be told apart by the separators above them.
+type Entry = (Field, ValueId);
+
+/// A node of the tree. Every node is serialized into a page of its own.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum BPlusNode {
+    /// Internal node with one more child than separators. The child at `i` holds the entries
+    /// from `separators[i - 1]` (inclusive) up to `separators[i]` (exclusive).
+    Internal {
+        separators: Vec<Entry>,
+        children: Vec<PageId>,
+    },
+    /// Leaf holding entries in order, linked to the leaves before and after it.
+    Leaf {
+        entries: Vec<Entry>,
+        prev: Option<PageId>,
+        next: Option<PageId>,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct BPlusMeta {
+    root: PageId,
+    idx_field: usize,
+    total_dim: usize,
+}
+
+/// A disk-resident B+ tree over a single attribute. Nodes are stored one per page in a heap
+/// file, and the leaves are linked both ways so that entries can be scanned in key order.
+///
+/// Nodes are split in half when they no longer fit in their page. Nodes are not merged on
+/// delete, so leaves can be left empty.
+pub struct BPlusTree {
+    hf: HeapFile,
+    path: PathBuf,
+    meta: BPlusMeta,
+}
+
+/// Entries of a B+ tree between two keys (inclusive), in key order or in reverse. The leaves
+/// are read one at a time as the scan reaches them.
+pub struct BPlusTreeIter<'a> {
+    tree: &'a BPlusTree,
+    // entries of the current leaf that are left, in scan order
+    entries: std::vec::IntoIter<Entry>,
+    next_leaf: Option<PageId>,
+    forward: bool,
+    min: Option<Field>,
+    max: Option<Field>,
+}
+
+impl BPlusTree {
+    /// Create a new empty tree stored in the file at `path`, replacing any file already there.
+    pub fn create(path: PathBuf, idx_field: usize, total_dim: usize) -> Result<Self, CrustyError> {
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        let hf = HeapFile::new(path.clone())?;
+        let meta = BPlusMeta {
+            root: META_PAGE_ID + 1,
+            idx_field,
+            total_dim,
+        };
+        let tree = BPlusTree { hf, path, meta };
+        tree.write_meta()?;
+        tree.append_node(&BPlusNode::Leaf {
+            entries: Vec::new(),
+            prev: None,
+            next: None,
+        })?;
+        Ok(tree)
+    }
+
+    /// Open a tree that was previously created in the file at `path`.
+    pub fn open(path: PathBuf) -> Result<Self, CrustyError> {
+        let hf = HeapFile::new(path.clone())?;
+        let bytes = hf
+            .read_page_from_file(META_PAGE_ID)?
+            .get_value(0)
+            .ok_or_else(|| CrustyError::CrustyError(String::from("Missing B+ tree metadata")))?;
+        let meta = serde_cbor::from_slice(&bytes)
+            .map_err(|e| CrustyError::CrustyError(format!("Invalid B+ tree metadata {}", e)))?;
+        Ok(BPlusTree { hf, path, meta })
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get_total_dim(&self) -> usize {
+        self.meta.total_dim
+    }
+
+    fn node_to_page(page_id: PageId, node: &BPlusNode) -> Result<Page, CrustyError> {
+        let bytes = serde_cbor::to_vec(node)
+            .map_err(|e| CrustyError::CrustyError(format!("Can't serialize B+ tree node {}", e)))?;
+        let mut page = Page::new(page_id);
+        match page.add_value(&bytes) {
+            Some(_) => Ok(page),
+            None => Err(CrustyError::CrustyError(String::from(
+                "B+ tree node does not fit in a page",
+            ))),
+        }
+    }
+
+    /// Check that an entry fits in a leaf and as a separator in an internal node, so that a key
+    /// too large for a page is rejected before the tree is changed.
+    fn check_entry_size(entry: &Entry) -> Result<(), CrustyError> {
+        let leaf = BPlusNode::Leaf {
+            entries: vec![entry.clone()],
+            prev: Some(0),
+            next: Some(0),
+        };
+        let internal = BPlusNode::Internal {
+            separators: vec![entry.clone()],
+            children: vec![0, 0],
+        };
+        BPlusTree::node_to_page(0, &leaf)?;
+        BPlusTree::node_to_page(0, &internal)?;
+        Ok(())
+    }
+
+    fn read_node(&self, page_id: PageId) -> Result<BPlusNode, CrustyError> {
+        let bytes = self
+            .hf
+            .read_page_from_file(page_id)?
+            .get_value(0)
+            .ok_or_else(|| CrustyError::CrustyError(format!("Empty B+ tree page {}", page_id)))?;
+        serde_cbor::from_slice(&bytes)
+            .map_err(|e| CrustyError::CrustyError(format!("Invalid B+ tree node {}", e)))
+    }
+
+    fn write_node(&self, page_id: PageId, node: &BPlusNode) -> Result<(), CrustyError> {
+        let page = BPlusTree::node_to_page(page_id, node)?;
+        self.hf.write_updated_page_to_file(&page, page_id)
+    }
+
+    /// Write the node to a new page at the end of the file and return its page id.
+    fn append_node(&self, node: &BPlusNode) -> Result<PageId, CrustyError> {
+        let page_id = self.hf.num_pages();
+        let page = BPlusTree::node_to_page(page_id, node)?;
+        self.hf.write_page_to_file(page)?;
+        Ok(page_id)
+    }
+
+    fn write_meta(&self) -> Result<(), CrustyError> {
+        let bytes = serde_cbor::to_vec(&self.meta)
+            .map_err(|e| CrustyError::CrustyError(format!("Can't serialize B+ tree metadata {}", e)))?;
+        let mut page = Page::new(META_PAGE_ID);
+        page.add_value(&bytes);
+        if self.hf.num_pages() == 0 {
+            self.hf.write_page_to_file(page)
+        } else {
+            self.hf.write_updated_page_to_file(&page, META_PAGE_ID)
+        }
+    }
+
+    // point a leaf back at a new leaf inserted before it
+    fn set_prev_leaf(&self, page_id: PageId, prev: PageId) -> Result<(), CrustyError> {
+        match self.read_node(page_id)? {
+            BPlusNode::Leaf { entries, next, .. } => {
+                self.write_node(page_id, &BPlusNode::Leaf { entries, prev: Some(prev), next })
+            }
+            BPlusNode::Internal { .. } => Err(CrustyError::CrustyError(format!(
+                "B+ tree page {} is not a leaf",
+                page_id
+            ))),
+        }
+    }
+
+    /// Pages from the root down to the leaf where `entry` belongs, the leaf last.
+    fn path_to(&self, entry: &Entry) -> Result<Vec<PageId>, CrustyError> {
+        let mut path = vec![self.meta.root];
+        while let BPlusNode::Internal { separators, children } = self.read_node(*path.last().unwrap())? {
+            path.push(children[separators.partition_point(|separator| separator <= entry)]);
+        }
+        Ok(path)
+    }
+
+    /// First leaf that can hold an entry with a key of at least `min`, or the leftmost leaf
+    /// without a bound.
+    fn first_leaf(&self, min: Option<&Field>) -> Result<PageId, CrustyError> {
+        let mut page_id = self.meta.root;
+        while let BPlusNode::Internal { separators, children } = self.read_node(page_id)? {
+            let idx = min.map_or(0, |min| separators.partition_point(|(key, _)| key < min));
+            page_id = children[idx];
+        }
+        Ok(page_id)
+    }
+
+    /// Last leaf that can hold an entry with a key of at most `max`, or the rightmost leaf
+    /// without a bound.
+    fn last_leaf(&self, max: Option<&Field>) -> Result<PageId, CrustyError> {
+        let mut page_id = self.meta.root;
+        while let BPlusNode::Internal { separators, children } = self.read_node(page_id)? {
+            let idx = max.map_or(separators.len(), |max| separators.partition_point(|(key, _)| key <= max));
+            page_id = children[idx];
+        }
+        Ok(page_id)
+    }
+
+    /// Insert a record into the tree. `val` is the full record and `rid` is where it is stored.
+    pub fn insert(&mut self, val: &[Field], rid: ValueId) -> Result<(), CrustyError> {
+        let entry = (val[self.meta.idx_field].clone(), rid);
+        BPlusTree::check_entry_size(&entry)?;
+        let mut path = self.path_to(&entry)?;
+        let page_id = path.pop().unwrap();
+        let (mut entries, prev, next) = match self.read_node(page_id)? {
+            BPlusNode::Leaf { entries, prev, next } => (entries, prev, next),
+            BPlusNode::Internal { .. } => {
+                return Err(CrustyError::CrustyError(String::from(
+                    "B+ tree search ended on an internal node",
+                )))
+            }
+        };
+        let idx = entries.partition_point(|other| *other < entry);
+        entries.insert(idx, entry);
+        let node = BPlusNode::Leaf { entries, prev, next };
+        if let Ok(page) = BPlusTree::node_to_page(page_id, &node) {
+            return self.hf.write_updated_page_to_file(&page, page_id);
+        }
+        let mut entries = match node {
+            BPlusNode::Leaf { entries, .. } => entries,
+            BPlusNode::Internal { .. } => unreachable!(),
+        };
+        // the right half moves to a new leaf linked in after the old one
+        let right = entries.split_off(entries.len() / 2);
+        let separator = right[0].clone();
+        let right_page_id = self.append_node(&BPlusNode::Leaf {
+            entries: right,
+            prev: Some(page_id),
+            next,
+        })?;
+        if let Some(next) = next {
+            self.set_prev_leaf(next, right_page_id)?;
+        }
+        self.write_node(page_id, &BPlusNode::Leaf {
+            entries,
+            prev,
+            next: Some(right_page_id),
+        })?;
+        self.insert_separator(path, page_id, separator, right_page_id)
+    }
+
+    /// Add the separator of a split child and the page of its new right sibling to the parent
+    /// at the end of `path`, splitting parents up the path as long as they overflow.
+    fn insert_separator(
+        &mut self,
+        mut path: Vec<PageId>,
+        mut page_id: PageId,
+        mut separator: Entry,
+        mut right_page_id: PageId,
+    ) -> Result<(), CrustyError> {
+        loop {
+            let parent_page_id = match path.pop() {
+                Some(parent_page_id) => parent_page_id,
+                None => {
+                    // the root was split, so the tree grows by one level
+                    self.meta.root = self.append_node(&BPlusNode::Internal {
+                        separators: vec![separator],
+                        children: vec![page_id, right_page_id],
+                    })?;
+                    return self.write_meta();
+                }
+            };
+            let (mut separators, mut children) = match self.read_node(parent_page_id)? {
+                BPlusNode::Internal { separators, children } => (separators, children),
+                BPlusNode::Leaf { .. } => {
+                    return Err(CrustyError::CrustyError(String::from(
+                        "B+ tree leaf found on the path to a leaf",
+                    )))
+                }
+            };
+            let idx = children
+                .iter()
+                .position(|child| *child == page_id)
+                .ok_or_else(|| CrustyError::CrustyError(String::from("B+ tree child not found")))?;
+            separators.insert(idx, separator);
+            children.insert(idx + 1, right_page_id);
+            let node = BPlusNode::Internal { separators, children };
+            if let Ok(page) = BPlusTree::node_to_page(parent_page_id, &node) {
+                return self.hf.write_updated_page_to_file(&page, parent_page_id);
+            }
+            let (mut separators, mut children) = match node {
+                BPlusNode::Internal { separators, children } => (separators, children),
+                BPlusNode::Leaf { .. } => unreachable!(),
+            };
+            // the middle separator moves up to tell the two halves apart
+            let mid = separators.len() / 2;
+            let right_separators = separators.split_off(mid + 1);
+            separator = separators.pop().unwrap();
+            let right_children = children.split_off(mid + 1);
+            self.write_node(parent_page_id, &BPlusNode::Internal { separators, children })?;
+            right_page_id = self.append_node(&BPlusNode::Internal {
+                separators: right_separators,
+                children: right_children,
+            })?;
+            page_id = parent_page_id;
+        }
+    }
+
+    /// Bulk load the tree from full records and the ids they are stored under. An empty tree is
+    /// built bottom up from the sorted entries, with nodes filled to `LOAD_FACTOR`, while
+    /// records are inserted one at a time into a tree that already has entries.
+    pub fn data_into_tree(&mut self, arr: &[(Vec<Field>, ValueId)]) -> Result<(), CrustyError> {
+        let is_empty = matches!(self.read_node(self.meta.root)?, BPlusNode::Leaf { ref entries, .. } if entries.is_empty());
+        if arr.is_empty() {
+            return Ok(());
+        }
+        if !is_empty {
+            for (val, rid) in arr {
+                self.insert(val, *rid)?;
+            }
+            return Ok(());
+        }
+        let mut entries: Vec<Entry> = arr.iter().map(|(val, rid)| (val[self.meta.idx_field].clone(), *rid)).collect();
+        entries.sort();
+        let leaves = BPlusTree::chunk_by_size(entries, |entry| entry)?;
+        // the first leaf replaces the empty root and the others are appended in order
+        let num_pages = usize::from(self.hf.num_pages());
+        let page_ids = (0..leaves.len())
+            .map(|i| if i == 0 { Ok(self.meta.root) } else { PageId::try_from(num_pages + i - 1) })
+            .collect::<Result<Vec<PageId>, _>>()
+            .map_err(|_| CrustyError::CrustyError(String::from("Too many pages for a B+ tree")))?;
+        let mut level = Vec::new();
+        for (i, entries) in leaves.into_iter().enumerate() {
+            let first = entries[0].clone();
+            let node = BPlusNode::Leaf {
+                entries,
+                prev: i.checked_sub(1).map(|i| page_ids[i]),
+                next: page_ids.get(i + 1).copied(),
+            };
+            if i == 0 {
+                self.write_node(page_ids[i], &node)?;
+            } else {
+                self.append_node(&node)?;
+            }
+            level.push((first, page_ids[i]));
+        }
+        while level.len() > 1 {
+            let mut next_level = Vec::new();
+            for group in BPlusTree::chunk_by_size(level, |(first, _)| first)? {
+                let first = group[0].0.clone();
+                let separators = group[1..].iter().map(|(first, _)| first.clone()).collect();
+                let children = group.iter().map(|(_, page_id)| *page_id).collect();
+                next_level.push((first, self.append_node(&BPlusNode::Internal { separators, children })?));
+            }
+            level = next_level;
+        }
+        self.meta.root = level[0].1;
+        self.write_meta()
+    }
+
+    /// Split items into groups that each take up to `LOAD_FACTOR` of a page once serialized.
+    /// `key` gives the part of an item that is stored in a node.
+    fn chunk_by_size<T, K, F>(items: Vec<T>, key: F) -> Result<Vec<Vec<T>>, CrustyError>
+    where
+        K: serde::Serialize,
+        F: Fn(&T) -> &K,
+    {
+        let budget = (PAGE_SIZE as f64 * LOAD_FACTOR) as usize;
+        let mut groups = vec![Vec::new()];
+        let mut size = 0;
+        for item in items {
+            let item_size = serde_cbor::to_vec(key(&item))
+                .map_err(|e| CrustyError::CrustyError(format!("Can't serialize B+ tree entry {}", e)))?
+                .len();
+            let group = groups.last_mut().unwrap();
+            if size + item_size > budget && group.len() > 1 {
+                groups.push(Vec::new());
+                size = 0;
+            }
+            size += item_size;
+            groups.last_mut().unwrap().push(item);
+        }
+        Ok(groups)
+    }
+
+    /// Delete the entry of the record stored under `rid`. `val` is the full record.
+    /// Returns whether the entry was found.
+    pub fn delete(&mut self, val: &[Field], rid: ValueId) -> Result<bool, CrustyError> {
+        let entry = (val[self.meta.idx_field].clone(), rid);
+        let page_id = *self.path_to(&entry)?.last().unwrap();
+        match self.read_node(page_id)? {
+            BPlusNode::Leaf { mut entries, prev, next } => match entries.binary_search(&entry) {
+                Ok(idx) => {
+                    entries.remove(idx);
+                    self.write_node(page_id, &BPlusNode::Leaf { entries, prev, next })?;
+                    Ok(true)
+                }
+                Err(_) => Ok(false),
+            },
+            BPlusNode::Internal { .. } => Err(CrustyError::CrustyError(String::from(
+                "B+ tree search ended on an internal node",
+            ))),
+        }
+    }
+
+    /// Entries with a key between `min` and `max` (inclusive) in key order, where a `None`
+    /// bound leaves its side open.
+    pub fn range(&self, min: Option<&Field>, max: Option<&Field>) -> Result<BPlusTreeIter<'_>, CrustyError> {
+        Ok(BPlusTreeIter::new(self, self.first_leaf(min)?, true, min, max))
+    }
+
+    /// Entries with a key between `min` and `max` (inclusive) from the largest key down.
+    pub fn range_rev(&self, min: Option<&Field>, max: Option<&Field>) -> Result<BPlusTreeIter<'_>, CrustyError> {
+        Ok(BPlusTreeIter::new(self, self.last_leaf(max)?, false, min, max))
+    }
+
+    /// Every entry of the tree in key order.
+    pub fn iter(&self) -> Result<BPlusTreeIter<'_>, CrustyError> {
+        self.range(None, None)
+    }
+
+    /// Get the ids of all records whose key is equal to `key`.
+    pub fn get(&self, key: &Field) -> Result<Vec<ValueId>, CrustyError> {
+        self.range(Some(key), Some(key))?.map(|entry| entry.map(|(_, rid)| rid)).collect()
+    }
+
+    /// Get the `k` records whose keys are closest to `key`, closest first. On a single attribute
+    /// they are among the `k` entries on either side of the key and the entries tied with them.
+    pub fn knn_with_metric(&self, key: &Field, k: usize, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        let mut res = Vec::new();
+        if k == 0 {
+            return Ok(res);
+        }
+        for (iter, forward) in [(self.range(Some(key), None)?, true), (self.range_rev(None, Some(key))?, false)] {
+            let mut found = 0;
+            let mut last_dist = 0.0;
+            for entry in iter {
+                let (field, rid) = entry?;
+                // entries equal to the key are found by the forward scan
+                if !forward && field == *key {
+                    continue;
+                }
+                let dist = metric.distance(std::slice::from_ref(key), std::slice::from_ref(&field));
+                if found >= k && last_dist < dist.0 {
+                    break;
+                }
+                found += 1;
+                last_dist = dist.0;
+                res.push(Neighbor { dist, key: vec![field], rid });
+            }
+        }
+        res.sort();
+        res.truncate(k);
+        Ok(res)
+    }
+
+    /// Get the records whose keys are within `radius` of `center`, closest first.
+    pub fn radius_query(&self, center: &Field, radius: f64, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        if radius < 0.0 {
+            return Ok(Vec::new());
+        }
+        // integer keys are within the radius rounded down of the center, while strings are
+        // compared on their first bytes and are all scanned
+        let (min, max) = match center {
+            Field::IntField(center) => {
+                let bound = |offset: i64| {
+                    let bound = i64::from(*center).saturating_add(offset);
+                    Field::IntField(bound.clamp(i64::from(i32::MIN), i64::from(i32::MAX)) as i32)
+                };
+                let radius = radius.floor() as i64;
+                (Some(bound(-radius)), Some(bound(radius)))
+            }
+            Field::StringField(_) => (None, None),
+        };
+        let mut res = Vec::new();
+        for entry in self.range(min.as_ref(), max.as_ref())? {
+            let (field, rid) = entry?;
+            let dist = metric.distance(std::slice::from_ref(center), std::slice::from_ref(&field));
+            if dist.0 <= radius {
+                res.push(Neighbor { dist, key: vec![field], rid });
+            }
+        }
+        res.sort();
+        Ok(res)
+    }
+
+    /// Number of entries in the tree.
+    pub fn num_entries(&self) -> Result<usize, CrustyError> {
+        let mut res = 0;
+        for entry in self.iter()? {
+            entry?;
+            res += 1;
+        }
+        Ok(res)
+    }
+
+    /// Number of levels from the root down to the leaves.
+    pub fn depth(&self) -> Result<usize, CrustyError> {
+        let mut depth = 1;
+        let mut page_id = self.meta.root;
+        while let BPlusNode::Internal { children, .. } = self.read_node(page_id)? {
+            depth += 1;
+            page_id = children[0];
+        }
+        Ok(depth)
+    }
+
+    /// Describe every place the tree breaks its invariants: nodes out of order or outside of the
+    /// separators above them, leaves at different depths and leaves linked out of key order.
+    pub fn check_invariants(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        // leaves in key order, with their depth and links
+        let mut leaves = Vec::new();
+        let mut stack: Vec<(PageId, usize, Option<Entry>, Option<Entry>)> = vec![(self.meta.root, 1, None, None)];
+        while let Some((page_id, depth, lower, upper)) = stack.pop() {
+            let in_bounds = |entry: &Entry| lower.as_ref().is_none_or(|lower| lower <= entry) && upper.as_ref().is_none_or(|upper| entry < upper);
+            match self.read_node(page_id) {
+                Ok(BPlusNode::Leaf { entries, prev, next }) => {
+                    if !entries.windows(2).all(|pair| pair[0] < pair[1]) {
+                        res.push(format!("B+ tree leaf {} is not sorted", page_id));
+                    }
+                    if !entries.iter().all(in_bounds) {
+                        res.push(format!("B+ tree leaf {} has entries outside of its separators", page_id));
+                    }
+                    leaves.push((page_id, depth, prev, next));
+                }
+                Ok(BPlusNode::Internal { separators, children }) => {
+                    if children.len() != separators.len() + 1 {
+                        res.push(format!(
+                            "B+ tree node {} has {} children for {} separators",
+                            page_id,
+                            children.len(),
+                            separators.len()
+                        ));
+                        continue;
+                    }
+                    if !separators.windows(2).all(|pair| pair[0] < pair[1]) {
+                        res.push(format!("B+ tree node {} is not sorted", page_id));
+                    }
+                    if !separators.iter().all(in_bounds) {
+                        res.push(format!("B+ tree node {} has separators outside of its parent's", page_id));
+                    }
+                    // children are pushed last first so that leaves are reached in key order
+                    for (i, child) in children.iter().enumerate().rev() {
+                        let child_lower = if i == 0 { lower.clone() } else { Some(separators[i - 1].clone()) };
+                        let child_upper = separators.get(i).cloned().or_else(|| upper.clone());
+                        stack.push((*child, depth + 1, child_lower, child_upper));
+                    }
+                }
+                Err(e) => res.push(format!("B+ tree page {} can't be read: {:?}", page_id, e)),
+            }
+        }
+        for (i, (page_id, depth, prev, next)) in leaves.iter().enumerate() {
+            if *depth != leaves[0].1 {
+                res.push(format!("B+ tree leaf {} is at depth {} rather than {}", page_id, depth, leaves[0].1));
+            }
+            let expected_prev = i.checked_sub(1).map(|i| leaves[i].0);
+            let expected_next = leaves.get(i + 1).map(|leaf| leaf.0);
+            if *prev != expected_prev || *next != expected_next {
+                res.push(format!(
+                    "B+ tree leaf {} is linked to {:?} and {:?} rather than {:?} and {:?}",
+                    page_id, prev, next, expected_prev, expected_next
+                ));
+            }
+        }
+        res
+    }
+}
+
+impl<'a> BPlusTreeIter<'a> {
+    fn new(tree: &'a BPlusTree, leaf: PageId, forward: bool, min: Option<&Field>, max: Option<&Field>) -> Self {
+        BPlusTreeIter {
+            tree,
+            entries: Vec::new().into_iter(),
+            next_leaf: Some(leaf),
+            forward,
+            min: min.cloned(),
+            max: max.cloned(),
+        }
+    }
+}
+
+impl Iterator for BPlusTreeIter<'_> {
+    type Item = Result<(Field, ValueId), CrustyError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                let below = self.min.as_ref().is_some_and(|min| entry.0 < *min);
+                let above = self.max.as_ref().is_some_and(|max| entry.0 > *max);
+                // entries before the start of the range are skipped, and the scan ends past its end
+                if (self.forward && above) || (!self.forward && below) {
+                    self.next_leaf = None;
+                    self.entries = Vec::new().into_iter();
+                    return None;
+                }
+                if below || above {
+                    continue;
+                }
+                return Some(Ok(entry));
+            }
+            let page_id = self.next_leaf.take()?;
+            match self.tree.read_node(page_id) {
+                Ok(BPlusNode::Leaf { mut entries, prev, next }) => {
+                    if self.forward {
+                        self.next_leaf = next;
+                    } else {
+                        self.next_leaf = prev;
+                        entries.reverse();
+                    }
+                    self.entries = entries.into_iter();
+                }
+                Ok(BPlusNode::Internal { .. }) => {
+                    return Some(Err(CrustyError::CrustyError(format!(
+                        "B+ tree leaf {} links to an internal node",
+                        page_id
+                    ))))
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+// queries on the index are given the key as a slice holding just the one indexed attribute
+fn single_key(key: &[Field]) -> Result<&Field, CrustyError> {
+    match key {
+        [field] => Ok(field),
+        _ => Err(CrustyError::CrustyError(format!("B+ tree queries need a key of 1 attribute, got {}", key.len()))),
+    }
+}
+
+impl MdIndex for BPlusTree {
+    fn tree_type(&self) -> &'static str {
+        "BTREE"
+    }
+
+    fn idx_fields(&self) -> Vec<usize> {
+        vec![self.meta.idx_field]
+    }
+
+    fn insert(&mut self, val: &[Field], rid: ValueId) -> Result<(), CrustyError> {
+        BPlusTree::insert(self, val, rid)
+    }
+
+    fn delete(&mut self, val: &[Field], rid: ValueId) -> Result<(), CrustyError> {
+        BPlusTree::delete(self, val, rid).map(|_| ())
+    }
+
+    fn bulk_load(&mut self, arr: &[(Vec<Field>, ValueId)]) -> Result<(), CrustyError> {
+        self.data_into_tree(arr)
+    }
+
+    fn point_query(&self, key: &[Field]) -> Result<Vec<ValueId>, CrustyError> {
+        self.get(single_key(key)?)
+    }
+
+    fn range_query(&self, min: &[Option<Field>], max: &[Option<Field>]) -> Result<Vec<ValueId>, CrustyError> {
+        let (min, max) = (min.first().and_then(Option::as_ref), max.first().and_then(Option::as_ref));
+        self.range(min, max)?.map(|entry| entry.map(|(_, rid)| rid)).collect()
+    }
+
+    fn knn_query(&self, key: &[Field], k: usize, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        self.knn_with_metric(single_key(key)?, k, metric)
+    }
+
+    fn radius_query(&self, center: &[Field], radius: f64, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        BPlusTree::radius_query(self, single_key(center)?, radius, metric)
+    }
+
+    fn stats(&self) -> Result<MdIndexStats, CrustyError> {
+        Ok(MdIndexStats {num_entries: self.num_entries()?, depth: self.depth()?})
+    }
+
+    fn serialize(&self, name: &str) -> SerializedIndex {
+        SerializedIndex::new(self, name, self.get_total_dim(), self.get_path())
+    }
+
+    fn check_invariants(&self) -> Vec<String> {
+        BPlusTree::check_invariants(self)
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(self.get_path())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::testutil::*;
+    use temp_testdir::TempDir;
+
+    fn rows(n: i32) -> Vec<(Vec<Field>, ValueId)> {
+        (0..n)
+            .map(|i| {
+                let row = vec![Field::IntField(i), Field::IntField((i * 37) % 1001)];
+                (row, ValueId::new_slot(1, (i / 100) as u16, (i % 100) as u16))
+            })
+            .collect()
+    }
+
+    // ids of the rows whose key is between min and max, in key order
+    fn brute_force_range(rows: &[(Vec<Field>, ValueId)], min: i32, max: i32) -> Vec<ValueId> {
+        let mut res: Vec<(Field, ValueId)> = rows
+            .iter()
+            .filter(|(row, _)| Field::IntField(min) <= row[1] && row[1] <= Field::IntField(max))
+            .map(|(row, rid)| (row[1].clone(), *rid))
+            .collect();
+        res.sort();
+        res.into_iter().map(|(_, rid)| rid).collect()
+    }
+
+    fn tree_path(tdir: &TempDir, name: &str) -> PathBuf {
+        let mut path = tdir.to_path_buf();
+        path.push(name);
+        path.set_extension("bpt");
+        path
+    }
+
+    #[test]
+    fn hs_bplus_insert_query_reopen() {
+        init();
+        let tdir = TempDir::new(gen_random_dir(), true);
+        let path = tree_path(&tdir, "bplus_test");
+        let rows = rows(3000);
+        let mut tree = BPlusTree::create(path.clone(), 1, 2).unwrap();
+        for (row, rid) in &rows {
+            tree.insert(row, *rid).unwrap();
+        }
+        // the tree no longer fits in a single leaf
+        assert!(tree.depth().unwrap() > 1);
+        assert_eq!(Vec::<String>::new(), tree.check_invariants());
+        assert_eq!(3000, tree.num_entries().unwrap());
+
+        let scanned: Vec<ValueId> = tree.range(Some(&Field::IntField(100)), Some(&Field::IntField(300))).unwrap().map(|entry| entry.unwrap().1).collect();
+        assert_eq!(brute_force_range(&rows, 100, 300), scanned);
+        let mut reversed: Vec<ValueId> = tree.range_rev(Some(&Field::IntField(100)), Some(&Field::IntField(300))).unwrap().map(|entry| entry.unwrap().1).collect();
+        reversed.reverse();
+        assert_eq!(scanned, reversed);
+        let keys: Vec<Field> = tree.iter().unwrap().map(|entry| entry.unwrap().0).collect();
+        assert_eq!(3000, keys.len());
+        assert!(keys.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(brute_force_range(&rows, 42, 42), tree.get(&Field::IntField(42)).unwrap());
+        assert!(tree.get(&Field::IntField(3000)).unwrap().is_empty());
+        // queries take the key alone, not the record it came from
+        assert_eq!(tree.get(&rows[42].0[1]).unwrap(), tree.point_query(&tree.key_of(&rows[42].0)).unwrap());
+        assert!(tree.point_query(&rows[42].0).is_err());
+
+        let key = Field::IntField(500);
+        let neighbors = |rows: &[(Vec<Field>, ValueId)]| -> Vec<Neighbor> {
+            let mut neighbors: Vec<Neighbor> = rows
+                .iter()
+                .map(|(row, rid)| Neighbor {dist: DistanceMetric::Euclidean.distance(&row[1..], &[key.clone()]), key: row[1..].to_vec(), rid: *rid})
+                .collect();
+            neighbors.sort();
+            neighbors
+        };
+        let mut expected = neighbors(&rows);
+        expected.truncate(12);
+        assert_eq!(expected, tree.knn_with_metric(&key, 12, DistanceMetric::Euclidean).unwrap());
+        let expected: Vec<Neighbor> = neighbors(&rows).into_iter().filter(|neighbor| neighbor.dist.0 <= 2.5).collect();
+        assert_eq!(expected, tree.radius_query(&key, 2.5, DistanceMetric::Manhattan).unwrap());
+
+        for (row, rid) in &rows[..1500] {
+            assert!(tree.delete(row, *rid).unwrap());
+        }
+        assert!(!tree.delete(&rows[0].0, rows[0].1).unwrap());
+        assert_eq!(Vec::<String>::new(), tree.check_invariants());
+        drop(tree);
+
+        let tree = BPlusTree::open(path).unwrap();
+        let scanned: Vec<ValueId> = tree.range(Some(&Field::IntField(100)), Some(&Field::IntField(300))).unwrap().map(|entry| entry.unwrap().1).collect();
+        assert_eq!(brute_force_range(&rows[1500..], 100, 300), scanned);
+    }
+
+    #[test]
+    fn hs_bplus_key_too_large() {
+        init();
+        let tdir = TempDir::new(gen_random_dir(), true);
+        let mut tree = BPlusTree::create(tree_path(&tdir, "bplus_large"), 1, 2).unwrap();
+        let small = vec![Field::IntField(0), Field::StringField(String::from("x"))];
+        tree.insert(&small, ValueId::new_slot(1, 0, 0)).unwrap();
+        let large = vec![Field::IntField(1), Field::StringField("y".repeat(PAGE_SIZE))];
+        assert!(tree.insert(&large, ValueId::new_slot(1, 0, 1)).is_err());
+        // the tree is left as it was
+        assert_eq!(1, tree.num_entries().unwrap());
+        assert_eq!(Vec::<String>::new(), tree.check_invariants());
+    }
+
+    #[test]
+    fn hs_bplus_bulk_load() {
+        init();
+        let tdir = TempDir::new(gen_random_dir(), true);
+        let rows = rows(3000);
+        let mut loaded = BPlusTree::create(tree_path(&tdir, "loaded"), 1, 2).unwrap();
+        loaded.data_into_tree(&rows[..2500]).unwrap();
+        assert_eq!(Vec::<String>::new(), loaded.check_invariants());
+        // a tree with entries takes the next records one at a time
+        loaded.data_into_tree(&rows[2500..]).unwrap();
+        assert_eq!(Vec::<String>::new(), loaded.check_invariants());
+        let mut inserted = BPlusTree::create(tree_path(&tdir, "inserted"), 1, 2).unwrap();
+        for (row, rid) in &rows {
+            inserted.insert(row, *rid).unwrap();
+        }
+        let entries = |tree: &BPlusTree| -> Vec<(Field, ValueId)> { tree.iter().unwrap().map(Result::unwrap).collect() };
+        assert_eq!(entries(&inserted), entries(&loaded));
+        // bulk loaded leaves are left with room for inserts
+        assert!(loaded.hf.num_pages() < inserted.hf.num_pages());
+        assert_eq!(brute_force_range(&rows, 0, 10), loaded.range_query(&[None], &[Some(Field::IntField(10))]).unwrap());
+    }
+}
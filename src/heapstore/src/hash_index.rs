@@ -0,0 +1,641 @@
+use crate::heapfile::HeapFile;
+use crate::page::Page;
+use common::ids::{PageId, ValueId};
+use common::md_index::{DistanceMetric, MdIndex, MdIndexStats, Neighbor, SerializedIndex};
+use common::{CrustyError, Field, PAGE_SIZE};
+use std::any::Any;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The first page of the file holds the metadata of the index.
+const META_PAGE_ID: PageId = 0;
+
+/// Number of directory slots stored in each directory page.
+const DIR_PAGE_SLOTS: usize = 1024;
+
+/// Buckets are not split past this depth. Keys whose hashes agree on this many low bits
+/// share a bucket, which grows overflow pages once it is full.
+const MAX_DEPTH: u32 = 16;
+
+/// Bytes of a page that the entries of a bucket can take once serialized, leaving room for
+/// the page header and the rest of the bucket.
+const PAGE_BUDGET: usize = PAGE_SIZE - 128;
+
+type Entry = (Field, ValueId);
+
+/// A bucket page. The first page of a bucket is pointed to by the directory and the pages
+/// after it hold the entries that did not fit.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct HashBucket {
+    /// Number of low bits of the hash shared by every key of the bucket.
+    local_depth: u32,
+    entries: Vec<Entry>,
+    overflow: Option<PageId>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct HashMeta {
+    /// Number of low bits of the hash used to pick a directory slot.
+    global_depth: u32,
+    /// Pages holding the directory, in slot order.
+    dir_pages: Vec<PageId>,
+    idx_field: usize,
+    total_dim: usize,
+}
+
+/// A disk-resident extendible hash index over a single attribute. Buckets are stored one per
+/// page in a heap file, along with a directory mapping the low bits of a key's hash to the
+/// bucket holding it.
+///
+/// A full bucket is split in two on the next bit of the hash, doubling the directory when the
+/// bucket already uses all of its bits. Buckets are not merged on delete, and the pages left
+/// over when a bucket with overflow pages is split are not reused.
+pub struct HashIndex {
+    hf: HeapFile,
+    path: PathBuf,
+    meta: HashMeta,
+    directory: Vec<PageId>,
+}
+
+impl HashIndex {
+    /// Create a new empty index stored in the file at `path`, replacing any file already there.
+    pub fn create(path: PathBuf, idx_field: usize, total_dim: usize) -> Result<Self, CrustyError> {
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        let hf = HeapFile::new(path.clone())?;
+        let meta = HashMeta {
+            global_depth: 0,
+            dir_pages: Vec::new(),
+            idx_field,
+            total_dim,
+        };
+        let mut index = HashIndex {
+            hf,
+            path,
+            meta,
+            directory: vec![META_PAGE_ID + 1],
+        };
+        index.write_meta()?;
+        index.write_bucket(
+            META_PAGE_ID + 1,
+            &HashBucket {
+                local_depth: 0,
+                entries: Vec::new(),
+                overflow: None,
+            },
+        )?;
+        index.write_directory()?;
+        Ok(index)
+    }
+
+    /// Open an index that was previously created in the file at `path`.
+    pub fn open(path: PathBuf) -> Result<Self, CrustyError> {
+        let hf = HeapFile::new(path.clone())?;
+        let meta: HashMeta = HashIndex::read_value(&hf, META_PAGE_ID, "metadata")?;
+        let mut directory = Vec::new();
+        for page_id in &meta.dir_pages {
+            let slots: Vec<PageId> = HashIndex::read_value(&hf, *page_id, "directory")?;
+            directory.extend(slots);
+        }
+        let index = HashIndex { hf, path, meta, directory };
+        if index.directory.len() != 1 << index.meta.global_depth {
+            return Err(CrustyError::CrustyError(format!(
+                "Hash index directory has {} slots rather than {}",
+                index.directory.len(),
+                1 << index.meta.global_depth
+            )));
+        }
+        Ok(index)
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get_total_dim(&self) -> usize {
+        self.meta.total_dim
+    }
+
+    /// Hash of a key. Where entries are stored depends on it, so unlike the std hasher it
+    /// can't change between builds. This is FNV-1a over the type and bytes of the key.
+    fn hash_key(key: &Field) -> u64 {
+        let (tag, bytes) = match key {
+            Field::IntField(i) => (0u8, i.to_le_bytes().to_vec()),
+            Field::StringField(s) => (1u8, s.as_bytes().to_vec()),
+        };
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in std::iter::once(tag).chain(bytes) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        hash
+    }
+
+    fn low_bits(hash: u64, depth: u32) -> usize {
+        (hash & ((1u64 << depth) - 1)) as usize
+    }
+
+    /// Directory slot of a key.
+    fn slot(&self, key: &Field) -> usize {
+        HashIndex::low_bits(HashIndex::hash_key(key), self.meta.global_depth)
+    }
+
+    fn value_to_page<T: serde::Serialize>(page_id: PageId, value: &T, what: &str) -> Result<Page, CrustyError> {
+        let bytes = serde_cbor::to_vec(value)
+            .map_err(|e| CrustyError::CrustyError(format!("Can't serialize hash index {} {}", what, e)))?;
+        let mut page = Page::new(page_id);
+        match page.add_value(&bytes) {
+            Some(_) => Ok(page),
+            None => Err(CrustyError::CrustyError(format!(
+                "Hash index {} does not fit in a page",
+                what
+            ))),
+        }
+    }
+
+    fn read_value<T: serde::de::DeserializeOwned>(hf: &HeapFile, page_id: PageId, what: &str) -> Result<T, CrustyError> {
+        let bytes = hf
+            .read_page_from_file(page_id)?
+            .get_value(0)
+            .ok_or_else(|| CrustyError::CrustyError(format!("Empty hash index page {}", page_id)))?;
+        serde_cbor::from_slice(&bytes)
+            .map_err(|e| CrustyError::CrustyError(format!("Invalid hash index {} {}", what, e)))
+    }
+
+    /// Write a page in place, or at the end of the file when `page_id` is the next page.
+    fn write_page(&self, page_id: PageId, page: Page) -> Result<(), CrustyError> {
+        if page_id == self.hf.num_pages() {
+            self.hf.write_page_to_file(page)
+        } else {
+            self.hf.write_updated_page_to_file(&page, page_id)
+        }
+    }
+
+    fn write_bucket(&self, page_id: PageId, bucket: &HashBucket) -> Result<(), CrustyError> {
+        self.write_page(page_id, HashIndex::value_to_page(page_id, bucket, "bucket")?)
+    }
+
+    fn write_meta(&self) -> Result<(), CrustyError> {
+        self.write_page(META_PAGE_ID, HashIndex::value_to_page(META_PAGE_ID, &self.meta, "metadata")?)
+    }
+
+    /// Write every page of the directory, adding pages as the directory grows.
+    fn write_directory(&mut self) -> Result<(), CrustyError> {
+        let num_chunks = self.directory.len().div_ceil(DIR_PAGE_SLOTS);
+        for i in 0..num_chunks {
+            if i == self.meta.dir_pages.len() {
+                self.meta.dir_pages.push(self.hf.num_pages());
+            }
+            let page_id = self.meta.dir_pages[i];
+            let end = self.directory.len().min((i + 1) * DIR_PAGE_SLOTS);
+            let chunk = &self.directory[i * DIR_PAGE_SLOTS..end];
+            self.write_page(page_id, HashIndex::value_to_page(page_id, &chunk, "directory")?)?;
+        }
+        self.write_meta()
+    }
+
+    /// Pages of the bucket starting at `head`, along with their contents.
+    fn read_chain(&self, head: PageId) -> Result<Vec<(PageId, HashBucket)>, CrustyError> {
+        let mut chain = Vec::new();
+        let mut page_id = Some(head);
+        while let Some(id) = page_id {
+            let bucket: HashBucket = HashIndex::read_value(&self.hf, id, "bucket")?;
+            page_id = bucket.overflow;
+            chain.push((id, bucket));
+        }
+        Ok(chain)
+    }
+
+    /// Write the entries of a bucket over as many pages as they need and return the first
+    /// page. Pages are taken from `free` before new ones are added to the file.
+    fn write_chain(&self, free: &mut Vec<PageId>, local_depth: u32, entries: Vec<Entry>) -> Result<PageId, CrustyError> {
+        let mut pages: Vec<Vec<Entry>> = vec![Vec::new()];
+        let mut size = 0;
+        for entry in entries {
+            let entry_size = serde_cbor::to_vec(&entry)
+                .map_err(|e| CrustyError::CrustyError(format!("Can't serialize hash index entry {}", e)))?
+                .len();
+            if size + entry_size > PAGE_BUDGET && !pages.last().unwrap().is_empty() {
+                pages.push(Vec::new());
+                size = 0;
+            }
+            size += entry_size;
+            pages.last_mut().unwrap().push(entry);
+        }
+        let mut next_new = self.hf.num_pages();
+        let mut page_ids = Vec::new();
+        for _ in 0..pages.len() {
+            if free.is_empty() {
+                page_ids.push(next_new);
+                next_new += 1;
+            } else {
+                page_ids.push(free.remove(0));
+            }
+        }
+        for (i, entries) in pages.into_iter().enumerate() {
+            let bucket = HashBucket {
+                local_depth,
+                entries,
+                overflow: page_ids.get(i + 1).copied(),
+            };
+            self.write_bucket(page_ids[i], &bucket)?;
+        }
+        Ok(page_ids[0])
+    }
+
+    /// Split the bucket at a directory slot in two on the next bit of the hash, doubling the
+    /// directory first if the bucket already uses every bit of it.
+    fn split(&mut self, slot: usize, chain: Vec<(PageId, HashBucket)>) -> Result<(), CrustyError> {
+        let local_depth = chain[0].1.local_depth;
+        if local_depth == self.meta.global_depth {
+            self.directory.extend_from_within(..);
+            self.meta.global_depth += 1;
+        }
+        let bit = 1usize << local_depth;
+        let mut free = Vec::new();
+        let mut low = Vec::new();
+        let mut high = Vec::new();
+        for (page_id, bucket) in chain {
+            free.push(page_id);
+            for entry in bucket.entries {
+                if HashIndex::hash_key(&entry.0) as usize & bit == 0 {
+                    low.push(entry);
+                } else {
+                    high.push(entry);
+                }
+            }
+        }
+        let low_head = self.write_chain(&mut free, local_depth + 1, low)?;
+        let high_head = self.write_chain(&mut free, local_depth + 1, high)?;
+        // every slot that pointed to the old bucket shares its low bits
+        for s in (slot & (bit - 1)..self.directory.len()).step_by(bit) {
+            self.directory[s] = if s & bit == 0 { low_head } else { high_head };
+        }
+        self.write_directory()
+    }
+
+    /// Insert a record into the index. `val` is the full record and `rid` is where it is stored.
+    pub fn insert(&mut self, val: &[Field], rid: ValueId) -> Result<(), CrustyError> {
+        let entry = (val[self.meta.idx_field].clone(), rid);
+        let hash = HashIndex::hash_key(&entry.0);
+        loop {
+            let slot = self.slot(&entry.0);
+            let mut chain = self.read_chain(self.directory[slot])?;
+            for (page_id, bucket) in chain.iter_mut() {
+                bucket.entries.push(entry.clone());
+                if let Ok(page) = HashIndex::value_to_page(*page_id, bucket, "bucket") {
+                    return self.hf.write_updated_page_to_file(&page, *page_id);
+                }
+                bucket.entries.pop();
+            }
+            // splitting can only help when some key of the bucket differs from the new one
+            // within the bits that buckets are split on
+            let local_depth = chain[0].1.local_depth;
+            let splittable = local_depth < MAX_DEPTH
+                && chain.iter().flat_map(|(_, bucket)| &bucket.entries).any(|(key, _)| {
+                    HashIndex::low_bits(HashIndex::hash_key(key), MAX_DEPTH) != HashIndex::low_bits(hash, MAX_DEPTH)
+                });
+            if splittable {
+                self.split(slot, chain)?;
+                continue;
+            }
+            let overflow = self.hf.num_pages();
+            self.write_bucket(
+                overflow,
+                &HashBucket {
+                    local_depth,
+                    entries: vec![entry],
+                    overflow: None,
+                },
+            )?;
+            let (last_id, mut last) = chain.pop().unwrap();
+            last.overflow = Some(overflow);
+            return self.write_bucket(last_id, &last);
+        }
+    }
+
+    /// Insert every record of a table into the index, one at a time.
+    pub fn data_into_tree(&mut self, arr: &[(Vec<Field>, ValueId)]) -> Result<(), CrustyError> {
+        for (val, rid) in arr {
+            self.insert(val, *rid)?;
+        }
+        Ok(())
+    }
+
+    /// Delete the entry of the record stored under `rid`. `val` is the full record.
+    /// Returns whether the entry was found.
+    pub fn delete(&mut self, val: &[Field], rid: ValueId) -> Result<bool, CrustyError> {
+        let entry = (val[self.meta.idx_field].clone(), rid);
+        for (page_id, mut bucket) in self.read_chain(self.directory[self.slot(&entry.0)])? {
+            if let Some(pos) = bucket.entries.iter().position(|other| *other == entry) {
+                bucket.entries.remove(pos);
+                self.write_bucket(page_id, &bucket)?;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Get the ids of the records whose key is equal to `key`.
+    pub fn get(&self, key: &Field) -> Result<Vec<ValueId>, CrustyError> {
+        let mut res = Vec::new();
+        for (_, bucket) in self.read_chain(self.directory[self.slot(key)])? {
+            res.extend(bucket.entries.into_iter().filter(|(other, _)| other == key).map(|(_, rid)| rid));
+        }
+        Ok(res)
+    }
+
+    /// First page of every bucket, each listed once.
+    fn bucket_heads(&self) -> Vec<PageId> {
+        let mut seen = HashSet::new();
+        self.directory.iter().copied().filter(|head| seen.insert(*head)).collect()
+    }
+
+    /// Every entry of the index, in no particular order.
+    pub fn entries(&self) -> Result<Vec<Entry>, CrustyError> {
+        let mut res = Vec::new();
+        for head in self.bucket_heads() {
+            for (_, bucket) in self.read_chain(head)? {
+                res.extend(bucket.entries);
+            }
+        }
+        Ok(res)
+    }
+
+    /// Get the ids of the records whose key is between `min` and `max` (inclusive). Only an
+    /// equal `min` and `max` is answered from a single bucket, other ranges read every bucket.
+    pub fn range(&self, min: Option<&Field>, max: Option<&Field>) -> Result<Vec<ValueId>, CrustyError> {
+        if let (Some(min), Some(max)) = (min, max) {
+            if min == max {
+                return self.get(min);
+            }
+        }
+        Ok(self
+            .entries()?
+            .into_iter()
+            .filter(|(key, _)| min.is_none_or(|min| min <= key) && max.is_none_or(|max| key <= max))
+            .map(|(_, rid)| rid)
+            .collect())
+    }
+
+    /// Get the records whose keys are within `radius` of `center`, closest first. Keys are
+    /// not ordered by the index, so every bucket is read.
+    pub fn radius_query(&self, center: &Field, radius: f64, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        let mut res = self.neighbors(center, metric)?;
+        res.retain(|neighbor| neighbor.dist.0 <= radius);
+        Ok(res)
+    }
+
+    /// Get the `k` records closest to `key`. Keys are not ordered by the index, so every
+    /// bucket is read.
+    pub fn knn_with_metric(&self, key: &Field, k: usize, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        let mut res = self.neighbors(key, metric)?;
+        res.truncate(k);
+        Ok(res)
+    }
+
+    // every entry with its distance to the key, closest first
+    fn neighbors(&self, key: &Field, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        let mut res: Vec<Neighbor> = self
+            .entries()?
+            .into_iter()
+            .map(|(field, rid)| Neighbor {
+                dist: metric.distance(std::slice::from_ref(key), std::slice::from_ref(&field)),
+                key: vec![field],
+                rid,
+            })
+            .collect();
+        res.sort();
+        Ok(res)
+    }
+
+    /// Number of entries in the index.
+    pub fn num_entries(&self) -> Result<usize, CrustyError> {
+        Ok(self.entries()?.len())
+    }
+
+    /// Number of pages in the longest bucket.
+    pub fn depth(&self) -> Result<usize, CrustyError> {
+        let mut depth = 0;
+        for head in self.bucket_heads() {
+            depth = depth.max(self.read_chain(head)?.len());
+        }
+        Ok(depth)
+    }
+
+    /// Describe every place the index breaks its invariants: buckets deeper than the
+    /// directory, slots pointing to a bucket that doesn't share their low bits and keys
+    /// stored in a bucket their hash doesn't lead to.
+    pub fn check_invariants(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        if self.directory.len() != 1 << self.meta.global_depth {
+            res.push(format!(
+                "Hash index directory has {} slots for depth {}",
+                self.directory.len(),
+                self.meta.global_depth
+            ));
+            return res;
+        }
+        let mut checked = HashSet::new();
+        for (slot, head) in self.directory.iter().enumerate() {
+            let chain = match self.read_chain(*head) {
+                Ok(chain) => chain,
+                Err(e) => {
+                    res.push(format!("Hash bucket {} can't be read: {:?}", head, e));
+                    continue;
+                }
+            };
+            let local_depth = chain[0].1.local_depth;
+            if local_depth > self.meta.global_depth {
+                res.push(format!(
+                    "Hash bucket {} has depth {} beyond the directory's {}",
+                    head, local_depth, self.meta.global_depth
+                ));
+                continue;
+            }
+            let first_slot = slot & ((1 << local_depth) - 1);
+            if self.directory[first_slot] != *head {
+                res.push(format!(
+                    "Hash index slot {} points to bucket {} rather than {}",
+                    slot, head, self.directory[first_slot]
+                ));
+            }
+            if !checked.insert(*head) {
+                continue;
+            }
+            for (page_id, bucket) in &chain {
+                for (key, rid) in &bucket.entries {
+                    if HashIndex::low_bits(HashIndex::hash_key(key), local_depth) != first_slot {
+                        res.push(format!(
+                            "Hash bucket page {} holds {:?} at {:?}, which hashes to another bucket",
+                            page_id, key, rid
+                        ));
+                    }
+                }
+            }
+        }
+        res
+    }
+}
+
+fn single_key(key: &[Field]) -> Result<&Field, CrustyError> {
+    match key {
+        [field] => Ok(field),
+        _ => Err(CrustyError::CrustyError(format!("Hash index queries need a key of 1 attribute, got {}", key.len()))),
+    }
+}
+
+impl MdIndex for HashIndex {
+    fn tree_type(&self) -> &'static str {
+        "HASH"
+    }
+
+    fn idx_fields(&self) -> Vec<usize> {
+        vec![self.meta.idx_field]
+    }
+
+    fn insert(&mut self, val: &[Field], rid: ValueId) -> Result<(), CrustyError> {
+        HashIndex::insert(self, val, rid)
+    }
+
+    fn delete(&mut self, val: &[Field], rid: ValueId) -> Result<(), CrustyError> {
+        HashIndex::delete(self, val, rid).map(|_| ())
+    }
+
+    fn bulk_load(&mut self, arr: &[(Vec<Field>, ValueId)]) -> Result<(), CrustyError> {
+        self.data_into_tree(arr)
+    }
+
+    fn point_query(&self, key: &[Field]) -> Result<Vec<ValueId>, CrustyError> {
+        self.get(single_key(key)?)
+    }
+
+    fn range_query(&self, min: &[Option<Field>], max: &[Option<Field>]) -> Result<Vec<ValueId>, CrustyError> {
+        self.range(min.first().and_then(Option::as_ref), max.first().and_then(Option::as_ref))
+    }
+
+    fn knn_query(&self, key: &[Field], k: usize, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        self.knn_with_metric(single_key(key)?, k, metric)
+    }
+
+    fn radius_query(&self, center: &[Field], radius: f64, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        HashIndex::radius_query(self, single_key(center)?, radius, metric)
+    }
+
+    fn stats(&self) -> Result<MdIndexStats, CrustyError> {
+        Ok(MdIndexStats {num_entries: self.num_entries()?, depth: self.depth()?})
+    }
+
+    fn serialize(&self, name: &str) -> SerializedIndex {
+        SerializedIndex::new(self, name, self.get_total_dim(), self.get_path())
+    }
+
+    fn check_invariants(&self) -> Vec<String> {
+        HashIndex::check_invariants(self)
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(self.get_path())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::testutil::*;
+    use temp_testdir::TempDir;
+
+    // keys repeat every 700 rows, and every row of the last 200 has the same key
+    fn rows(n: i32) -> Vec<(Vec<Field>, ValueId)> {
+        (0..n)
+            .map(|i| {
+                let key = if i >= n - 200 { 7 } else { (i * 37) % 700 };
+                let row = vec![Field::IntField(i), Field::IntField(key)];
+                (row, ValueId::new_slot(1, (i / 100) as u16, (i % 100) as u16))
+            })
+            .collect()
+    }
+
+    fn brute_force_get(rows: &[(Vec<Field>, ValueId)], key: i32) -> Vec<ValueId> {
+        rows.iter()
+            .filter(|(row, _)| row[1] == Field::IntField(key))
+            .map(|(_, rid)| *rid)
+            .collect()
+    }
+
+    fn sorted(mut rids: Vec<ValueId>) -> Vec<ValueId> {
+        rids.sort();
+        rids
+    }
+
+    fn index_path(tdir: &TempDir, name: &str) -> PathBuf {
+        let mut path = tdir.to_path_buf();
+        path.push(name);
+        path.set_extension("hash");
+        path
+    }
+
+    #[test]
+    fn hs_hash_insert_query_reopen() {
+        init();
+        let tdir = TempDir::new(gen_random_dir(), true);
+        let path = index_path(&tdir, "hash_test");
+        let rows = rows(3000);
+        let mut index = HashIndex::create(path.clone(), 1, 2).unwrap();
+        index.data_into_tree(&rows).unwrap();
+        // the index no longer fits in a single bucket and the repeated key overflows its page
+        assert!(index.meta.global_depth > 1);
+        assert!(index.depth().unwrap() > 1);
+        assert_eq!(Vec::<String>::new(), index.check_invariants());
+        assert_eq!(3000, index.num_entries().unwrap());
+        for key in [0, 7, 42, 699] {
+            assert_eq!(sorted(brute_force_get(&rows, key)), sorted(index.get(&Field::IntField(key)).unwrap()));
+        }
+        assert!(index.get(&Field::IntField(700)).unwrap().is_empty());
+        // queries take the key alone, not the record it came from
+        assert_eq!(sorted(index.get(&rows[42].0[1]).unwrap()), sorted(index.point_query(&index.key_of(&rows[42].0)).unwrap()));
+        assert!(index.point_query(&rows[42].0).is_err());
+        let in_range: Vec<ValueId> = rows
+            .iter()
+            .filter(|(row, _)| Field::IntField(100) <= row[1] && row[1] <= Field::IntField(120))
+            .map(|(_, rid)| *rid)
+            .collect();
+        assert_eq!(sorted(in_range), sorted(index.range(Some(&Field::IntField(100)), Some(&Field::IntField(120))).unwrap()));
+
+        for (row, rid) in &rows[..1500] {
+            assert!(index.delete(row, *rid).unwrap());
+        }
+        assert!(!index.delete(&rows[0].0, rows[0].1).unwrap());
+        assert_eq!(Vec::<String>::new(), index.check_invariants());
+        drop(index);
+
+        let index = HashIndex::open(path).unwrap();
+        assert_eq!(1500, index.num_entries().unwrap());
+        for key in [7, 42] {
+            assert_eq!(sorted(brute_force_get(&rows[1500..], key)), sorted(index.get(&Field::IntField(key)).unwrap()));
+        }
+    }
+
+    #[test]
+    fn hs_hash_string_keys() {
+        init();
+        let tdir = TempDir::new(gen_random_dir(), true);
+        let mut index = HashIndex::create(index_path(&tdir, "hash_strings"), 0, 1).unwrap();
+        let rows: Vec<(Vec<Field>, ValueId)> = (0..500)
+            .map(|i| (vec![Field::StringField(format!("key{}", i % 250))], ValueId::new_slot(1, 0, i as u16)))
+            .collect();
+        index.data_into_tree(&rows).unwrap();
+        assert_eq!(Vec::<String>::new(), index.check_invariants());
+        let key = Field::StringField(String::from("key17"));
+        assert_eq!(vec![ValueId::new_slot(1, 0, 17), ValueId::new_slot(1, 0, 267)], sorted(index.get(&key).unwrap()));
+        let neighbors = index.knn_with_metric(&key, 2, DistanceMetric::Euclidean).unwrap();
+        assert_eq!(2, neighbors.len());
+        assert_eq!(0.0, neighbors[0].dist.0);
+    }
+}
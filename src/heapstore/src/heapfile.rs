@@ -1,14 +1,16 @@
+use crate::index_registry::index_type;
 use crate::page::Page;
-use common::ids::PageId;
-use common::{CrustyError, PAGE_SIZE};
-use std::fs::{metadata, File, OpenOptions};
+use common::ids::{ContainerId, PageId};
+use common::{CrustyError, Tuple, PAGE_SIZE};
+use std::fs::{self, metadata, File, OpenOptions};
 use std::os::unix::prelude::FileExt;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU16, Ordering};
 use std::sync::{Arc, RwLock};
 use std::collections::HashMap;
-use common::md_index::KdTree;
-use common::md_index::R_Tree;
+use common::md_index::{MdIndex, SerializedIndex};
+use common::ids::ValueId;
+use common::Field;
 //use std::io::prelude::*;
 //use std::io::BufWriter;
 //use std::io::{Seek, SeekFrom};
@@ -25,43 +27,16 @@ use common::md_index::R_Tree;
 ///
 /// Your code should persist what information is needed to recreate the heapfile.
 
-pub struct KdIndex {
-    pub tree: KdTree,
-    pub name: String,
-}
-
-pub struct RIndex {
-    pub tree: R_Tree,
-    pub name: String,
-}
 pub(crate) struct HeapFile {
     pub num_page: Arc<RwLock<PageId>>,
     pub heap_file: Arc<RwLock<File>>,
-    pub kd_index_map: Arc<RwLock<HashMap<String, Arc<RwLock<KdIndex>>>>>,
-    pub r_index_map: Arc<RwLock<HashMap<String, Arc<RwLock<RIndex>>>>>,
+    /// The indexes on this heap file, by name.
+    pub index_map: Arc<RwLock<HashMap<String, Box<dyn MdIndex>>>>,
     // The following are for profiling/ correctness checks
     pub read_count: AtomicU16,
     pub write_count: AtomicU16,
 }
 
-impl KdIndex {
-    pub fn new(tree_dim: usize, name: String, idx_fields: Vec<usize>, total_dim: usize) -> Self {
-        Self {
-            tree: KdTree::new(tree_dim, idx_fields.clone(), total_dim),
-            name,
-        }
-    }
-}
-
-impl RIndex {
-    pub fn new(tree_dim: usize, name: String, idx_fields: Vec<usize>, total_dim: usize) -> Self {
-        Self {
-            tree: R_Tree::new(tree_dim, idx_fields.clone(), total_dim),
-            name,
-        }
-    }
-}
-
 /// HeapFile required functions
 impl HeapFile {
     // Given a path to a file, get the number of pages it holds
@@ -92,8 +67,7 @@ impl HeapFile {
         Ok(HeapFile {
             num_page: Arc::new(RwLock::new(num_page)),
             heap_file: Arc::new(RwLock::new(file)),
-            kd_index_map: Arc::new(RwLock::new(HashMap::new())),
-            r_index_map: Arc::new(RwLock::new(HashMap::new())),
+            index_map: Arc::new(RwLock::new(HashMap::new())),
             read_count: AtomicU16::new(0),
             write_count: AtomicU16::new(0),
         })
@@ -106,6 +80,94 @@ impl HeapFile {
         *self.num_page.read().unwrap()
     }
 
+    /// Whether any index has been built on this heap file.
+    pub(crate) fn has_indexes(&self) -> bool {
+        !self.index_map.read().unwrap().is_empty()
+    }
+
+    /// Add a newly inserted value to every index on this heap file. If an index can't take the
+    /// value, the indexes already updated are put back and every index is left as it was.
+    pub(crate) fn insert_into_indexes(&self, val: &[Field], rid: ValueId) -> Result<(), CrustyError> {
+        self.update_all_indexes(|index| index.insert(val, rid), |index| index.delete(val, rid))
+    }
+
+    /// Remove a deleted value from every index on this heap file. If an index can't drop the
+    /// value, the indexes already updated are put back and every index is left as it was.
+    pub(crate) fn delete_from_indexes(&self, val: &[Field], rid: ValueId) -> Result<(), CrustyError> {
+        self.update_all_indexes(|index| index.delete(val, rid), |index| index.insert(val, rid))
+    }
+
+    /// Apply `update` to every index, undoing it with `undo` on the indexes already updated if
+    /// one of them fails.
+    fn update_all_indexes(
+        &self,
+        update: impl Fn(&mut dyn MdIndex) -> Result<(), CrustyError>,
+        undo: impl Fn(&mut dyn MdIndex) -> Result<(), CrustyError>,
+    ) -> Result<(), CrustyError> {
+        let mut index_map = self.index_map.write().unwrap();
+        let mut indexes: Vec<&mut Box<dyn MdIndex>> = index_map.values_mut().collect();
+        for i in 0..indexes.len() {
+            if let Err(e) = update(indexes[i].as_mut()) {
+                for index in &mut indexes[..i] {
+                    if let Err(undo_err) = undo(index.as_mut()) {
+                        error!("Can't undo the update of an index: {:?}", undo_err);
+                    }
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the index with the given name from this heap file, deleting its file if it has one.
+    /// Returns whether the index existed.
+    pub(crate) fn remove_index(&self, name: &str) -> Result<bool, CrustyError> {
+        match self.index_map.write().unwrap().remove(name) {
+            Some(index) => {
+                if let Some(path) = index.path() {
+                    fs::remove_file(path)?;
+                }
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Paths of the files holding indexes of this heap file.
+    pub(crate) fn get_index_paths(&self) -> Vec<PathBuf> {
+        self.index_map
+            .read()
+            .unwrap()
+            .values()
+            .filter_map(|index| index.path().map(Path::to_path_buf))
+            .collect()
+    }
+
+    /// Get the serializable form of every index on this heap file.
+    pub(crate) fn get_serialized_indexes(&self) -> Vec<SerializedIndex> {
+        self.index_map
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, index)| index.serialize(name))
+            .collect()
+    }
+
+    /// Recreate the indexes of this heap file from their serialized form.
+    pub(crate) fn load_serialized_indexes(
+        &self,
+        serialized_indexes: Vec<SerializedIndex>,
+    ) -> Result<(), CrustyError> {
+        for serialized_index in serialized_indexes {
+            let index = (index_type(&serialized_index.tree_type)?.load)(&serialized_index)?;
+            self.index_map
+                .write()
+                .unwrap()
+                .insert(serialized_index.name, index);
+        }
+        Ok(())
+    }
+
     /// Read the page from the file.
     /// Errors could arise from the filesystem or invalid pageId
     pub(crate) fn read_page_from_file(&self, pid: PageId) -> Result<Page, CrustyError> {
@@ -148,6 +210,139 @@ impl HeapFile {
         *self.num_page.write().unwrap() += 1;
         Ok(())
     }
+
+    /// Rewrite the file with its values sorted by `sort_key` of their records, packed into pages
+    /// from the first one, and rebuild every index from scratch for the new ids. The file stays
+    /// write locked throughout, so no value is read, added or moved in between. Values with the
+    /// same sort key keep their order. Returns the number of pages written.
+    ///
+    /// The new indexes are all built before the file is touched, those kept in their own file next
+    /// to the live one, so a failed build leaves the file and its indexes as they were.
+    pub(crate) fn cluster<K: Ord>(
+        &self,
+        container_id: ContainerId,
+        sort_key: impl Fn(&[Field]) -> K,
+    ) -> Result<PageId, CrustyError> {
+        let file = self.heap_file.write().unwrap();
+        let mut num_page = self.num_page.write().unwrap();
+        let mut records = Vec::new();
+        for pid in 0..*num_page {
+            let mut buf = [0u8; PAGE_SIZE];
+            file.read_at(&mut buf, (usize::from(pid) * PAGE_SIZE).try_into().unwrap())?;
+            let mut page_iter = Page::from_bytes(&buf).into_iter();
+            while let Some((val, _)) = page_iter.next_with_slot_id() {
+                let field_vals = Tuple::from_bytes(&val).field_vals;
+                records.push((sort_key(&field_vals), val, field_vals));
+            }
+        }
+        records.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+
+        let mut pages: Vec<Page> = Vec::new();
+        let mut entries = Vec::new();
+        for (_, val, field_vals) in records {
+            let slot_id = match pages.last_mut().and_then(|page| page.add_value(&val)) {
+                Some(slot_id) => slot_id,
+                None => {
+                    let mut page = Page::new(pages.len() as PageId);
+                    let slot_id = page.add_value(&val).expect("Value does not fit in an empty page");
+                    pages.push(page);
+                    slot_id
+                }
+            };
+            entries.push((field_vals, ValueId::new_slot(container_id, (pages.len() - 1) as PageId, slot_id)));
+        }
+        let mut index_map = self.index_map.write().unwrap();
+        let rebuilt = Self::build_indexes(&index_map, &entries)?;
+
+        file.set_len(0)?;
+        for (page_id, page) in pages.iter().enumerate() {
+            file.write_at(&page.get_bytes(), (page_id * PAGE_SIZE).try_into().unwrap())?;
+        }
+        *num_page = u16::try_from(pages.len()).unwrap();
+
+        for (name, index, new_path) in rebuilt {
+            match new_path {
+                None => {
+                    index_map.insert(name, index);
+                }
+                Some(new_path) => {
+                    // both files must be closed before the new one takes the place of the old one
+                    let old_index = index_map.remove(&name).unwrap();
+                    let path = old_index.path().unwrap().to_path_buf();
+                    let index_type = index_type(old_index.tree_type())?;
+                    drop(old_index);
+                    drop(index);
+                    fs::rename(&new_path, &path)?;
+                    let open = index_type.open.ok_or_else(|| {
+                        CrustyError::CrustyError(format!("Can't open a {} index from a file", index_type.description))
+                    })?;
+                    index_map.insert(name, open(&path)?);
+                }
+            }
+        }
+        Ok(pages.len() as PageId)
+    }
+
+    /// Build a copy of every index in `index_map` holding just `entries`. Indexes kept in their own
+    /// file are built in a new file next to theirs, whose path comes with the copy. If any build
+    /// fails, the new files are removed and the error returned.
+    #[allow(clippy::type_complexity)]
+    fn build_indexes(
+        index_map: &HashMap<String, Box<dyn MdIndex>>,
+        entries: &[(Vec<Field>, ValueId)],
+    ) -> Result<Vec<(String, Box<dyn MdIndex>, Option<PathBuf>)>, CrustyError> {
+        let mut rebuilt: Vec<(String, Box<dyn MdIndex>, Option<PathBuf>)> = Vec::new();
+        // the indexes of an empty file are already empty
+        let Some((field_vals, _)) = entries.first() else {
+            return Ok(rebuilt);
+        };
+        let total_dim = field_vals.len();
+        for (name, index) in index_map {
+            let new_path = index.path().map(|path| {
+                let mut new_path = path.as_os_str().to_owned();
+                new_path.push(".new");
+                PathBuf::from(new_path)
+            });
+            // kinds kept in memory ignore the path
+            let built = index_type(index.tree_type()).and_then(|index_type| {
+                let mut built = (index_type.create)(new_path.as_deref().unwrap_or(Path::new("")), index.idx_fields(), total_dim)?;
+                built.bulk_load(entries)?;
+                Ok(built)
+            });
+            match built {
+                Ok(built) => rebuilt.push((name.clone(), built, new_path)),
+                Err(e) => {
+                    let new_paths = rebuilt.into_iter().map(|(_, _, new_path)| new_path).chain([new_path]);
+                    for new_path in new_paths.flatten() {
+                        let _ = fs::remove_file(new_path);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(rebuilt)
+    }
+
+    /// Overwrite a page that is already in the underlying file.
+    pub(crate) fn write_updated_page_to_file(
+        &self,
+        page: &Page,
+        page_id: PageId,
+    ) -> Result<(), CrustyError> {
+        #[cfg(feature = "profile")]
+        {
+            self.write_count.fetch_add(1, Ordering::Relaxed);
+        }
+        if page_id >= self.num_pages() {
+            return Err(CrustyError::CrustyError(String::from("pid invalid")));
+        }
+        let buf = page.get_bytes();
+        self.heap_file
+            .write()
+            .unwrap()
+            .write_at(&buf, (usize::from(page_id) * PAGE_SIZE).try_into().unwrap())?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
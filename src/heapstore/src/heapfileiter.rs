@@ -1,6 +1,6 @@
 use crate::heapfile::HeapFile;
 use crate::page::PageIter;
-use common::ids::{ContainerId, PageId, TransactionId};
+use common::ids::{ContainerId, PageId, TransactionId, ValueId};
 use std::sync::Arc;
 
 #[allow(dead_code)]
@@ -10,6 +10,7 @@ use std::sync::Arc;
 /// This should store the state/metadata required to iterate through the file.
 /// HINT: This will need an Arc<HeapFile>
 pub struct HeapFileIterator {
+    container_id: ContainerId,
     hf: Arc<HeapFile>,
     index: PageId,
     // Iterator of most currently accessed page
@@ -22,44 +23,48 @@ pub struct HeapFileIterator {
 impl HeapFileIterator {
     /// Create a new HeapFileIterator that stores the container_id, tid, and heapFile pointer.
     /// This should initialize the state required to iterate through the heap file.
-    pub(crate) fn new(_container_id: ContainerId, _tid: TransactionId, hf: Arc<HeapFile>) -> Self {
+    pub(crate) fn new(container_id: ContainerId, _tid: TransactionId, hf: Arc<HeapFile>) -> Self {
         HeapFileIterator {
+            container_id,
             hf,
             index: 0,
             curr_pg_iter: PageIter::gen_empty_pg_iter(),
             end_of_page: true,
         }
     }
-}
 
-/// Trait implementation for heap file iterator.
-/// Note this will need to iterate through the pages and their respective iterators.
-/// Recursive function to keep calling next until we are not at the end of a page
-impl Iterator for HeapFileIterator {
-    type Item = Vec<u8>;
-    fn next(&mut self) -> Option<Self::Item> {
+    /// Get the next value of the heap file along with the ValueId it is stored under.
+    /// Recursive function to keep calling next until we are not at the end of a page
+    pub fn next_with_id(&mut self) -> Option<(Vec<u8>, ValueId)> {
         let curr_num_pg = *self.hf.num_page.read().unwrap();
         if curr_num_pg == 0 || curr_num_pg <= self.index {
             return None;
         }
-        match self.end_of_page {
-            true => {
-                // if at end of current page, set curr_pg_iter to the iterator of the next page
-                self.curr_pg_iter = self.hf.read_page_from_file(self.index).unwrap().into_iter();
-                self.end_of_page = false
-            }
-            false => (),
+        if self.end_of_page {
+            // if at end of current page, set curr_pg_iter to the iterator of the next page
+            self.curr_pg_iter = self.hf.read_page_from_file(self.index).unwrap().into_iter();
+            self.end_of_page = false
         }
-        {
-            match &self.curr_pg_iter.next() {
-                Some(data) => Some(data.clone()),
-                None => {
-                    // If at end of page, increment index to access next page and call next recursively
-                    self.index += 1;
-                    self.end_of_page = true;
-                    self.next()
-                }
+        match self.curr_pg_iter.next_with_slot_id() {
+            Some((data, slot_id)) => Some((
+                data,
+                ValueId::new_slot(self.container_id, self.index, slot_id),
+            )),
+            None => {
+                // If at end of page, increment index to access next page and call next recursively
+                self.index += 1;
+                self.end_of_page = true;
+                self.next_with_id()
             }
         }
     }
 }
+
+/// Trait implementation for heap file iterator.
+/// Note this will need to iterate through the pages and their respective iterators.
+impl Iterator for HeapFileIterator {
+    type Item = Vec<u8>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_with_id().map(|(value, _)| value)
+    }
+}
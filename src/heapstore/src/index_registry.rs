@@ -0,0 +1,193 @@
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use common::md_index::{KdTree, MdIndex, QuadTree, R_Tree, SerializedIndex, ZOrderIndex};
+use common::CrustyError;
+
+use crate::bplus_tree::BPlusTree;
+use crate::hash_index::HashIndex;
+use crate::kdb_tree::KdbTree;
+
+type IndexResult = Result<Box<dyn MdIndex>, CrustyError>;
+
+/// A kind of index that can be built on a heap file. Storage, the catalog and the SQL parser only
+/// know index kinds through their entry in `INDEX_TYPES`.
+pub struct IndexType {
+    /// Name of the kind, as stored with its indexes and in the catalog.
+    pub tree_type: &'static str,
+    /// Name of the kind in error messages.
+    pub description: &'static str,
+    /// Names of the kind in `CREATE INDEX ... USING`, in lower case.
+    pub methods: &'static [&'static str],
+    /// Numbers of attributes the kind can index.
+    pub dims: RangeInclusive<usize>,
+    /// Extension of the file the index lives in, for kinds kept in their own file.
+    pub extension: Option<&'static str>,
+    /// Make an empty index over `idx_fields` of records with `total_dim` attributes. Kinds kept in
+    /// their own file create it at the given path.
+    pub create: fn(&Path, Vec<usize>, usize) -> IndexResult,
+    /// Read an index of the kind back from its serialized form.
+    pub load: fn(&SerializedIndex) -> IndexResult,
+    /// Open an index kept in its own file at the given path, for kinds kept in their own file.
+    pub open: Option<fn(&Path) -> IndexResult>,
+}
+
+impl IndexType {
+    /// Check that the kind can index `dim` attributes.
+    pub fn check_dim(&self, dim: usize) -> Result<(), CrustyError> {
+        if self.dims.contains(&dim) {
+            return Ok(());
+        }
+        let msg = match (*self.dims.start(), *self.dims.end()) {
+            (1, 1) => format!("{} indexes are on a single column", self.description),
+            (min, usize::MAX) => format!("{} indexes need at least {} columns", self.description, min),
+            (min, max) => format!("{} indexes need {} to {} columns", self.description, min, max),
+        };
+        Err(CrustyError::CrustyError(msg))
+    }
+}
+
+pub static INDEX_TYPES: &[IndexType] = &[
+    IndexType {
+        tree_type: "KD",
+        description: "KD tree",
+        methods: &["kdtree", "kd"],
+        dims: 1..=usize::MAX,
+        extension: None,
+        create: |_, idx_fields, total_dim| Ok(Box::new(KdTree::new(idx_fields.len(), idx_fields, total_dim))),
+        load: |serialized| Ok(Box::new(serialized.payload::<KdTree>()?)),
+        open: None,
+    },
+    IndexType {
+        tree_type: "R",
+        description: "R-tree",
+        methods: &["rtree", "r"],
+        dims: R_Tree::MIN_DIM..=R_Tree::MAX_DIM,
+        extension: None,
+        create: |_, idx_fields, total_dim| Ok(Box::new(R_Tree::new(idx_fields.len(), idx_fields, total_dim))),
+        load: |serialized| Ok(Box::new(R_Tree::from_serialized(serialized)?)),
+        open: None,
+    },
+    IndexType {
+        tree_type: "KDB",
+        description: "KDB tree",
+        methods: &["kdbtree", "kdb"],
+        dims: 1..=usize::MAX,
+        extension: Some("kdb"),
+        create: |path, idx_fields, total_dim| {
+            Ok(Box::new(KdbTree::create(path.to_path_buf(), idx_fields.len(), idx_fields, total_dim)?))
+        },
+        load: |serialized| Ok(Box::new(KdbTree::open(serialized.payload()?)?)),
+        open: Some(|path| Ok(Box::new(KdbTree::open(path.to_path_buf())?))),
+    },
+    IndexType {
+        tree_type: "QUAD",
+        description: "Quadtree",
+        methods: &["quadtree", "octree", "quad"],
+        dims: QuadTree::MIN_DIM..=QuadTree::MAX_DIM,
+        extension: None,
+        create: |_, idx_fields, total_dim| Ok(Box::new(QuadTree::new(idx_fields.len(), idx_fields, total_dim))),
+        load: |serialized| Ok(Box::new(serialized.payload::<QuadTree>()?)),
+        open: None,
+    },
+    IndexType {
+        tree_type: "Z",
+        description: "Z-order",
+        methods: &["zorder", "morton", "z"],
+        dims: ZOrderIndex::MIN_DIM..=ZOrderIndex::MAX_DIM,
+        extension: None,
+        create: |_, idx_fields, total_dim| Ok(Box::new(ZOrderIndex::new(idx_fields.len(), idx_fields, total_dim))),
+        load: |serialized| Ok(Box::new(serialized.payload::<ZOrderIndex>()?)),
+        open: None,
+    },
+    IndexType {
+        tree_type: "BTREE",
+        description: "B+ tree",
+        methods: &["btree"],
+        dims: 1..=1,
+        extension: Some("bpt"),
+        create: |path, idx_fields, total_dim| Ok(Box::new(BPlusTree::create(path.to_path_buf(), idx_fields[0], total_dim)?)),
+        load: |serialized| Ok(Box::new(BPlusTree::open(serialized.payload()?)?)),
+        open: Some(|path| Ok(Box::new(BPlusTree::open(path.to_path_buf())?))),
+    },
+    IndexType {
+        tree_type: "HASH",
+        description: "Hash",
+        methods: &["hash"],
+        dims: 1..=1,
+        extension: Some("hash"),
+        create: |path, idx_fields, total_dim| Ok(Box::new(HashIndex::create(path.to_path_buf(), idx_fields[0], total_dim)?)),
+        load: |serialized| Ok(Box::new(HashIndex::open(serialized.payload()?)?)),
+        open: Some(|path| Ok(Box::new(HashIndex::open(path.to_path_buf())?))),
+    },
+];
+
+/// The kind of index stored under `tree_type`.
+pub fn index_type(tree_type: &str) -> Result<&'static IndexType, CrustyError> {
+    INDEX_TYPES
+        .iter()
+        .find(|index_type| index_type.tree_type == tree_type)
+        .ok_or_else(|| CrustyError::CrustyError(format!("Tree type {} not supported", tree_type)))
+}
+
+/// The kind of index named `method` in `CREATE INDEX ... USING`, ignoring case.
+pub fn index_type_for_method(method: &str) -> Option<&'static IndexType> {
+    let method = method.to_lowercase();
+    INDEX_TYPES.iter().find(|index_type| index_type.methods.contains(&method.as_str()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::ids::ValueId;
+    use common::testutil::*;
+    use common::Field;
+    use std::collections::HashSet;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn hs_registry_lookup() {
+        let tree_types: HashSet<&str> = INDEX_TYPES.iter().map(|index_type| index_type.tree_type).collect();
+        assert_eq!(INDEX_TYPES.len(), tree_types.len());
+        for index_type in INDEX_TYPES {
+            assert_eq!(index_type.tree_type, self::index_type(index_type.tree_type).unwrap().tree_type);
+            for method in index_type.methods {
+                assert_eq!(index_type.tree_type, index_type_for_method(&method.to_uppercase()).unwrap().tree_type);
+            }
+        }
+        assert!(self::index_type("GIST").is_err());
+        assert!(index_type_for_method("gist").is_none());
+        assert!(self::index_type("BTREE").unwrap().check_dim(2).is_err());
+        assert!(self::index_type("R").unwrap().check_dim(1).is_err());
+        assert!(self::index_type("KD").unwrap().check_dim(5).is_ok());
+    }
+
+    #[test]
+    fn hs_registry_serialize_load() {
+        init();
+        let tdir = TempDir::new(gen_random_dir(), true);
+        let rows: Vec<(Vec<Field>, ValueId)> = (0..20)
+            .map(|i| (vec![Field::IntField(i % 4), Field::IntField(i % 5), Field::IntField(i)], ValueId::new_slot(1, 0, i as u16)))
+            .collect();
+        for index_type in INDEX_TYPES {
+            let idx_fields: Vec<usize> = (0..*index_type.dims.start()).collect();
+            let path = tdir.join(format!("registry_{}", index_type.tree_type));
+            let mut index = (index_type.create)(&path, idx_fields, 3).unwrap();
+            index.bulk_load(&rows).unwrap();
+            // the serialized index goes through the same JSON as the indexes of a storage manager
+            let json = serde_json::to_string(&index.serialize("idx")).unwrap();
+            let serialized: SerializedIndex = serde_json::from_str(&json).unwrap();
+            assert_eq!(index_type.tree_type, serialized.tree_type);
+            let loaded = (index_type.load)(&serialized).unwrap();
+            assert_eq!(index.idx_fields(), loaded.idx_fields());
+            assert_eq!(20, loaded.stats().unwrap().num_entries);
+            let key = loaded.key_of(&rows[7].0);
+            let mut expected = index.point_query(&key).unwrap();
+            let mut res = loaded.point_query(&key).unwrap();
+            expected.sort();
+            res.sort();
+            assert!(res.contains(&rows[7].1));
+            assert_eq!(expected, res);
+        }
+    }
+}
@@ -0,0 +1,701 @@
+use crate::heapfile::HeapFile;
+use crate::page::Page;
+use common::ids::{PageId, ValueId};
+use common::md_index::{Distance, DistanceMetric, IndexEntry, KdTree, MdIndex, MdIndexStats, Neighbor, SerializedIndex};
+use common::{CrustyError, Field};
+use std::any::Any;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The first page of the file holds the metadata of the tree.
+const META_PAGE_ID: PageId = 0;
+
+/// Bounds of the region covered by a child of a region node. A key is in the region
+/// when `min <= key < max` holds on every dimension, where `None` is unbounded.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Region {
+    min: Vec<Option<Field>>,
+    max: Vec<Option<Field>>,
+    page_id: PageId,
+}
+
+/// A node of the tree. Every node is serialized into a page of its own.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum KdbNode {
+    /// Internal node whose children partition the region of the node.
+    Region(Vec<Region>),
+    /// Leaf node holding the index entries that fall into its region.
+    Point(Vec<IndexEntry>),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct KdbMeta {
+    root: PageId,
+    dim: usize,
+    idx_fields: Vec<usize>,
+    total_dim: usize,
+}
+
+/// Items of the priority queue used by the nearest neighbour search. Nodes come
+/// before entries at the same distance, and entries are ordered by key and record id.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum KnnItem {
+    Node(PageId),
+    Entry(IndexEntry),
+}
+
+/// A disk-resident KD-B-tree. Nodes are stored one per page in a heap file, so only
+/// the pages on the search path need to be read into memory.
+///
+/// When a leaf overflows it is split on the median of one of the key attributes.
+/// When a region node overflows it is split on one of the boundaries of its children,
+/// and any child straddling the split is split as well, all the way down to the leaves.
+/// Nodes are not merged on delete.
+pub struct KdbTree {
+    hf: HeapFile,
+    path: PathBuf,
+    meta: KdbMeta,
+}
+
+impl Region {
+    fn unbounded(dim: usize) -> Self {
+        Region {
+            min: vec![None; dim],
+            max: vec![None; dim],
+            page_id: 0,
+        }
+    }
+
+    fn contains(&self, key: &[Field]) -> bool {
+        key.iter().enumerate().all(|(i, val)| {
+            self.min[i].as_ref().is_none_or(|min| val >= min)
+                && self.max[i].as_ref().is_none_or(|max| val < max)
+        })
+    }
+
+    /// Whether the region overlaps the closed box between `min` and `max`, where a missing bound
+    /// leaves its side of the box open.
+    fn intersects(&self, min: &[Option<Field>], max: &[Option<Field>]) -> bool {
+        (0..self.min.len()).all(|i| {
+            let (min, max) = (min.get(i).and_then(Option::as_ref), max.get(i).and_then(Option::as_ref));
+            self.min[i].as_ref().zip(max).is_none_or(|(region_min, max)| region_min <= max)
+                && self.max[i].as_ref().zip(min).is_none_or(|(region_max, min)| min < region_max)
+        })
+    }
+
+    /// Whether the region lies entirely on the left of `value` on the given dimension.
+    fn below(&self, dim: usize, value: &Field) -> bool {
+        self.max[dim].as_ref().is_some_and(|max| max <= value)
+    }
+
+    /// Whether the region lies entirely on the right of `value` on the given dimension.
+    fn above(&self, dim: usize, value: &Field) -> bool {
+        self.min[dim].as_ref().is_some_and(|min| min >= value)
+    }
+
+    /// Cut the region in two at `value` on the given dimension.
+    fn split(&self, dim: usize, value: &Field, left_page: PageId, right_page: PageId) -> (Self, Self) {
+        let mut left = self.clone();
+        let mut right = self.clone();
+        left.max[dim] = Some(value.clone());
+        left.page_id = left_page;
+        right.min[dim] = Some(value.clone());
+        right.page_id = right_page;
+        (left, right)
+    }
+
+    /// Distance from the key to the closest point of the region.
+    fn min_dist(&self, key: &[Field], metric: DistanceMetric) -> Distance {
+        let gaps = key.iter().enumerate().map(|(i, val)| {
+            let val = DistanceMetric::coordinate(val);
+            match (&self.min[i], &self.max[i]) {
+                (Some(min), _) if val < DistanceMetric::coordinate(min) => DistanceMetric::coordinate(min) - val,
+                // the region is half open, so it holds integers up to max - 1 and strings sorting before max
+                (_, Some(max)) => {
+                    let largest = match max {
+                        Field::IntField(max) => i64::from(*max) - 1,
+                        Field::StringField(_) => DistanceMetric::coordinate(max),
+                    };
+                    (val - largest).max(0)
+                }
+                _ => 0,
+            }
+        });
+        metric.combine(gaps)
+    }
+}
+
+impl KdbTree {
+    /// Create a new empty tree stored in the file at `path`, replacing any file already there.
+    pub fn create(
+        path: PathBuf,
+        dim: usize,
+        idx_fields: Vec<usize>,
+        total_dim: usize,
+    ) -> Result<Self, CrustyError> {
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        let hf = HeapFile::new(path.clone())?;
+        let meta = KdbMeta {
+            root: META_PAGE_ID + 1,
+            dim,
+            idx_fields,
+            total_dim,
+        };
+        let tree = KdbTree { hf, path, meta };
+        tree.write_meta()?;
+        tree.append_node(&KdbNode::Point(Vec::new()))?;
+        Ok(tree)
+    }
+
+    /// Open a tree that was previously created in the file at `path`.
+    pub fn open(path: PathBuf) -> Result<Self, CrustyError> {
+        let hf = HeapFile::new(path.clone())?;
+        let bytes = hf
+            .read_page_from_file(META_PAGE_ID)?
+            .get_value(0)
+            .ok_or_else(|| CrustyError::CrustyError(String::from("Missing KDB tree metadata")))?;
+        let meta = serde_cbor::from_slice(&bytes)
+            .map_err(|e| CrustyError::CrustyError(format!("Invalid KDB tree metadata {}", e)))?;
+        Ok(KdbTree { hf, path, meta })
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get_idx_fields(&self) -> Vec<usize> {
+        self.meta.idx_fields.clone()
+    }
+
+    pub fn get_total_dim(&self) -> usize {
+        self.meta.total_dim
+    }
+
+    /// Take the values of the indexed attributes out of a full record.
+    pub fn get_key(&self, val: &[Field]) -> Vec<Field> {
+        self.meta.idx_fields.iter().map(|idx| val[*idx].clone()).collect()
+    }
+
+    fn node_to_page(page_id: PageId, node: &KdbNode) -> Result<Page, CrustyError> {
+        let bytes = serde_cbor::to_vec(node)
+            .map_err(|e| CrustyError::CrustyError(format!("Can't serialize KDB node {}", e)))?;
+        let mut page = Page::new(page_id);
+        match page.add_value(&bytes) {
+            Some(_) => Ok(page),
+            None => Err(CrustyError::CrustyError(String::from(
+                "KDB node does not fit in a page",
+            ))),
+        }
+    }
+
+    fn fits(node: &KdbNode) -> bool {
+        KdbTree::node_to_page(0, node).is_ok()
+    }
+
+    fn read_node(&self, page_id: PageId) -> Result<KdbNode, CrustyError> {
+        let bytes = self
+            .hf
+            .read_page_from_file(page_id)?
+            .get_value(0)
+            .ok_or_else(|| CrustyError::CrustyError(format!("Empty KDB page {}", page_id)))?;
+        serde_cbor::from_slice(&bytes)
+            .map_err(|e| CrustyError::CrustyError(format!("Invalid KDB node {}", e)))
+    }
+
+    fn write_node(&self, page_id: PageId, node: &KdbNode) -> Result<(), CrustyError> {
+        let page = KdbTree::node_to_page(page_id, node)?;
+        self.hf.write_updated_page_to_file(&page, page_id)
+    }
+
+    /// Write the node to a new page at the end of the file and return its page id.
+    fn append_node(&self, node: &KdbNode) -> Result<PageId, CrustyError> {
+        let page_id = self.hf.num_pages();
+        let page = KdbTree::node_to_page(page_id, node)?;
+        self.hf.write_page_to_file(page)?;
+        Ok(page_id)
+    }
+
+    fn write_meta(&self) -> Result<(), CrustyError> {
+        let bytes = serde_cbor::to_vec(&self.meta)
+            .map_err(|e| CrustyError::CrustyError(format!("Can't serialize KDB metadata {}", e)))?;
+        let mut page = Page::new(META_PAGE_ID);
+        page.add_value(&bytes);
+        if self.hf.num_pages() == 0 {
+            self.hf.write_page_to_file(page)
+        } else {
+            self.hf.write_updated_page_to_file(&page, META_PAGE_ID)
+        }
+    }
+
+    /// Insert a record into the tree. `val` is the full record and `rid` is where it is stored.
+    pub fn insert(&mut self, val: &[Field], rid: ValueId) -> Result<(), CrustyError> {
+        let key = self.get_key(val);
+        let mut path = Vec::new();
+        let mut page_id = self.meta.root;
+        loop {
+            match self.read_node(page_id)? {
+                KdbNode::Region(children) => {
+                    let child = children.iter().find(|child| child.contains(&key)).ok_or_else(|| {
+                        CrustyError::CrustyError(String::from("KDB regions do not cover the key"))
+                    })?;
+                    path.push(page_id);
+                    page_id = child.page_id;
+                }
+                KdbNode::Point(mut entries) => {
+                    entries.push(IndexEntry::new(key, rid));
+                    let node = KdbNode::Point(entries);
+                    if KdbTree::fits(&node) {
+                        return self.write_node(page_id, &node);
+                    }
+                    return self.split_overflow(path, page_id, node);
+                }
+            }
+        }
+    }
+
+    /// Split a node that no longer fits in its page and propagate the split up the path.
+    fn split_overflow(
+        &mut self,
+        mut path: Vec<PageId>,
+        mut page_id: PageId,
+        mut node: KdbNode,
+    ) -> Result<(), CrustyError> {
+        loop {
+            let (dim, value) = KdbTree::choose_split(&node)?;
+            let (left, right) = self.partition(node, dim, &value)?;
+            self.write_node(page_id, &left)?;
+            let right_page_id = self.append_node(&right)?;
+            let parent_page_id = match path.pop() {
+                Some(parent_page_id) => parent_page_id,
+                None => {
+                    // the root was split, so the tree grows by one level
+                    let (left, right) =
+                        Region::unbounded(self.meta.dim).split(dim, &value, page_id, right_page_id);
+                    self.meta.root = self.append_node(&KdbNode::Region(vec![left, right]))?;
+                    return self.write_meta();
+                }
+            };
+            let mut children = match self.read_node(parent_page_id)? {
+                KdbNode::Region(children) => children,
+                KdbNode::Point(_) => {
+                    return Err(CrustyError::CrustyError(String::from(
+                        "KDB leaf found on the path to a leaf",
+                    )))
+                }
+            };
+            let idx = children
+                .iter()
+                .position(|child| child.page_id == page_id)
+                .ok_or_else(|| CrustyError::CrustyError(String::from("KDB child not found")))?;
+            let (left, right) = children[idx].split(dim, &value, page_id, right_page_id);
+            children[idx] = left;
+            children.insert(idx + 1, right);
+            node = KdbNode::Region(children);
+            if KdbTree::fits(&node) {
+                return self.write_node(parent_page_id, &node);
+            }
+            page_id = parent_page_id;
+        }
+    }
+
+    /// Pick the dimension and value to split an overflowing node on. Keys smaller than the
+    /// value go to the left node and the others go to the right node.
+    fn choose_split(node: &KdbNode) -> Result<(usize, Field), CrustyError> {
+        let mut best: Option<(usize, usize, Field)> = None;
+        match node {
+            KdbNode::Point(entries) => {
+                // prefer the dimension whose median gives the most even split
+                for dim in 0..entries[0].key.len() {
+                    let mut vals: Vec<&Field> = entries.iter().map(|entry| &entry.key[dim]).collect();
+                    vals.sort();
+                    let mut value = vals[vals.len() / 2];
+                    if value == vals[0] {
+                        match vals.iter().find(|val| **val > value) {
+                            Some(val) => value = val,
+                            None => continue,
+                        }
+                    }
+                    let left = vals.iter().filter(|val| **val < value).count();
+                    let balance = left.min(vals.len() - left);
+                    if best.as_ref().is_none_or(|(score, _, _)| balance > *score) {
+                        best = Some((balance, dim, value.clone()));
+                    }
+                }
+            }
+            KdbNode::Region(children) => {
+                // split on a child boundary, straddling as few children as possible
+                for dim in 0..children[0].min.len() {
+                    let mut candidates: Vec<&Field> =
+                        children.iter().filter_map(|child| child.min[dim].as_ref()).collect();
+                    candidates.sort();
+                    candidates.dedup();
+                    for value in candidates {
+                        let left = children.iter().filter(|child| child.below(dim, value)).count();
+                        let right = children.iter().filter(|child| child.above(dim, value)).count();
+                        let straddle = children.len() - left - right;
+                        if left + straddle == 0 || right + straddle == 0 {
+                            continue;
+                        }
+                        // fewer straddling children first, then the most even split
+                        let score = usize::MAX - straddle * children.len() - left.abs_diff(right);
+                        if best.as_ref().is_none_or(|(best_score, _, _)| score > *best_score) {
+                            best = Some((score, dim, value.clone()));
+                        }
+                    }
+                }
+            }
+        }
+        match best {
+            Some((_, dim, value)) => Ok((dim, value)),
+            None => Err(CrustyError::CrustyError(String::from(
+                "Can't split KDB node, too many entries share the same key",
+            ))),
+        }
+    }
+
+    /// Divide the content of a node at `value` on the given dimension. Children of a region
+    /// node that straddle the split are themselves split.
+    fn partition(
+        &mut self,
+        node: KdbNode,
+        dim: usize,
+        value: &Field,
+    ) -> Result<(KdbNode, KdbNode), CrustyError> {
+        match node {
+            KdbNode::Point(entries) => {
+                let (left, right) = entries.into_iter().partition(|entry| &entry.key[dim] < value);
+                Ok((KdbNode::Point(left), KdbNode::Point(right)))
+            }
+            KdbNode::Region(children) => {
+                let mut left = Vec::new();
+                let mut right = Vec::new();
+                for child in children {
+                    if child.below(dim, value) {
+                        left.push(child);
+                    } else if child.above(dim, value) {
+                        right.push(child);
+                    } else {
+                        let child_node = self.read_node(child.page_id)?;
+                        let (child_left, child_right) = self.partition(child_node, dim, value)?;
+                        self.write_node(child.page_id, &child_left)?;
+                        let right_page_id = self.append_node(&child_right)?;
+                        let (left_region, right_region) =
+                            child.split(dim, value, child.page_id, right_page_id);
+                        left.push(left_region);
+                        right.push(right_region);
+                    }
+                }
+                Ok((KdbTree::region_node(left), KdbTree::region_node(right)))
+            }
+        }
+    }
+
+    // a region node left without children becomes an empty leaf covering the same region
+    fn region_node(children: Vec<Region>) -> KdbNode {
+        if children.is_empty() {
+            KdbNode::Point(Vec::new())
+        } else {
+            KdbNode::Region(children)
+        }
+    }
+
+    /// Bulk load the tree from full records and the ids they are stored under.
+    pub fn data_into_tree(&mut self, arr: &[(Vec<Field>, ValueId)]) -> Result<(), CrustyError> {
+        for (val, rid) in arr {
+            self.insert(val, *rid)?;
+        }
+        Ok(())
+    }
+
+    /// Delete the entry of the record stored under `rid`. `val` is the full record.
+    /// Returns whether the entry was found.
+    pub fn delete(&mut self, val: &[Field], rid: ValueId) -> Result<bool, CrustyError> {
+        let key = self.get_key(val);
+        let mut page_id = self.meta.root;
+        loop {
+            match self.read_node(page_id)? {
+                KdbNode::Region(children) => match children.iter().find(|child| child.contains(&key)) {
+                    Some(child) => page_id = child.page_id,
+                    None => return Ok(false),
+                },
+                KdbNode::Point(mut entries) => {
+                    let len = entries.len();
+                    entries.retain(|entry| entry.key != key || entry.rid != rid);
+                    if entries.len() == len {
+                        return Ok(false);
+                    }
+                    self.write_node(page_id, &KdbNode::Point(entries))?;
+                    return Ok(true);
+                }
+            }
+        }
+    }
+
+    /// Get the ids of all records with the given key.
+    pub fn get(&self, key: &[Field]) -> Result<Vec<ValueId>, CrustyError> {
+        self.range_query(key, key)
+    }
+
+    /// Get the ids of all records whose key lies in the box between `min` and `max` (inclusive).
+    pub fn range_query(&self, min: &[Field], max: &[Field]) -> Result<Vec<ValueId>, CrustyError> {
+        let min: Vec<Option<Field>> = min.iter().cloned().map(Some).collect();
+        let max: Vec<Option<Field>> = max.iter().cloned().map(Some).collect();
+        self.partial_range_query(&min, &max)
+    }
+
+    /// Get the ids of all records whose key lies between `min` and `max` (inclusive). The bounds
+    /// are given over the indexed attributes, and a `None` bound leaves its side of the attribute open.
+    pub fn partial_range_query(&self, min: &[Option<Field>], max: &[Option<Field>]) -> Result<Vec<ValueId>, CrustyError> {
+        let mut res = Vec::new();
+        let mut stack = vec![self.meta.root];
+        while let Some(page_id) = stack.pop() {
+            match self.read_node(page_id)? {
+                KdbNode::Region(children) => {
+                    for child in children.iter().rev() {
+                        if child.intersects(min, max) {
+                            stack.push(child.page_id);
+                        }
+                    }
+                }
+                KdbNode::Point(entries) => {
+                    for entry in entries {
+                        if KdTree::if_within_bounds(&entry.key, min, max) {
+                            res.push(entry.rid);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    /// Get the records whose keys are within `radius` of `center`, closest first.
+    pub fn radius_query(&self, center: &[Field], radius: f64, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        let mut res = Vec::new();
+        let mut stack = vec![self.meta.root];
+        while let Some(page_id) = stack.pop() {
+            match self.read_node(page_id)? {
+                KdbNode::Region(children) => {
+                    for child in children {
+                        if child.min_dist(center, metric).0 <= radius {
+                            stack.push(child.page_id);
+                        }
+                    }
+                }
+                KdbNode::Point(entries) => {
+                    for IndexEntry {key, rid} in entries {
+                        let dist = metric.distance(center, &key);
+                        if dist.0 <= radius {
+                            res.push(Neighbor {dist, key, rid});
+                        }
+                    }
+                }
+            }
+        }
+        res.sort();
+        Ok(res)
+    }
+
+    /// Number of entries in the tree.
+    pub fn num_entries(&self) -> Result<usize, CrustyError> {
+        let mut res = 0;
+        let mut stack = vec![self.meta.root];
+        while let Some(page_id) = stack.pop() {
+            match self.read_node(page_id)? {
+                KdbNode::Region(children) => stack.extend(children.iter().map(|child| child.page_id)),
+                KdbNode::Point(entries) => res += entries.len(),
+            }
+        }
+        Ok(res)
+    }
+
+    /// Get the `k` records whose keys are closest to `key`, closest first.
+    /// Pages are visited in order of their distance to `key`, so the search stops as soon
+    /// as the next page can't hold anything closer than the records already found.
+    pub fn knn_with_metric(&self, key: &[Field], k: usize, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        let mut res = Vec::new();
+        let mut heap = BinaryHeap::new();
+        heap.push(Reverse((Distance(0.0), KnnItem::Node(self.meta.root))));
+        while let Some(Reverse((dist, item))) = heap.pop() {
+            if res.len() >= k {
+                break;
+            }
+            match item {
+                KnnItem::Entry(IndexEntry {key, rid}) => res.push(Neighbor {dist, key, rid}),
+                KnnItem::Node(page_id) => match self.read_node(page_id)? {
+                    KdbNode::Region(children) => {
+                        for child in children {
+                            heap.push(Reverse((child.min_dist(key, metric), KnnItem::Node(child.page_id))));
+                        }
+                    }
+                    KdbNode::Point(entries) => {
+                        for entry in entries {
+                            heap.push(Reverse((metric.distance(key, &entry.key), KnnItem::Entry(entry))));
+                        }
+                    }
+                },
+            }
+        }
+        Ok(res)
+    }
+}
+
+impl MdIndex for KdbTree {
+    fn tree_type(&self) -> &'static str {
+        "KDB"
+    }
+
+    fn idx_fields(&self) -> Vec<usize> {
+        self.get_idx_fields()
+    }
+
+    fn insert(&mut self, val: &[Field], rid: ValueId) -> Result<(), CrustyError> {
+        KdbTree::insert(self, val, rid)
+    }
+
+    fn delete(&mut self, val: &[Field], rid: ValueId) -> Result<(), CrustyError> {
+        KdbTree::delete(self, val, rid).map(|_| ())
+    }
+
+    fn bulk_load(&mut self, arr: &[(Vec<Field>, ValueId)]) -> Result<(), CrustyError> {
+        self.data_into_tree(arr)
+    }
+
+    fn point_query(&self, key: &[Field]) -> Result<Vec<ValueId>, CrustyError> {
+        self.get(key)
+    }
+
+    fn range_query(&self, min: &[Option<Field>], max: &[Option<Field>]) -> Result<Vec<ValueId>, CrustyError> {
+        self.partial_range_query(min, max)
+    }
+
+    fn knn_query(&self, key: &[Field], k: usize, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        self.knn_with_metric(key, k, metric)
+    }
+
+    fn radius_query(&self, center: &[Field], radius: f64, metric: DistanceMetric) -> Result<Vec<Neighbor>, CrustyError> {
+        KdbTree::radius_query(self, center, radius, metric)
+    }
+
+    // every point node is at the same depth, so the first child is followed down
+    fn stats(&self) -> Result<MdIndexStats, CrustyError> {
+        let mut depth = 1;
+        let mut node = self.read_node(self.meta.root)?;
+        while let KdbNode::Region(children) = node {
+            let child = match children.first() {
+                Some(child) => child.page_id,
+                None => break,
+            };
+            depth += 1;
+            node = self.read_node(child)?;
+        }
+        Ok(MdIndexStats {num_entries: self.num_entries()?, depth})
+    }
+
+    fn serialize(&self, name: &str) -> SerializedIndex {
+        SerializedIndex::new(self, name, self.get_total_dim(), self.get_path())
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(self.get_path())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::testutil::*;
+    use temp_testdir::TempDir;
+
+    fn brute_force_range(rows: &[(Vec<Field>, ValueId)], min: &[Field], max: &[Field]) -> Vec<ValueId> {
+        let mut res: Vec<ValueId> = rows
+            .iter()
+            .filter(|(row, _)| KdTree::if_within_range(&row[1..], min, max))
+            .map(|(_, rid)| *rid)
+            .collect();
+        res.sort();
+        res
+    }
+
+    #[test]
+    fn hs_kdb_insert_query_reopen() {
+        init();
+        let tdir = TempDir::new(gen_random_dir(), true);
+        let mut path = tdir.to_path_buf();
+        path.push("kdb_test");
+        path.set_extension("kdb");
+
+        let mut rows = Vec::new();
+        for i in 0..3000 {
+            let row = vec![
+                Field::IntField(i),
+                Field::IntField((i * 37) % 101),
+                Field::IntField((i * 53) % 97),
+            ];
+            rows.push((row, ValueId::new_slot(1, (i / 100) as u16, (i % 100) as u16)));
+        }
+        let mut tree = KdbTree::create(path.clone(), 2, vec![1, 2], 3).unwrap();
+        tree.data_into_tree(&rows).unwrap();
+        // the tree no longer fits in a single leaf
+        assert!(tree.hf.num_pages() > 10);
+
+        let min = vec![Field::IntField(10), Field::IntField(20)];
+        let max = vec![Field::IntField(30), Field::IntField(60)];
+        let mut res = tree.range_query(&min, &max).unwrap();
+        res.sort();
+        assert_eq!(brute_force_range(&rows, &min, &max), res);
+
+        // the first attribute is only bounded above and the second only below
+        let mut res = tree.partial_range_query(&[None, Some(Field::IntField(90))], &[Some(Field::IntField(5))]).unwrap();
+        res.sort();
+        let mut expected: Vec<ValueId> = rows
+            .iter()
+            .filter(|(row, _)| row[1] <= Field::IntField(5) && row[2] >= Field::IntField(90))
+            .map(|(_, rid)| *rid)
+            .collect();
+        expected.sort();
+        assert_eq!(expected, res);
+
+        let mut res = tree.get(&tree.get_key(&rows[42].0)).unwrap();
+        res.sort();
+        assert_eq!(brute_force_range(&rows, &rows[42].0[1..], &rows[42].0[1..]), res);
+
+        let key = [Field::IntField(50), Field::IntField(50)];
+        for metric in [DistanceMetric::Euclidean, DistanceMetric::Manhattan, DistanceMetric::Chebyshev] {
+            let mut neighbors: Vec<Neighbor> = rows
+                .iter()
+                .map(|(row, rid)| Neighbor {dist: metric.distance(&row[1..], &key), key: row[1..].to_vec(), rid: *rid})
+                .collect();
+            neighbors.sort();
+            neighbors.truncate(5);
+            assert_eq!(neighbors, tree.knn_with_metric(&key, 5, metric).unwrap());
+
+            neighbors = rows
+                .iter()
+                .map(|(row, rid)| Neighbor {dist: metric.distance(&row[1..], &key), key: row[1..].to_vec(), rid: *rid})
+                .filter(|neighbor| neighbor.dist.0 <= 3.0)
+                .collect();
+            neighbors.sort();
+            assert_eq!(neighbors, tree.radius_query(&key, 3.0, metric).unwrap());
+        }
+
+        for (row, rid) in &rows[..1500] {
+            assert!(tree.delete(row, *rid).unwrap());
+        }
+        assert!(!tree.delete(&rows[0].0, rows[0].1).unwrap());
+        drop(tree);
+
+        let tree = KdbTree::open(path).unwrap();
+        let mut res = tree.range_query(&min, &max).unwrap();
+        res.sort();
+        assert_eq!(brute_force_range(&rows[1500..], &min, &max), res);
+    }
+}
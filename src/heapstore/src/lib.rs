@@ -2,8 +2,12 @@
 extern crate log;
 #[macro_use]
 extern crate serde;
+mod bplus_tree;
+mod hash_index;
 mod heapfile;
 mod heapfileiter;
+pub mod index_registry;
+mod kdb_tree;
 mod page;
 pub mod storage_manager;
 pub mod testutil;
@@ -3,7 +3,7 @@ use common::PAGE_SIZE;
 use std::cmp::max;
 use std::collections::HashMap;
 use std::convert::TryInto;
-use std::mem::size_of_val;
+use std::mem::{size_of, size_of_val};
 
 pub type ValAddr = u16;
 
@@ -104,6 +104,14 @@ impl Page {
                 .clone_from_slice(&self.data[usize::from(slot.start)..usize::from(slot.end)]);
             self.data[(curr_end - slot_size)..curr_end].clone_from_slice(&slot_data);
             curr_end -= slot_size;
+            // the slot has to follow its data
+            self.header.slot_arr.insert(
+                slot.slot_id,
+                HashSlot {
+                    start: u16::try_from(curr_end).unwrap(),
+                    end: u16::try_from(curr_end + slot_size).unwrap(),
+                },
+            );
         }
         Some((curr_end - data_size).try_into().unwrap())
     }
@@ -116,6 +124,11 @@ impl Page {
     pub fn get_first_free_space(&mut self, data_size: usize) -> Option<ValAddr> {
         let num_slot = self.header.slot_arr.len();
         if num_slot == 0 {
+            // the value also needs room for its slot in the header
+            let slot_size = size_of::<SlotId>() + 2 * size_of::<ValAddr>();
+            if self.get_header_size() + slot_size + data_size > PAGE_SIZE {
+                return None;
+            }
             return Some(u16::try_from(PAGE_SIZE - data_size).unwrap());
         }
         let mut total_size: usize = 0;
@@ -353,10 +366,9 @@ impl PageIter {
     }
 }
 
-/// The implementation of the (consuming) page iterator.
-impl Iterator for PageIter {
-    type Item = Vec<u8>;
-    fn next(&mut self) -> Option<Self::Item> {
+impl PageIter {
+    /// Get the next value of the page along with the slot it is stored in.
+    pub fn next_with_slot_id(&mut self) -> Option<(Vec<u8>, SlotId)> {
         let mut res: Vec<u8>;
         let slot = &self.slot_vec.get(self.index);
         self.index += 1;
@@ -366,13 +378,21 @@ impl Iterator for PageIter {
                 res[0..usize::from(vec_slot.end - vec_slot.start)].clone_from_slice(
                     &self.data[usize::from(vec_slot.start)..usize::from(vec_slot.end)],
                 );
-                Some(res)
+                Some((res, vec_slot.slot_id))
             }
             None => None,
         }
     }
 }
 
+/// The implementation of the (consuming) page iterator.
+impl Iterator for PageIter {
+    type Item = Vec<u8>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_with_slot_id().map(|(value, _)| value)
+    }
+}
+
 /// The implementation of IntoIterator which allows an iterator to be created
 /// for a page. This should create the PageIter struct with the appropriate state/metadata
 /// on initialization.
@@ -679,6 +699,23 @@ mod tests {
         assert_eq!(Some(4), p.add_value(&tuple_bytes_small2));
     }
 
+    #[test]
+    fn hs_page_defragment() {
+        init();
+        let mut p = Page::new(0);
+        let tuple_bytes = get_random_byte_vec(40);
+        let tuple_bytes2 = get_random_byte_vec(40);
+        assert_eq!(Some(0), p.add_value(&tuple_bytes));
+        assert_eq!(Some(1), p.add_value(&tuple_bytes2));
+        assert_eq!(Some(()), p.delete_value(0));
+
+        // only fits once the remaining value is moved to the end of the page
+        let tuple_bytes_big = get_random_byte_vec(PAGE_SIZE - 100);
+        assert_eq!(Some(0), p.add_value(&tuple_bytes_big));
+        assert_eq!(tuple_bytes2, p.get_value(1).unwrap());
+        assert_eq!(tuple_bytes_big, p.get_value(0).unwrap());
+    }
+
     #[test]
     fn hs_page_size() {
         init();
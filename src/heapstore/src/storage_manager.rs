@@ -1,20 +1,19 @@
-use crate::heapfile::{HeapFile, KdIndex, RIndex};
+use crate::heapfile::HeapFile;
 use crate::heapfileiter::HeapFileIterator;
+use crate::index_registry;
 use crate::page::Page;
 use common::prelude::*;
 use common::storage_trait::StorageTrait;
+use common::md_index::{DistanceMetric, MdIndex, Neighbor, SerializedIndex, SpatialPredicate, ZOrderIndex};
 use common::Field;
 use common::testutil::gen_random_dir;
 use common::PAGE_SIZE;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::fs::File;
-use std::os::unix::prelude::FileExt;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
 use std::sync::{Arc, RwLock};
-use common::md_index::KdTree;
-use common::md_index::R_Tree;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct SerializedHeapFile {
@@ -116,15 +115,113 @@ impl StorageManager {
         page: &Page,
         page_id: PageId,
     ) -> Result<(), CrustyError> {
-        let page_serialized = page.get_bytes();
         let hf_map = &self.hf_map.read().unwrap();
         let hf = hf_map.get(&container_id).unwrap();
-        let underlying_file = hf.heap_file.write().unwrap();
-        underlying_file.write_at(
-            &page_serialized,
-            (usize::from(page_id) * PAGE_SIZE).try_into().unwrap(),
-        )?;
-        Ok(())
+        hf.write_updated_page_to_file(page, page_id)
+    }
+
+    /// Find the first page of the container that can hold the value, creating a new page
+    /// if none of the existing pages have enough space.
+    fn insert_value_into_page(
+        &self,
+        container_id: ContainerId,
+        value: &[u8],
+        tid: TransactionId,
+    ) -> ValueId {
+        let container_num_page = self.get_num_pages(container_id);
+        for i in 0..container_num_page {
+            let page = &mut self
+                .get_page(container_id, i, tid, Permissions::ReadWrite, false)
+                .unwrap();
+            match &page.add_value(value) {
+                None => continue,
+                Some(slot_id) => {
+                    self.write_updated_page_to_file(container_id, page, i)
+                        .expect("Can't write updated page to file");
+                    return ValueId {
+                        container_id,
+                        segment_id: None,
+                        page_id: Some(i),
+                        slot_id: Some(*slot_id),
+                    };
+                }
+            }
+        }
+        let mut new_page = Page::new(container_num_page);
+        let slot_id = &new_page.add_value(value).unwrap();
+        self.write_page(container_id, new_page, tid)
+            .expect("Can't write new page to file");
+        ValueId {
+            container_id,
+            segment_id: None,
+            page_id: Some(self.get_num_pages(container_id) - 1),
+            slot_id: Some(*slot_id),
+        }
+    }
+
+    /// Keep the indexes of a container in sync with an inserted or deleted value.
+    /// Values are only decoded as tuples when the container has an index.
+    /// On error the indexes are left as they were.
+    fn update_indexes(&self, value: &[u8], id: ValueId, is_insert: bool) -> Result<(), CrustyError> {
+        let hf_map = self.hf_map.read().unwrap();
+        let hf = match hf_map.get(&id.container_id) {
+            Some(hf) if hf.has_indexes() => hf,
+            _ => return Ok(()),
+        };
+        let tuple = Tuple::from_bytes(value);
+        if is_insert {
+            hf.insert_into_indexes(&tuple.field_vals, id)
+        } else {
+            hf.delete_from_indexes(&tuple.field_vals, id)
+        }
+    }
+
+    /// Insert a value into a container and its indexes. If an index can't be updated, the value
+    /// is taken back out of its page and the error is returned, leaving the container as it was.
+    pub fn try_insert_value(
+        &self,
+        container_id: ContainerId,
+        value: Vec<u8>,
+        tid: TransactionId,
+    ) -> Result<ValueId, CrustyError> {
+        if value.len() > PAGE_SIZE {
+            return Err(CrustyError::CrustyError(String::from(
+                "Cannot handle inserting a value larger than the page size",
+            )));
+        }
+        let value_id = self.insert_value_into_page(container_id, &value, tid);
+        if let Err(e) = self.update_indexes(&value, value_id, true) {
+            let page_id = value_id.page_id.unwrap();
+            let mut page = self
+                .get_page(container_id, page_id, tid, Permissions::ReadWrite, false)
+                .ok_or_else(|| CrustyError::CrustyError(String::from("Couldn't find page")))?;
+            page.delete_value(value_id.slot_id.unwrap());
+            self.write_updated_page_to_file(container_id, &page, page_id)?;
+            return Err(e);
+        }
+        Ok(value_id)
+    }
+
+    /// Write the indexes of every container to `{storage_path}/indexes/<container_id>`
+    /// so that they can be recreated when the storage manager is restarted.
+    fn persist_indexes(&self) {
+        let filepath = format!("{}/indexes", self.storage_path);
+        fs::create_dir_all(&filepath).expect("Can't create sm index directory");
+        for (container_id, hf) in self.hf_map.read().unwrap().iter() {
+            let filename = format!("{}/{}", filepath, container_id);
+            let serialized_indexes = hf.get_serialized_indexes();
+            if serialized_indexes.is_empty() {
+                if Path::new(&filename).exists() {
+                    fs::remove_file(&filename).expect("Can't remove stale index file");
+                }
+                continue;
+            }
+            serde_json::to_writer(
+                fs::File::create(filename).expect("error creating file"),
+                &serialized_indexes,
+            )
+            .expect("error serializing indexes");
+        }
     }
 
     fn get_attribute_list(attributes: &str) -> Vec<String> {
@@ -140,187 +237,429 @@ impl StorageManager {
         attribute_list.clone()  
     }
 
-    fn scan_tuple_for_range(tuple: &Tuple, min: &Vec<Field>, max: &Vec<Field>, idx_fields: &Vec<usize>) -> bool {
-        if KdTree::if_within_range(&tuple.field_vals, min, max, idx_fields) {
-            return true
-        }
-        else {
-            return false
+    /// Fetch the tuples stored under the given value ids.
+    fn get_tuples_by_ids(&self, ids: &[ValueId]) -> Vec<Tuple> {
+        let tid = TransactionId::new();
+        let mut res = Vec::new();
+        for id in ids {
+            let bytes = self
+                .get_value(*id, tid, Permissions::ReadOnly)
+                .expect("Index points to a missing record");
+            res.push(Tuple::from_bytes(&bytes));
         }
+        res
     }
 
-    pub fn create_index_by_id(&self, tree_type: &str, index_name: &str, container_id: ContainerId, attributes: &str, table: &Table) {
+    pub fn create_index_by_id(&self, tree_type: &str, index_name: &str, container_id: ContainerId, attributes: &str, table: &Table) -> Result<(), CrustyError> {
         debug!("Comes to create_index_by_id in Storage Manager");
-        let hf_map = &self.hf_map.read().unwrap();
-        let hf = hf_map.get(&container_id).unwrap();
-        let hf_iterator = self.get_iterator(container_id, TransactionId::new(), Permissions::ReadOnly);
+        let index_type = index_registry::index_type(tree_type)?;
         let schema = &table.schema;
         let mut field_vec = Vec::new();
-        let attribute_list = StorageManager::get_attribute_list(attributes);
-        for attribute_name in attribute_list {
-            let field_index = schema.get_field_index(&attribute_name);
-            if field_index.is_some() {
-                field_vec.push(*field_index.unwrap());
-            }
-            else {
-                error!("Field not found");
+        for attribute_name in StorageManager::get_attribute_list(attributes) {
+            match schema.get_field_index(&attribute_name) {
+                Some(field_index) => field_vec.push(*field_index),
+                None => {
+                    return Err(CrustyError::CrustyError(format!("Field {} not found", attribute_name)));
+                }
             }
         }
+        index_type.check_dim(field_vec.len())?;
+        if !self.hf_map.read().unwrap().contains_key(&container_id) {
+            return Err(CrustyError::CrustyError(String::from("Couldn't find Container")));
+        }
+        let mut hf_iterator = self.get_iterator(container_id, TransactionId::new(), Permissions::ReadOnly);
         let mut bulk_load_data = Vec::new();
-        for (i, val) in hf_iterator.enumerate() {
+        while let Some((val, rid)) = hf_iterator.next_with_id() {
             let tuple = Tuple::from_bytes(&val);
-            bulk_load_data.push(tuple.field_vals.clone());
+            bulk_load_data.push((tuple.field_vals, rid));
         }
-        debug!("Bulk load data array {:?}", &bulk_load_data);
+        let mut path = PathBuf::from(format!("{}/indexes/{}_{}", self.storage_path, container_id, index_name));
+        if let Some(extension) = index_type.extension {
+            // indexes kept in their own file live next to the serialized indexes
+            fs::create_dir_all(path.parent().unwrap())?;
+            path.set_extension(extension);
+        }
+        let mut index = (index_type.create)(&path, field_vec, schema.attributes.len())?;
+        index.bulk_load(&bulk_load_data)?;
+        let hf_map = self.hf_map.read().unwrap();
+        let hf = hf_map
+            .get(&container_id)
+            .ok_or_else(|| CrustyError::CrustyError(String::from("Couldn't find Container")))?;
+        hf.index_map.write().unwrap().insert(index_name.to_string(), index);
+        Ok(())
+    }
+
+    /// Remove an index from a container.
+    pub fn drop_index(&self, container_id: ContainerId, index_name: &str) -> Result<(), CrustyError> {
+        let hf_map = self.hf_map.read().unwrap();
+        let hf = hf_map
+            .get(&container_id)
+            .ok_or_else(|| CrustyError::CrustyError(String::from("Couldn't find Container")))?;
+        if hf.remove_index(index_name)? {
+            Ok(())
+        } else {
+            Err(CrustyError::CrustyError(format!("Index {} not found", index_name)))
+        }
+    }
+
+    /// Number of records in an index of a container.
+    pub fn index_num_entries(&self, container_id: ContainerId, index_name: &str) -> Result<usize, CrustyError> {
+        self.with_index(container_id, index_name, None, |index| Ok(index.stats()?.num_entries))
+    }
+
+    /// Run `f` on an index of a container. When `tree_type` is given, the index must be of that type.
+    fn with_index<T, F>(&self, container_id: ContainerId, index_name: &str, tree_type: Option<&str>, f: F) -> Result<T, CrustyError>
+    where
+        F: FnOnce(&dyn MdIndex) -> Result<T, CrustyError>,
+    {
+        let hf_map = self.hf_map.read().unwrap();
+        let hf = hf_map
+            .get(&container_id)
+            .ok_or_else(|| CrustyError::CrustyError(String::from("Couldn't find Container")))?;
+        let index_map = hf.index_map.read().unwrap();
+        let index = index_map
+            .get(index_name)
+            .ok_or_else(|| CrustyError::CrustyError(format!("Index {} not found", index_name)))?;
         match tree_type {
-            "KD" => { hf.kd_index_map.write().unwrap().insert(index_name.to_string(), 
-                Arc::new(RwLock::new(KdIndex::new(field_vec.len(), index_name.to_string(), field_vec.clone(), schema.attributes.len()))));
-                hf.kd_index_map.write().unwrap().get(index_name).unwrap().write().unwrap().tree.data_into_tree(&mut bulk_load_data[..]);
-                //hf.kd_index_map.write().unwrap().get(index_name).unwrap().write().unwrap().tree.print_tree();
-            },
-            "R" => {        hf.r_index_map.write().unwrap().insert(index_name.to_string(), 
-                Arc::new(RwLock::new(RIndex::new(field_vec.len(), index_name.to_string(), field_vec.clone(), schema.attributes.len()))));
-                hf.r_index_map.write().unwrap().get(index_name).unwrap().write().unwrap().tree.data_into_tree(&mut bulk_load_data[..]);
-                //hf.r_index_map.write().unwrap().get(index_name).unwrap().write().unwrap().tree.print_tree();
-            },
-            _ => {error!("CreateIndex Tree Type Command not supported");},
+            Some(tree_type) if tree_type != index.tree_type() => Err(CrustyError::CrustyError(format!(
+                "Index {} is a {} index, not {}",
+                index_name,
+                index.tree_type(),
+                tree_type
+            ))),
+            _ => f(index.as_ref()),
+        }
+    }
+
+    /// Check an index of a container against the records of the container, and describe every
+    /// discrepancy: records the index does not find under their key, index entries without a
+    /// record or repeated, and for KD trees the nodes breaking the tree invariants. An empty
+    /// result means the index agrees with the container.
+    pub fn check_index(&self, container_id: ContainerId, index_name: &str) -> Result<Vec<String>, CrustyError> {
+        if !self.hf_map.read().unwrap().contains_key(&container_id) {
+            return Err(CrustyError::CrustyError(String::from("Couldn't find Container")));
+        }
+        let mut records = Vec::new();
+        let mut hf_iterator = self.get_iterator(container_id, TransactionId::new(), Permissions::ReadOnly);
+        while let Some((val, rid)) = hf_iterator.next_with_id() {
+            records.push((Tuple::from_bytes(&val).field_vals, rid));
+        }
+        self.with_index(container_id, index_name, None, |index| {
+            let mut res = StorageManager::check_index_entries(&records, |val| index.point_query(&index.key_of(val)), index.range_query(&[], &[])?)?;
+            res.extend(index.check_invariants());
+            Ok(res)
+        })
+    }
+
+    // `get` finds the ids stored under the key of a record, and `index_ids` holds every id in the index
+    fn check_index_entries<F>(records: &[(Vec<Field>, ValueId)], get: F, index_ids: Vec<ValueId>) -> Result<Vec<String>, CrustyError>
+    where
+        F: Fn(&[Field]) -> Result<Vec<ValueId>, CrustyError>,
+    {
+        let describe = |rid: &ValueId| format!("page {} slot {}", rid.page_id.unwrap_or_default(), rid.slot_id.unwrap_or_default());
+        let mut res = Vec::new();
+        let mut record_ids = HashSet::new();
+        for (val, rid) in records {
+            record_ids.insert(*rid);
+            if !get(val)?.contains(rid) {
+                res.push(format!("Record {:?} at {} is not found under its key", val, describe(rid)));
+            }
+        }
+        let mut seen = HashSet::new();
+        for rid in index_ids {
+            if !seen.insert(rid) {
+                res.push(format!("Record at {} has more than one entry", describe(&rid)));
+            } else if !record_ids.contains(&rid) {
+                res.push(format!("Entry for {} has no record", describe(&rid)));
+            }
+        }
+        Ok(res)
+    }
+
+    /// Rewrite the pages of a container with its records sorted by the curve key of one of its
+    /// Z-order indexes, so that records close in the indexed attributes share pages and a scan
+    /// over a region of them reads few pages. Records move, so every index of the container is
+    /// rebuilt with their new ids. Returns the number of pages written.
+    pub fn cluster_container(&self, container_id: ContainerId, index_name: &str) -> Result<PageId, CrustyError> {
+        let idx_fields = self.with_index(container_id, index_name, Some("Z"), |index| Ok(index.idx_fields()))?;
+        let hf_map = self.hf_map.read().unwrap();
+        let hf = hf_map
+            .get(&container_id)
+            .ok_or_else(|| CrustyError::CrustyError(String::from("Couldn't find Container")))?;
+        hf.cluster(container_id, |field_vals| {
+            let key: Vec<Field> = idx_fields.iter().map(|idx| field_vals[*idx].clone()).collect();
+            ZOrderIndex::z_value(&key)
+        })
+    }
+
+    fn get_index_idx_fields(&self, tree_type: &str, index_name: &str, container_id: ContainerId) -> Result<Vec<usize>, CrustyError> {
+        self.with_index(container_id, index_name, Some(tree_type), |index| Ok(index.idx_fields()))
+    }
+
+    fn parse_index_value(attribute_val: String, field_index: usize, schema: &TableSchema) -> Result<Field, CrustyError> {
+        let attribute = schema
+            .get_attribute(field_index)
+            .ok_or_else(|| CrustyError::CrustyError(format!("Attribute {} not found", field_index)))?;
+        match &attribute.dtype {
+            DataType::Int => attribute_val.parse::<i32>().map(Field::IntField).map_err(|_| {
+                CrustyError::CrustyError(format!("{} is not an integer value of {}", attribute_val, attribute.name))
+            }),
+            DataType::String => Ok(Field::StringField(attribute_val)),
+        }
+    }
+
+    // a key has a value for every indexed attribute
+    fn parse_index_key(attribute_vals: Vec<String>, idx_fields: &[usize], schema: &TableSchema) -> Result<Vec<Field>, CrustyError> {
+        if attribute_vals.len() != idx_fields.len() {
+            return Err(CrustyError::CrustyError(format!(
+                "Index key has {} values for {} attributes",
+                attribute_vals.len(),
+                idx_fields.len()
+            )));
         }
+        attribute_vals
+            .into_iter()
+            .zip(idx_fields)
+            .map(|(attribute_val, field_index)| StorageManager::parse_index_value(attribute_val, *field_index, schema))
+            .collect()
+    }
+
+    // a `*` (or nothing) stands for any value of the attribute, and so do the values left out at the end
+    fn parse_index_bounds(attribute_vals: Vec<String>, idx_fields: &[usize], schema: &TableSchema) -> Result<Vec<Option<Field>>, CrustyError> {
+        if attribute_vals.len() > idx_fields.len() {
+            return Err(CrustyError::CrustyError(format!(
+                "Index bound has {} values for {} attributes",
+                attribute_vals.len(),
+                idx_fields.len()
+            )));
+        }
+        attribute_vals
+            .into_iter()
+            .zip(idx_fields)
+            .map(|(attribute_val, field_index)| {
+                if attribute_val.is_empty() || attribute_val == "*" {
+                    Ok(None)
+                } else {
+                    StorageManager::parse_index_value(attribute_val, *field_index, schema).map(Some)
+                }
+            })
+            .collect()
     }
 
     fn use_index_equal (&self, tree_type: &str, index_name: &str, container_id: ContainerId, attributes: &str, table: &Table) -> Vec<Tuple> {
         debug!("Comes to use_index_equal in Storage Manager");
-        let hf_map = &self.hf_map.read().unwrap();
-        let hf = hf_map.get(&container_id).unwrap();
-        let schema = &table.schema;
-        let attribute_vals = StorageManager::get_attribute_list(attributes);
-        let mut field_vec = Vec::new(); //LOTS OF ERROR CHECKING
-        let mut i = 0;
-        let mut idx_fields = Vec::new();
-        match tree_type {
-            "KD" => { idx_fields = hf.kd_index_map.write().unwrap().get(index_name).unwrap().write().unwrap().tree.idx_fields.clone();},
-            "R" => {idx_fields = hf.r_index_map.write().unwrap().get(index_name).unwrap().write().unwrap().tree.get_idx_fields().clone();},
-            _ => {error!("UseIndex Tree Type Command not supported");},
-        }
-        for attribute_val in attribute_vals {
-            match &schema.get_attribute(idx_fields[i]).unwrap().dtype {
-                Int => {field_vec.push(Field::IntField(attribute_val.parse::<i32>().unwrap()))},
-                String => {field_vec.push(Field::StringField(attribute_val))},
-            }
-            i += 1;
+        // a key with wildcards matches the records equal to it on the other attributes
+        if StorageManager::get_attribute_list(attributes).iter().any(|val| val == "*") {
+            return self.use_index_range(tree_type, index_name, container_id, &format!("{};{}", attributes, attributes), table);
+        }
+        let ids = self.with_index(container_id, index_name, Some(tree_type), |index| {
+            let key = StorageManager::parse_index_key(StorageManager::get_attribute_list(attributes), &index.idx_fields(), &table.schema)?;
+            index.point_query(&key)
+        });
+        match ids {
+            Ok(ids) => self.get_tuples_by_ids(&ids),
+            Err(e) => {error!("UseIndex failed: {:?}", e); Vec::new()},
         }
-        let mut res = Vec::new();
-        match tree_type {
-            "KD" => {res = hf.kd_index_map.write().unwrap().get(index_name).unwrap().write().unwrap().tree.get(&field_vec);},
-            "R" => {res = hf.r_index_map.write().unwrap().get(index_name).unwrap().write().unwrap().tree.get(&field_vec);},
-            _ => {error!("UseIndex Tree Type Command not supported");},
-        }        
-        //KdTree::print_vec(&res);
-        return KdTree::vec_field_to_tuple(&res);
+    }
+
+    /// Get the ids of the records of a container whose indexed attributes equal `key`, using
+    /// the given index.
+    pub fn index_point_query(
+        &self,
+        tree_type: &str,
+        index_name: &str,
+        container_id: ContainerId,
+        key: &[Field],
+    ) -> Result<Vec<ValueId>, CrustyError> {
+        self.with_index(container_id, index_name, Some(tree_type), |index| index.point_query(key))
+    }
+
+    /// Get the ids of the records of a container whose indexed attributes lie between `min`
+    /// and `max` (inclusive), using the given index. A `None` bound leaves its side of the
+    /// attribute open.
+    pub fn index_range_query(
+        &self,
+        tree_type: &str,
+        index_name: &str,
+        container_id: ContainerId,
+        min: &[Option<Field>],
+        max: &[Option<Field>],
+    ) -> Result<Vec<ValueId>, CrustyError> {
+        self.with_index(container_id, index_name, Some(tree_type), |index| index.range_query(min, max))
     }
 
     fn use_index_range(&self, tree_type: &str, index_name: &str, container_id: ContainerId, attributes: &str, table: &Table) -> Vec<Tuple> {
         debug!("Comes to use_index_range in Storage Manager");
-        let hf_map = &self.hf_map.read().unwrap();
-        let hf = hf_map.get(&container_id).unwrap();
-        let schema = &table.schema;
-        let mut tokens = attributes.split(";");
-        let mut j = 0;
-        let mut min = Vec::new();
-        let mut max = Vec::new();
-        let mut idx_fields = Vec::new();
-        match tree_type {
-            "KD" => { idx_fields = hf.kd_index_map.write().unwrap().get(index_name).unwrap().write().unwrap().tree.idx_fields.clone();},
-            "R" => {idx_fields = hf.r_index_map.write().unwrap().get(index_name).unwrap().write().unwrap().tree.get_idx_fields().clone();},
-            _ => {error!("UseIndex Tree Type Command not supported");},
-        }
-        while let Some(attribute_min_max_val) = tokens.next() {
-            let min_max_val_list = StorageManager::get_attribute_list(attribute_min_max_val);
-            for i in 0..min_max_val_list.len() {
-                match &schema.get_attribute(idx_fields[i]).unwrap().dtype {
-                    Int => {
-                            if j == 0 {
-                                min.push(Field::IntField(min_max_val_list[i].parse::<i32>().unwrap()));
-                            }
-                            else {
-                                max.push(Field::IntField(min_max_val_list[i].parse::<i32>().unwrap()));
-                            }
-                        },
-                    String => {
-                        if j == 0 {
-                            min.push(Field::StringField(min_max_val_list[i].to_string()));
-                        }
-                        else {
-                            max.push(Field::StringField(min_max_val_list[i].to_string()));
-                        }
-                    }
-                }
-            }     
-            j += 1;     
+        let ids = self.get_index_idx_fields(tree_type, index_name, container_id).and_then(|idx_fields| {
+            // a missing upper corner leaves every attribute unbounded above
+            let mut tokens = attributes.split(';');
+            let min = StorageManager::parse_index_bounds(StorageManager::get_attribute_list(tokens.next().unwrap_or("()")), &idx_fields, &table.schema)?;
+            let max = StorageManager::parse_index_bounds(StorageManager::get_attribute_list(tokens.next().unwrap_or("()")), &idx_fields, &table.schema)?;
+            self.index_range_query(tree_type, index_name, container_id, &min, &max)
+        });
+        match ids {
+            Ok(ids) => self.get_tuples_by_ids(&ids),
+            Err(e) => {error!("UseIndex failed: {:?}", e); Vec::new()},
         }
-        let mut res = Vec::new();
-        match tree_type {
-            "KD" => {
-                res = hf.kd_index_map.write().unwrap().get(index_name).unwrap().write().unwrap().tree.range_query(&min, &max);},
-            "R" => {     
-                let hf_iterator = self.get_iterator(container_id, TransactionId::new(), Permissions::ReadOnly);   
-                let padded_min = KdTree::padding2(&min, &idx_fields, schema.attributes.len());
-                let padded_max = KdTree::padding2(&max, &idx_fields, schema.attributes.len());
-                // debug!("min is {:?}", padded_min);
-                // debug!("max is {:?}", padded_max);
-                for (i, val) in hf_iterator.enumerate() {
-                    let tuple = Tuple::from_bytes(&val);
-                    if StorageManager::scan_tuple_for_range(&tuple, &padded_min, &padded_max, &idx_fields) {
-                        res.push(tuple.field_vals.clone());
-                    }
+    }
+
+    /// Get the `k` records of a container whose indexed attributes are closest to `key`,
+    /// closest first, using the given index.
+    pub fn index_knn_query(
+        &self,
+        tree_type: &str,
+        index_name: &str,
+        container_id: ContainerId,
+        key: &[Field],
+        k: usize,
+        metric: DistanceMetric,
+    ) -> Result<Vec<Neighbor>, CrustyError> {
+        self.with_index(container_id, index_name, Some(tree_type), |index| index.knn_query(key, k, metric))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn use_index_knn(&self, tree_type: &str, index_name: &str, container_id: ContainerId, attributes: &str, _k: Option<&str>, metric: Option<&str>, table: &Table) -> Vec<Tuple> {
+        debug!("Comes to use_index_knn in Storage Manager");
+        let k = match _k.map(|k| k.parse::<usize>()) {
+            Some(Ok(k)) => k,
+            _ => {error!("no k specified"); return Vec::new()},
+        };
+        let metric = match StorageManager::parse_metric(metric) {
+            Some(metric) => metric,
+            None => return Vec::new(),
+        };
+        let neighbors = self.get_index_idx_fields(tree_type, index_name, container_id).and_then(|idx_fields| {
+            let key = StorageManager::parse_index_key(StorageManager::get_attribute_list(attributes), &idx_fields, &table.schema)?;
+            debug!("field vec is {:?}", &key);
+            self.index_knn_query(tree_type, index_name, container_id, &key, k, metric)
+        });
+        match neighbors {
+            Ok(neighbors) => self.get_tuples_with_distances(&neighbors),
+            Err(e) => {error!("UseIndex failed: {:?}", e); Vec::new()},
+        }
+    }
+
+    fn parse_metric(metric: Option<&str>) -> Option<DistanceMetric> {
+        match metric {
+            Some(name) => {
+                let metric = DistanceMetric::from_name(name);
+                if metric.is_none() {
+                    error!("Distance metric {} not supported", name);
                 }
-            },
-            _ => {error!("UseIndex Tree Type Command not supported");},
+                metric
+            }
+            None => Some(DistanceMetric::default()),
         }
-        return KdTree::vec_field_to_tuple(&res);
     }
 
-    fn use_index_knn(&self, tree_type: &str, index_name: &str, container_id: ContainerId, attributes: &str, _k: Option<&str>, table: &Table) -> Vec<Tuple> {
-        debug!("Comes to use_index_knn in Storage Manager");
-        let hf_map = &self.hf_map.read().unwrap();
-        let hf = hf_map.get(&container_id).unwrap();
-        let schema = &table.schema;
-        if _k.is_none() {
-            error!("no k specified");
-        }
-        let k = _k.unwrap().parse::<usize>().unwrap();
-        let attribute_vals = StorageManager::get_attribute_list(attributes);
-        let mut field_vec = Vec::new(); //LOTS OF ERROR CHECKING
-        let mut i = 0;
-        let mut idx_fields = Vec::new();
-        match tree_type {
-            "KD" => { idx_fields = hf.kd_index_map.write().unwrap().get(index_name).unwrap().write().unwrap().tree.idx_fields.clone();},
-            "R" => {idx_fields = hf.r_index_map.write().unwrap().get(index_name).unwrap().write().unwrap().tree.get_idx_fields().clone();},
-            _ => {error!("UseIndex Tree Type Command not supported");},
-        }
-        for attribute_val in attribute_vals {
-            match &schema.get_attribute(idx_fields[i]).unwrap().dtype {
-                Int => {field_vec.push(Field::IntField(attribute_val.parse::<i32>().unwrap()))},
-                String => {field_vec.push(Field::StringField(attribute_val))},
+    // the distance to the query key is added as a last column
+    fn get_tuples_with_distances(&self, neighbors: &[Neighbor]) -> Vec<Tuple> {
+        let ids: Vec<ValueId> = neighbors.iter().map(|neighbor| neighbor.rid).collect();
+        let mut res = self.get_tuples_by_ids(&ids);
+        for (tuple, neighbor) in res.iter_mut().zip(neighbors) {
+            tuple.field_vals.push(neighbor.dist.to_field());
+        }
+        res
+    }
+
+    /// Get the records of a container whose indexed attributes are within `radius` of `center`,
+    /// closest first, using the given index.
+    pub fn index_radius_query(
+        &self,
+        tree_type: &str,
+        index_name: &str,
+        container_id: ContainerId,
+        center: &[Field],
+        radius: f64,
+        metric: DistanceMetric,
+    ) -> Result<Vec<Neighbor>, CrustyError> {
+        self.with_index(container_id, index_name, Some(tree_type), |index| index.radius_query(center, radius, metric))
+    }
+
+    /// Get the ids of the records of a container whose indexed attributes match `key` under a
+    /// spatial join predicate, using the given index. Records found through a grid cell with
+    /// string attributes may not match, so callers check the predicate on what they read.
+    pub fn index_spatial_probe(
+        &self,
+        tree_type: &str,
+        index_name: &str,
+        container_id: ContainerId,
+        key: &[Field],
+        predicate: &SpatialPredicate,
+    ) -> Result<Vec<ValueId>, CrustyError> {
+        match predicate {
+            SpatialPredicate::Within { radius, metric } => Ok(self
+                .index_radius_query(tree_type, index_name, container_id, key, *radius, *metric)?
+                .into_iter()
+                .map(|neighbor| neighbor.rid)
+                .collect()),
+            SpatialPredicate::SameCell { cell_size } => {
+                let (min, max) = SpatialPredicate::cell_bounds(key, *cell_size);
+                self.index_range_query(tree_type, index_name, container_id, &min, &max)
             }
-            i += 1;
         }
-        debug!("field vec is {:?}", &field_vec);
-        let mut res = Vec::new();
-        match tree_type {
-            "KD" => {res = hf.kd_index_map.write().unwrap().get(index_name).unwrap().write().unwrap().tree.knn(&field_vec, k);},
-            "R" => {res = hf.r_index_map.write().unwrap().get(index_name).unwrap().write().unwrap().tree.knn(&field_vec, k);},
-            _ => {error!("UseIndex Tree Type Command not supported");},
-        }      
-        //KdTree::print_vec(&res);
-        return KdTree::vec_field_to_tuple(&res);
     }
 
-    pub fn use_index_by_id(&self, tree_type: &str, query_type: &str, index_name: &str, container_id: ContainerId, attributes: &str, _k: Option<&str>, table: &Table) -> Vec<Tuple> {
+    /// Get the pairs of ids of the records of two containers whose indexed attributes match under
+    /// a spatial join predicate, walking down an R-tree index of each container together.
+    pub fn index_spatial_join(
+        &self,
+        left: (ContainerId, &str),
+        right: (ContainerId, &str),
+        predicate: &SpatialPredicate,
+    ) -> Result<Vec<(ValueId, ValueId)>, CrustyError> {
+        let hf_map = self.hf_map.read().unwrap();
+        let get_hf = |container_id| {
+            hf_map
+                .get(&container_id)
+                .ok_or_else(|| CrustyError::CrustyError(String::from("Couldn't find Container")))
+        };
+        let left_map = get_hf(left.0)?.index_map.read().unwrap();
+        // both indexes may be on the same container, whose map is then only locked once
+        let right_guard;
+        let right_map = if left.0 == right.0 {
+            &left_map
+        } else {
+            right_guard = get_hf(right.0)?.index_map.read().unwrap();
+            &right_guard
+        };
+        let missing_index = |index_name: &str| CrustyError::CrustyError(format!("Index {} not found", index_name));
+        let left_index = left_map.get(left.1).ok_or_else(|| missing_index(left.1))?;
+        let right_index = right_map.get(right.1).ok_or_else(|| missing_index(right.1))?;
+        left_index.spatial_join(right_index.as_ref(), predicate)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn use_index_radius(&self, tree_type: &str, index_name: &str, container_id: ContainerId, attributes: &str, radius: Option<&str>, metric: Option<&str>, table: &Table) -> Vec<Tuple> {
+        debug!("Comes to use_index_radius in Storage Manager");
+        let radius = match radius.map(|radius| radius.parse::<f64>()) {
+            Some(Ok(radius)) => radius,
+            _ => {error!("no radius specified"); return Vec::new()},
+        };
+        let metric = match StorageManager::parse_metric(metric) {
+            Some(metric) => metric,
+            None => return Vec::new(),
+        };
+        let neighbors = self.get_index_idx_fields(tree_type, index_name, container_id).and_then(|idx_fields| {
+            let center = StorageManager::parse_index_key(StorageManager::get_attribute_list(attributes), &idx_fields, &table.schema)?;
+            self.index_radius_query(tree_type, index_name, container_id, &center, radius, metric)
+        });
+        match neighbors {
+            Ok(neighbors) => self.get_tuples_with_distances(&neighbors),
+            Err(e) => {error!("UseIndex failed: {:?}", e); Vec::new()},
+        }
+    }
+
+    /// Run an index query. `_k` is the number of records of a KNN query or the distance of a
+    /// RADIUS query, which both take a `metric` that defaults to euclidean distance.
+    #[allow(clippy::too_many_arguments)]
+    pub fn use_index_by_id(&self, tree_type: &str, query_type: &str, index_name: &str, container_id: ContainerId, attributes: &str, _k: Option<&str>, metric: Option<&str>, table: &Table) -> Vec<Tuple> {
         debug!("Comes to use_index_by_id in Storage Manager");
         match query_type {
             "RANGE" => {self.use_index_range(tree_type, index_name, container_id, attributes, table)},
             "EQ" => {self.use_index_equal(tree_type, index_name, container_id, attributes, table)},
-            "KNN" => {self.use_index_knn(tree_type, index_name, container_id, attributes, _k, table)},
+            "KNN" => {self.use_index_knn(tree_type, index_name, container_id, attributes, _k, metric, table)},
+            "RADIUS" => {self.use_index_radius(tree_type, index_name, container_id, attributes, _k, metric, table)},
             _ => {error!("UseIndex command not supported"); Vec::new()},
         }
     }
@@ -361,6 +700,15 @@ impl StorageTrait for StorageManager {
                     .read()
                     .unwrap();
                 let heap_file = HeapFile::new(heap_file_path.to_path_buf()).unwrap();
+                let index_path = format!("{}/indexes/{}", &storage_path, container_id);
+                if Path::new(&index_path).exists() {
+                    let reader = File::open(&index_path).expect("error opening file");
+                    let serialized_indexes: Vec<SerializedIndex> =
+                        serde_json::from_reader(reader).expect("error reading from json");
+                    heap_file
+                        .load_serialized_indexes(serialized_indexes)
+                        .expect("error loading indexes");
+                }
                 hf_map.insert(container_id, heap_file);
             }
         }
@@ -389,6 +737,8 @@ impl StorageTrait for StorageManager {
     /// Returns the value id associated with the stored value.
     /// Function will need to find the first page that can hold the value.
     /// A new page may need to be created if no space on existing pages can be found.
+    /// The trait gives no way to report an error, so callers that can handle one use
+    /// `try_insert_value`.
     fn insert_value(
         &self,
         container_id: ContainerId,
@@ -398,36 +748,8 @@ impl StorageTrait for StorageManager {
         if value.len() > PAGE_SIZE {
             panic!("Cannot handle inserting a value larger than the page size");
         }
-        let container_num_page = self.get_num_pages(container_id);
-        for i in 0..container_num_page {
-            let page = &mut self
-                .get_page(container_id, i, tid, Permissions::ReadWrite, false)
-                .unwrap();
-            match &page.add_value(&value) {
-                None => continue,
-                Some(slot_id) => {
-                    //self.write_data_to_file(container_id, page, i, *slot_id, value);
-                    self.write_updated_page_to_file(container_id, page, i)
-                        .expect("Can't write updated page to file");
-                    return ValueId {
-                        container_id,
-                        segment_id: None,
-                        page_id: Some(i),
-                        slot_id: Some(*slot_id),
-                    };
-                }
-            }
-        }
-        let mut new_page = Page::new(container_num_page);
-        let slot_id = &new_page.add_value(&value).unwrap();
-        self.write_page(container_id, new_page, tid)
-            .expect("Can't write new page to file");
-        ValueId {
-            container_id,
-            segment_id: None,
-            page_id: Some(self.get_num_pages(container_id) - 1),
-            slot_id: Some(*slot_id),
-        }
+        self.try_insert_value(container_id, value, tid)
+            .expect("Can't insert value")
     }
 
     /// Insert some bytes into a container for vector of values (e.g. record).
@@ -457,9 +779,14 @@ impl StorageTrait for StorageManager {
                 false,
             )
             .unwrap();
+        let old_value = page.get_value(id.slot_id.unwrap());
         match &page.delete_value(id.slot_id.unwrap()) {
             None => Ok(()),
             Some(_) => {
+                // the indexes go first, so the page is only written once they dropped the value
+                if let Some(old_value) = old_value {
+                    self.update_indexes(&old_value, id, false)?;
+                }
                 self.write_updated_page_to_file(id.container_id, page, id.page_id.unwrap())
                     .expect("Can't write updated data to file");
                 Ok(())
@@ -476,8 +803,16 @@ impl StorageTrait for StorageManager {
         id: ValueId,
         _tid: TransactionId,
     ) -> Result<ValueId, CrustyError> {
-        self.delete_value(id, _tid).expect("Can't delete value");
-        Ok(self.insert_value(id.container_id, value, _tid))
+        let old_value = self.get_value(id, _tid, Permissions::ReadOnly)?;
+        self.delete_value(id, _tid)?;
+        match self.try_insert_value(id.container_id, value, _tid) {
+            Ok(new_id) => Ok(new_id),
+            Err(e) => {
+                // put the old value back, possibly under a new id
+                self.try_insert_value(id.container_id, old_value, _tid)?;
+                Err(e)
+            }
+        }
     }
 
     /// Create a new container to be stored.
@@ -528,15 +863,25 @@ impl StorageTrait for StorageManager {
     /// Remove the container and all stored values in the container.
     /// If the container is persisted remove the underlying files
     fn remove_container(&self, container_id: ContainerId) -> Result<(), CrustyError> {
-        let hf_serialized_map = &self.hf_serialized_map.read().unwrap();
-        let serialized_hf = hf_serialized_map.get(&container_id).unwrap();
-        let hf_filepath = serialized_hf.hf_path.read().unwrap().clone();
-        self.hf_map.write().unwrap().remove(&container_id);
+        let hf_filepath = self.hf_serialized_map.read().unwrap()[&container_id]
+            .hf_path
+            .read()
+            .unwrap()
+            .clone();
+        if let Some(hf) = self.hf_map.write().unwrap().remove(&container_id) {
+            for index_path in hf.get_index_paths() {
+                fs::remove_file(&index_path).expect("Can't remove container index");
+            }
+        }
         self.hf_serialized_map
             .write()
             .unwrap()
             .remove(&container_id);
         fs::remove_file(&hf_filepath).expect("Can't remove container");
+        let index_path = format!("{}/indexes/{}", self.storage_path, container_id);
+        if Path::new(&index_path).exists() {
+            fs::remove_file(&index_path).expect("Can't remove container indexes");
+        }
         Ok(())
     }
 
@@ -628,6 +973,7 @@ impl StorageTrait for StorageManager {
             )
             .expect("error deserializing storage manager");
         }
+        self.persist_indexes();
     }
 
     fn import_csv(
@@ -673,7 +1019,7 @@ impl StorageTrait for StorageManager {
                         "server::csv_utils about to insert tuple into container_id: {:?}",
                         &container_id
                     );
-                    self.insert_value(container_id, tuple.get_bytes(), _tid);
+                    self.try_insert_value(container_id, tuple.get_bytes(), _tid)?;
                     inserted_records += 1;
                 }
                 _ => {
@@ -711,6 +1057,7 @@ impl Drop for StorageManager {
             )
             .expect("error deserializing storage manager");
         }
+        self.persist_indexes();
     }
 }
 
@@ -722,6 +1069,78 @@ mod test {
     use common::storage_trait::StorageTrait;
     use common::testutil::*;
 
+    /// Table "test" with the integer attributes a, b and c.
+    fn test_table() -> Table {
+        Table::new(
+            String::from("test"),
+            TableSchema::from_vecs(
+                vec!["a", "b", "c"],
+                vec![DataType::Int, DataType::Int, DataType::Int],
+            ),
+        )
+    }
+
+    /// Tuples of the test table, the last two sharing b and c.
+    fn test_tuples() -> Vec<Tuple> {
+        create_tuple_list(vec![vec![1, 2, 3], vec![4, 5, 6], vec![7, 5, 6]])
+    }
+
+    /// Store the tuples in a container, returning their ids.
+    fn insert_tuples(sm: &StorageManager, cid: ContainerId, tuples: &[Tuple]) -> Vec<ValueId> {
+        let tid = TransactionId::new();
+        tuples.iter().map(|tuple| sm.insert_value(cid, tuple.get_bytes(), tid)).collect()
+    }
+
+    /// Key of a tuple over the indexed attributes, as given to `use_index_by_id`.
+    fn index_key(tuple: &Tuple, attributes: &str) -> String {
+        let table = test_table();
+        let fields: Vec<String> = attributes
+            .trim_matches(|c| c == '(' || c == ')')
+            .split(',')
+            .map(|attr| tuple.field_vals[*table.schema.get_field_index(attr).unwrap()].to_string())
+            .collect();
+        format!("({})", fields.join(","))
+    }
+
+    /// Build an index on the test table and check that it survives a restart, follows deletes and
+    /// updates of the heap file and agrees with the heap file throughout.
+    fn check_index_lifecycle(tree_type: &str, attributes: &str) {
+        init();
+        let storage_path = gen_random_dir().to_string_lossy().to_string();
+        let table = test_table();
+        let cid = 1;
+        let tid = TransactionId::new();
+        let tuples = test_tuples();
+        let ids = {
+            let sm = StorageManager::new(storage_path.clone());
+            sm.create_table(cid).unwrap();
+            let ids = insert_tuples(&sm, cid, &tuples);
+            sm.create_index_by_id(tree_type, "idx", cid, attributes, &table).unwrap();
+            assert_eq!(3, sm.index_num_entries(cid, "idx").unwrap());
+            sm.shutdown();
+            ids
+        };
+
+        let sm = StorageManager::new(storage_path);
+        assert!(sm.check_index(cid, "idx").unwrap().is_empty());
+        let query = |tuple: &Tuple| {
+            let mut res = sm.use_index_by_id(tree_type, "EQ", "idx", cid, &index_key(tuple, attributes), None, None, &table);
+            res.sort_by(|a, b| a.field_vals[0].cmp(&b.field_vals[0]));
+            res
+        };
+        assert_eq!(vec![tuples[1].clone(), tuples[2].clone()], query(&tuples[1]));
+
+        sm.delete_value(ids[1], tid).unwrap();
+        let updated = create_tuple_list(vec![vec![1, 8, 9]]).pop().unwrap();
+        sm.update_value(updated.get_bytes(), ids[0], tid).unwrap();
+        assert_eq!(vec![tuples[2].clone()], query(&tuples[2]));
+        assert!(query(&tuples[0]).is_empty());
+        assert_eq!(vec![updated.clone()], query(&updated));
+        assert_eq!(2, sm.index_num_entries(cid, "idx").unwrap());
+        assert!(sm.check_index(cid, "idx").unwrap().is_empty());
+        sm.reset().unwrap();
+    }
+
     #[test]
     fn hs_sm_a_insert() {
         init();
@@ -827,4 +1246,343 @@ mod test {
         }
         assert_eq!(1000, count);
     }
+
+    #[test]
+    fn hs_sm_index_persist() {
+        init();
+        let storage_path = gen_random_dir().to_string_lossy().to_string();
+        let table = test_table();
+        let cid = 1;
+        let tuples = test_tuples();
+        {
+            let sm = StorageManager::new(storage_path.clone());
+            sm.create_table(cid).unwrap();
+            insert_tuples(&sm, cid, &tuples);
+            sm.create_index_by_id("KD", "kd_idx", cid, "(b,c)", &table).unwrap();
+            sm.create_index_by_id("R", "r_idx", cid, "(b,c)", &table).unwrap();
+            sm.shutdown();
+        }
+
+        let sm = StorageManager::new(storage_path.clone());
+        let mut res = sm.use_index_by_id("KD", "EQ", "kd_idx", cid, "(5,6)", None, None, &table);
+        res.sort_by(|a, b| a.field_vals[0].cmp(&b.field_vals[0]));
+        assert_eq!(vec![tuples[1].clone(), tuples[2].clone()], res);
+        let res = sm.use_index_by_id("R", "EQ", "r_idx", cid, "(2,3)", None, None, &table);
+        assert_eq!(vec![tuples[0].clone()], res);
+        sm.reset().unwrap();
+    }
+
+    #[test]
+    fn hs_sm_index_sync() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let table = test_table();
+        let cid = 1;
+        let tid = TransactionId::new();
+        let tuples = test_tuples();
+        sm.create_table(cid).unwrap();
+        sm.create_index_by_id("KD", "kd_idx", cid, "(b,c)", &table).unwrap();
+        sm.create_index_by_id("R", "r_idx", cid, "(b,c)", &table).unwrap();
+        let ids = insert_tuples(&sm, cid, &tuples);
+        for (tree_type, index_name) in [("KD", "kd_idx"), ("R", "r_idx")] {
+            let mut res = sm.use_index_by_id(tree_type, "EQ", index_name, cid, "(5,6)", None, None, &table);
+            res.sort_by(|a, b| a.field_vals[0].cmp(&b.field_vals[0]));
+            assert_eq!(vec![tuples[1].clone(), tuples[2].clone()], res);
+            assert_eq!(3, sm.index_num_entries(cid, index_name).unwrap());
+        }
+
+        sm.delete_value(ids[1], tid).unwrap();
+        let updated = create_tuple_list(vec![vec![1, 8, 9]]).pop().unwrap();
+        sm.update_value(updated.get_bytes(), ids[0], tid).unwrap();
+        for (tree_type, index_name) in [("KD", "kd_idx"), ("R", "r_idx")] {
+            let res = sm.use_index_by_id(tree_type, "EQ", index_name, cid, "(5,6)", None, None, &table);
+            assert_eq!(vec![tuples[2].clone()], res);
+            let res = sm.use_index_by_id(tree_type, "EQ", index_name, cid, "(2,3)", None, None, &table);
+            assert!(res.is_empty());
+            let res = sm.use_index_by_id(tree_type, "EQ", index_name, cid, "(8,9)", None, None, &table);
+            assert_eq!(vec![updated.clone()], res);
+            assert_eq!(2, sm.index_num_entries(cid, index_name).unwrap());
+        }
+    }
+
+    #[test]
+    fn hs_sm_index_write_error() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let table = Table::new(
+            String::from("test"),
+            TableSchema::from_vecs(vec!["a", "s"], vec![DataType::Int, DataType::String]),
+        );
+        let cid = 1;
+        let tid = TransactionId::new();
+        sm.create_table(cid).unwrap();
+        let small = create_tuple_list(vec![vec![1], vec![2]])
+            .into_iter()
+            .map(|tuple| Tuple::new(vec![tuple.field_vals[0].clone(), Field::StringField(String::from("x"))]))
+            .collect::<Vec<Tuple>>();
+        let ids = insert_tuples(&sm, cid, &small);
+        sm.create_index_by_id("KD", "kd_idx", cid, "(a)", &table).unwrap();
+        sm.create_index_by_id("BTREE", "btree_idx", cid, "(s)", &table).unwrap();
+
+        // the record fits in a heap page, but a B+ tree node holding its key does not
+        let large = Tuple::new(vec![Field::IntField(3), Field::StringField("y".repeat(PAGE_SIZE - 100))]);
+        assert!(sm.try_insert_value(cid, large.get_bytes(), tid).is_err());
+        assert!(sm.update_value(large.get_bytes(), ids[0], tid).is_err());
+        let mut values: Vec<Tuple> = sm
+            .get_iterator(cid, tid, Permissions::ReadOnly)
+            .map(|value| Tuple::from_bytes(&value))
+            .collect();
+        values.sort_by(|a, b| a.field_vals[0].cmp(&b.field_vals[0]));
+        assert_eq!(small, values);
+        for index_name in ["kd_idx", "btree_idx"] {
+            assert_eq!(2, sm.index_num_entries(cid, index_name).unwrap());
+            assert!(sm.check_index(cid, index_name).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn hs_sm_kd_index() {
+        check_index_lifecycle("KD", "(b,c)");
+    }
+
+    #[test]
+    fn hs_sm_r_index() {
+        check_index_lifecycle("R", "(b,c)");
+    }
+
+    #[test]
+    fn hs_sm_kdb_index() {
+        check_index_lifecycle("KDB", "(b,c)");
+    }
+
+    #[test]
+    fn hs_sm_quad_index() {
+        check_index_lifecycle("QUAD", "(b,c)");
+    }
+
+    #[test]
+    fn hs_sm_z_index() {
+        check_index_lifecycle("Z", "(b,c)");
+    }
+
+    #[test]
+    fn hs_sm_btree_index() {
+        check_index_lifecycle("BTREE", "(b)");
+        // B+ trees take a single column
+        let sm = StorageManager::new_test_sm();
+        sm.create_table(1).unwrap();
+        assert!(sm.create_index_by_id("BTREE", "bad_idx", 1, "(b,c)", &test_table()).is_err());
+        assert!(sm.index_num_entries(1, "bad_idx").is_err());
+        // unknown columns are not dropped from the key
+        assert!(sm.create_index_by_id("KD", "bad_idx", 1, "(b,x)", &test_table()).is_err());
+        assert!(sm.index_num_entries(1, "bad_idx").is_err());
+        assert!(sm.create_index_by_id("KD", "bad_idx", 2, "(b,c)", &test_table()).is_err());
+    }
+
+    #[test]
+    fn hs_sm_hash_index() {
+        check_index_lifecycle("HASH", "(c)");
+    }
+
+    #[test]
+    fn hs_sm_parse_index_key() {
+        let schema = test_table().schema;
+        let idx_fields = [1, 2];
+        let parse_key = |vals: &[&str]| {
+            StorageManager::parse_index_key(vals.iter().map(|val| val.to_string()).collect(), &idx_fields, &schema)
+        };
+        let parse_bounds = |vals: &[&str]| {
+            StorageManager::parse_index_bounds(vals.iter().map(|val| val.to_string()).collect(), &idx_fields, &schema)
+        };
+        assert_eq!(vec![Field::IntField(1), Field::IntField(-2)], parse_key(&["1", "-2"]).unwrap());
+        assert!(parse_key(&["1"]).is_err());
+        assert!(parse_key(&["1", "2", "3"]).is_err());
+        assert!(parse_key(&["1", "abc"]).is_err());
+        assert!(StorageManager::parse_index_key(vec![String::from("1")], &[], &schema).is_err());
+
+        assert_eq!(vec![None, Some(Field::IntField(2))], parse_bounds(&["*", "2"]).unwrap());
+        assert_eq!(vec![Some(Field::IntField(1))], parse_bounds(&["1"]).unwrap());
+        assert!(parse_bounds(&["1", "2", "3"]).is_err());
+        assert!(parse_bounds(&["abc"]).is_err());
+
+        // bad keys and bounds find nothing rather than panic
+        let sm = StorageManager::new_test_sm();
+        let table = test_table();
+        sm.create_table(1).unwrap();
+        insert_tuples(&sm, 1, &test_tuples());
+        sm.create_index_by_id("KD", "kd_idx", 1, "(b,c)", &table).unwrap();
+        assert!(sm.use_index_by_id("KD", "RANGE", "kd_idx", 1, "(0,0,0);(9,9,9)", None, None, &table).is_empty());
+        assert!(sm.use_index_by_id("KD", "EQ", "kd_idx", 1, "(abc,1)", None, None, &table).is_empty());
+        assert!(sm.use_index_by_id("KD", "KNN", "kd_idx", 1, "(1)", Some("2"), None, &table).is_empty());
+    }
+
+    #[test]
+    fn hs_sm_check_index() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let table = test_table();
+        let cid = 1;
+        let tuples = test_tuples();
+        sm.create_table(cid).unwrap();
+        let ids = insert_tuples(&sm, cid, &tuples);
+        sm.create_index_by_id("KD", "kd_idx", cid, "(b,c)", &table).unwrap();
+        sm.create_index_by_id("R", "r_idx", cid, "(b,c)", &table).unwrap();
+        sm.create_index_by_id("KDB", "kdb_idx", cid, "(b,c)", &table).unwrap();
+        for index_name in ["kd_idx", "r_idx", "kdb_idx"] {
+            assert!(sm.check_index(cid, index_name).unwrap().is_empty());
+        }
+        assert!(sm.check_index(cid, "missing_idx").is_err());
+
+        // entries changed behind the back of the heap file
+        {
+            let hf_map = sm.hf_map.read().unwrap();
+            let hf = hf_map.get(&cid).unwrap();
+            let mut index_map = hf.index_map.write().unwrap();
+            index_map.get_mut("kd_idx").unwrap().delete(&tuples[1].field_vals, ids[1]).unwrap();
+            let extra = ValueId::new_slot(cid, 3, 0);
+            index_map.get_mut("r_idx").unwrap().insert(&tuples[0].field_vals, extra).unwrap();
+        }
+        let problems = sm.check_index(cid, "kd_idx").unwrap();
+        assert_eq!(problems, vec![format!("Record {:?} at page 0 slot 1 is not found under its key", tuples[1].field_vals)]);
+        let problems = sm.check_index(cid, "r_idx").unwrap();
+        assert_eq!(problems, vec![String::from("Entry for page 3 slot 0 has no record")]);
+    }
+
+    #[test]
+    fn hs_sm_cluster() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let table = test_table();
+        let cid = 1;
+        let tid = TransactionId::new();
+        let tuples = create_tuple_list((0..600).map(|i| vec![i, (i * 37) % 101, (i * 53) % 97]).collect());
+        sm.create_table(cid).unwrap();
+        let mut ids = Vec::new();
+        for tuple in &tuples {
+            ids.push(sm.insert_value(cid, tuple.get_bytes(), tid));
+        }
+        // leave holes in the pages
+        for id in ids.iter().step_by(4) {
+            sm.delete_value(*id, tid).unwrap();
+        }
+        sm.create_index_by_id("KD", "kd_idx", cid, "(b,c)", &table).unwrap();
+        sm.create_index_by_id("Z", "z_idx", cid, "(b,c)", &table).unwrap();
+        assert!(sm.cluster_container(cid, "kd_idx").is_err());
+        let num_pages = sm.get_num_pages(cid);
+        let clustered_pages = sm.cluster_container(cid, "z_idx").unwrap();
+        assert!(clustered_pages < num_pages);
+        assert_eq!(sm.get_num_pages(cid), clustered_pages);
+
+        let mut z_values = Vec::new();
+        let mut hf_iterator = sm.get_iterator(cid, tid, Permissions::ReadOnly);
+        while let Some((val, _)) = hf_iterator.next_with_id() {
+            z_values.push(ZOrderIndex::z_value(&Tuple::from_bytes(&val).field_vals[1..]));
+        }
+        assert_eq!(z_values.len(), 450);
+        assert!(z_values.windows(2).all(|pair| pair[0] <= pair[1]));
+        for index_name in ["kd_idx", "z_idx"] {
+            assert!(sm.check_index(cid, index_name).unwrap().is_empty());
+            assert_eq!(450, sm.index_num_entries(cid, index_name).unwrap());
+        }
+        let res = sm.use_index_by_id("KD", "EQ", "kd_idx", cid, "(37,53)", None, None, &table);
+        assert_eq!(vec![tuples[1].clone()], res);
+    }
+
+    #[test]
+    fn hs_sm_cluster_rebuilds_indexes() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let table = test_table();
+        let cid = 1;
+        let tid = TransactionId::new();
+        let tuples = create_tuple_list((0..600).map(|i| vec![i, (i * 37) % 101, (i * 53) % 97]).collect());
+        sm.create_table(cid).unwrap();
+        let mut ids = Vec::new();
+        for tuple in &tuples {
+            ids.push(sm.insert_value(cid, tuple.get_bytes(), tid));
+        }
+        for id in ids.iter().step_by(3) {
+            sm.delete_value(*id, tid).unwrap();
+        }
+        // indexes kept in their own file end up at their old paths next to those kept in memory
+        let indexes = [("BTREE", "btree_idx", "(a)"), ("KDB", "kdb_idx", "(b,c)"), ("HASH", "hash_idx", "(c)"), ("R", "r_idx", "(c,a)"), ("Z", "z_idx", "(b,c)")];
+        for (tree_type, index_name, attributes) in indexes {
+            sm.create_index_by_id(tree_type, index_name, cid, attributes, &table).unwrap();
+        }
+        let index_paths = || {
+            let mut paths = sm.hf_map.read().unwrap()[&cid].get_index_paths();
+            paths.sort();
+            paths
+        };
+        let paths = index_paths();
+        assert_eq!(3, paths.len());
+        sm.cluster_container(cid, "z_idx").unwrap();
+        assert_eq!(paths, index_paths());
+        for (_, index_name, _) in indexes {
+            assert_eq!(Vec::<String>::new(), sm.check_index(cid, index_name).unwrap());
+            assert_eq!(400, sm.index_num_entries(cid, index_name).unwrap());
+        }
+        let res = sm.use_index_by_id("BTREE", "EQ", "btree_idx", cid, "(1)", None, None, &table);
+        assert_eq!(vec![tuples[1].clone()], res);
+        assert!(sm.use_index_by_id("BTREE", "EQ", "btree_idx", cid, "(0)", None, None, &table).is_empty());
+    }
+
+    #[test]
+    fn hs_sm_cluster_failed_index_build() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let table = test_table();
+        let cid = 1;
+        let tid = TransactionId::new();
+        let tuples = create_tuple_list((0..300).map(|i| vec![i, (i * 37) % 101, (i * 53) % 97]).collect());
+        sm.create_table(cid).unwrap();
+        let ids = insert_tuples(&sm, cid, &tuples);
+        let indexes = [("BTREE", "btree_idx", "(a)"), ("KDB", "kdb_idx", "(b,c)"), ("HASH", "hash_idx", "(c)"), ("Z", "z_idx", "(b,c)")];
+        for (tree_type, index_name, attributes) in indexes {
+            sm.create_index_by_id(tree_type, index_name, cid, attributes, &table).unwrap();
+        }
+        // the new KDB tree can't be created where it would be built
+        let index_dir = format!("{}/indexes", sm.storage_path);
+        fs::create_dir(format!("{}/{}_kdb_idx.kdb.new", index_dir, cid)).unwrap();
+        let num_pages = sm.get_num_pages(cid);
+        assert!(sm.cluster_container(cid, "z_idx").is_err());
+
+        assert_eq!(num_pages, sm.get_num_pages(cid));
+        for (id, tuple) in ids.iter().zip(&tuples) {
+            assert_eq!(tuple.get_bytes(), sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+        }
+        for (_, index_name, _) in indexes {
+            assert_eq!(Vec::<String>::new(), sm.check_index(cid, index_name).unwrap());
+            assert_eq!(300, sm.index_num_entries(cid, index_name).unwrap());
+        }
+        let res = sm.use_index_by_id("BTREE", "EQ", "btree_idx", cid, "(7)", None, None, &table);
+        assert_eq!(vec![tuples[7].clone()], res);
+        // only the blocked path is left next to the live indexes
+        let mut new_files: Vec<_> = fs::read_dir(&index_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".new"))
+            .collect();
+        new_files.sort();
+        assert_eq!(vec![format!("{}_kdb_idx.kdb.new", cid)], new_files);
+    }
+
+    #[test]
+    fn hs_sm_drop_index() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let table = test_table();
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        sm.create_index_by_id("KD", "kd_idx", cid, "(b,c)", &table).unwrap();
+        sm.create_index_by_id("KDB", "kdb_idx", cid, "(b,c)", &table).unwrap();
+        let kdb_path = format!("{}/indexes/{}_kdb_idx.kdb", sm.storage_path, cid);
+        assert!(Path::new(&kdb_path).exists());
+
+        sm.drop_index(cid, "kd_idx").unwrap();
+        sm.drop_index(cid, "kdb_idx").unwrap();
+        assert!(!Path::new(&kdb_path).exists());
+        assert!(sm.index_range_query("KD", "kd_idx", cid, &[], &[]).is_err());
+        assert!(sm.drop_index(cid, "kd_idx").is_err());
+    }
 }
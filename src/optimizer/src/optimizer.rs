@@ -2,7 +2,9 @@ use common::catalog::Catalog;
 use common::ids::StateType;
 use common::logical_plan::*;
 use common::physical_plan::*;
-use common::CrustyError;
+use common::table::IndexInfo;
+use common::{CrustyError, DataType, Field, TableSchema};
+use std::collections::HashMap;
 
 pub struct Optimizer {}
 
@@ -79,6 +81,7 @@ impl Optimizer {
                     right_table: left_table,
                     hash_table_state_id: 0,
                     hash_table_key: FieldIdentifier::new("default_name", "default_column"),
+                    right_index: None,
                 }))
                 // Ok(PhysicalOp::NestedLoopJoin(PhysicalNestedLoopJoinNode {
                 //     left,
@@ -96,6 +99,237 @@ impl Optimizer {
         }
     }
 
+    /// Finds the scans whose output goes straight into a filter, along with the predicate of that filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `logical_plan` - the logical plan to look for filtered scans in
+    fn get_filtered_scans(logical_plan: &LogicalPlan) -> HashMap<OpIndex, Predicate> {
+        let mut res = HashMap::new();
+        for (idx, node) in logical_plan.node_references() {
+            if let LogicalOp::Filter(FilterNode { predicate, .. }) = node.data() {
+                for child in logical_plan.edges(idx) {
+                    if let Some(LogicalOp::Scan(_)) = logical_plan.get_operator(child) {
+                        res.insert(child, predicate.clone());
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    /// Narrows the range of a column down to the values allowed by a conjunction of simple predicates.
+    /// Returns the lower and upper bound (inclusive) of the column, if the predicates set any.
+    ///
+    /// # Arguments
+    ///
+    /// * `column` - the column to get the range of, prefixed by its table alias
+    /// * `dtype` - the type of the column
+    /// * `predicates` - the predicates that must all hold
+    fn get_column_range(
+        column: &str,
+        dtype: &DataType,
+        predicates: &[SimplePredicate],
+    ) -> (Option<Field>, Option<Field>) {
+        let mut min: Option<Field> = None;
+        let mut max: Option<Field> = None;
+        for predicate in predicates {
+            let (ident, op, operand) = match (&predicate.left, &predicate.right) {
+                (PredExpr::Ident(i), PredExpr::Literal(f)) => (i, predicate.op, f),
+                (PredExpr::Literal(f), PredExpr::Ident(i)) => (i, predicate.op.flip(), f),
+                _ => continue,
+            };
+            let same_type = matches!(
+                (dtype, operand),
+                (DataType::Int, Field::IntField(_)) | (DataType::String, Field::StringField(_))
+            );
+            if ident.column() != column || !same_type {
+                continue;
+            }
+            // strict bounds are kept inclusive, the filter above the scan removes the extra records
+            let (lower, upper) = match op {
+                SimplePredicateOp::Equals => (true, true),
+                SimplePredicateOp::GreaterThan | SimplePredicateOp::GreaterThanOrEq => (true, false),
+                SimplePredicateOp::LessThan | SimplePredicateOp::LessThanOrEq => (false, true),
+                SimplePredicateOp::NotEq | SimplePredicateOp::All => (false, false),
+            };
+            if lower && min.as_ref().is_none_or(|min| operand > min) {
+                min = Some(operand.clone());
+            }
+            if upper && max.as_ref().is_none_or(|max| operand < max) {
+                max = Some(operand.clone());
+            }
+        }
+        (min, max)
+    }
+
+    /// Builds an index scan over one index of a table for the given predicates.
+    /// Returns the scan and the number of indexed columns the predicates restrict,
+    /// or None if the index can't be used to answer the predicates.
+    ///
+    /// # Arguments
+    ///
+    /// * `scan` - the scan to replace
+    /// * `index` - the index to scan
+    /// * `schema` - the schema of the scanned table
+    /// * `predicates` - the predicates that must all hold
+    fn get_index_scan(
+        scan: &ScanNode,
+        index: &IndexInfo,
+        schema: &TableSchema,
+        predicates: &[SimplePredicate],
+    ) -> Option<(PhysicalIndexScanNode, usize)> {
+        let mut min = Vec::new();
+        let mut max = Vec::new();
+        let mut restricted = 0;
+        for column in &index.columns {
+            let dtype = schema.get_attribute(*schema.get_field_index(column)?)?.dtype();
+            let column = format!("{}.{}", scan.alias, column);
+            let (column_min, column_max) = Optimizer::get_column_range(&column, dtype, predicates);
+            if column_min.is_some() || column_max.is_some() {
+                restricted += 1;
+            }
+            min.push(column_min);
+            max.push(column_max);
+        }
+        if restricted == 0 {
+            return None;
+        }
+        // hash indexes only find the records equal to a key
+        if index.tree_type == "HASH" && min != max {
+            return None;
+        }
+        Some((
+            PhysicalIndexScanNode {
+                alias: scan.alias.clone(),
+                container_id: scan.container_id,
+                index_name: index.name.clone(),
+                tree_type: index.tree_type.clone(),
+                min,
+                max,
+            },
+            restricted,
+        ))
+    }
+
+    /// Bounds the integer columns of a radius predicate by the box around the ball it selects.
+    /// Keys are integer points, so each column is within the radius rounded down of the center.
+    ///
+    /// # Arguments
+    ///
+    /// * `predicate` - the radius predicate to bound
+    fn get_radius_bounds(predicate: &RadiusPredicate) -> Vec<SimplePredicate> {
+        let radius = predicate.radius.floor() as i64;
+        let mut bounds = Vec::new();
+        for (column, center) in predicate.columns.iter().zip(&predicate.center) {
+            let center = match center {
+                Field::IntField(center) => i64::from(*center),
+                Field::StringField(_) => continue,
+            };
+            let bound = |offset: i64| {
+                let bound = center.saturating_add(offset);
+                Field::IntField(bound.clamp(i64::from(i32::MIN), i64::from(i32::MAX)) as i32)
+            };
+            bounds.push(SimplePredicate {
+                left: PredExpr::Ident(column.clone()),
+                op: SimplePredicateOp::GreaterThanOrEq,
+                right: PredExpr::Literal(bound(-radius)),
+            });
+            bounds.push(SimplePredicate {
+                left: PredExpr::Ident(column.clone()),
+                op: SimplePredicateOp::LessThanOrEq,
+                right: PredExpr::Literal(bound(radius)),
+            });
+        }
+        bounds
+    }
+
+    /// Picks the index of a table that best answers the predicate of the filter above a scan.
+    /// Only predicates that are a conjunction of comparisons to constants, or a radius around
+    /// a constant point, can be answered with an index, since they select a box of the indexed
+    /// columns.
+    ///
+    /// # Arguments
+    ///
+    /// * `scan` - the scan to replace
+    /// * `predicate` - the predicate of the filter above the scan
+    /// * `catalog` - the catalog holding the indexes of the table
+    fn choose_index_scan<T: Catalog>(
+        scan: &ScanNode,
+        predicate: &Predicate,
+        catalog: &T,
+    ) -> Option<PhysicalIndexScanNode> {
+        let predicates = match predicate {
+            Predicate::SimplePredicate(simple_predicate) => vec![simple_predicate.clone()],
+            Predicate::CompoundPredicate(CompoundPredicate {
+                op: CompoundPredicateOp::And,
+                simple_predicates,
+            }) => simple_predicates.clone(),
+            Predicate::CompoundPredicate(_) => return None,
+            Predicate::RadiusPredicate(radius_predicate) => {
+                Optimizer::get_radius_bounds(radius_predicate)
+            }
+        };
+        let indexes = catalog.get_indexes(scan.container_id);
+        if indexes.is_empty() {
+            return None;
+        }
+        let schema = catalog.get_table_schema(scan.container_id).ok()?;
+        let mut best: Option<(PhysicalIndexScanNode, usize)> = None;
+        for index in &indexes {
+            if let Some((index_scan, restricted)) =
+                Optimizer::get_index_scan(scan, index, &schema, &predicates)
+            {
+                // prefer the index whose columns are the most restricted
+                if best.as_ref().is_none_or(|(_, best_restricted)| restricted > *best_restricted) {
+                    best = Some((index_scan, restricted));
+                }
+            }
+        }
+        best.map(|(index_scan, _)| index_scan)
+    }
+
+    /// Picks a hash index on the join column of the right table of a hash join, which the join
+    /// probes instead of hashing the right table. The index holds every record of the table, so
+    /// it is only used when the right child is a scan with no filter above it.
+    ///
+    /// # Arguments
+    ///
+    /// * `logical_plan` - the logical plan holding the join
+    /// * `idx` - the index of the join in the logical plan
+    /// * `join` - the physical join
+    /// * `catalog` - the catalog holding the indexes of the table
+    fn choose_join_index<T: Catalog>(
+        logical_plan: &LogicalPlan,
+        idx: OpIndex,
+        join: &PhysicalHashJoinNode,
+        catalog: &T,
+    ) -> Option<PhysicalJoinIndex> {
+        if !matches!(join.op, SimplePredicateOp::Equals) {
+            return None;
+        }
+        let scan = match logical_plan.get_operator(logical_plan.edges(idx).nth(1)?)? {
+            LogicalOp::Scan(scan) => scan,
+            _ => return None,
+        };
+        catalog
+            .get_indexes(scan.container_id)
+            .into_iter()
+            .find(|index| {
+                let column = match index.columns.as_slice() {
+                    [column] => format!("{}.{}", scan.alias, column),
+                    _ => return false,
+                };
+                index.tree_type == "HASH"
+                    && (join.left.column() == column || join.right.column() == column)
+            })
+            .map(|index| PhysicalJoinIndex {
+                container_id: scan.container_id,
+                index_name: index.name,
+                tree_type: index.tree_type,
+            })
+    }
+
     /// Converts a logical plan into a physical plan
     ///
     /// # Arguments
@@ -109,10 +343,34 @@ impl Optimizer {
         is_mat_view: bool,
     ) -> Result<PhysicalPlan, CrustyError> {
         let mut physical_plan = PhysicalPlan::new();
+        let filtered_scans = Optimizer::get_filtered_scans(&logical_plan);
         for (idx, node) in logical_plan.node_references() {
             let logical_op = node.data();
-            let physical_op =
-                self.logical_op_to_physical_op(logical_op.clone(), &mut physical_plan, catalog)?;
+            // a filtered scan is replaced by an index scan when the table has a suitable index,
+            // the filter is kept to check the predicates the index can't answer exactly
+            let index_scan = match (logical_op, filtered_scans.get(&idx)) {
+                (LogicalOp::Scan(scan), Some(predicate)) => {
+                    Optimizer::choose_index_scan(scan, predicate, catalog)
+                }
+                _ => None,
+            };
+            let physical_op = match index_scan {
+                Some(index_scan) => {
+                    physical_plan.add_base_table(index_scan.container_id);
+                    PhysicalOp::IndexScan(index_scan)
+                }
+                None => {
+                    self.logical_op_to_physical_op(logical_op.clone(), &mut physical_plan, catalog)?
+                }
+            };
+            let physical_op = match physical_op {
+                PhysicalOp::HashJoin(mut join) => {
+                    join.right_index =
+                        Optimizer::choose_join_index(&logical_plan, idx, &join, catalog);
+                    PhysicalOp::HashJoin(join)
+                }
+                physical_op => physical_op,
+            };
             physical_plan.add_node(physical_op);
             if !is_mat_view {
                 logical_plan.root();
@@ -158,6 +416,8 @@ impl Optimizer {
 mod test {
     use super::*;
     use common::database::Database;
+    use common::table::Table;
+    use std::sync::{Arc, RwLock};
 
     fn logical_plan1() -> LogicalPlan {
         LogicalPlan::from_json("{\"edges\":{\"1\":[\"0\"]},\"nodes\":{\"0\":{\"Scan\":{\"alias\":\"test\",\"container_id\":0,\"timestamp\":0}},\"1\":{\"Project\":{\"identifiers\":\"Wildcard\"}}},\"root\":\"1\"}").unwrap()
@@ -171,6 +431,206 @@ mod test {
         LogicalPlan::from_json("{\"edges\":{\"1\":[\"0\"],\"2\":[\"1\"]},\"nodes\":{\"0\":{\"Scan\":{\"alias\":\"test\",\"container_id\":0,\"timestamp\":0}},\"1\":{\"Filter\":{\"predicate\":{\"CompoundPredicate\":{\"op\":\"And\",\"simple_predicates\":[{\"left\":{\"Ident\":{\"alias\":\"a\",\"column\":\"test.a\",\"op\":null,\"table\":\"test\"}},\"op\":\"Equals\",\"right\":{\"Literal\":{\"IntField\":4}}},{\"left\":{\"Ident\":{\"alias\":\"b\",\"column\":\"test.b\",\"op\":null,\"table\":\"test\"}},\"op\":\"Equals\",\"right\":{\"Literal\":{\"IntField\":2}}}]}},\"table\":\"test\"}},\"2\":{\"Project\":{\"identifiers\":\"Wildcard\"}}},\"root\":\"2\"}").unwrap()
     }
 
+    fn logical_plan4() -> LogicalPlan {
+        LogicalPlan::from_json("{\"edges\":{\"1\":[\"0\"],\"2\":[\"1\"]},\"nodes\":{\"0\":{\"Scan\":{\"alias\":\"test\",\"container_id\":0,\"timestamp\":0}},\"1\":{\"Filter\":{\"predicate\":{\"CompoundPredicate\":{\"op\":\"Or\",\"simple_predicates\":[{\"left\":{\"Ident\":{\"alias\":\"a\",\"column\":\"test.a\",\"op\":null,\"table\":\"test\"}},\"op\":\"Equals\",\"right\":{\"Literal\":{\"IntField\":4}}},{\"left\":{\"Ident\":{\"alias\":\"b\",\"column\":\"test.b\",\"op\":null,\"table\":\"test\"}},\"op\":\"Equals\",\"right\":{\"Literal\":{\"IntField\":2}}}]}},\"table\":\"test\"}},\"2\":{\"Project\":{\"identifiers\":\"Wildcard\"}}},\"root\":\"2\"}").unwrap()
+    }
+
+    fn logical_plan5() -> LogicalPlan {
+        LogicalPlan::from_json("{\"edges\":{\"1\":[\"0\"],\"2\":[\"1\"]},\"nodes\":{\"0\":{\"Scan\":{\"alias\":\"test\",\"container_id\":0,\"timestamp\":0}},\"1\":{\"Filter\":{\"predicate\":{\"RadiusPredicate\":{\"columns\":[{\"alias\":\"c\",\"column\":\"test.c\",\"op\":null,\"table\":\"test\"},{\"alias\":\"b\",\"column\":\"test.b\",\"op\":null,\"table\":\"test\"}],\"center\":[{\"IntField\":10},{\"IntField\":2147483646}],\"radius\":2.5,\"metric\":\"Manhattan\"}},\"table\":\"test\"}},\"2\":{\"Project\":{\"identifiers\":\"Wildcard\"}}},\"root\":\"2\"}").unwrap()
+    }
+
+    fn indexed_db(columns: Vec<&str>) -> Database {
+        let db = Database::new(String::from("test"));
+        let schema = TableSchema::from_vecs(vec!["a", "b", "c"], vec![DataType::Int; 3]);
+        db.tables.write().unwrap().insert(
+            0,
+            Arc::new(RwLock::new(Table::new(String::from("test"), schema))),
+        );
+        db.add_index(IndexInfo::new(
+            String::from("idx"),
+            String::from("KD"),
+            0,
+            columns.into_iter().map(String::from).collect(),
+            0,
+        ))
+        .unwrap();
+        db
+    }
+
+    fn get_index_scan(physical_plan: &PhysicalPlan) -> Option<PhysicalIndexScanNode> {
+        (0..physical_plan.node_count()).find_map(|idx| match physical_plan.get_operator(idx) {
+            Some(PhysicalOp::IndexScan(node)) => Some(node.clone()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn test_index_scan_from_logical() {
+        let opt = Optimizer::new();
+
+        // a = 4 and b = 2 on an index of (b, c)
+        let db = indexed_db(vec!["b", "c"]);
+        let physical_plan = opt
+            .logical_plan_to_physical_plan(logical_plan3(), &db, false)
+            .unwrap();
+        assert_eq!(physical_plan.node_count(), 3);
+        let index_scan = get_index_scan(&physical_plan).unwrap();
+        assert_eq!(index_scan.index_name, "idx");
+        assert_eq!(index_scan.min, vec![Some(Field::IntField(2)), None]);
+        assert_eq!(index_scan.max, vec![Some(Field::IntField(2)), None]);
+        // the filter is kept to check a = 4
+        assert!((0..physical_plan.node_count()).any(|idx| matches!(
+            physical_plan.get_operator(idx),
+            Some(PhysicalOp::Filter(_))
+        )));
+        assert_eq!(physical_plan.base_tables().len(), 1);
+
+        // no indexed column is restricted
+        let db = indexed_db(vec!["c"]);
+        let physical_plan = opt
+            .logical_plan_to_physical_plan(logical_plan3(), &db, false)
+            .unwrap();
+        assert!(get_index_scan(&physical_plan).is_none());
+
+        // a disjunction can't be answered with a single range
+        let db = indexed_db(vec!["a", "b"]);
+        let physical_plan = opt
+            .logical_plan_to_physical_plan(logical_plan4(), &db, false)
+            .unwrap();
+        assert!(get_index_scan(&physical_plan).is_none());
+    }
+
+    #[test]
+    fn test_radius_index_scan_from_logical() {
+        let opt = Optimizer::new();
+
+        // the box around c = 10 and b = i32::MAX - 1, at most 2.5 away
+        let db = indexed_db(vec!["b", "c"]);
+        let physical_plan = opt
+            .logical_plan_to_physical_plan(logical_plan5(), &db, false)
+            .unwrap();
+        let index_scan = get_index_scan(&physical_plan).unwrap();
+        assert_eq!(
+            index_scan.min,
+            vec![Some(Field::IntField(i32::MAX - 3)), Some(Field::IntField(8))]
+        );
+        assert_eq!(
+            index_scan.max,
+            vec![Some(Field::IntField(i32::MAX)), Some(Field::IntField(12))]
+        );
+        // the filter is kept to check the distance
+        assert!((0..physical_plan.node_count()).any(|idx| matches!(
+            physical_plan.get_operator(idx),
+            Some(PhysicalOp::Filter(_))
+        )));
+
+        let db = indexed_db(vec!["a"]);
+        let physical_plan = opt
+            .logical_plan_to_physical_plan(logical_plan5(), &db, false)
+            .unwrap();
+        assert!(get_index_scan(&physical_plan).is_none());
+    }
+
+    // test joined with other on test.a = other.b, built the way the translator adds joins
+    fn join_plan() -> LogicalPlan {
+        let mut plan = LogicalPlan::new();
+        let project = plan.add_node(LogicalOp::Project(ProjectNode {
+            identifiers: ProjectIdentifiers::Wildcard,
+        }));
+        let join = plan.add_node(LogicalOp::Join(JoinNode {
+            left: FieldIdentifier::new("test", "test.a"),
+            right: FieldIdentifier::new("other", "other.b"),
+            op: SimplePredicateOp::Equals,
+            left_table: Some(String::from("test")),
+            right_table: Some(String::from("other")),
+        }));
+        let left = plan.add_node(LogicalOp::Scan(ScanNode {
+            alias: String::from("test"),
+            container_id: 0,
+        }));
+        let right = plan.add_node(LogicalOp::Scan(ScanNode {
+            alias: String::from("other"),
+            container_id: 1,
+        }));
+        plan.add_edge(project, join);
+        plan.add_edge(join, right);
+        plan.add_edge(join, left);
+        plan
+    }
+
+    fn get_join(physical_plan: &PhysicalPlan) -> PhysicalHashJoinNode {
+        (0..physical_plan.node_count())
+            .find_map(|idx| match physical_plan.get_operator(idx) {
+                Some(PhysicalOp::HashJoin(node)) => Some(node.clone()),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_hash_join_index_from_logical() {
+        let opt = Optimizer::new();
+        let db = indexed_db(vec!["a"]);
+        let schema = TableSchema::from_vecs(vec!["a", "b"], vec![DataType::Int; 2]);
+        db.tables.write().unwrap().insert(
+            1,
+            Arc::new(RwLock::new(Table::new(String::from("other"), schema))),
+        );
+        let hash_index = |name: &str, column: &str| {
+            IndexInfo::new(
+                String::from(name),
+                String::from("HASH"),
+                1,
+                vec![String::from(column)],
+                0,
+            )
+        };
+
+        // the KD index of the left table and a hash index off the join column are not used
+        db.add_index(hash_index("other_a", "a")).unwrap();
+        let physical_plan = opt
+            .logical_plan_to_physical_plan(join_plan(), &db, false)
+            .unwrap();
+        assert!(get_join(&physical_plan).right_index.is_none());
+
+        db.add_index(hash_index("other_b", "b")).unwrap();
+        let physical_plan = opt
+            .logical_plan_to_physical_plan(join_plan(), &db, false)
+            .unwrap();
+        let right_index = get_join(&physical_plan).right_index.unwrap();
+        assert_eq!(right_index.index_name, "other_b");
+        assert_eq!(right_index.container_id, 1);
+    }
+
+    #[test]
+    fn test_hash_index_scan_needs_equality() {
+        let opt = Optimizer::new();
+        let db = Database::new(String::from("test"));
+        let schema = TableSchema::from_vecs(vec!["a", "b", "c"], vec![DataType::Int; 3]);
+        db.tables.write().unwrap().insert(
+            0,
+            Arc::new(RwLock::new(Table::new(String::from("test"), schema))),
+        );
+        db.add_index(IndexInfo::new(
+            String::from("idx"),
+            String::from("HASH"),
+            0,
+            vec![String::from("b")],
+            0,
+        ))
+        .unwrap();
+        // b = 2
+        let physical_plan = opt
+            .logical_plan_to_physical_plan(logical_plan2(), &db, false)
+            .unwrap();
+        assert_eq!(get_index_scan(&physical_plan).unwrap().index_name, "idx");
+        // c within 2.5 of 10 and b within 2.5 of i32::MAX - 1
+        let physical_plan = opt
+            .logical_plan_to_physical_plan(logical_plan5(), &db, false)
+            .unwrap();
+        assert!(get_index_scan(&physical_plan).is_none());
+    }
+
     #[test]
     fn test_mat_view_from_logical() {
         let db = Database::new(String::from("test"));
@@ -1,14 +1,30 @@
 use super::OpIterator;
+use common::logical_plan::CompoundPredicateOp;
+use common::md_index::DistanceMetric;
 use common::{CrustyError, Field, SimplePredicateOp, TableSchema, Tuple};
 
 /// Compares the fields of tuples.
-pub struct FilterPredicate {
-    /// Operation used to compare.
-    op: SimplePredicateOp,
-    /// Index of the field to filter.
-    field_ind: usize,
-    /// Operand to compare against.
-    operand: Field,
+pub enum FilterPredicate {
+    /// Compares a field to a constant.
+    Compare {
+        /// Operation used to compare.
+        op: SimplePredicateOp,
+        /// Index of the field to filter.
+        field_ind: usize,
+        /// Operand to compare against.
+        operand: Field,
+    },
+    /// Checks that the point made of some fields is within a distance of a constant point.
+    Radius {
+        /// Indices of the fields giving the coordinates of the point.
+        field_inds: Vec<usize>,
+        /// Point to measure the distance to.
+        center: Vec<Field>,
+        /// Largest distance allowed (inclusive).
+        radius: f64,
+        /// How the distance is measured.
+        metric: DistanceMetric,
+    },
 }
 
 impl FilterPredicate {
@@ -20,7 +36,7 @@ impl FilterPredicate {
     /// * `field_ind` - Field index to compare against
     /// * `operand` - Field value to compare passed in tuples to    
     fn new(op: SimplePredicateOp, field_ind: usize, operand: Field) -> Self {
-        Self {
+        FilterPredicate::Compare {
             op,
             field_ind,
             operand,
@@ -33,15 +49,37 @@ impl FilterPredicate {
     ///
     /// * `tuple` - Tuple to apply the filter to.
     fn filter(&self, tuple: &Tuple) -> bool {
-        let field = tuple.get_field(self.field_ind).unwrap();
-        self.op.compare(field, &self.operand)
+        match self {
+            FilterPredicate::Compare {
+                op,
+                field_ind,
+                operand,
+            } => {
+                let field = tuple.get_field(*field_ind).unwrap();
+                op.compare(field, operand)
+            }
+            FilterPredicate::Radius {
+                field_inds,
+                center,
+                radius,
+                metric,
+            } => {
+                let point: Vec<Field> = field_inds
+                    .iter()
+                    .map(|field_ind| tuple.get_field(*field_ind).unwrap().clone())
+                    .collect();
+                metric.distance(&point, center).0 <= *radius
+            }
+        }
     }
 }
 
 /// Filter oeprator.
 pub struct Filter {
-    /// Predicate to filter by.
-    predicate: FilterPredicate,
+    /// Predicates to filter by.
+    predicates: Vec<FilterPredicate>,
+    /// Operation combining the results of the predicates.
+    compound_op: CompoundPredicateOp,
     /// Schema of the child.
     schema: TableSchema,
     /// Boolean determining if iterator is open.
@@ -64,12 +102,87 @@ impl Filter {
         child: Box<dyn OpIterator>,
     ) -> Self {
         Self {
-            predicate: FilterPredicate::new(op, field_ind, operand),
+            predicates: vec![FilterPredicate::new(op, field_ind, operand)],
+            compound_op: CompoundPredicateOp::And,
+            schema: child.get_schema().clone(),
+            open: false,
+            child,
+        }
+    }
+
+    /// Filter constructor for a compound predicate.
+    ///
+    /// # Arguments
+    ///
+    /// * `compound_op` - Operation combining the simple predicates.
+    /// * `ops` - Operation of each simple predicate.
+    /// * `field_inds` - Field index compared by each simple predicate.
+    /// * `operands` - Value each field is compared to.
+    /// * `child` - Child OpIterator passing data into the operator.
+    pub fn new_compound(
+        compound_op: CompoundPredicateOp,
+        ops: Vec<SimplePredicateOp>,
+        field_inds: Vec<usize>,
+        operands: Vec<Field>,
+        child: Box<dyn OpIterator>,
+    ) -> Self {
+        let predicates = ops
+            .into_iter()
+            .zip(field_inds)
+            .zip(operands)
+            .map(|((op, field_ind), operand)| FilterPredicate::new(op, field_ind, operand))
+            .collect();
+        Self {
+            predicates,
+            compound_op,
+            schema: child.get_schema().clone(),
+            open: false,
+            child,
+        }
+    }
+
+    /// Filter constructor for a radius predicate.
+    ///
+    /// # Arguments
+    ///
+    /// * `field_inds` - Indices of the fields giving the coordinates of the point.
+    /// * `center` - Point to measure the distance to.
+    /// * `radius` - Largest distance allowed (inclusive).
+    /// * `metric` - How the distance is measured.
+    /// * `child` - Child OpIterator passing data into the operator.
+    pub fn new_radius(
+        field_inds: Vec<usize>,
+        center: Vec<Field>,
+        radius: f64,
+        metric: DistanceMetric,
+        child: Box<dyn OpIterator>,
+    ) -> Self {
+        Self {
+            predicates: vec![FilterPredicate::Radius {
+                field_inds,
+                center,
+                radius,
+                metric,
+            }],
+            compound_op: CompoundPredicateOp::And,
             schema: child.get_schema().clone(),
             open: false,
             child,
         }
     }
+
+    /// Whether the tuple satisfies the compound predicate.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuple` - Tuple to apply the filter to.
+    fn filter(&self, tuple: &Tuple) -> bool {
+        self.predicates
+            .iter()
+            .fold(self.compound_op.identity(), |res, predicate| {
+                self.compound_op.apply(res, predicate.filter(tuple))
+            })
+    }
 }
 
 impl OpIterator for Filter {
@@ -85,7 +198,7 @@ impl OpIterator for Filter {
 
         let mut res = None;
         while let Some(t) = self.child.next()? {
-            if self.predicate.filter(&t) {
+            if self.filter(&t) {
                 res = Some(t);
                 break;
             }
@@ -238,6 +351,62 @@ mod test {
         filter.close()
     }
 
+    #[test]
+    fn test_compound_and() -> Result<(), CrustyError> {
+        let ti = mock_ti(-5, 5, WIDTH);
+        let mut filter = Filter::new_compound(
+            CompoundPredicateOp::And,
+            vec![SimplePredicateOp::GreaterThan, SimplePredicateOp::LessThanOrEq],
+            vec![0, 1],
+            vec![Field::IntField(-2), Field::IntField(1)],
+            Box::new(ti),
+        );
+        let mut expected = mock_ti(-1, 2, WIDTH);
+        filter.open()?;
+        expected.open()?;
+        match_all_tuples(Box::new(filter), Box::new(expected))
+    }
+
+    #[test]
+    fn test_compound_or() -> Result<(), CrustyError> {
+        let ti = mock_ti(-5, 5, WIDTH);
+        let mut filter = Filter::new_compound(
+            CompoundPredicateOp::Or,
+            vec![SimplePredicateOp::Equals, SimplePredicateOp::Equals],
+            vec![0, 2],
+            vec![Field::IntField(-5), Field::IntField(4)],
+            Box::new(ti),
+        );
+        filter.open()?;
+        assert_eq!(tuple_repeat_field(-5, WIDTH), filter.next()?.unwrap());
+        assert_eq!(tuple_repeat_field(4, WIDTH), filter.next()?.unwrap());
+        assert!(filter.next()?.is_none());
+        filter.close()
+    }
+
+    #[test]
+    fn test_radius() -> Result<(), CrustyError> {
+        // the tuples are on the diagonal, so (i, i) is |i| away from the center in chebyshev distance
+        for (metric, low, high) in [
+            (DistanceMetric::Euclidean, -1, 2),
+            (DistanceMetric::Manhattan, -1, 2),
+            (DistanceMetric::Chebyshev, -2, 3),
+        ] {
+            let mut filter = Filter::new_radius(
+                vec![0, 1],
+                vec![Field::IntField(0), Field::IntField(0)],
+                2.0,
+                metric,
+                Box::new(mock_ti(-5, 5, WIDTH)),
+            );
+            let mut expected = mock_ti(low, high, WIDTH);
+            filter.open()?;
+            expected.open()?;
+            match_all_tuples(Box::new(filter), Box::new(expected))?;
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_no_equal_tuples() -> Result<(), CrustyError> {
         let mut filter = get_filter(0, SimplePredicateOp::Equals, Field::IntField(5));
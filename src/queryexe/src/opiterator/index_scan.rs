@@ -0,0 +1,229 @@
+use super::{OpIterator, SeqScan};
+use crate::StorageManager;
+use common::ids::Permissions;
+use common::ids::{ContainerId, TransactionId, ValueId};
+use common::storage_trait::StorageTrait;
+use common::table::*;
+use common::{CrustyError, Field, TableSchema, Tuple};
+use std::sync::{Arc, RwLock};
+
+/// Index scan operator
+///
+/// Returns the records whose indexed columns lie between `min` and `max` (inclusive).
+pub struct IndexScan {
+    schema: TableSchema,
+    open: bool,
+    storage_manager: &'static StorageManager,
+    container_id: ContainerId,
+    transaction_id: TransactionId,
+    index_name: String,
+    tree_type: String,
+    min: Vec<Option<Field>>,
+    max: Vec<Option<Field>>,
+    /// Ids of the records found by the index, looked up when the operator is opened.
+    ids: Vec<ValueId>,
+    /// Position of the next record to return.
+    pos: usize,
+}
+
+impl IndexScan {
+    /// Constructor for the index scan operator.
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - Table to scan over.
+    /// * `table_alias` - Table alias given by the user.
+    /// * `index_name` - Index to use.
+    /// * `tree_type` - Kind of tree backing the index.
+    /// * `min` - Lower bound of each indexed column, `None` if it is not bounded below.
+    /// * `max` - Upper bound of each indexed column, `None` if it is not bounded above.
+    /// * `tid` - Transaction used to read the table.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        storage_manager: &'static StorageManager,
+        table: Arc<RwLock<Table>>,
+        table_alias: &str,
+        container_id: &ContainerId,
+        index_name: &str,
+        tree_type: &str,
+        min: Vec<Option<Field>>,
+        max: Vec<Option<Field>>,
+        tid: TransactionId,
+    ) -> Self {
+        let table_ref = table.read().unwrap();
+        Self {
+            schema: SeqScan::schema(&table_ref.schema, table_alias),
+            open: false,
+            storage_manager,
+            container_id: *container_id,
+            transaction_id: tid,
+            index_name: index_name.to_string(),
+            tree_type: tree_type.to_string(),
+            min,
+            max,
+            ids: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl OpIterator for IndexScan {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.ids = self.storage_manager.index_range_query(
+            &self.tree_type,
+            &self.index_name,
+            self.container_id,
+            &self.min,
+            &self.max,
+        )?;
+        // read the records in storage order
+        self.ids.sort();
+        self.pos = 0;
+        self.open = true;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        match self.ids.get(self.pos) {
+            Some(id) => {
+                self.pos += 1;
+                let bytes =
+                    self.storage_manager
+                        .get_value(*id, self.transaction_id, Permissions::ReadOnly)?;
+                Ok(Some(Tuple::from_bytes(&bytes)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.open = false;
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        self.pos = 0;
+        Ok(())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_must_use)]
+mod test {
+    use super::*;
+    use crate::opiterator::testutil::sum_int_fields;
+    use common::ids::TransactionId;
+    use common::testutil::*;
+    use common::DataType;
+
+    const TABLE: &str = "IndexScan";
+
+    fn get_schema() -> TableSchema {
+        TableSchema::from_vecs(vec!["a", "b", "c"], vec![DataType::Int; 3])
+    }
+
+    fn get_index_scan(tree_type: &str, min: Vec<i32>, max: Vec<i32>) -> IndexScan {
+        let bounds = |vals: Vec<i32>| int_vec_to_tuple(vals).field_vals.into_iter().map(Some).collect();
+        get_partial_index_scan(tree_type, bounds(min), bounds(max))
+    }
+
+    fn get_partial_index_scan(tree_type: &str, min: Vec<Option<Field>>, max: Vec<Option<Field>>) -> IndexScan {
+        // Create test table
+        let table = Table::new(TABLE.to_string(), get_schema());
+        // Create test SM with a container
+        let smb = Box::new(StorageManager::new_test_sm());
+        let sm: &'static StorageManager = Box::leak(smb);
+        let cid = 0;
+        sm.create_table(cid).unwrap();
+        // Create test data
+        let tid = TransactionId::new();
+        for tuple in create_tuple_list(vec![vec![1, 2, 3], vec![4, 5, 6], vec![7, 8, 9], vec![2, 5, 1]]) {
+            sm.insert_value(cid, tuple.get_bytes(), tid);
+        }
+        sm.create_index_by_id(tree_type, "idx", cid, "(b,c)", &table).unwrap();
+        IndexScan::new(
+            sm,
+            Arc::new(RwLock::new(table)),
+            TABLE,
+            &cid,
+            "idx",
+            tree_type,
+            min,
+            max,
+            tid,
+        )
+    }
+
+    #[test]
+    fn test_open() -> Result<(), CrustyError> {
+        let mut scan = get_index_scan("KD", vec![0, 0], vec![9, 9]);
+        assert!(!scan.open);
+        scan.open()?;
+        assert!(scan.open);
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_next_not_open() {
+        let mut scan = get_index_scan("KD", vec![0, 0], vec![9, 9]);
+        scan.next();
+    }
+
+    #[test]
+    fn test_next() -> Result<(), CrustyError> {
+        for tree_type in ["KD", "R", "KDB", "QUAD", "Z"] {
+            let mut scan = get_index_scan(tree_type, vec![2, 1], vec![5, 6]);
+            scan.open()?;
+            assert_eq!(sum_int_fields(&mut scan)?, 1 + 2 + 3 + 4 + 5 + 6 + 2 + 5 + 1);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_open_bounds() -> Result<(), CrustyError> {
+        for tree_type in ["KD", "R", "KDB", "QUAD", "Z"] {
+            // c >= 6, b unbounded
+            let mut scan = get_partial_index_scan(tree_type, vec![None, Some(Field::IntField(6))], vec![]);
+            scan.open()?;
+            assert_eq!(sum_int_fields(&mut scan)?, 4 + 5 + 6 + 7 + 8 + 9);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewind() -> Result<(), CrustyError> {
+        let mut scan = get_index_scan("KD", vec![5, 0], vec![9, 9]);
+        scan.open()?;
+        let sum_before = sum_int_fields(&mut scan)?;
+        scan.rewind()?;
+        let sum_after = sum_int_fields(&mut scan)?;
+        assert_eq!(sum_before, sum_after);
+        assert_eq!(sum_before, 4 + 5 + 6 + 7 + 8 + 9 + 2 + 5 + 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_schema() {
+        let scan = get_index_scan("KD", vec![0, 0], vec![9, 9]);
+        let original = get_schema();
+        let prefixed = scan.get_schema();
+        assert_eq!(original.size(), scan.get_schema().size());
+        for (orig_attr, prefixed_attr) in original.attributes().zip(prefixed.attributes()) {
+            assert_eq!(
+                format!("{}.{}", TABLE, orig_attr.name()),
+                prefixed_attr.name()
+            );
+        }
+    }
+}
@@ -1,4 +1,7 @@
 use super::{OpIterator, TupleIterator};
+use crate::StorageManager;
+use common::ids::{ContainerId, Permissions, TransactionId};
+use common::storage_trait::StorageTrait;
 use common::{CrustyError, Field, SimplePredicateOp, TableSchema, Tuple};
 use std::collections::HashMap;
 
@@ -165,6 +168,18 @@ pub struct HashEqJoin {
     right_open: bool,
     join_map: HashMap<Field, Vec<Tuple>>,
     curr_idx: usize,
+    /// Hash index of the right table on its join field. When set, the right tuples are found
+    /// by probing it rather than by building `join_map` from the right child.
+    index: Option<JoinIndex>,
+}
+
+/// A persistent hash index of the table under the right child of a hash join.
+struct JoinIndex {
+    storage_manager: &'static StorageManager,
+    container_id: ContainerId,
+    index_name: String,
+    tree_type: String,
+    tid: TransactionId,
 }
 
 impl HashEqJoin {
@@ -201,8 +216,78 @@ impl HashEqJoin {
             right_open: false,
             join_map: HashMap::new(),
             curr_idx: 0,
+            index: None,
         }
     }
+
+    /// Constructor for a hash equi-join operator that probes an index of the right table
+    /// instead of hashing the tuples of the right child. The right child must be a scan of
+    /// the whole indexed table.
+    ///
+    /// # Arguments
+    ///
+    /// * `left_index` - Index of the left field in join condition.
+    /// * `right_index` - Index of the right field in join condition, the field the index is on.
+    /// * `left_child` - Left child of join operator.
+    /// * `right_child` - Right child of join operator.
+    /// * `storage_manager` - Storage manager holding the index.
+    /// * `container_id` - Container of the right table.
+    /// * `index_name` - Name of the index.
+    /// * `tree_type` - Kind of index.
+    /// * `tid` - Transaction reading the right table.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_index(
+        left_index: usize,
+        right_index: usize,
+        left_child: Box<dyn OpIterator>,
+        right_child: Box<dyn OpIterator>,
+        storage_manager: &'static StorageManager,
+        container_id: ContainerId,
+        index_name: &str,
+        tree_type: &str,
+        tid: TransactionId,
+    ) -> Self {
+        let mut join = HashEqJoin::new(
+            SimplePredicateOp::Equals,
+            left_index,
+            right_index,
+            left_child,
+            right_child,
+        );
+        join.index = Some(JoinIndex {
+            storage_manager,
+            container_id,
+            index_name: index_name.to_string(),
+            tree_type: tree_type.to_string(),
+            tid,
+        });
+        join
+    }
+
+    /// Reads the right tuples matching a key through the index, if the join has one. Only the
+    /// matches of the last key probed are kept.
+    fn probe_index(&mut self, key: &Field) -> Result<(), CrustyError> {
+        let index = match &self.index {
+            Some(index) if !self.join_map.contains_key(key) => index,
+            _ => return Ok(()),
+        };
+        let ids = index.storage_manager.index_point_query(
+            &index.tree_type,
+            &index.index_name,
+            index.container_id,
+            std::slice::from_ref(key),
+        )?;
+        let mut matches = Vec::new();
+        for id in ids {
+            let bytes = index
+                .storage_manager
+                .get_value(id, index.tid, Permissions::ReadOnly)?;
+            matches.push(Tuple::from_bytes(&bytes));
+        }
+        self.join_map.clear();
+        self.join_map.insert(key.clone(), matches);
+        Ok(())
+    }
 }
 
 impl OpIterator for HashEqJoin {
@@ -211,12 +296,15 @@ impl OpIterator for HashEqJoin {
         self.left_child.open()?;
         self.right_open = true;
         self.right_child.open()?;
-        while let Some(right) = self.right_child.next()? {
-            let right_field = right.get_field(self.predicate.right_index).unwrap();
-            self.join_map
-                .entry(right_field.clone())
-                .or_default()
-                .push(right);
+        // with an index, the right tuples are read as the left tuples probe for them
+        if self.index.is_none() {
+            while let Some(right) = self.right_child.next()? {
+                let right_field = right.get_field(self.predicate.right_index).unwrap();
+                self.join_map
+                    .entry(right_field.clone())
+                    .or_default()
+                    .push(right);
+            }
         }
         //println!("{:?}", self.join_map);
         self.curr_left = self.left_child.next()?;
@@ -233,8 +321,10 @@ impl OpIterator for HashEqJoin {
         }
         while self.curr_left.is_some() {
             let left = self.curr_left.as_ref().unwrap();
-            let left_field = left.get_field(self.predicate.left_index).unwrap();
-            match self.join_map.get_mut(left_field) {
+            let left_field = left.get_field(self.predicate.left_index).unwrap().clone();
+            self.probe_index(&left_field)?;
+            let left = self.curr_left.as_ref().unwrap();
+            match self.join_map.get_mut(&left_field) {
                 None => {
                     self.curr_left = self.left_child.next()?;
                     self.curr_idx = 0;
@@ -289,7 +379,9 @@ impl OpIterator for HashEqJoin {
 mod test {
     use super::*;
     use crate::opiterator::testutil::*;
+    use common::table::Table;
     use common::testutil::*;
+    use common::DataType;
 
     const WIDTH1: usize = 2;
     const WIDTH2: usize = 3;
@@ -535,5 +627,48 @@ mod test {
         fn eq_join() -> Result<(), CrustyError> {
             test_eq_join(JoinType::HashEq)
         }
+
+        fn join_rows(op: &mut HashEqJoin) -> Result<Vec<Vec<Field>>, CrustyError> {
+            let mut res = Vec::new();
+            while let Some(tuple) = op.next()? {
+                res.push(tuple.field_vals);
+            }
+            res.sort();
+            Ok(res)
+        }
+
+        #[test]
+        fn eq_join_with_index() -> Result<(), CrustyError> {
+            let sm: &'static StorageManager = Box::leak(Box::new(StorageManager::new_test_sm()));
+            let cid = 1;
+            let tid = TransactionId::new();
+            let rows = vec![
+                vec![1, 2, 3],
+                vec![2, 3, 4],
+                vec![3, 4, 5],
+                vec![3, 9, 9],
+                vec![5, 6, 7],
+            ];
+            let schema = TableSchema::from_vecs(vec!["a", "b", "c"], vec![DataType::Int; WIDTH2]);
+            sm.create_table(cid)?;
+            for tuple in create_tuple_list(rows.clone()) {
+                sm.insert_value(cid, tuple.get_bytes(), tid);
+            }
+            let table = Table::new(String::from("test"), schema.clone());
+            sm.create_index_by_id("HASH", "idx", cid, "(a)", &table).unwrap();
+            let right = || Box::new(TupleIterator::new(create_tuple_list(rows.clone()), schema.clone()));
+
+            let mut hashed = HashEqJoin::new(SimplePredicateOp::Equals, 0, 0, Box::new(scan1()), right());
+            let mut indexed = HashEqJoin::new_with_index(0, 0, Box::new(scan1()), right(), sm, cid, "idx", "HASH", tid);
+            hashed.open()?;
+            indexed.open()?;
+            let expected = join_rows(&mut hashed)?;
+            // 1 and 5 match once and 3 matches twice
+            assert_eq!(4, expected.len());
+            assert_eq!(expected, join_rows(&mut indexed)?);
+            indexed.rewind()?;
+            assert_eq!(expected, join_rows(&mut indexed)?);
+            Ok(())
+        }
     }
 }
@@ -1,16 +1,20 @@
 pub use self::aggregate::Aggregate;
 pub use self::filter::{Filter, FilterPredicate};
+pub use self::index_scan::IndexScan;
 pub use self::join::{HashEqJoin, Join, JoinPredicate};
 pub use self::project::ProjectIterator;
 pub use self::seqscan::SeqScan;
+pub use self::spatial_join::{KnnJoin, RTreeJoin, RTreeJoinInput, SpatialJoin};
 pub use self::tuple_iterator::TupleIterator;
 use common::{CrustyError, TableSchema, Tuple};
 
 mod aggregate;
 mod filter;
+mod index_scan;
 mod join;
 mod project;
 mod seqscan;
+mod spatial_join;
 mod testutil;
 mod tuple_iterator;
 
@@ -50,7 +50,7 @@ impl SeqScan {
     /// # Arguments
     /// * `src_schema` - Schema of the source.
     /// * `alias` - Alias of the table.
-    fn schema(src_schema: &TableSchema, alias: &str) -> TableSchema {
+    pub(crate) fn schema(src_schema: &TableSchema, alias: &str) -> TableSchema {
         let mut attrs = Vec::new();
         for a in src_schema.attributes() {
             let new_name = format!("{}.{}", alias, a.name());
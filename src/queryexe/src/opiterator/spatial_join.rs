@@ -0,0 +1,641 @@
+use super::{OpIterator, SeqScan};
+use crate::StorageManager;
+use common::ids::Permissions;
+use common::ids::{ContainerId, TransactionId, ValueId};
+use common::md_index::{Distance, DistanceMetric, Neighbor, SpatialPredicate};
+use common::storage_trait::StorageTrait;
+use common::table::*;
+use common::{CrustyError, Field, TableSchema, Tuple};
+use std::sync::{Arc, RwLock};
+
+/// Values of the join attributes of a tuple, in index order.
+fn join_key(tuple: &Tuple, indexes: &[usize]) -> Vec<Field> {
+    indexes
+        .iter()
+        .map(|idx| tuple.get_field(*idx).unwrap().clone())
+        .collect()
+}
+
+/// Reads the tuple stored under an id.
+fn read_tuple(
+    storage_manager: &StorageManager,
+    id: ValueId,
+    tid: TransactionId,
+) -> Result<Tuple, CrustyError> {
+    let bytes = storage_manager.get_value(id, tid, Permissions::ReadOnly)?;
+    Ok(Tuple::from_bytes(&bytes))
+}
+
+/// Index nested loop spatial join.
+///
+/// For every tuple of the left child, probes a multidimensional index of the right table for the
+/// records whose indexed attributes match the left join attributes under the predicate.
+pub struct SpatialJoin {
+    predicate: SpatialPredicate,
+    /// Join attributes of the left tuples, in the order of the indexed attributes.
+    left_indexes: Vec<usize>,
+    /// Indexed attributes of the right table, in index order.
+    right_indexes: Vec<usize>,
+    left_child: Box<dyn OpIterator>,
+    storage_manager: &'static StorageManager,
+    container_id: ContainerId,
+    index_name: String,
+    tree_type: String,
+    transaction_id: TransactionId,
+    schema: TableSchema,
+    open: bool,
+    curr_left: Option<Tuple>,
+    /// Ids of the right records found for the current left tuple.
+    matches: Vec<ValueId>,
+    /// Position of the next right record to read.
+    pos: usize,
+}
+
+impl SpatialJoin {
+    /// Constructor for the index nested loop spatial join operator.
+    ///
+    /// # Arguments
+    ///
+    /// * `predicate` - Condition under which a left and a right tuple are joined.
+    /// * `left_indexes` - Join attributes of the left tuples, in the order of the indexed attributes.
+    /// * `right_indexes` - Indexed attributes of the right table, in index order.
+    /// * `left_child` - Left child of the join operator.
+    /// * `table` - Right table, probed through its index.
+    /// * `table_alias` - Alias of the right table.
+    /// * `container_id` - Container of the right table.
+    /// * `index_name` - Index of the right table to probe.
+    /// * `tree_type` - Kind of tree backing the index.
+    /// * `tid` - Transaction used to read the right table.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        storage_manager: &'static StorageManager,
+        predicate: SpatialPredicate,
+        left_indexes: Vec<usize>,
+        right_indexes: Vec<usize>,
+        left_child: Box<dyn OpIterator>,
+        table: Arc<RwLock<Table>>,
+        table_alias: &str,
+        container_id: &ContainerId,
+        index_name: &str,
+        tree_type: &str,
+        tid: TransactionId,
+    ) -> Result<Self, CrustyError> {
+        predicate.validate()?;
+        let right_schema = SeqScan::schema(&table.read().unwrap().schema, table_alias);
+        Ok(Self {
+            predicate,
+            left_indexes,
+            right_indexes,
+            schema: TableSchema::merge(left_child.get_schema(), &right_schema),
+            left_child,
+            storage_manager,
+            container_id: *container_id,
+            index_name: index_name.to_string(),
+            tree_type: tree_type.to_string(),
+            transaction_id: tid,
+            open: false,
+            curr_left: None,
+            matches: Vec::new(),
+            pos: 0,
+        })
+    }
+}
+
+impl OpIterator for SpatialJoin {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.left_child.open()?;
+        self.curr_left = None;
+        self.matches.clear();
+        self.pos = 0;
+        self.open = true;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        loop {
+            if let Some(left) = &self.curr_left {
+                let left_key = join_key(left, &self.left_indexes);
+                while let Some(id) = self.matches.get(self.pos) {
+                    self.pos += 1;
+                    let right = read_tuple(self.storage_manager, *id, self.transaction_id)?;
+                    // the index may return records that only share a grid cell on some attributes
+                    if self.predicate.matches(&left_key, &join_key(&right, &self.right_indexes)) {
+                        return Ok(Some(Tuple::merge(left, &right)));
+                    }
+                }
+            }
+            self.curr_left = self.left_child.next()?;
+            let left = match &self.curr_left {
+                Some(left) => left,
+                None => return Ok(None),
+            };
+            self.matches = self.storage_manager.index_spatial_probe(
+                &self.tree_type,
+                &self.index_name,
+                self.container_id,
+                &join_key(left, &self.left_indexes),
+                &self.predicate,
+            )?;
+            // read the records in storage order
+            self.matches.sort();
+            self.pos = 0;
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        self.left_child.close()?;
+        self.open = false;
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        self.left_child.rewind()?;
+        self.curr_left = None;
+        self.matches.clear();
+        self.pos = 0;
+        Ok(())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+/// All nearest neighbors join.
+///
+/// For every tuple of the left child, finds the `k` records of the right table whose indexed
+/// attributes are closest to the left join attributes, using an index of the right table. Every
+/// pair is returned with the distance between the two keys in thousandths as a last
+/// `distance_milli` attribute, the neighbors of a left tuple coming closest first.
+pub struct KnnJoin {
+    k: usize,
+    metric: DistanceMetric,
+    /// Join attributes of the left tuples, in the order of the indexed attributes.
+    left_indexes: Vec<usize>,
+    left_child: Box<dyn OpIterator>,
+    storage_manager: &'static StorageManager,
+    container_id: ContainerId,
+    index_name: String,
+    tree_type: String,
+    transaction_id: TransactionId,
+    schema: TableSchema,
+    open: bool,
+    curr_left: Option<Tuple>,
+    /// Neighbors of the current left tuple, closest first.
+    neighbors: Vec<Neighbor>,
+    /// Position of the next neighbor to return.
+    pos: usize,
+}
+
+impl KnnJoin {
+    /// Constructor for the all nearest neighbors join operator.
+    ///
+    /// # Arguments
+    ///
+    /// * `k` - Number of neighbors of every left tuple.
+    /// * `metric` - Metric the distance between keys is measured with.
+    /// * `left_indexes` - Join attributes of the left tuples, in the order of the indexed attributes.
+    /// * `left_child` - Left child of the join operator.
+    /// * `table` - Right table, searched through its index.
+    /// * `table_alias` - Alias of the right table.
+    /// * `container_id` - Container of the right table.
+    /// * `index_name` - Index of the right table to search.
+    /// * `tree_type` - Kind of tree backing the index.
+    /// * `tid` - Transaction used to read the right table.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        storage_manager: &'static StorageManager,
+        k: usize,
+        metric: DistanceMetric,
+        left_indexes: Vec<usize>,
+        left_child: Box<dyn OpIterator>,
+        table: Arc<RwLock<Table>>,
+        table_alias: &str,
+        container_id: &ContainerId,
+        index_name: &str,
+        tree_type: &str,
+        tid: TransactionId,
+    ) -> Self {
+        let right_schema = SeqScan::schema(&table.read().unwrap().schema, table_alias);
+        let mut attributes = TableSchema::merge(left_child.get_schema(), &right_schema).attributes;
+        attributes.push(Distance::column());
+        Self {
+            k,
+            metric,
+            left_indexes,
+            left_child,
+            storage_manager,
+            container_id: *container_id,
+            index_name: index_name.to_string(),
+            tree_type: tree_type.to_string(),
+            transaction_id: tid,
+            schema: TableSchema::new(attributes),
+            open: false,
+            curr_left: None,
+            neighbors: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl OpIterator for KnnJoin {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.left_child.open()?;
+        self.curr_left = None;
+        self.neighbors.clear();
+        self.pos = 0;
+        self.open = true;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        loop {
+            if let (Some(left), Some(neighbor)) = (&self.curr_left, self.neighbors.get(self.pos)) {
+                self.pos += 1;
+                let right = read_tuple(self.storage_manager, neighbor.rid, self.transaction_id)?;
+                let mut res = Tuple::merge(left, &right);
+                res.field_vals.push(neighbor.dist.to_field());
+                return Ok(Some(res));
+            }
+            self.curr_left = self.left_child.next()?;
+            let left = match &self.curr_left {
+                Some(left) => left,
+                None => return Ok(None),
+            };
+            self.neighbors = self.storage_manager.index_knn_query(
+                &self.tree_type,
+                &self.index_name,
+                self.container_id,
+                &join_key(left, &self.left_indexes),
+                self.k,
+                self.metric,
+            )?;
+            self.pos = 0;
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        self.left_child.close()?;
+        self.open = false;
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        self.left_child.rewind()?;
+        self.curr_left = None;
+        self.neighbors.clear();
+        self.pos = 0;
+        Ok(())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+/// One side of an R-tree join: a table and the R-tree index used to join it.
+pub struct RTreeJoinInput {
+    pub table: Arc<RwLock<Table>>,
+    pub table_alias: String,
+    pub container_id: ContainerId,
+    pub index_name: String,
+}
+
+/// Spatial join of two tables that both have an R-tree index over their join attributes.
+///
+/// The matching pairs are found when the operator is opened, by walking down both R-trees
+/// together and skipping the pairs of subtrees that can't hold a match.
+pub struct RTreeJoin {
+    predicate: SpatialPredicate,
+    storage_manager: &'static StorageManager,
+    left: (ContainerId, String),
+    right: (ContainerId, String),
+    transaction_id: TransactionId,
+    schema: TableSchema,
+    open: bool,
+    /// Ids of the left and right records of every match.
+    pairs: Vec<(ValueId, ValueId)>,
+    /// Position of the next pair to return.
+    pos: usize,
+}
+
+impl RTreeJoin {
+    /// Constructor for the R-tree join operator.
+    ///
+    /// # Arguments
+    ///
+    /// * `predicate` - Condition under which a left and a right tuple are joined.
+    /// * `left` - Left table and its R-tree index.
+    /// * `right` - Right table and its R-tree index, over as many attributes as the left one.
+    /// * `tid` - Transaction used to read the tables.
+    pub fn new(
+        storage_manager: &'static StorageManager,
+        predicate: SpatialPredicate,
+        left: RTreeJoinInput,
+        right: RTreeJoinInput,
+        tid: TransactionId,
+    ) -> Result<Self, CrustyError> {
+        predicate.validate()?;
+        let left_schema = SeqScan::schema(&left.table.read().unwrap().schema, &left.table_alias);
+        let right_schema = SeqScan::schema(&right.table.read().unwrap().schema, &right.table_alias);
+        Ok(Self {
+            predicate,
+            storage_manager,
+            left: (left.container_id, left.index_name),
+            right: (right.container_id, right.index_name),
+            transaction_id: tid,
+            schema: TableSchema::merge(&left_schema, &right_schema),
+            open: false,
+            pairs: Vec::new(),
+            pos: 0,
+        })
+    }
+}
+
+impl OpIterator for RTreeJoin {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.pairs = self.storage_manager.index_spatial_join(
+            (self.left.0, &self.left.1),
+            (self.right.0, &self.right.1),
+            &self.predicate,
+        )?;
+        self.pairs.sort();
+        self.pos = 0;
+        self.open = true;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        match self.pairs.get(self.pos) {
+            Some((left_id, right_id)) => {
+                self.pos += 1;
+                let left = read_tuple(self.storage_manager, *left_id, self.transaction_id)?;
+                let right = read_tuple(self.storage_manager, *right_id, self.transaction_id)?;
+                Ok(Some(Tuple::merge(&left, &right)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        self.open = false;
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        self.pos = 0;
+        Ok(())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_must_use)]
+mod test {
+    use super::*;
+    use crate::opiterator::testutil::num_tuples;
+    use crate::opiterator::TupleIterator;
+    use common::testutil::*;
+    use common::DataType;
+
+    fn get_schema() -> TableSchema {
+        TableSchema::from_vecs(vec!["a", "b", "c"], vec![DataType::Int; 3])
+    }
+
+    fn test_sm() -> &'static StorageManager {
+        Box::leak(Box::new(StorageManager::new_test_sm()))
+    }
+
+    fn left_rows() -> Vec<Vec<i32>> {
+        (0..40).map(|i| vec![i, (i * 7) % 23, (i * 11) % 19]).collect()
+    }
+
+    fn right_rows() -> Vec<Vec<i32>> {
+        (0..30).map(|i| vec![100 + i, (i * 5) % 21, (i * 3) % 17]).collect()
+    }
+
+    /// Stores the rows in a new container with an index over (b, c).
+    fn indexed_table(
+        sm: &'static StorageManager,
+        cid: ContainerId,
+        name: &str,
+        tree_type: &str,
+        rows: Vec<Vec<i32>>,
+    ) -> RTreeJoinInput {
+        let table = Table::new(name.to_string(), get_schema());
+        sm.create_table(cid).unwrap();
+        let tid = TransactionId::new();
+        for tuple in create_tuple_list(rows) {
+            sm.insert_value(cid, tuple.get_bytes(), tid);
+        }
+        sm.create_index_by_id(tree_type, "idx", cid, "(b,c)", &table).unwrap();
+        RTreeJoinInput {
+            table: Arc::new(RwLock::new(table)),
+            table_alias: name.to_string(),
+            container_id: cid,
+            index_name: String::from("idx"),
+        }
+    }
+
+    fn expected_matches(predicate: &SpatialPredicate) -> u32 {
+        let mut count = 0;
+        for left in left_rows() {
+            for right in right_rows() {
+                let left_key = int_vec_to_tuple(left[1..].to_vec()).field_vals;
+                let right_key = int_vec_to_tuple(right[1..].to_vec()).field_vals;
+                if predicate.matches(&left_key, &right_key) {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    fn predicates() -> Vec<SpatialPredicate> {
+        vec![
+            SpatialPredicate::Within {
+                radius: 3.0,
+                metric: DistanceMetric::Euclidean,
+            },
+            SpatialPredicate::same_cell(5).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn test_spatial_join() -> Result<(), CrustyError> {
+        for tree_type in ["KD", "R", "KDB"] {
+            let sm = test_sm();
+            let right = indexed_table(sm, 0, "right", tree_type, right_rows());
+            for predicate in predicates() {
+                let left = TupleIterator::new(create_tuple_list(left_rows()), get_schema());
+                let mut join = SpatialJoin::new(
+                    sm,
+                    predicate,
+                    vec![1, 2],
+                    vec![1, 2],
+                    Box::new(left),
+                    right.table.clone(),
+                    "right",
+                    &0,
+                    "idx",
+                    tree_type,
+                    TransactionId::new(),
+                )?;
+                assert_eq!(join.get_schema().size(), 6);
+                join.open()?;
+                let count = num_tuples(&mut join)?;
+                assert!(count > 0);
+                assert_eq!(count, expected_matches(&predicate));
+                join.rewind()?;
+                assert_eq!(num_tuples(&mut join)?, count);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_r_tree_join() -> Result<(), CrustyError> {
+        for predicate in predicates() {
+            let sm = test_sm();
+            let left = indexed_table(sm, 0, "left", "R", left_rows());
+            let right = indexed_table(sm, 1, "right", "R", right_rows());
+            let mut join = RTreeJoin::new(sm, predicate, left, right, TransactionId::new())?;
+            join.open()?;
+            let first = join.next()?.unwrap();
+            let left_key = first.field_vals[1..3].to_vec();
+            let right_key = first.field_vals[4..6].to_vec();
+            assert!(predicate.matches(&left_key, &right_key));
+            join.rewind()?;
+            assert_eq!(num_tuples(&mut join)?, expected_matches(&predicate));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_knn_join() -> Result<(), CrustyError> {
+        for tree_type in ["KD", "R", "KDB", "QUAD", "Z"] {
+            let sm = test_sm();
+            let right = indexed_table(sm, 0, "right", tree_type, right_rows());
+            let left = TupleIterator::new(create_tuple_list(left_rows()), get_schema());
+            let metric = DistanceMetric::Manhattan;
+            let mut join = KnnJoin::new(
+                sm,
+                3,
+                metric,
+                vec![1, 2],
+                Box::new(left),
+                right.table.clone(),
+                "right",
+                &0,
+                "idx",
+                tree_type,
+                TransactionId::new(),
+            );
+            assert_eq!(join.get_schema().size(), 7);
+            join.open()?;
+            for left in left_rows() {
+                let left_key = int_vec_to_tuple(left[1..].to_vec()).field_vals;
+                let mut expected: Vec<Neighbor> = right_rows()
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, right)| {
+                        let key = int_vec_to_tuple(right[1..].to_vec()).field_vals;
+                        Neighbor {
+                            dist: metric.distance(&left_key, &key),
+                            key,
+                            rid: ValueId::new_slot(0, 0, i as u16),
+                        }
+                    })
+                    .collect();
+                expected.sort_by_key(|neighbor| neighbor.dist);
+                for neighbor in expected.iter().take(3) {
+                    let tuple = join.next()?.unwrap();
+                    assert_eq!(tuple.field_vals[..3], int_vec_to_tuple(left.clone()).field_vals[..]);
+                    assert_eq!(tuple.field_vals[6], neighbor.dist.to_field());
+                }
+            }
+            assert!(join.next()?.is_none());
+            join.rewind()?;
+            assert_eq!(num_tuples(&mut join)?, 3 * left_rows().len() as u32);
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_next_not_open() {
+        let sm = test_sm();
+        let left = indexed_table(sm, 0, "left", "R", left_rows());
+        let right = indexed_table(sm, 1, "right", "R", right_rows());
+        let mut join = RTreeJoin::new(sm, predicates()[0], left, right, TransactionId::new()).unwrap();
+        join.next();
+    }
+
+    #[test]
+    #[should_panic(expected = "Operator has not been opened")]
+    fn test_close_not_open() {
+        let sm = test_sm();
+        let left = indexed_table(sm, 0, "left", "R", left_rows());
+        let right = indexed_table(sm, 1, "right", "R", right_rows());
+        let mut join = RTreeJoin::new(sm, predicates()[0], left, right, TransactionId::new()).unwrap();
+        join.close();
+    }
+
+    #[test]
+    fn test_invalid_cell_size() {
+        let sm = test_sm();
+        let right = indexed_table(sm, 0, "right", "KD", right_rows());
+        for cell_size in [0, -5] {
+            let left = TupleIterator::new(create_tuple_list(left_rows()), get_schema());
+            let predicate = SpatialPredicate::SameCell { cell_size };
+            assert!(SpatialJoin::new(
+                sm,
+                predicate,
+                vec![1, 2],
+                vec![1, 2],
+                Box::new(left),
+                right.table.clone(),
+                "right",
+                &0,
+                "idx",
+                "KD",
+                TransactionId::new(),
+            )
+            .is_err());
+        }
+        let left = indexed_table(sm, 1, "left", "R", left_rows());
+        let right = indexed_table(sm, 2, "right", "R", right_rows());
+        let predicate = SpatialPredicate::SameCell { cell_size: 0 };
+        assert!(RTreeJoin::new(sm, predicate, left, right, TransactionId::new()).is_err());
+    }
+}
@@ -224,6 +224,33 @@ impl Executor {
                     alias
                 ))),
             },
+            PhysicalOp::IndexScan(PhysicalIndexScanNode {
+                alias,
+                container_id,
+                index_name,
+                tree_type,
+                min,
+                max,
+            }) => match catalog.get_table_id(alias) {
+                Some(alias_id) => {
+                    let table = catalog.get_table_ptr(alias_id)?;
+                    Ok(Box::new(IndexScan::new(
+                        storage_manager,
+                        table,
+                        alias,
+                        container_id,
+                        index_name,
+                        tree_type,
+                        min.clone(),
+                        max.clone(),
+                        tid,
+                    )))
+                }
+                None => Err(CrustyError::CrustyError(format!(
+                    "Table {} has no container id ",
+                    alias
+                ))),
+            },
             PhysicalOp::Project(PhysicalProjectNode { identifiers }) => {
                 let child = children.next().ok_or_else(|| err.clone())??;
                 match &identifiers {
@@ -277,8 +304,8 @@ impl Executor {
 
                 // Sometimes the join condition is written in reverse of the join tables order.
                 if !left_schema.contains(left.column()) {
-                    let left_index = Executor::get_field_index(left.column(), right_schema)?;
-                    let right_index = Executor::get_field_index(right.column(), left_schema)?;
+                    let left_index = Executor::get_field_index(right.column(), left_schema)?;
+                    let right_index = Executor::get_field_index(left.column(), right_schema)?;
                     Ok(Box::new(Join::new(
                         op.flip(),
                         left_index,
@@ -302,6 +329,7 @@ impl Executor {
                 left,
                 right,
                 op,
+                right_index,
                 ..
             }) => {
                 let left_child = children.next().ok_or_else(|| err.clone())??;
@@ -309,10 +337,34 @@ impl Executor {
                 let right_child = children.next().ok_or_else(|| err.clone())??;
                 let right_schema = right_child.get_schema();
 
+                if let Some(PhysicalJoinIndex {
+                    container_id,
+                    index_name,
+                    tree_type,
+                }) = right_index
+                {
+                    let (left, right) = if left_schema.contains(left.column()) {
+                        (left, right)
+                    } else {
+                        (right, left)
+                    };
+                    let left_index = Executor::get_field_index(left.column(), left_schema)?;
+                    let right_index = Executor::get_field_index(right.column(), right_schema)?;
+                    Ok(Box::new(HashEqJoin::new_with_index(
+                        left_index,
+                        right_index,
+                        left_child,
+                        right_child,
+                        storage_manager,
+                        *container_id,
+                        index_name,
+                        tree_type,
+                        tid,
+                    )))
                 // Sometimes the join condition is written in reverse of the join tables order.
-                if !left_schema.contains(left.column()) {
-                    let left_index = Executor::get_field_index(left.column(), right_schema)?;
-                    let right_index = Executor::get_field_index(right.column(), left_schema)?;
+                } else if !left_schema.contains(left.column()) {
+                    let left_index = Executor::get_field_index(right.column(), left_schema)?;
+                    let right_index = Executor::get_field_index(left.column(), right_schema)?;
                     Ok(Box::new(HashEqJoin::new(
                         op.flip(),
                         left_index,
@@ -334,7 +386,7 @@ impl Executor {
             }
             PhysicalOp::Filter(PhysicalFilterNode { predicate, .. }) => {
                 let child = children.next().ok_or_else(|| err.clone())??;
-                let (identifiers, ops, operands, _compound_predicate_op) = match predicate {
+                let (identifiers, ops, operands, compound_predicate_op) = match predicate {
                     Predicate::SimplePredicate(SimplePredicate { left, op, right }) => {
                         match (left, right) {
                             (PredExpr::Ident(i), PredExpr::Literal(f)) => {
@@ -349,49 +401,68 @@ impl Executor {
                             }
                         }
                     }
-                    Predicate::CompoundPredicate(_) => {
-                        //Only supporting simple filter predicates right now
-                        unimplemented!()
-                    } // Predicate::CompoundPredicate(CompoundPredicate {
-                      //     op,
-                      //     simple_predicates,
-                      // }) => {
-                      //     let mut identifiers = Vec::new();
-                      //     let mut ops = Vec::new();
-                      //     let mut operands = Vec::new();
-                      //     for simple_predicate in simple_predicates {
-                      //         match (&simple_predicate.left, &simple_predicate.right) {
-                      //             (PredExpr::Ident(i), PredExpr::Literal(f)) => {
-                      //                 identifiers.push(i.clone());
-                      //                 ops.push(simple_predicate.op);
-                      //                 operands.push(f.clone());
-                      //             }
-                      //             (PredExpr::Literal(f), PredExpr::Ident(i)) => {
-                      //                 identifiers.push(i.clone());
-                      //                 ops.push(simple_predicate.op.flip());
-                      //                 operands.push(f.clone());
-                      //             }
-                      //             _ => {
-                      //                 return Err(err.clone());
-                      //             }
-                      //         }
-                      //     }
-
-                      //     (identifiers, ops, operands, Some(op.clone()))
-                      // }
+                    Predicate::CompoundPredicate(CompoundPredicate {
+                        op,
+                        simple_predicates,
+                    }) => {
+                        let mut identifiers = Vec::new();
+                        let mut ops = Vec::new();
+                        let mut operands = Vec::new();
+                        for simple_predicate in simple_predicates {
+                            match (&simple_predicate.left, &simple_predicate.right) {
+                                (PredExpr::Ident(i), PredExpr::Literal(f)) => {
+                                    identifiers.push(i.clone());
+                                    ops.push(simple_predicate.op);
+                                    operands.push(f.clone());
+                                }
+                                (PredExpr::Literal(f), PredExpr::Ident(i)) => {
+                                    identifiers.push(i.clone());
+                                    ops.push(simple_predicate.op.flip());
+                                    operands.push(f.clone());
+                                }
+                                _ => {
+                                    return Err(err.clone());
+                                }
+                            }
+                        }
+
+                        (identifiers, ops, operands, Some(op.clone()))
+                    }
+                    Predicate::RadiusPredicate(RadiusPredicate {
+                        columns,
+                        center,
+                        radius,
+                        metric,
+                    }) => {
+                        let field_inds = columns
+                            .iter()
+                            .map(|column| {
+                                Executor::get_field_index(column.column(), child.get_schema())
+                            })
+                            .collect::<Result<Vec<usize>, CrustyError>>()?;
+                        let filter =
+                            Filter::new_radius(field_inds, center.clone(), *radius, *metric, child);
+                        return Ok(Box::new(filter));
+                    }
                 };
-                let indexes: Vec<usize> = identifiers
+                let indexes = identifiers
                     .iter()
                     .map(|identifier| {
-                        Executor::get_field_index(identifier.column(), child.get_schema()).unwrap()
+                        Executor::get_field_index(identifier.column(), child.get_schema())
                     })
-                    .collect();
-                if identifiers.len() > 1 {
-                    todo!(); // fix
-                } else if identifiers.is_empty() {
+                    .collect::<Result<Vec<usize>, CrustyError>>()?;
+                if identifiers.is_empty() {
                     Err(CrustyError::ExecutionError(String::from("No predicates")))
+                } else if let Some(compound_predicate_op) = compound_predicate_op {
+                    let filter = Filter::new_compound(
+                        compound_predicate_op,
+                        ops,
+                        indexes,
+                        operands,
+                        child,
+                    );
+                    Ok(Box::new(filter))
                 } else {
-                    //let idx = Executor::get_field_index(identifier.column(), child.get_schema())?;
                     let filter = Filter::new(
                         *ops.get(0).unwrap(),
                         *indexes.get(0).unwrap(),
@@ -451,6 +522,124 @@ impl Executor {
     }
 }
 
+#[cfg(test)]
+mod join_test {
+    use super::*;
+    use common::database::Database;
+    use common::ids::{StateType, TransactionId};
+    use common::storage_trait::StorageTrait;
+    use common::table::Table;
+    use common::testutil::*;
+    use std::sync::{Arc, RwLock};
+
+    // a (x, y, z) and b (p, q), where b.q = a.x and b.p = a.y pick different pairs
+    fn test_db(sm: &'static StorageManager) -> Database {
+        let db = Database::new(String::from("test"));
+        let tid = TransactionId::new();
+        let tables = [
+            (
+                "a",
+                vec!["x", "y", "z"],
+                vec![vec![1, 2, 3], vec![2, 3, 4], vec![3, 9, 5]],
+            ),
+            (
+                "b",
+                vec!["p", "q"],
+                vec![vec![2, 1], vec![9, 3], vec![3, 7]],
+            ),
+        ];
+        for (cid, (name, columns, rows)) in tables.into_iter().enumerate() {
+            let cid = cid as ContainerId;
+            let schema =
+                TableSchema::from_vecs(columns.clone(), vec![DataType::Int; columns.len()]);
+            db.tables.write().unwrap().insert(
+                cid,
+                Arc::new(RwLock::new(Table::new(String::from(name), schema))),
+            );
+            db.named_containers
+                .write()
+                .unwrap()
+                .insert(cid, (String::from(name), StateType::BaseTable));
+            sm.create_table(cid).unwrap();
+            for tuple in create_tuple_list(rows) {
+                sm.insert_value(cid, tuple.get_bytes(), tid);
+            }
+        }
+        db
+    }
+
+    // a joined with b on b.q = a.x, with the condition naming the right table first
+    fn reversed_join_plan(join: PhysicalOp) -> PhysicalPlan {
+        let mut plan = PhysicalPlan::new();
+        let join = plan.add_node(join);
+        let left = plan.add_node(PhysicalOp::Scan(PhysicalScanNode {
+            alias: String::from("a"),
+            container_id: 0,
+        }));
+        let right = plan.add_node(PhysicalOp::Scan(PhysicalScanNode {
+            alias: String::from("b"),
+            container_id: 1,
+        }));
+        plan.add_edge(join, right);
+        plan.add_edge(join, left);
+        plan.set_root(join).unwrap();
+        plan
+    }
+
+    fn run_join(join: PhysicalOp) -> Result<Vec<Vec<Field>>, CrustyError> {
+        let sm: &'static StorageManager = Box::leak(Box::new(StorageManager::new_test_sm()));
+        let db = test_db(sm);
+        let plan = reversed_join_plan(join);
+        let tid = TransactionId::new();
+        let mut op = Executor::physical_plan_to_op_iterator(sm, &db, &plan, tid, 0)?;
+        op.open()?;
+        let mut res = Vec::new();
+        while let Some(tuple) = op.next()? {
+            res.push(tuple.field_vals);
+        }
+        res.sort();
+        Ok(res)
+    }
+
+    fn expected() -> Vec<Vec<Field>> {
+        let mut res = create_tuple_list(vec![vec![1, 2, 3, 2, 1], vec![3, 9, 5, 9, 3]])
+            .into_iter()
+            .map(|tuple| tuple.field_vals)
+            .collect::<Vec<_>>();
+        res.sort();
+        res
+    }
+
+    #[test]
+    fn test_reversed_nested_loop_join() -> Result<(), CrustyError> {
+        let join = PhysicalOp::NestedLoopJoin(PhysicalNestedLoopJoinNode {
+            left: FieldIdentifier::new("b", "b.q"),
+            right: FieldIdentifier::new("a", "a.x"),
+            op: SimplePredicateOp::Equals,
+            left_table: Some(String::from("b")),
+            right_table: Some(String::from("a")),
+        });
+        assert_eq!(expected(), run_join(join)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reversed_hash_join() -> Result<(), CrustyError> {
+        let join = PhysicalOp::HashJoin(PhysicalHashJoinNode {
+            left: FieldIdentifier::new("b", "b.q"),
+            right: FieldIdentifier::new("a", "a.x"),
+            op: SimplePredicateOp::Equals,
+            left_table: Some(String::from("b")),
+            right_table: Some(String::from("a")),
+            hash_table_state_id: 0,
+            hash_table_key: FieldIdentifier::new("default_name", "default_column"),
+            right_index: None,
+        });
+        assert_eq!(expected(), run_join(join)?);
+        Ok(())
+    }
+}
+
 /* FIXME
 #[cfg(test)]
 mod test {
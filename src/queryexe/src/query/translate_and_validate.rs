@@ -1,5 +1,6 @@
 use common::catalog::Catalog;
 use common::logical_plan::*;
+use common::md_index::DistanceMetric;
 use common::{get_name, CrustyError, DataType, Field, SimplePredicateOp};
 use sqlparser::ast::{
     BinaryOperator, Expr, Function, FunctionArg, JoinConstraint, JoinOperator, SelectItem, SetExpr,
@@ -63,7 +64,7 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
 
         let mut field = None;
         for table in &self.tables {
-            let table_id = self.catalog.get_table_id(&identifiers[0].to_string());
+            let table_id = self.catalog.get_table_id(table);
 
             if table_id.is_some() && self.catalog.is_valid_column(table_id.unwrap(), &orig) {
                 if field.is_some() {
@@ -217,6 +218,15 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
                     }
                     table.to_string()
                 }
+                Predicate::RadiusPredicate(radius_predicate) => {
+                    let table = radius_predicate.columns[0].table();
+                    if radius_predicate.columns.iter().any(|id| id.table() != table) {
+                        return Err(CrustyError::ValidationError(String::from(
+                            "Where includes identifiers to columns in multiple tables",
+                        )));
+                    }
+                    table.to_string()
+                }
             };
 
             let op = FilterNode { table, predicate };
@@ -432,6 +442,9 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
     /// * `expr` - Expression to parse.
     fn process_binary_op(&self, expr: &Expr) -> Result<Predicate, CrustyError> {
         match expr {
+            Expr::BinaryOp { left, op, right } if Self::distance_metric(left).is_some() => Ok(
+                Predicate::RadiusPredicate(self.process_radius_predicate(left, op, right)?),
+            ),
             Expr::BinaryOp { op, .. } => match Self::binary_op_to_predicate_op(op)? {
                 PredicateOp::SimplePredicateOp(_) => Ok(Predicate::SimplePredicate(
                     self.process_simple_predicate(expr)?,
@@ -446,6 +459,88 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
         }
     }
 
+    /// Gets the metric of a distance function, or None if the expression is not one.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - Expression to check.
+    fn distance_metric(expr: &Expr) -> Option<DistanceMetric> {
+        match expr {
+            Expr::Function(Function { name, .. }) => match &get_name(name).ok()?.to_uppercase()[..] {
+                "DISTANCE" | "EUCLIDEAN_DISTANCE" => Some(DistanceMetric::Euclidean),
+                "MANHATTAN_DISTANCE" => Some(DistanceMetric::Manhattan),
+                "CHEBYSHEV_DISTANCE" => Some(DistanceMetric::Chebyshev),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Parses a comparison of a distance function to a constant, such as
+    /// `DISTANCE(x, y, 3, 4) <= 5`, to a radius predicate. The arguments of the
+    /// function are the columns followed by the coordinates of the point to measure to.
+    ///
+    /// # Arguments
+    ///
+    /// * `left` - Distance function.
+    /// * `op` - Comparison operator.
+    /// * `right` - Largest distance allowed.
+    fn process_radius_predicate(
+        &self,
+        left: &Expr,
+        op: &BinaryOperator,
+        right: &Expr,
+    ) -> Result<RadiusPredicate, CrustyError> {
+        let metric = Self::distance_metric(left).unwrap();
+        if *op != BinaryOperator::LtEq {
+            return Err(CrustyError::ValidationError(String::from(
+                "Distances can only be compared with <=",
+            )));
+        }
+        let radius = match right {
+            Expr::Value(Value::Number(s, _)) => s.parse::<f64>().map_err(|_| {
+                CrustyError::ValidationError(format!("Unsupported distance {}", s))
+            })?,
+            _ => {
+                return Err(CrustyError::ValidationError(String::from(
+                    "Distances can only be compared to a number",
+                )))
+            }
+        };
+        let args = match left {
+            Expr::Function(Function { args, .. }) => args,
+            _ => unreachable!(),
+        };
+        if args.is_empty() || args.len() % 2 != 0 {
+            return Err(CrustyError::ValidationError(String::from(
+                "Distance takes the columns followed by as many coordinates",
+            )));
+        }
+        let mut columns = Vec::new();
+        let mut center = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            let arg = match arg {
+                FunctionArg::Named { name: _, arg } => arg,
+                FunctionArg::Unnamed(arg) => arg,
+            };
+            match (i < args.len() / 2, self.expr_to_pred_expr(arg)?) {
+                (true, PredExpr::Ident(ident)) => columns.push(ident),
+                (false, PredExpr::Literal(field)) => center.push(field),
+                _ => {
+                    return Err(CrustyError::ValidationError(String::from(
+                        "Distance takes the columns followed by as many coordinates",
+                    )))
+                }
+            }
+        }
+        Ok(RadiusPredicate {
+            columns,
+            center,
+            radius,
+            metric,
+        })
+    }
+
     /// Parses an expression to a simple predicate.
     ///
     /// # Arguments
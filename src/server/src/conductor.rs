@@ -1,9 +1,10 @@
-use sqlparser::ast::Statement;
+use sqlparser::ast::{Expr, ObjectType, Statement};
 use std::sync::Arc;
 
 use crate::queryexe::query::TranslateAndValidate;
 use common::ids::LogicalTimeStamp;
 use common::Tuple;
+use common::md_index::Distance;
 use common::TableSchema;
 use common::catalog::Catalog;
 use common::database::Database;
@@ -14,6 +15,7 @@ use txn_manager::transactions::Transaction;
 
 use crate::csv_utils;
 use crate::database_state::DatabaseState;
+use crate::handler::get_db_state;
 use crate::server_state::ServerState;
 use crate::sql_parser::{ParserResponse, SQLParser};
 use crate::worker::Message;
@@ -147,8 +149,8 @@ impl Conductor {
                         )))
                     }
                 };
-                self.process_create_index(query, db_state);
-                Ok(format!("Finished running CreateIndex query"))
+                let res = self.process_create_index(&query, db_state)?;
+                Ok(res.result().to_string())
             }
             commands::Commands::UseIndex(query) => {
                 info!("Processing COMMAND::UseIndex {:?}", query);
@@ -164,15 +166,29 @@ impl Conductor {
                         )))
                     }
                 };
-                let (tuple_res, container_name) = self.process_use_index(query, db_state);
-                let container_id = db_state.database.get_table_id(&container_name).unwrap();
-                let schema = &db_state.database.get_table(container_id).unwrap().schema;
-                let res = self.executor.execute(Some(tuple_res.clone()), schema);
+                let (tuple_res, schema) = self.process_use_index(query, db_state)?;
+                let res = self.executor.execute(Some(tuple_res), &schema);
                 match res {
                     Ok(qr) => Ok(qr.result),
                     Err(e) => Err(e),
                 }
             }
+            commands::Commands::CheckIndex(index_name) => {
+                info!("Processing COMMAND::CheckIndex {:?}", index_name);
+                let db_state = get_db_state(server_state, client_id).ok_or_else(|| {
+                    CrustyError::CrustyError(String::from("No active DB or DB not found"))
+                })?;
+                let res = db_state.check_index(&index_name)?;
+                Ok(res.result().to_string())
+            }
+            commands::Commands::Cluster(index_name) => {
+                info!("Processing COMMAND::Cluster {:?}", index_name);
+                let db_state = get_db_state(server_state, client_id).ok_or_else(|| {
+                    CrustyError::CrustyError(String::from("No active DB or DB not found"))
+                })?;
+                let res = db_state.cluster(&index_name)?;
+                Ok(res.result().to_string())
+            }
             #[allow(unused_variables)]
             commands::Commands::RunQueryPartial(name_and_range) => todo!(),
             commands::Commands::ConvertQuery(args) => {
@@ -265,6 +281,13 @@ impl Conductor {
                     None => Ok(String::from("No active DB or DB not found")),
                 }
             }
+            commands::Commands::ShowIndexes => {
+                info!("Processing COMMAND::ShowIndexes");
+                match get_db_state(server_state, client_id) {
+                    Some(db_state) => db_state.get_index_descriptions(),
+                    None => Ok(String::from("No active DB or DB not found")),
+                }
+            }
             commands::Commands::ShowQueries => {
                 info!("Processing COMMAND::ShowQueries");
                 let db_id_ref = server_state.active_connections.read().unwrap();
@@ -401,6 +424,21 @@ impl Conductor {
                 } => Err(CrustyError::CrustyError(String::from(
                     "Inserts not currently supported",
                 ))),
+                Statement::Drop {
+                    object_type: ObjectType::Index,
+                    if_exists,
+                    names,
+                    ..
+                } => {
+                    info!("Processing DROP index: {:?}", names);
+                    let mut res = Vec::new();
+                    for name in names {
+                        res.push(db_state.drop_index(&get_name(name)?, *if_exists)?);
+                    }
+                    Ok(QueryResult::new(
+                        &res.iter().map(|qr| qr.result()).collect::<Vec<_>>().join("\n"),
+                    ))
+                }
                 _ => Err(CrustyError::CrustyError(String::from("Not supported"))),
             }
         }
@@ -446,25 +484,88 @@ impl Conductor {
     }
 
 
-    fn process_create_index(&mut self, query: String, db_state: &'static DatabaseState) {
+    /// Runs a CREATE INDEX statement.
+    ///
+    /// # Arguments
+    ///
+    /// * `statement` - CREATE INDEX statement.
+    /// * `tree_type` - Kind of tree to back the index with.
+    pub fn run_create_index(
+        &mut self,
+        statement: &Statement,
+        tree_type: &str,
+        db_state: &'static DatabaseState,
+    ) -> Result<QueryResult, CrustyError> {
+        match statement {
+            Statement::CreateIndex {
+                name,
+                table_name,
+                columns,
+                ..
+            } => {
+                info!("Processing CREATE index: {:?}", name);
+                let mut column_names = Vec::new();
+                for column in columns {
+                    match &column.expr {
+                        Expr::Identifier(ident) => column_names.push(ident.value.clone()),
+                        expr => {
+                            return Err(CrustyError::CrustyError(format!(
+                                "Can only index columns, not {}",
+                                expr
+                            )))
+                        }
+                    }
+                }
+                db_state.create_index(
+                    tree_type,
+                    &get_name(name)?,
+                    &get_name(table_name)?,
+                    &column_names,
+                )
+            }
+            _ => Err(CrustyError::CrustyError(String::from(
+                "Not a CREATE INDEX statement",
+            ))),
+        }
+    }
+
+    /// Runs `\createIndex <tree type> <index name> <table> (<columns>)`.
+    fn process_create_index(
+        &mut self,
+        query: &str,
+        db_state: &'static DatabaseState,
+    ) -> Result<QueryResult, CrustyError> {
         debug!("Comes to process_create_index in Conductor");
-        let mut tokens = query.split(" ");
-        tokens.next();
-        let _tree_type = tokens.next();
-        let _index_name = tokens.next();
-        let _container = tokens.next();
-        let _attributes = tokens.next();
-        if  _tree_type.is_none() || _index_name.is_none() || _container.is_none() || _attributes.is_none() {
-            //error
+        // the column list may contain spaces, so it is split off before the rest is tokenized
+        let (head, columns) = match query.find('(') {
+            Some(pos) => query.split_at(pos),
+            None => (query, ""),
+        };
+        let tokens: Vec<&str> = head.split_whitespace().collect();
+        let columns: Vec<String> = columns
+            .trim()
+            .trim_start_matches('(')
+            .trim_end_matches(')')
+            .split(',')
+            .map(|column| column.trim().to_string())
+            .filter(|column| !column.is_empty())
+            .collect();
+        match tokens.as_slice() {
+            [tree_type, index_name, table_name] => {
+                db_state.create_index(tree_type, index_name, table_name, &columns)
+            }
+            _ => Err(CrustyError::CrustyError(String::from(
+                "Usage: \\createIndex <tree type> <index name> <table> (<columns>)",
+            ))),
         }
-        let tree_type = _tree_type.unwrap();
-        let index_name = _index_name.unwrap();
-        let container = _container.unwrap();
-        let attributes = _attributes.unwrap();
-        db_state.create_index(tree_type, index_name, container, attributes);
     }
 
-    fn process_use_index(&mut self, query: String, db_state: &'static DatabaseState) -> (Vec<Tuple>, String) {
+    // returns the records found and their schema
+    fn process_use_index(
+        &mut self,
+        query: String,
+        db_state: &'static DatabaseState,
+    ) -> Result<(Vec<Tuple>, TableSchema), CrustyError> {
         debug!("Comes to process_create_index in Conductor");
         let mut tokens = query.split(" ");
         tokens.next();
@@ -474,14 +575,36 @@ impl Conductor {
         let _container = tokens.next();
         let _attributes = tokens.next();
         let _k = tokens.next();
+        let metric = tokens.next();
         if _tree_type.is_none() || _query_type.is_none() || _index_name.is_none() || _container.is_none() || _attributes.is_none() {
-            //error
+            return Err(CrustyError::CrustyError(String::from(
+                "Usage: \\useIndex <tree type> <query type> <index name> <table> <key> [k] [metric] (KNN, RADIUS and KNNJOIN results end with distance_milli, the distance in thousandths)",
+            )));
         }
         let tree_type = _tree_type.unwrap();
         let query_type = _query_type.unwrap();
         let index_name = _index_name.unwrap();
         let container = _container.unwrap();
         let attributes = _attributes.unwrap();
-        (db_state.use_index(tree_type, query_type, index_name, container, attributes, _k), container.to_string().clone())
+        // a KNN join gives the outer table and its join attributes as the key
+        if query_type == "KNNJOIN" {
+            return db_state.knn_join(tree_type, index_name, container, attributes, _k, metric);
+        }
+        // so do spatial joins, whose radius or cell size comes in place of k
+        if query_type == "WITHIN" || query_type == "SAMECELL" {
+            return db_state.spatial_join(tree_type, query_type, index_name, container, attributes, _k, metric);
+        }
+        let table = db_state
+            .database
+            .get_table_id(container)
+            .and_then(|container_id| db_state.database.get_table(container_id))
+            .ok_or_else(|| CrustyError::CrustyError(format!("Table {} not found", container)))?;
+        let tuples = db_state.use_index(tree_type, query_type, index_name, container, attributes, _k, metric);
+        let mut attributes = table.schema.attributes.clone();
+        // KNN and RADIUS queries also return the distance of each record to the query key, in thousandths
+        if query_type == "KNN" || query_type == "RADIUS" {
+            attributes.push(Distance::column());
+        }
+        Ok((tuples, TableSchema::new(attributes)))
     }
 }
@@ -13,7 +13,10 @@ use common::database::Database;
 use common::ids::{AtomicTimeStamp, StateMeta, StateType};
 use common::physical_plan::PhysicalPlan;
 use common::prelude::*;
-use common::table::Table;
+use common::md_index::{DistanceMetric, SpatialPredicate};
+use heapstore::index_registry;
+use crate::queryexe::opiterator::{KnnJoin, OpIterator, RTreeJoin, RTreeJoinInput, SeqScan, SpatialJoin};
+use common::table::{IndexInfo, Table};
 use common::{get_attr, Attribute, QueryResult};
 use sqlparser::ast::ColumnDef;
 use sqlparser::ast::TableConstraint;
@@ -187,6 +190,35 @@ impl DatabaseState {
         }
     }
 
+    /// Lists the indexes of the database with their tables, columns and row counts.
+    /// Row counts are refreshed from the storage manager first.
+    pub fn get_index_descriptions(&self) -> Result<String, CrustyError> {
+        let mut descriptions = Vec::new();
+        for index in self.database.get_all_indexes() {
+            let num_rows = self
+                .storage_manager
+                .index_num_entries(index.container_id, &index.name)?;
+            self.database.set_index_rows(&index.name, num_rows);
+            let table_name = match self.database.get_table(index.container_id) {
+                Some(table) => table.name,
+                None => index.container_id.to_string(),
+            };
+            descriptions.push(format!(
+                "{} {} {} ({}) {} rows",
+                index.name,
+                index.tree_type,
+                table_name,
+                index.columns.join(", "),
+                num_rows
+            ));
+        }
+        if descriptions.is_empty() {
+            Ok(String::from("No indexes"))
+        } else {
+            Ok(descriptions.join("\n"))
+        }
+    }
+
     pub fn get_registered_query_names(&self) -> Result<String, CrustyError> {
         self.query_registrar.get_registered_query_names()
     }
@@ -277,27 +309,351 @@ impl DatabaseState {
             None,
         )?;
         tables_ref.insert(table_id, Arc::new(RwLock::new(table)));
+        drop(tables_ref);
+        // the primary key is indexed by a B+ tree on its first column
+        if let Some(pk) = pks.first() {
+            if pks.len() > 1 {
+                info!(
+                    "Primary key of {} has {} columns, only {} is indexed",
+                    table_name,
+                    pks.len(),
+                    pk.value
+                );
+            }
+            if let Err(e) = self.create_index(
+                "BTREE",
+                &format!("{}_pkey", table_name),
+                table_name,
+                &[pk.value.clone()],
+            ) {
+                // a table whose key can't be indexed is not created at all
+                self.database.remove_table(table_id);
+                self.storage_manager.remove_container(table_id)?;
+                return Err(e);
+            }
+        }
         Ok(QueryResult::new(&format!("Table {} created", table_name)))
     }
 
-    pub fn create_index(&self, tree_type: &str, index_name: &str, container_name: &str, attributes: &str) {
+    /// Builds an index on the columns of a table and records it in the catalog.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree_type` - Kind of tree backing the index.
+    /// * `index_name` - Name of the new index.
+    /// * `table_name` - Table to index.
+    /// * `columns` - Indexed columns, in key order.
+    pub fn create_index(
+        &self,
+        tree_type: &str,
+        index_name: &str,
+        table_name: &str,
+        columns: &[String],
+    ) -> Result<QueryResult, CrustyError> {
         debug!("Comes to create_index in Database State");
-        let container_id = self.database.get_table_id(container_name);
-        if container_id.is_some() {
-            let table = self.database.get_table(container_id.unwrap()).unwrap();
-            self.storage_manager.create_index_by_id(tree_type, index_name, container_id.unwrap(), attributes, &table);
-        } else {   
-            error!("ERROR: Table not found");    
-            //error
-        } 
+        let index_type = index_registry::index_type(tree_type)?;
+        if self.database.get_index(index_name).is_some() {
+            return Err(CrustyError::CrustyError(format!(
+                "Index {} already exists",
+                index_name
+            )));
+        }
+        let container_id = self.database.get_table_id(table_name).ok_or_else(|| {
+            CrustyError::CrustyError(format!("Table {} not found", table_name))
+        })?;
+        let table = self.database.get_table(container_id).unwrap();
+        if columns.is_empty() {
+            return Err(CrustyError::CrustyError(String::from(
+                "Index needs at least one column",
+            )));
+        }
+        index_type.check_dim(columns.len())?;
+        for column in columns {
+            if table.schema.get_field_index(column).is_none() {
+                return Err(CrustyError::CrustyError(format!(
+                    "Column {} not found in table {}",
+                    column, table_name
+                )));
+            }
+        }
+        self.storage_manager.create_index_by_id(
+            tree_type,
+            index_name,
+            container_id,
+            &format!("({})", columns.join(",")),
+            &table,
+        )?;
+        let num_rows = self
+            .storage_manager
+            .index_num_entries(container_id, index_name)?;
+        // record the index so the optimizer can plan index scans with it
+        self.database.add_index(IndexInfo::new(
+            index_name.to_string(),
+            tree_type.to_string(),
+            container_id,
+            columns.to_vec(),
+            num_rows,
+        ))?;
+        Ok(QueryResult::new(&format!("Index {} created", index_name)))
+    }
+
+    /// Removes an index from its table and from the catalog.
+    ///
+    /// # Arguments
+    ///
+    /// * `index_name` - Name of the index to drop.
+    /// * `if_exists` - Whether a missing index is ignored rather than an error.
+    pub fn drop_index(&self, index_name: &str, if_exists: bool) -> Result<QueryResult, CrustyError> {
+        match self.database.remove_index(index_name) {
+            Some(index) => {
+                self.storage_manager
+                    .drop_index(index.container_id, index_name)?;
+                Ok(QueryResult::new(&format!("Index {} dropped", index_name)))
+            }
+            None if if_exists => Ok(QueryResult::new(&format!(
+                "Index {} does not exist, skipping",
+                index_name
+            ))),
+            None => Err(CrustyError::CrustyError(format!(
+                "Index {} not found",
+                index_name
+            ))),
+        }
+    }
+
+    /// Checks that an index agrees with the records of its table. A consistent index gives a
+    /// message with the number of records checked, and an inconsistent one an error listing
+    /// every discrepancy.
+    ///
+    /// # Arguments
+    ///
+    /// * `index_name` - Name of the index to check.
+    pub fn check_index(&self, index_name: &str) -> Result<QueryResult, CrustyError> {
+        let index = self
+            .database
+            .get_index(index_name)
+            .ok_or_else(|| CrustyError::CrustyError(format!("Index {} not found", index_name)))?;
+        let problems = self
+            .storage_manager
+            .check_index(index.container_id, index_name)?;
+        if problems.is_empty() {
+            let num_rows = self
+                .storage_manager
+                .index_num_entries(index.container_id, index_name)?;
+            Ok(QueryResult::new(&format!(
+                "Index {} is consistent ({} rows)",
+                index_name, num_rows
+            )))
+        } else {
+            Err(CrustyError::CrustyError(format!(
+                "Index {} has {} problems:\n{}",
+                index_name,
+                problems.len(),
+                problems.join("\n")
+            )))
+        }
+    }
+
+    /// Rewrites the pages of the table of a Z-order index with its records in the curve order of
+    /// the index, so that scans over a region of the indexed attributes read fewer pages.
+    ///
+    /// # Arguments
+    ///
+    /// * `index_name` - Name of the Z-order index giving the order.
+    pub fn cluster(&self, index_name: &str) -> Result<QueryResult, CrustyError> {
+        let index = self
+            .database
+            .get_index(index_name)
+            .ok_or_else(|| CrustyError::CrustyError(format!("Index {} not found", index_name)))?;
+        let num_pages = self
+            .storage_manager
+            .cluster_container(index.container_id, index_name)?;
+        Ok(QueryResult::new(&format!(
+            "Clustered on index {} ({} pages)",
+            index_name, num_pages
+        )))
+    }
+
+    /// Join every record of an outer table to its `k` nearest records of the inner table, found
+    /// with an index of the inner table. `outer` names the outer table and its join attributes,
+    /// in the order of the indexed attributes, as in `points(x,y)`.
+    pub fn knn_join(
+        &self,
+        tree_type: &str,
+        index_name: &str,
+        inner_name: &str,
+        outer: &str,
+        k: Option<&str>,
+        metric: Option<&str>,
+    ) -> Result<(Vec<Tuple>, TableSchema), CrustyError> {
+        let k = k
+            .and_then(|k| k.parse::<usize>().ok())
+            .ok_or_else(|| CrustyError::CrustyError(String::from("no k specified")))?;
+        let metric = Self::parse_metric(metric)?;
+        let (outer_name, columns) = Self::parse_join_outer(outer)?;
+        let (outer_id, outer_table) = self.get_join_table(outer_name)?;
+        let (inner_id, inner_table) = self.get_join_table(inner_name)?;
+        let left_indexes = Self::column_indexes(&outer_table, outer_name, &columns)?;
+        let tid = TransactionId::new();
+        let outer_scan = SeqScan::new(self.storage_manager, outer_table, outer_name, &outer_id, tid);
+        let mut join = KnnJoin::new(
+            self.storage_manager,
+            k,
+            metric,
+            left_indexes,
+            Box::new(outer_scan),
+            inner_table,
+            inner_name,
+            &inner_id,
+            index_name,
+            tree_type,
+            tid,
+        );
+        Self::run_join(&mut join)
+    }
+
+    /// Join every record of an outer table to the records of the inner table matching it under a
+    /// spatial predicate, found with an index of the inner table. `predicate` is `WITHIN`, whose
+    /// `param` is the largest distance under `metric`, or `SAMECELL`, whose `param` is the size of
+    /// the grid cells. `outer` is given as for `knn_join`. When the index is an R-tree and the
+    /// outer table has an R-tree over its join attributes, both trees are walked together rather
+    /// than probing the index for every outer record.
+    #[allow(clippy::too_many_arguments)]
+    pub fn spatial_join(
+        &self,
+        tree_type: &str,
+        predicate: &str,
+        index_name: &str,
+        inner_name: &str,
+        outer: &str,
+        param: Option<&str>,
+        metric: Option<&str>,
+    ) -> Result<(Vec<Tuple>, TableSchema), CrustyError> {
+        let predicate = match predicate {
+            "WITHIN" => SpatialPredicate::Within {
+                radius: param
+                    .and_then(|radius| radius.parse::<f64>().ok())
+                    .ok_or_else(|| CrustyError::CrustyError(String::from("no radius specified")))?,
+                metric: Self::parse_metric(metric)?,
+            },
+            "SAMECELL" => SpatialPredicate::same_cell(
+                param
+                    .and_then(|cell_size| cell_size.parse::<i64>().ok())
+                    .ok_or_else(|| CrustyError::CrustyError(String::from("no cell size specified")))?,
+            )?,
+            _ => {
+                return Err(CrustyError::CrustyError(format!(
+                    "Spatial join predicate {} not supported",
+                    predicate
+                )))
+            }
+        };
+        let (outer_name, columns) = Self::parse_join_outer(outer)?;
+        let (outer_id, outer_table) = self.get_join_table(outer_name)?;
+        let (inner_id, inner_table) = self.get_join_table(inner_name)?;
+        let index = self
+            .database
+            .get_index(index_name)
+            .filter(|index| index.container_id == inner_id)
+            .ok_or_else(|| CrustyError::CrustyError(format!("Index {} not found on {}", index_name, inner_name)))?;
+        let outer_index = self.database.get_all_indexes().into_iter().find(|outer_index| {
+            outer_index.tree_type == "R" && outer_index.container_id == outer_id && outer_index.columns == columns
+        });
+        let tid = TransactionId::new();
+        match outer_index {
+            Some(outer_index) if tree_type == "R" && index.tree_type == "R" => {
+                debug!("Joining R-trees {} and {}", outer_index.name, index_name);
+                let left = RTreeJoinInput {
+                    table: outer_table,
+                    table_alias: outer_name.to_string(),
+                    container_id: outer_id,
+                    index_name: outer_index.name,
+                };
+                let right = RTreeJoinInput {
+                    table: inner_table,
+                    table_alias: inner_name.to_string(),
+                    container_id: inner_id,
+                    index_name: index_name.to_string(),
+                };
+                Self::run_join(&mut RTreeJoin::new(self.storage_manager, predicate, left, right, tid)?)
+            }
+            _ => {
+                let left_indexes = Self::column_indexes(&outer_table, outer_name, &columns)?;
+                let right_indexes = Self::column_indexes(&inner_table, inner_name, &index.columns)?;
+                let outer_scan = SeqScan::new(self.storage_manager, outer_table, outer_name, &outer_id, tid);
+                let mut join = SpatialJoin::new(
+                    self.storage_manager,
+                    predicate,
+                    left_indexes,
+                    right_indexes,
+                    Box::new(outer_scan),
+                    inner_table,
+                    inner_name,
+                    &inner_id,
+                    index_name,
+                    tree_type,
+                    tid,
+                )?;
+                Self::run_join(&mut join)
+            }
+        }
+    }
+
+    fn parse_metric(metric: Option<&str>) -> Result<DistanceMetric, CrustyError> {
+        match metric {
+            Some(name) => DistanceMetric::from_name(name)
+                .ok_or_else(|| CrustyError::CrustyError(format!("Distance metric {} not supported", name))),
+            None => Ok(DistanceMetric::default()),
+        }
+    }
+
+    // splits `points(x,y)` into the table and its join attributes
+    fn parse_join_outer(outer: &str) -> Result<(&str, Vec<String>), CrustyError> {
+        let (outer_name, columns) = outer
+            .strip_suffix(')')
+            .and_then(|outer| outer.split_once('('))
+            .ok_or_else(|| CrustyError::CrustyError(format!("Can't parse the outer table {}", outer)))?;
+        Ok((outer_name, columns.split(',').map(|column| column.trim().to_string()).collect()))
+    }
+
+    fn get_join_table(&self, name: &str) -> Result<(ContainerId, Arc<RwLock<Table>>), CrustyError> {
+        let container_id = self
+            .database
+            .get_table_id(name)
+            .ok_or_else(|| CrustyError::CrustyError(format!("Table {} not found", name)))?;
+        Ok((container_id, self.database.get_table_ptr(container_id)?))
+    }
+
+    fn column_indexes(table: &Arc<RwLock<Table>>, table_name: &str, columns: &[String]) -> Result<Vec<usize>, CrustyError> {
+        let schema = &table.read().unwrap().schema;
+        columns
+            .iter()
+            .map(|column| {
+                schema.get_field_index(column).copied().ok_or_else(|| {
+                    CrustyError::CrustyError(format!("Column {} not found in {}", column, table_name))
+                })
+            })
+            .collect()
+    }
+
+    // runs a join to completion, returning its records and their schema
+    fn run_join(join: &mut dyn OpIterator) -> Result<(Vec<Tuple>, TableSchema), CrustyError> {
+        join.open()?;
+        let mut tuples = Vec::new();
+        while let Some(tuple) = join.next()? {
+            tuples.push(tuple);
+        }
+        join.close()?;
+        Ok((tuples, join.get_schema().clone()))
     }
 
-    pub fn use_index(&self, tree_type: &str, query_type: &str, index_name: &str, container_name: &str, attributes: &str, _k: Option<&str>) -> Vec<Tuple> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn use_index(&self, tree_type: &str, query_type: &str, index_name: &str, container_name: &str, attributes: &str, _k: Option<&str>, metric: Option<&str>) -> Vec<Tuple> {
         debug!("Comes to use_index in Database State");
         let container_id = self.database.get_table_id(container_name);
         if container_id.is_some() {
             let table = self.database.get_table(container_id.unwrap()).unwrap();
-            self.storage_manager.use_index_by_id(tree_type, query_type, index_name, container_id.unwrap(), attributes, _k, &table)
+            self.storage_manager.use_index_by_id(tree_type, query_type, index_name, container_id.unwrap(), attributes, _k, metric, &table)
         } else {   
             error!("ERROR: Table not found");    
             Vec::new()
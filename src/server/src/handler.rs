@@ -4,13 +4,42 @@ use std::io::{Read, Write};
 use std::net::{Shutdown, TcpStream};
 
 use crate::conductor::Conductor;
+use crate::database_state::DatabaseState;
 use crate::server_state::ServerState;
 use crate::sql_parser::{ParserResponse, SQLParser};
 
 use crate::Executor;
 use common::commands::{Commands, Response};
+use common::{CrustyError, QueryResult};
 use optimizer::optimizer::Optimizer;
 
+/// Gets the database the client is connected to.
+pub(crate) fn get_db_state(server_state: &'static ServerState, client_id: u64) -> Option<&'static DatabaseState> {
+    let db_id_ref = server_state.active_connections.read().unwrap();
+    let db_id = db_id_ref.get(&client_id)?;
+    let db_ref = server_state.id_to_db.read().unwrap();
+    db_ref.get(db_id).copied()
+}
+
+/// Turns the result of running a SQL statement into a response for the client.
+fn sql_response(res: Result<QueryResult, CrustyError>, quiet: bool) -> Response {
+    match res {
+        Ok(qr) => {
+            if quiet {
+                debug!("Query result is good. Sending QuietOK");
+                Response::QuietOk
+            } else {
+                info!("Success running SQL query");
+                Response::QueryResult(qr)
+            }
+        }
+        Err(err) => {
+            info!("Error while executing SQL query");
+            Response::Err(err.to_string())
+        }
+    }
+}
+
 /// Waits for user commands and dispatches the commands.
 ///
 /// # Arguments
@@ -72,32 +101,25 @@ pub fn handle_client_request(mut stream: TcpStream, server_state: &'static Serve
                         match SQLParser::parse_sql(sql) {
                             // SQL Query
                             ParserResponse::SQL(ast) => {
-                                let db_id_ref = server_state.active_connections.read().unwrap();
-                                match db_id_ref.get(&client_id) {
-                                    Some(db_id) => {
-                                        let db_ref = server_state.id_to_db.read().unwrap();
-                                        let db_state = db_ref.get(db_id).unwrap();
-                                        match conductor.run_sql(ast, db_state) {
-                                            Ok(qr) => {
-                                                if quiet {
-                                                    debug!("Query result is good. Sending QuietOK");
-                                                    Response::QuietOk
-                                                } else {
-                                                    info!("Success running SQL query");
-                                                    Response::QueryResult(qr)
-                                                }
-                                            }
-                                            Err(err) => {
-                                                info!("Error while executing SQL query");
-                                                Response::Err(err.to_string())
-                                            }
-                                        }
+                                match get_db_state(server_state, client_id) {
+                                    Some(db_state) => {
+                                        sql_response(conductor.run_sql(ast, db_state), quiet)
                                     }
                                     None => {
                                         Response::Err("No active DB or DB not found".to_string())
                                     }
                                 }
                             }
+                            ParserResponse::CreateIndex {
+                                statement,
+                                tree_type,
+                            } => match get_db_state(server_state, client_id) {
+                                Some(db_state) => sql_response(
+                                    conductor.run_create_index(&statement, &tree_type, db_state),
+                                    quiet,
+                                ),
+                                None => Response::Err("No active DB or DB not found".to_string()),
+                            },
                             // Errors
                             ParserResponse::SQLError(e) => {
                                 Response::Err(format!("SQL error: {}", e))
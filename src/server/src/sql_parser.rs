@@ -2,7 +2,10 @@ use sqlparser::parser::Parser;
 
 use sqlparser::ast::TableConstraint;
 use sqlparser::ast::{ColumnDef, ColumnOption, Ident, Statement};
+use sqlparser::dialect::keywords::Keyword;
 use sqlparser::parser::ParserError;
+use sqlparser::tokenizer::{Token, Tokenizer};
+use heapstore::index_registry;
 
 pub struct SQLParser {}
 
@@ -14,6 +17,11 @@ pub enum ParserResponse {
     SQLError(ParserError),
     SQL(Vec<Statement>),
     SQLConstraintError(String),
+    /// CREATE INDEX statement along with the tree type named by its USING clause.
+    CreateIndex {
+        statement: Statement,
+        tree_type: String,
+    },
 }
 
 impl SQLParser {
@@ -23,6 +31,11 @@ impl SQLParser {
 
     /// Validates sql string, first if it is sql itself, then if it has a primary key
     pub fn parse_sql(sql: String) -> ParserResponse {
+        // USING is not understood by the parser, so it is taken out beforehand
+        let (sql, index_method) = match SQLParser::take_index_method(&sql) {
+            Ok(res) => res,
+            Err(e) => return e,
+        };
         // Allows for multiple checks and different errors for each fail
         let request = SQLParser::validate_sql(sql);
         match request {
@@ -37,24 +50,81 @@ impl SQLParser {
                 }
 
                 let statement = ast.first().unwrap();
-                if let Statement::CreateTable {
-                    columns,
-                    constraints,
-                    ..
-                } = statement
-                {
-                    match SQLParser::get_pks(columns, constraints) {
+                match statement {
+                    Statement::CreateTable {
+                        columns,
+                        constraints,
+                        ..
+                    } => match SQLParser::get_pks(columns, constraints) {
                         Ok(_) => request,
                         Err(e) => e,
-                    }
-                } else {
-                    request
+                    },
+                    Statement::CreateIndex { .. } => ParserResponse::CreateIndex {
+                        statement: statement.clone(),
+                        tree_type: index_method.unwrap_or_else(|| String::from("KD")),
+                    },
+                    _ => request,
                 }
             }
             _ => unreachable!(),
         }
     }
 
+    /// Takes the `USING <method>` clause out of a CREATE INDEX statement.
+    ///
+    /// Returns the statement without the clause and the tree type for the method, if one was given.
+    /// Methods are `kdtree` (or `kd`), `rtree` (or `r`), `kdbtree` (or `kdb`), `quadtree` (or `octree`
+    /// or `quad`), `zorder` (or `morton` or `z`), `btree` and `hash`.
+    fn take_index_method(sql: &str) -> Result<(String, Option<String>), ParserResponse> {
+        let dialect = sqlparser::dialect::GenericDialect {};
+        let tokens = match Tokenizer::new(&dialect, sql).tokenize() {
+            Ok(tokens) => tokens,
+            // leave the error to the parser
+            Err(_) => return Ok((sql.to_string(), None)),
+        };
+        let keywords: Vec<(usize, &Token)> = tokens
+            .iter()
+            .enumerate()
+            .filter(|(_, token)| !matches!(token, Token::Whitespace(_)))
+            .collect();
+        let is_keyword = |i: usize, keyword: Keyword| match keywords.get(i) {
+            Some((_, Token::Word(word))) => word.keyword == keyword,
+            _ => false,
+        };
+        let index_pos = if is_keyword(1, Keyword::UNIQUE) { 2 } else { 1 };
+        if !is_keyword(0, Keyword::CREATE) || !is_keyword(index_pos, Keyword::INDEX) {
+            return Ok((sql.to_string(), None));
+        }
+        let using_pos = match (0..keywords.len()).find(|i| is_keyword(*i, Keyword::USING)) {
+            Some(pos) => pos,
+            None => return Ok((sql.to_string(), None)),
+        };
+        let tree_type = match keywords.get(using_pos + 1) {
+            Some((_, Token::Word(word))) => match index_registry::index_type_for_method(&word.value) {
+                Some(index_type) => index_type.tree_type,
+                None => {
+                    return Err(ParserResponse::SQLConstraintError(format!(
+                        "Unknown index method {}",
+                        word.value.to_lowercase()
+                    )))
+                }
+            },
+            _ => {
+                return Err(ParserResponse::SQLConstraintError(String::from(
+                    "USING requires an index method",
+                )))
+            }
+        };
+        let (start, end) = (keywords[using_pos].0, keywords[using_pos + 1].0);
+        let sql = tokens
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i < start || *i > end)
+            .map(|(_, token)| token.to_string())
+            .collect();
+        Ok((sql, Some(tree_type.to_string())))
+    }
+
     /// Returns Request::SQL if given string is valid sql, else returns Request::SQLError
     fn validate_sql(sql: String) -> ParserResponse {
         let dialect = sqlparser::dialect::GenericDialect {};
@@ -334,4 +404,68 @@ mod libtests {
             }
         }
     }
+
+    #[test]
+    fn test_create_index_using() {
+        let sql = String::from("CREATE INDEX idx ON test USING rtree (b, c)");
+        match SQLParser::parse_sql(sql) {
+            ParserResponse::CreateIndex {
+                statement: Statement::CreateIndex { name, columns, .. },
+                tree_type,
+            } => {
+                assert_eq!(tree_type, "R");
+                assert_eq!(name.to_string(), "idx");
+                assert_eq!(columns.len(), 2);
+            }
+            res => panic!("Unexpected parser response {:?}", res),
+        }
+
+        let sql = String::from("create index idx on test (b)");
+        match SQLParser::parse_sql(sql) {
+            ParserResponse::CreateIndex { tree_type, .. } => assert_eq!(tree_type, "KD"),
+            res => panic!("Unexpected parser response {:?}", res),
+        }
+
+        let sql = String::from("create index idx on test using kdb (b, c)");
+        match SQLParser::parse_sql(sql) {
+            ParserResponse::CreateIndex { tree_type, .. } => assert_eq!(tree_type, "KDB"),
+            res => panic!("Unexpected parser response {:?}", res),
+        }
+
+        let sql = String::from("create index idx on test using octree (a, b, c)");
+        match SQLParser::parse_sql(sql) {
+            ParserResponse::CreateIndex { tree_type, .. } => assert_eq!(tree_type, "QUAD"),
+            res => panic!("Unexpected parser response {:?}", res),
+        }
+
+        let sql = String::from("create index idx on test using zorder (b, c)");
+        match SQLParser::parse_sql(sql) {
+            ParserResponse::CreateIndex { tree_type, .. } => assert_eq!(tree_type, "Z"),
+            res => panic!("Unexpected parser response {:?}", res),
+        }
+
+        let sql = String::from("create index idx on test using btree (b)");
+        match SQLParser::parse_sql(sql) {
+            ParserResponse::CreateIndex { tree_type, .. } => assert_eq!(tree_type, "BTREE"),
+            res => panic!("Unexpected parser response {:?}", res),
+        }
+
+        let sql = String::from("create index idx on test using hash (b)");
+        match SQLParser::parse_sql(sql) {
+            ParserResponse::CreateIndex { tree_type, .. } => assert_eq!(tree_type, "HASH"),
+            res => panic!("Unexpected parser response {:?}", res),
+        }
+
+        let sql = String::from("create index idx on test using gist (b, c)");
+        assert!(matches!(
+            SQLParser::parse_sql(sql),
+            ParserResponse::SQLConstraintError(_)
+        ));
+    }
+
+    #[test]
+    fn test_using_outside_create_index() {
+        let sql = String::from("select * from a join b using (c)");
+        assert!(matches!(SQLParser::parse_sql(sql), ParserResponse::SQL(_)));
+    }
 }